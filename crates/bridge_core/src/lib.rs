@@ -45,14 +45,19 @@ use std::{
     ptr,
     result::Result as StdResult,
     slice,
-    sync::Mutex,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
 };
 use tectonic_errors::prelude::*;
 use tectonic_io_base::{
-    digest::DigestData, normalize_tex_path, InputFeatures, InputHandle, IoProvider, OpenResult,
-    OutputHandle,
+    digest::DigestData, normalize_tex_path, InputFeatures, InputHandle, InputOrigin, IoProvider,
+    OpenResult, OutputHandle,
+};
+use tectonic_status_base::{
+    tt_error, tt_warning, MessageKind, Progress, SourceLocation, StatusBackend,
 };
-use tectonic_status_base::{tt_error, tt_warning, MessageKind, StatusBackend};
 
 /// Possible failures for “system request” calls to the driver.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -150,6 +155,18 @@ pub trait DriverHooks {
     ) -> StdResult<(), SystemRequestError> {
         Err(SystemRequestError::NotImplemented)
     }
+
+    /// The engine is checking whether it should stop processing.
+    ///
+    /// Engines call this function periodically while they work. If it returns
+    /// true, the engine aborts with an error. This allows drivers to stop
+    /// runaway processing, for instance to enforce a time limit. Detailed
+    /// information about the reason for the interruption should be logged or
+    /// stored inside the hook function. The default implementation never
+    /// interrupts processing.
+    fn check_interrupt(&mut self, _status: &mut dyn StatusBackend) -> bool {
+        false
+    }
 }
 
 /// This type provides a minimal [`DriverHooks`] implementation.
@@ -199,6 +216,12 @@ impl EngineAbortedError {
         }
     }
 
+    fn new_cancelled() -> Self {
+        EngineAbortedError {
+            message: "processing was cancelled".to_owned(),
+        }
+    }
+
     unsafe fn new_with_details() -> Self {
         let ptr = _ttbc_get_error_message();
         let message = CStr::from_ptr(ptr).to_string_lossy().into_owned();
@@ -214,12 +237,42 @@ impl Display for EngineAbortedError {
 
 impl std::error::Error for EngineAbortedError {}
 
+/// A token that can be used to cancel engine processing from another thread.
+///
+/// Clones of a token share the same state, so one clone can be handed to a
+/// [`CoreBridgeLauncher`] while another is kept to cancel the work. Once a
+/// token has been cancelled, engines launched with it abort with an
+/// [`EngineAbortedError`] at their next opportunity, which is generally quite
+/// soon: the check is made when reading or writing files, when shipping out
+/// pages, and periodically while processing. Engines launched after
+/// cancellation abort immediately.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Create a new token that has not been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the processing associated with this token.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Query whether this token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
 /// A mechanism for launching bridged FFI code.
 pub struct CoreBridgeLauncher<'a> {
     hooks: &'a mut dyn DriverHooks,
     status: &'a mut dyn StatusBackend,
     security: SecuritySettings,
     filesystem_emulation_settings: FsEmulationSettings,
+    cancellation: Option<CancellationToken>,
 }
 
 impl<'a> CoreBridgeLauncher<'a> {
@@ -244,6 +297,7 @@ impl<'a> CoreBridgeLauncher<'a> {
             status,
             security,
             filesystem_emulation_settings: FsEmulationSettings::default(),
+            cancellation: None,
         }
     }
 
@@ -262,6 +316,12 @@ impl<'a> CoreBridgeLauncher<'a> {
         self
     }
 
+    /// Allow the engine to be cancelled with the specified token.
+    pub fn with_cancellation_token(&mut self, token: CancellationToken) -> &mut Self {
+        self.cancellation = Some(token);
+        self
+    }
+
     /// Invoke a function to launch a bridged FFI engine with a global mutex
     /// held.
     ///
@@ -280,11 +340,17 @@ impl<'a> CoreBridgeLauncher<'a> {
         F: FnOnce(&mut CoreBridgeState<'_>) -> Result<T>,
     {
         let _guard = ENGINE_LOCK.lock().unwrap();
+
+        if self.cancellation.as_ref().map(|c| c.is_cancelled()) == Some(true) {
+            return Err(EngineAbortedError::new_cancelled().into());
+        }
+
         let mut state = CoreBridgeState::new(
             self.security.clone(),
             self.hooks,
             self.status,
             self.filesystem_emulation_settings.clone(),
+            self.cancellation.clone(),
         );
         let result = callback(&mut state);
 
@@ -331,6 +397,15 @@ pub struct CoreBridgeState<'a> {
     /// recent input didn't have a filesystem path (it came from a bundle or
     /// memory or something else).
     latest_input_path: Option<PathBuf>,
+
+    /// Whether the most recently opened input file is part of the document
+    /// being processed, rather than a support file from the bundle. Only such
+    /// files are reported as progress, so that the packages loaded by a LaTeX
+    /// document don't drown out its own files.
+    latest_input_is_document: bool,
+
+    /// A token that may be used to cancel this invocation.
+    cancellation: Option<CancellationToken>,
 }
 
 impl<'a> CoreBridgeState<'a> {
@@ -339,6 +414,7 @@ impl<'a> CoreBridgeState<'a> {
         hooks: &'a mut dyn DriverHooks,
         status: &'a mut dyn StatusBackend,
         fs_emulation_settings: FsEmulationSettings,
+        cancellation: Option<CancellationToken>,
     ) -> CoreBridgeState<'a> {
         CoreBridgeState {
             security,
@@ -347,10 +423,16 @@ impl<'a> CoreBridgeState<'a> {
             output_handles: Vec::new(),
             input_handles: Vec::new(),
             latest_input_path: None,
+            latest_input_is_document: false,
             fs_emulation_settings,
+            cancellation,
         }
     }
 
+    fn is_cancelled(&self) -> bool {
+        self.cancellation.as_ref().map(|c| c.is_cancelled()) == Some(true)
+    }
+
     fn input_open_name_format(
         &mut self,
         name: &str,
@@ -582,6 +664,7 @@ impl<'a> CoreBridgeState<'a> {
             }
         };
 
+        self.latest_input_is_document = ih.origin() == InputOrigin::Filesystem;
         self.input_handles.push(Box::new(ih));
         self.latest_input_path = path;
         &mut **self.input_handles.last_mut().unwrap()
@@ -602,6 +685,7 @@ impl<'a> CoreBridgeState<'a> {
             }
         };
 
+        self.latest_input_is_document = true;
         self.input_handles.push(Box::new(ih));
         self.latest_input_path = path;
         &mut **self.input_handles.last_mut().unwrap()
@@ -715,6 +799,16 @@ impl<'a> CoreBridgeState<'a> {
             true
         }
     }
+
+    fn check_interrupt(&mut self) -> Interruption {
+        if self.is_cancelled() {
+            Interruption::Cancelled
+        } else if self.hooks.check_interrupt(self.status) {
+            Interruption::Driver
+        } else {
+            Interruption::None
+        }
+    }
 }
 
 /// A type for storing settings about potentially insecure engine features.
@@ -830,28 +924,118 @@ impl Default for FsEmulationSettings {
     }
 }
 
+/// Build the location of a diagnostic from the values passed in from C.
+///
+/// A null *file* means that the location is unknown.
+unsafe fn source_location(file: *const libc::c_char, line: libc::c_int) -> Option<SourceLocation> {
+    if file.is_null() {
+        return None;
+    }
+
+    let rfile = CStr::from_ptr(file);
+    Some(SourceLocation {
+        file: rfile.to_string_lossy().into_owned(),
+        line: if line > 0 { Some(line as u32) } else { None },
+    })
+}
+
 // The entry points.
 
 /// Issue a warning.
 ///
+/// The *file* and *line* give the position in the input that the warning
+/// pertains to, as with `ttbc_diag_set_location`. If *file* is null, the
+/// location is unknown.
+///
 /// # Safety
 ///
-/// This function is unsafe because it accepts a raw C string.
+/// This function is unsafe because it accepts raw C strings.
 #[no_mangle]
-pub unsafe extern "C" fn ttbc_issue_warning(es: &mut CoreBridgeState, text: *const libc::c_char) {
+pub unsafe extern "C" fn ttbc_issue_warning(
+    es: &mut CoreBridgeState,
+    text: *const libc::c_char,
+    file: *const libc::c_char,
+    line: libc::c_int,
+) {
     let rtext = CStr::from_ptr(text);
-    tt_warning!(es.status, "{}", rtext.to_string_lossy());
+    es.status.report_diagnostic(
+        MessageKind::Warning,
+        format_args!("{}", rtext.to_string_lossy()),
+        source_location(file, line).as_ref(),
+    );
 }
 
 /// Issue an error.
 ///
+/// The *file* and *line* give the position in the input that the error
+/// pertains to, as with `ttbc_diag_set_location`. If *file* is null, the
+/// location is unknown.
+///
 /// # Safety
 ///
-/// This function is unsafe because it accepts a raw C string.
+/// This function is unsafe because it accepts raw C strings.
 #[no_mangle]
-pub unsafe extern "C" fn ttbc_issue_error(es: &mut CoreBridgeState, text: *const libc::c_char) {
+pub unsafe extern "C" fn ttbc_issue_error(
+    es: &mut CoreBridgeState,
+    text: *const libc::c_char,
+    file: *const libc::c_char,
+    line: libc::c_int,
+) {
     let rtext = CStr::from_ptr(text);
-    tt_error!(es.status, "{}", rtext.to_string_lossy());
+    es.status.report_diagnostic(
+        MessageKind::Error,
+        format_args!("{}", rtext.to_string_lossy()),
+        source_location(file, line).as_ref(),
+    );
+}
+
+/// Report that the engine has started reading an input file.
+///
+/// The engine should call this function right after opening the file. The
+/// report is only passed on if the file is the primary input or was found on
+/// the filesystem, so that files loaded from the bundle are skipped.
+///
+/// # Safety
+///
+/// This function is unsafe because it accepts a raw C string.
+#[no_mangle]
+pub unsafe extern "C" fn ttbc_report_input_opened(
+    es: &mut CoreBridgeState,
+    name: *const libc::c_char,
+) {
+    if !es.latest_input_is_document {
+        return;
+    }
+
+    let rname = CStr::from_ptr(name).to_string_lossy();
+    es.status
+        .report_progress(&Progress::InputOpened { name: &rname });
+}
+
+/// Report that the engine has shipped out a page.
+///
+/// The *page* is the number of pages shipped out so far, and *file* is the
+/// name of the input file being read at the time, or NULL if there is none.
+///
+/// # Safety
+///
+/// This function is unsafe because it accepts a raw C string.
+#[no_mangle]
+pub unsafe extern "C" fn ttbc_report_page_shipped_out(
+    es: &mut CoreBridgeState,
+    page: libc::c_int,
+    file: *const libc::c_char,
+) {
+    let rfile = if file.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(file).to_string_lossy())
+    };
+
+    es.status.report_progress(&Progress::PageShippedOut {
+        page: page.max(0) as u32,
+        file: rfile.as_deref(),
+    });
 }
 
 /// Calculate the MD5 digest of a Tectonic file.
@@ -1190,6 +1374,7 @@ pub extern "C" fn ttbc_input_close(
 pub struct Diagnostic {
     message: String,
     kind: MessageKind,
+    location: Option<SourceLocation>,
 }
 
 /// Create a new diagnostic that will be reported as a warning.
@@ -1198,6 +1383,7 @@ pub extern "C" fn ttbc_diag_begin_warning() -> *mut Diagnostic {
     let warning = Box::new(Diagnostic {
         message: String::new(),
         kind: MessageKind::Warning,
+        location: None,
     });
     Box::into_raw(warning)
 }
@@ -1208,10 +1394,29 @@ pub extern "C" fn ttbc_diag_begin_error() -> *mut Diagnostic {
     let warning = Box::new(Diagnostic {
         message: String::new(),
         kind: MessageKind::Error,
+        location: None,
     });
     Box::into_raw(warning)
 }
 
+/// Record the input file and line number that a diagnostic pertains to.
+///
+/// The *file* should be the name of the input as it was passed to
+/// `ttbc_input_open`. If *line* is not positive, the line number is taken to be
+/// unknown.
+///
+/// # Safety
+///
+/// This function is unsafe because it accepts a raw C string.
+#[no_mangle]
+pub unsafe extern "C" fn ttbc_diag_set_location(
+    diag: &mut Diagnostic,
+    file: *const libc::c_char,
+    line: libc::c_int,
+) {
+    diag.location = source_location(file, line);
+}
+
 /// Append text to a diagnostic.
 ///
 /// # Safety
@@ -1228,8 +1433,11 @@ pub unsafe extern "C" fn ttbc_diag_append(diag: &mut Diagnostic, text: *const li
 pub extern "C" fn ttbc_diag_finish(es: &mut CoreBridgeState, diag: *mut Diagnostic) {
     // By creating the box, we will free the diagnostic when this function exits.
     let rdiag = unsafe { Box::from_raw(diag as *mut Diagnostic) };
-    es.status
-        .report(rdiag.kind, format_args!("{}", rdiag.message), None);
+    es.status.report_diagnostic(
+        rdiag.kind,
+        format_args!("{}", rdiag.message),
+        rdiag.location.as_ref(),
+    );
 }
 
 /// Run a shell command
@@ -1255,6 +1463,35 @@ pub unsafe extern "C" fn ttbc_shell_escape(
     libc::c_int::from(es.shell_escape(&rcmd))
 }
 
+/// Reasons that engine processing might need to stop early.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(C)]
+pub enum Interruption {
+    /// Processing may continue.
+    None = 0,
+
+    /// The driver asked for processing to stop.
+    Driver = 1,
+
+    /// Processing was cancelled with a [`CancellationToken`].
+    Cancelled = 2,
+}
+
+/// Check whether processing should stop.
+#[no_mangle]
+pub extern "C" fn ttbc_check_interrupt(es: &mut CoreBridgeState) -> Interruption {
+    es.check_interrupt()
+}
+
+/// Check whether processing has been cancelled.
+///
+/// Unlike [`ttbc_check_interrupt`], this doesn't consult the driver, so it is
+/// cheap enough to call for every read and write.
+#[no_mangle]
+pub extern "C" fn ttbc_is_cancelled(es: &mut CoreBridgeState) -> bool {
+    es.is_cancelled()
+}
+
 /// Different types of files that can be opened by TeX engines
 ///
 /// This enumeration is used to guess filename extensions to try when looking
@@ -89,6 +89,7 @@ ttstub_diag_printf(ttbc_diagnostic_t *diag, const char *format, ...)
 
 static ttbc_state_t *tectonic_global_bridge_core = NULL;
 static jmp_buf jump_buffer;
+static ttbc_input_location_func_t input_location_func = NULL;
 
 
 NORETURN PRINTF_FUNC(1,2) int
@@ -125,6 +126,14 @@ void
 ttbc_global_engine_exit(void)
 {
     tectonic_global_bridge_core = NULL;
+    input_location_func = NULL;
+}
+
+
+void
+ttbc_global_set_input_location_func(ttbc_input_location_func_t func)
+{
+    input_location_func = func;
 }
 
 
@@ -132,11 +141,18 @@ PRINTF_FUNC(1,2) void
 ttstub_issue_warning(const char *format, ...)
 {
     va_list ap;
+    char *file = NULL;
+    int line = 0;
 
     va_start(ap, format);
     vsnprintf(format_buf, BUF_SIZE, format, ap);
     va_end(ap);
-    ttbc_issue_warning(tectonic_global_bridge_core, format_buf);
+
+    if (input_location_func != NULL)
+        file = input_location_func(&line);
+
+    ttbc_issue_warning(tectonic_global_bridge_core, format_buf, file, line);
+    free(file);
 }
 
 
@@ -144,11 +160,18 @@ PRINTF_FUNC(1,2) void
 ttstub_issue_error(const char *format, ...)
 {
     va_list ap;
+    char *file = NULL;
+    int line = 0;
 
     va_start(ap, format);
     vsnprintf(format_buf, BUF_SIZE, format, ap); /* Not ideal to (ab)use format_buf here */
     va_end(ap);
-    ttbc_issue_error(tectonic_global_bridge_core, format_buf);
+
+    if (input_location_func != NULL)
+        file = input_location_func(&line);
+
+    ttbc_issue_error(tectonic_global_bridge_core, format_buf, file, line);
+    free(file);
 }
 
 
@@ -159,6 +182,20 @@ ttstub_diag_finish(ttbc_diagnostic_t *diag)
 }
 
 
+void
+ttstub_report_input_opened(const char *name)
+{
+    ttbc_report_input_opened(tectonic_global_bridge_core, name);
+}
+
+
+void
+ttstub_report_page_shipped_out(int page, const char *file)
+{
+    ttbc_report_page_shipped_out(tectonic_global_bridge_core, page, file);
+}
+
+
 rust_output_handle_t
 ttstub_output_open(char const *path, int is_gz)
 {
@@ -183,6 +220,7 @@ ttstub_output_putc(rust_output_handle_t handle, int c)
 size_t
 ttstub_output_write(rust_output_handle_t handle, const char *data, size_t len)
 {
+    ttstub_check_cancelled();
     return ttbc_output_write(tectonic_global_bridge_core, handle, (const uint8_t*) data, len);
 }
 
@@ -282,6 +320,7 @@ ttstub_input_seek(rust_input_handle_t handle, ssize_t offset, int whence)
 ssize_t
 ttstub_input_read(rust_input_handle_t handle, char *data, size_t len)
 {
+    ttstub_check_cancelled();
     return ttbc_input_read(tectonic_global_bridge_core, handle, (uint8_t *) data, len);
 }
 
@@ -323,3 +362,25 @@ ttstub_shell_escape(const unsigned short *cmd, size_t len)
 {
     return ttbc_shell_escape(tectonic_global_bridge_core, cmd, len);
 }
+
+void
+ttstub_check_interrupt(void)
+{
+    switch (ttbc_check_interrupt(tectonic_global_bridge_core)) {
+    case TTBC_INTERRUPTION_NONE:
+        break;
+    case TTBC_INTERRUPTION_CANCELLED:
+        _tt_abort("processing was cancelled");
+        break;
+    default:
+        _tt_abort("processing was interrupted by the driver");
+        break;
+    }
+}
+
+void
+ttstub_check_cancelled(void)
+{
+    if (ttbc_is_cancelled(tectonic_global_bridge_core))
+        _tt_abort("processing was cancelled");
+}
@@ -221,6 +221,18 @@ PRINTF_FUNC(2,0) void ttstub_diag_vprintf(ttbc_diagnostic_t *diag, const char *f
 jmp_buf *ttbc_global_engine_enter(ttbc_state_t *api);
 void ttbc_global_engine_exit(void);
 
+/* An engine that keeps track of its position in the input can register a
+ * function that reports it, so that the warnings and errors issued with
+ * ttstub_issue_warning() and ttstub_issue_error() are given a location. The
+ * function should return the name of the current input file, allocated with
+ * malloc(), and store the current line number in `*line`; or return NULL if
+ * there is no current input file. The function is forgotten by
+ * ttbc_global_engine_exit(). */
+
+typedef char *(*ttbc_input_location_func_t)(int *line);
+
+void ttbc_global_set_input_location_func(ttbc_input_location_func_t func);
+
 NORETURN PRINTF_FUNC(1,2) int _tt_abort(const char *format, ...);
 
 PRINTF_FUNC(1,2) void ttstub_issue_warning(const char *format, ...);
@@ -228,6 +240,9 @@ PRINTF_FUNC(1,2) void ttstub_issue_error(const char *format, ...);
 
 void ttstub_diag_finish(ttbc_diagnostic_t *diag);
 
+void ttstub_report_input_opened(const char *name);
+void ttstub_report_page_shipped_out(int page, const char *file);
+
 rust_output_handle_t ttstub_output_open(char const *path, int is_gz);
 rust_output_handle_t ttstub_output_open_stdout(void);
 int ttstub_output_putc(rust_output_handle_t handle, int c);
@@ -251,6 +266,9 @@ int ttstub_get_file_md5(char const *path, char *digest);
 
 int ttstub_shell_escape(const unsigned short *cmd, size_t len);
 
+void ttstub_check_interrupt(void);
+void ttstub_check_cancelled(void);
+
 END_EXTERN_C
 
 #endif /* not TECTONIC_CORE_BRIDGE_H */
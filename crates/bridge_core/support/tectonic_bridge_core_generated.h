@@ -113,6 +113,24 @@ typedef enum {
   TTBC_FILE_FORMAT_VF = 33,
 } ttbc_file_format;
 
+/**
+ * Reasons that engine processing might need to stop early.
+ */
+typedef enum {
+  /**
+   * Processing may continue.
+   */
+  TTBC_INTERRUPTION_NONE = 0,
+  /**
+   * The driver asked for processing to stop.
+   */
+  TTBC_INTERRUPTION_DRIVER = 1,
+  /**
+   * Processing was cancelled with a [`CancellationToken`].
+   */
+  TTBC_INTERRUPTION_CANCELLED = 2,
+} ttbc_interruption;
+
 /**
  * The CoreBridgeState structure is a handle to Rust state that can be used by
  * C/C++ engine code to perform basic I/O functions.
@@ -140,20 +158,53 @@ extern const char *_ttbc_get_error_message(void);
 /**
  * Issue a warning.
  *
+ * The *file* and *line* give the position in the input that the warning
+ * pertains to, as with `ttbc_diag_set_location`. If *file* is null, the
+ * location is unknown.
+ *
  * # Safety
  *
- * This function is unsafe because it accepts a raw C string.
+ * This function is unsafe because it accepts raw C strings.
  */
-void ttbc_issue_warning(ttbc_state_t *es, const char *text);
+void ttbc_issue_warning(ttbc_state_t *es, const char *text, const char *file, int line);
 
 /**
  * Issue an error.
  *
+ * The *file* and *line* give the position in the input that the error
+ * pertains to, as with `ttbc_diag_set_location`. If *file* is null, the
+ * location is unknown.
+ *
+ * # Safety
+ *
+ * This function is unsafe because it accepts raw C strings.
+ */
+void ttbc_issue_error(ttbc_state_t *es, const char *text, const char *file, int line);
+
+/**
+ * Report that the engine has started reading an input file.
+ *
+ * The engine should call this function right after opening the file. The
+ * report is only passed on if the file is the primary input or was found on
+ * the filesystem, so that files loaded from the bundle are skipped.
+ *
  * # Safety
  *
  * This function is unsafe because it accepts a raw C string.
  */
-void ttbc_issue_error(ttbc_state_t *es, const char *text);
+void ttbc_report_input_opened(ttbc_state_t *es, const char *name);
+
+/**
+ * Report that the engine has shipped out a page.
+ *
+ * The *page* is the number of pages shipped out so far, and *file* is the
+ * name of the input file being read at the time, or NULL if there is none.
+ *
+ * # Safety
+ *
+ * This function is unsafe because it accepts a raw C string.
+ */
+void ttbc_report_page_shipped_out(ttbc_state_t *es, int page, const char *file);
 
 /**
  * Calculate the MD5 digest of a Tectonic file.
@@ -320,6 +371,19 @@ ttbc_diagnostic_t *ttbc_diag_begin_error(void);
  */
 void ttbc_diag_append(ttbc_diagnostic_t *diag, const char *text);
 
+/**
+ * Record the input file and line number that a diagnostic pertains to.
+ *
+ * The *file* should be the name of the input as it was passed to
+ * `ttbc_input_open`. If *line* is not positive, the line number is taken to be
+ * unknown.
+ *
+ * # Safety
+ *
+ * This function is unsafe because it accepts a raw C string.
+ */
+void ttbc_diag_set_location(ttbc_diagnostic_t *diag, const char *file, int line);
+
 /**
  * "Finish" a diagnostic: report it to the driver and free the diagnostic object.
  */
@@ -334,6 +398,19 @@ void ttbc_diag_finish(ttbc_state_t *es, ttbc_diagnostic_t *diag);
  */
 int ttbc_shell_escape(ttbc_state_t *es, const uint16_t *cmd, size_t len);
 
+/**
+ * Check whether processing should stop.
+ */
+ttbc_interruption ttbc_check_interrupt(ttbc_state_t *es);
+
+/**
+ * Check whether processing has been cancelled.
+ *
+ * Unlike [`ttbc_check_interrupt`], this doesn't consult the driver, so it is
+ * cheap enough to call for every read and write.
+ */
+bool ttbc_is_cancelled(ttbc_state_t *es);
+
 #ifdef __cplusplus
 } // extern "C"
 #endif // __cplusplus
@@ -15,15 +15,25 @@
 //! which you’ll pass to [`Cache::open()`]. When using this function, you must
 //! explicitly specify the concrete [`CacheBackend`] type that will service
 //! backend requests.
+//!
+//! The cache is laid out as follows. The `urls` directory maps each start URL
+//! to the digest of the bundle that it most recently pointed to. The
+//! `redirects`, `indexes`, and `manifests` directories contain one text file
+//! per bundle digest, recording the bundle’s resolved URL, its index, and the
+//! list of its files that have actually been cached. Finally, the `files`
+//! directory contains the cached file data, stored by content digest, so that
+//! identical files are shared between bundles. Use [`Cache::bundles()`] and
+//! [`Cache::gc()`] to inspect and clean up these data.
 
 use fs2::FileExt;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     env,
     fs::{self, File},
     io::{BufRead, BufReader, Error as IoError, ErrorKind as IoErrorKind, Read, Write},
     path::{Path, PathBuf},
     str::FromStr,
+    time::SystemTime,
 };
 use tectonic_errors::prelude::*;
 use tectonic_io_base::{
@@ -91,6 +101,193 @@ impl Cache {
     ) -> Result<CachingBundle<CB>> {
         CachingBundle::new(url, only_cached, status, &self.root)
     }
+
+    /// Get information about all of the bundles that have data in this cache.
+    ///
+    /// The results are sorted by bundle digest.
+    pub fn bundles(&self) -> Result<Vec<CachedBundleInfo>> {
+        let url_refs = self.url_references()?;
+        let mut digests: HashSet<String> = url_refs.keys().cloned().collect();
+
+        for subdir in ["redirects", "indexes", "manifests"] {
+            digests.extend(list_txt_digests(&self.root.join(subdir))?.into_keys());
+        }
+
+        let data_base = self.root.join("files");
+        let mut digests: Vec<String> = digests.into_iter().collect();
+        digests.sort();
+        let mut infos = Vec::with_capacity(digests.len());
+
+        for digest_text in digests {
+            let digest = DigestData::from_str(&digest_text)?;
+            let resolved_url =
+                fs::read_to_string(make_txt_path(&self.root.join("redirects"), &digest_text)).ok();
+            let mut size = 0;
+
+            for subdir in ["redirects", "indexes", "manifests"] {
+                size += file_size(&make_txt_path(&self.root.join(subdir), &digest_text));
+            }
+
+            let file_digests =
+                read_manifest_digests(&make_txt_path(&self.root.join("manifests"), &digest_text))?;
+
+            for file_digest in &file_digests {
+                size += file_size(&make_data_path(&data_base, file_digest));
+            }
+
+            infos.push(CachedBundleInfo {
+                digest,
+                resolved_url,
+                n_files: file_digests.len(),
+                size,
+                last_used: url_refs.get(&digest_text).copied(),
+            });
+        }
+
+        Ok(infos)
+    }
+
+    /// Get the total size, in bytes, of the data stored in this cache.
+    pub fn size(&self) -> Result<u64> {
+        let mut size = 0;
+
+        for subdir in ["urls", "redirects", "indexes", "manifests", "files"] {
+            size += tree_size(&self.root.join(subdir))?;
+        }
+
+        Ok(size)
+    }
+
+    /// Remove cached data for all bundles except the ones listed in *keep*.
+    ///
+    /// This removes the URL mappings, indexes, and manifests associated with
+    /// the other bundles, as well as any cached files that aren't used by any
+    /// of the kept bundles. Purge the cache entirely by keeping nothing.
+    ///
+    /// Nothing prevents another Tectonic process from using the cache while
+    /// this operation is in progress, so callers should take care to only
+    /// run it when the cache is otherwise idle.
+    pub fn gc(&self, keep: &[DigestData]) -> Result<CacheGcSummary> {
+        let keep: HashSet<String> = keep.iter().map(|d| d.to_string()).collect();
+        let mut summary = CacheGcSummary::default();
+        let mut removed_digests = HashSet::new();
+
+        // URL mappings pointing to bundles that we're dropping.
+
+        for (path, digest_text) in list_url_entries(&self.root.join("urls"))? {
+            if !keep.contains(&digest_text) {
+                summary.n_bytes += remove_cache_file(&path)?;
+            }
+        }
+
+        // Per-bundle metadata.
+
+        let mut live_files = HashSet::new();
+
+        for subdir in ["redirects", "indexes", "manifests"] {
+            for (digest_text, path) in list_txt_digests(&self.root.join(subdir))? {
+                if keep.contains(&digest_text) {
+                    if subdir == "manifests" {
+                        live_files.extend(read_manifest_digests(&path)?);
+                    }
+                } else {
+                    summary.n_bytes += remove_cache_file(&path)?;
+                    removed_digests.insert(digest_text);
+                }
+            }
+        }
+
+        summary.n_bundles = removed_digests.len();
+
+        // Cached file data that aren't referenced by any of the kept
+        // manifests. The data directory has a two-level structure: see
+        // `DigestData::create_two_part_path()`.
+
+        let data_base = self.root.join("files");
+
+        for (prefix, dir) in list_dir(&data_base)? {
+            if !dir.is_dir() {
+                continue;
+            }
+
+            for (rest, path) in list_dir(&dir)? {
+                if !live_files.contains(&format!("{prefix}{rest}")) {
+                    summary.n_bytes += remove_cache_file(&path)?;
+                    summary.n_files += 1;
+                }
+            }
+
+            // This will fail if the directory isn't empty, which is fine.
+            let _ = fs::remove_dir(&dir);
+        }
+
+        Ok(summary)
+    }
+
+    /// Find out which bundle digests are referenced by the cached start URLs.
+    ///
+    /// Returns a map from digest text to the most recent time that the
+    /// bundle was opened through one of those URLs.
+    fn url_references(&self) -> Result<HashMap<String, SystemTime>> {
+        let mut refs: HashMap<String, SystemTime> = HashMap::new();
+
+        for (path, digest_text) in list_url_entries(&self.root.join("urls"))? {
+            let used = fs::metadata(&path)?.modified()?;
+            let entry = refs.entry(digest_text).or_insert(used);
+
+            if used > *entry {
+                *entry = used;
+            }
+        }
+
+        Ok(refs)
+    }
+}
+
+/// Information about the data cached for one bundle.
+///
+/// This type is returned by [`Cache::bundles()`].
+#[derive(Clone, Debug)]
+pub struct CachedBundleInfo {
+    /// The digest of the bundle.
+    pub digest: DigestData,
+
+    /// The resolved URL of the bundle, if it is known.
+    pub resolved_url: Option<String>,
+
+    /// The number of files from the bundle that have been cached.
+    pub n_files: usize,
+
+    /// The total size of the cached data for this bundle, in bytes.
+    ///
+    /// Cached files that are shared with other bundles are counted for each of
+    /// them.
+    pub size: u64,
+
+    /// The last time that the bundle was opened through a URL.
+    ///
+    /// Opening a bundle updates the modification time of the cache file that
+    /// maps its URL to its digest, and this is the latest such time among the
+    /// URLs that point to the bundle. Anything else that changes the
+    /// modification times of the cache files will affect it, too.
+    ///
+    /// This is `None` if no cached URL currently points to this bundle, which
+    /// usually means that the bundle behind the URL has been updated since it
+    /// was used.
+    pub last_used: Option<SystemTime>,
+}
+
+/// A summary of the data removed by [`Cache::gc()`].
+#[derive(Clone, Debug, Default)]
+pub struct CacheGcSummary {
+    /// The number of bundles whose data were removed.
+    pub n_bundles: usize,
+
+    /// The number of cached files that were removed.
+    pub n_files: usize,
+
+    /// The total size of everything that was removed, in bytes.
+    pub n_bytes: u64,
 }
 
 /// Information describing a cache backend.
@@ -328,7 +525,16 @@ impl<CB: CacheBackend> CachingBundle<CB> {
 
         let cached_pull_data =
             match load_cached_pull_data::<CB>(&digest_path, &resolved_base, &index_base)? {
-                Some(c) => c,
+                Some(c) => {
+                    // Record that this URL is still in use, so that its data
+                    // survive garbage collection. This is only a hint, so
+                    // errors are ignored.
+                    let _ = File::options()
+                        .write(true)
+                        .open(&digest_path)
+                        .and_then(|f| f.set_modified(SystemTime::now()));
+                    c
+                }
                 None => {
                     // Some portion of the required cached data is missing. We need to
                     // do a complete pull and then cache the results.
@@ -764,3 +970,140 @@ fn ensure_cache_dir(root: &Path, path: &str) -> Result<PathBuf> {
 fn make_txt_path(base: &Path, name: &str) -> PathBuf {
     base.join(name).with_extension("txt")
 }
+
+/// Get the path where the data for a cached file are stored.
+///
+/// This is the same path as [`DigestData::create_two_part_path()`], but
+/// without creating any directories.
+fn make_data_path(base: &Path, digest_text: &str) -> PathBuf {
+    base.join(&digest_text[..2]).join(&digest_text[2..])
+}
+
+/// List the entries in a directory, returning their names and paths.
+///
+/// A missing directory is treated as empty. Entries with non-UTF-8 names
+/// can't have been created by the cache, and are skipped.
+fn list_dir(dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let entries = match fs::read_dir(dir) {
+        Err(ref e) if e.kind() == IoErrorKind::NotFound => return Ok(Vec::new()),
+        r => atry!(r; ["failed to read directory `{}`", dir.display()]),
+    };
+
+    let mut result = Vec::new();
+
+    for entry in entries {
+        let entry = entry?;
+
+        if let Ok(name) = entry.file_name().into_string() {
+            result.push((name, entry.path()));
+        }
+    }
+
+    Ok(result)
+}
+
+/// List the `<digest>.txt` files in a directory.
+///
+/// Returns a map from digest text to file path.
+fn list_txt_digests(dir: &Path) -> Result<HashMap<String, PathBuf>> {
+    let mut result = HashMap::new();
+
+    for (name, path) in list_dir(dir)? {
+        if let Some(stem) = name.strip_suffix(".txt") {
+            if DigestData::from_str(stem).is_ok() {
+                result.insert(stem.to_owned(), path);
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+/// List the start-URL entries in the `urls` directory.
+///
+/// Returns the path of each entry and the text of the digest that it records.
+/// Entries that don't contain a valid digest are skipped.
+fn list_url_entries(dir: &Path) -> Result<Vec<(PathBuf, String)>> {
+    let mut result = Vec::new();
+
+    for (_name, path) in list_dir(dir)? {
+        let text = match fs::read_to_string(&path) {
+            Ok(t) => t,
+            Err(_) => continue,
+        };
+        let text = text.trim();
+
+        if DigestData::from_str(text).is_ok() {
+            result.push((path, text.to_owned()));
+        }
+    }
+
+    Ok(result)
+}
+
+/// Read the file digests listed in a cache manifest.
+///
+/// A missing manifest is treated as empty.
+fn read_manifest_digests(path: &Path) -> Result<Vec<String>> {
+    let f = match File::open(path) {
+        Err(ref e) if e.kind() == IoErrorKind::NotFound => return Ok(Vec::new()),
+        r => atry!(r; ["failed to open `{}`", path.display()]),
+    };
+
+    let mut digests = Vec::new();
+
+    for line in BufReader::new(f).lines() {
+        let line = line?;
+
+        if let Some(digest) = line.rsplit(' ').next() {
+            if DigestData::from_str(digest).is_ok() {
+                digests.push(digest.to_owned());
+            }
+        }
+    }
+
+    Ok(digests)
+}
+
+/// Get the size of a file, treating errors as zero.
+fn file_size(path: &Path) -> u64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+/// Get the total size of the files in a directory tree.
+fn tree_size(dir: &Path) -> Result<u64> {
+    let mut size = 0;
+
+    for (_name, path) in list_dir(dir)? {
+        let md = fs::symlink_metadata(&path)?;
+
+        if md.is_dir() {
+            size += tree_size(&path)?;
+        } else {
+            size += md.len();
+        }
+    }
+
+    Ok(size)
+}
+
+/// Remove a file from the cache, returning its size.
+///
+/// Cached data files are marked read-only, which prevents their removal on
+/// some platforms, so we clear that flag first.
+fn remove_cache_file(path: &Path) -> Result<u64> {
+    let md = fs::metadata(path)?;
+    let mut perms = md.permissions();
+
+    if perms.readonly() {
+        #[allow(clippy::permissions_set_readonly_false)]
+        perms.set_readonly(false);
+        fs::set_permissions(path, perms)?;
+    }
+
+    atry!(
+        fs::remove_file(path);
+        ["failed to remove cache file `{}`", path.display()]
+    );
+    Ok(md.len())
+}
@@ -0,0 +1,335 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Creating new bundles from files on the local filesystem.
+//!
+//! Tectonic looks up support files by name only, so a bundle is a flat
+//! collection of files. The [`BundleCreator`] type gathers files from one or
+//! more directory trees, such as a TeX Live `texmf-dist` tree, flattens them,
+//! and writes them out as either a ZIP bundle, usable with
+//! [`crate::zip::ZipBundle`], or as an indexed tar file, usable through
+//! [`crate::itar::IndexedTarBackend`] once it is served over HTTP.
+
+use flate2::{write::GzEncoder, Compression};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+use tectonic_errors::prelude::*;
+use tectonic_io_base::digest::{self, Digest, DigestData};
+use tectonic_status_base::StatusBackend;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::Bundle;
+
+/// A type for creating a new bundle from local files.
+///
+/// Files are keyed by their base names. If several files with the same name
+/// are added, the first one wins, in the same way that the first match wins
+/// when TeX searches its input path.
+#[derive(Debug, Default)]
+pub struct BundleCreator {
+    files: BTreeMap<String, FileSource>,
+}
+
+/// Where the contents of a file in a new bundle come from.
+#[derive(Debug)]
+enum FileSource {
+    Path(PathBuf),
+    Data(Vec<u8>),
+}
+
+impl BundleCreator {
+    /// Create a new, empty bundle creator.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a file to the bundle.
+    ///
+    /// Returns false if the file was not added because the bundle already
+    /// contains a file with the same name. Files named `SHA256SUM` are always
+    /// skipped, since the bundle digest is computed when the bundle is written.
+    /// Names containing whitespace are an error, since they can't be
+    /// represented in the index of an indexed tar bundle.
+    pub fn add_file<P: Into<PathBuf>>(&mut self, path: P) -> Result<bool> {
+        let path = path.into();
+
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(n) => n.to_owned(),
+            None => bail!("cannot add `{}` to a bundle: bad filename", path.display()),
+        };
+
+        check_name(&name)?;
+
+        if name == digest::DIGEST_NAME || self.files.contains_key(&name) {
+            return Ok(false);
+        }
+
+        self.files.insert(name, FileSource::Path(path));
+        Ok(true)
+    }
+
+    /// Add a file to the bundle given its name and contents.
+    ///
+    /// Returns false if the file was not added because the bundle already
+    /// contains a file with the same name, or the name is `SHA256SUM`. As with
+    /// [`Self::add_file`], names containing whitespace are an error.
+    pub fn add_data<S: Into<String>>(&mut self, name: S, data: Vec<u8>) -> Result<bool> {
+        let name = name.into();
+        check_name(&name)?;
+
+        if name == digest::DIGEST_NAME || self.files.contains_key(&name) {
+            return Ok(false);
+        }
+
+        self.files.insert(name, FileSource::Data(data));
+        Ok(true)
+    }
+
+    /// Add all of the files in a directory tree to the bundle.
+    ///
+    /// The tree is traversed in sorted order so that the results are
+    /// reproducible. Hidden files and directories are ignored. Returns the
+    /// number of files that were skipped because a file with the same name had
+    /// already been added.
+    pub fn add_tree<P: AsRef<Path>>(&mut self, root: P) -> Result<usize> {
+        let root = root.as_ref();
+        let mut entries = Vec::new();
+
+        for entry in atry!(fs::read_dir(root); ["couldn\'t read directory `{}`", root.display()]) {
+            let entry = entry?;
+
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+
+            entries.push(entry.path());
+        }
+
+        entries.sort();
+        let mut n_skipped = 0;
+
+        for path in entries {
+            if path.is_dir() {
+                n_skipped += self.add_tree(&path)?;
+            } else if !self.add_file(path)? {
+                n_skipped += 1;
+            }
+        }
+
+        Ok(n_skipped)
+    }
+
+    /// Get the number of files that have been added to the bundle.
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Check whether no files have been added to the bundle.
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Write the bundle as a ZIP file.
+    ///
+    /// Returns the digest of the new bundle, which is also stored in it as the
+    /// `SHA256SUM` file.
+    pub fn write_zip<P: AsRef<Path>>(&self, path: P) -> Result<DigestData> {
+        write_zip(path.as_ref(), self.contents())
+    }
+
+    /// Write the bundle as an indexed tar file.
+    ///
+    /// The tar file is written to *path*, and the index to a file of the same
+    /// name with `.index.gz` appended, which is where
+    /// [`crate::itar::IndexedTarBackend`] will look for it. Returns the digest
+    /// of the new bundle, which is also stored in it as the `SHA256SUM` file.
+    pub fn write_indexed_tar<P: AsRef<Path>>(&self, path: P) -> Result<DigestData> {
+        write_indexed_tar(path.as_ref(), self.contents())
+    }
+
+    /// Iterate over the names and contents of the files, in sorted order.
+    fn contents(&self) -> impl Iterator<Item = Result<(String, Cow<'_, [u8]>)>> {
+        self.files.iter().map(|(name, source)| {
+            let contents = match source {
+                FileSource::Path(path) => {
+                    Cow::Owned(atry!(fs::read(path); ["couldn\'t read `{}`", path.display()]))
+                }
+                FileSource::Data(data) => Cow::Borrowed(&data[..]),
+            };
+
+            Ok((name.clone(), contents))
+        })
+    }
+}
+
+/// Check that a file name can be stored in a bundle.
+///
+/// The index of an indexed tar bundle has one line per file, with the name,
+/// offset, and length separated by whitespace, so names can't contain any.
+fn check_name(name: &str) -> Result<()> {
+    ensure!(
+        !name.contains(char::is_whitespace),
+        "cannot add `{}` to a bundle: file names may not contain whitespace",
+        name
+    );
+    Ok(())
+}
+
+/// Write the contents of an existing bundle as an indexed tar file.
+///
+/// This is like [`BundleCreator::write_indexed_tar`], but the files come from
+/// *bundle*. They are read one at a time, so that large bundles can be
+/// converted without loading them into memory all at once. The digest of the
+/// new bundle is computed afresh, and is returned.
+pub fn write_bundle_as_indexed_tar<B: Bundle + ?Sized, P: AsRef<Path>>(
+    bundle: &mut B,
+    path: P,
+    status: &mut dyn StatusBackend,
+) -> Result<DigestData> {
+    let mut names = bundle.all_files(status)?;
+    names.retain(|n| n != digest::DIGEST_NAME);
+    names.sort();
+
+    let contents = names.into_iter().map(|name| {
+        let mut ih = atry!(
+            bundle.input_open_name(&name, status).must_exist();
+            ["couldn\'t open bundle file `{}`", name]
+        );
+        let mut data = Vec::new();
+        ih.read_to_end(&mut data)?;
+        Ok((name, Cow::Owned(data)))
+    });
+
+    write_indexed_tar(path.as_ref(), contents)
+}
+
+fn write_zip<'a, I>(path: &Path, files: I) -> Result<DigestData>
+where
+    I: Iterator<Item = Result<(String, Cow<'a, [u8]>)>>,
+{
+    let file = atry!(File::create(path); ["couldn\'t create `{}`", path.display()]);
+    let mut zip = ZipWriter::new(file);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let digest = write_files(files, |name, contents| {
+        zip.start_file(name, options)?;
+        zip.write_all(contents)?;
+        Ok(())
+    })?;
+
+    zip.finish()?;
+    Ok(digest)
+}
+
+fn write_indexed_tar<'a, I>(path: &Path, files: I) -> Result<DigestData>
+where
+    I: Iterator<Item = Result<(String, Cow<'a, [u8]>)>>,
+{
+    let file = atry!(File::create(path); ["couldn\'t create `{}`", path.display()]);
+    let mut tar = tar::Builder::new(file);
+
+    let mut index_path = path.as_os_str().to_owned();
+    index_path.push(".index.gz");
+    let index_path = PathBuf::from(index_path);
+    let index_file = atry!(
+        File::create(&index_path);
+        ["couldn\'t create `{}`", index_path.display()]
+    );
+    let mut index = GzEncoder::new(index_file, Compression::default());
+
+    let digest = write_files(files, |name, contents| {
+        check_name(name)?;
+
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        tar.append_data(&mut header, name, contents)?;
+
+        // The data are padded out to a multiple of the tar block size,
+        // after which the builder's stream is positioned.
+        let padded_len = (contents.len() as u64).div_ceil(512) * 512;
+        let offset = tar.get_mut().stream_position()? - padded_len;
+        writeln!(index, "{} {} {}", name, offset, contents.len())?;
+        Ok(())
+    })?;
+
+    tar.into_inner()?;
+    index.finish()?;
+    Ok(digest)
+}
+
+/// Feed every file to a writer callback, followed by the `SHA256SUM` file.
+///
+/// The files must be provided in sorted order. The bundle digest is computed
+/// from their names and SHA256 digests.
+fn write_files<'a, I, F>(files: I, mut write: F) -> Result<DigestData>
+where
+    I: Iterator<Item = Result<(String, Cow<'a, [u8]>)>>,
+    F: FnMut(&str, &[u8]) -> Result<()>,
+{
+    let mut bundle_digest = digest::create();
+
+    for item in files {
+        let (name, contents) = item?;
+        write(&name, &contents)?;
+
+        bundle_digest.update(name.as_bytes());
+        bundle_digest.update([0u8]);
+        bundle_digest.update(digest::create().chain_update(&contents).finalize());
+    }
+
+    let digest = DigestData::from(bundle_digest);
+    write(
+        digest::DIGEST_NAME,
+        format!("{}\n", digest.to_string()).as_bytes(),
+    )?;
+    Ok(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cache::CacheBackend, itar::IndexedTarBackend};
+    use flate2::read::GzDecoder;
+
+    #[test]
+    fn whitespace_names() {
+        let mut creator = BundleCreator::new();
+        assert!(creator.add_data("a b.tex", Vec::new()).is_err());
+        assert!(creator.add_data("a\tb.tex", Vec::new()).is_err());
+        assert!(creator.add_file("/texmf/a b.sty").is_err());
+        assert!(creator.is_empty());
+
+        // Only the file name matters, not the directory that it's in.
+        assert!(creator.add_file("/tex mf/a.sty").unwrap());
+        assert!(creator.add_data("b.tex", b"b".to_vec()).unwrap());
+        assert_eq!(creator.len(), 2);
+    }
+
+    #[test]
+    fn indexed_tar_index() {
+        let mut creator = BundleCreator::new();
+        creator.add_data("a.tex", b"a".to_vec()).unwrap();
+        creator.add_data("b.tex", b"bb".to_vec()).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bundle.tar");
+        creator.write_indexed_tar(&path).unwrap();
+
+        let mut index = String::new();
+        GzDecoder::new(File::open(dir.path().join("bundle.tar.index.gz")).unwrap())
+            .read_to_string(&mut index)
+            .unwrap();
+
+        let names: Vec<_> = index
+            .lines()
+            .map(|line| IndexedTarBackend::parse_index_line(line).unwrap().0)
+            .collect();
+        assert_eq!(names, ["a.tex", "b.tex", digest::DIGEST_NAME]);
+    }
+}
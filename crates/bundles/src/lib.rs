@@ -16,6 +16,9 @@
 //! - [`dir::DirBundle`] turns a directory full of files into a bundle; it is
 //!   useful for testing and lightweight usage.
 //! - [`zip::ZipBundle`] for a ZIP-format bundle.
+//!
+//! The [`create::BundleCreator`] type can be used to create new bundles from
+//! files on the local filesystem.
 
 use std::{io::Read, str::FromStr};
 use tectonic_errors::{anyhow::bail, atry, Result};
@@ -23,6 +26,7 @@ use tectonic_io_base::{digest, digest::DigestData, IoProvider, OpenResult};
 use tectonic_status_base::StatusBackend;
 
 pub mod cache;
+pub mod create;
 pub mod dir;
 pub mod itar;
 pub mod zip;
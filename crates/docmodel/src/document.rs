@@ -3,9 +3,9 @@
 
 //! A single Tectonic document.
 //!
-//! Every document is part of a [`crate::workspace::Workspace`]. At the moment
-//! workspaces can only contain a single document each, but in the future it
-//! might become possible for one workspace to contain multiple documents.
+//! Every document is part of a [`crate::workspace::Workspace`]. A workspace
+//! either consists of a single document, or is described by a workspace
+//! manifest listing several member documents.
 //!
 //! This crate, on its own, does not provide document-processing capabilities.
 //! The main `tectonic` crate provides extension traits that set up document
@@ -79,7 +79,27 @@ impl Document {
     ) -> Result<Self> {
         let mut toml_text = String::new();
         toml_data.read_to_string(&mut toml_text)?;
-        let doc: syntax::Document = toml::from_str(&toml_text)?;
+        Self::new_from_toml_text(src_dir, build_dir, &toml_text, None)
+    }
+
+    /// Initialize a Document from TOML text, possibly inheriting settings
+    /// from an enclosing workspace.
+    ///
+    /// If the document doesn't specify its own `doc.bundle`, *default_bundle*
+    /// is used instead. It is an error if neither is available.
+    pub(crate) fn new_from_toml_text<P1: Into<PathBuf>, P2: Into<PathBuf>>(
+        src_dir: P1,
+        build_dir: P2,
+        toml_text: &str,
+        default_bundle: Option<&str>,
+    ) -> Result<Self> {
+        let doc: syntax::Document = toml::from_str(toml_text)?;
+
+        let bundle_loc = match (doc.doc.bundle, default_bundle) {
+            (Some(b), _) => b,
+            (None, Some(b)) => b.to_owned(),
+            (None, None) => bail!("TOML specification must define `doc.bundle`"),
+        };
 
         let mut outputs = HashMap::new();
 
@@ -102,7 +122,7 @@ impl Document {
             src_dir: src_dir.into(),
             build_dir: build_dir.into(),
             name: doc.doc.name,
-            bundle_loc: doc.doc.bundle,
+            bundle_loc,
             outputs,
         })
     }
@@ -123,7 +143,7 @@ impl Document {
         let doc = syntax::Document {
             doc: syntax::DocSection {
                 name: self.name.clone(),
-                bundle: self.bundle_loc.clone(),
+                bundle: Some(self.bundle_loc.clone()),
             },
             outputs,
         };
@@ -209,6 +229,11 @@ pub struct OutputProfile {
     /// The name of the preamble file within the `src` directory.
     pub preamble_file: String,
 
+    /// Whether the preamble should be precompiled into a custom format file,
+    /// which can be reused as long as the preamble and the files that it
+    /// reads are unchanged.
+    pub precompile_preamble: bool,
+
     /// The name of the index (main) file within the `src` directory.
     pub index_file: String,
 
@@ -230,6 +255,54 @@ pub struct OutputProfile {
     /// Directory is not managed and any files created in it will not be deleted.
     ///
     pub shell_escape_cwd: Option<String>,
+
+    /// If set, the names of the only programs that shell-escape may run.
+    ///
+    /// This is the equivalent of the `shell_escape_commands` setting of TeX
+    /// Live's “restricted” shell-escape mode. Commands are split into
+    /// arguments by Tectonic and run directly, without an intermediate shell,
+    /// so that shell syntax can't be used to sneak in other programs. If this
+    /// is set, shell-escape is implicitly activated.
+    pub shell_escape_commands: Option<Vec<String>>,
+
+    /// External programs to run during the build, such as `pythontex` or
+    /// `asymptote`.
+    ///
+    /// Like shell-escape, these are run with the full privileges of the user,
+    /// so they should only be used with trusted input.
+    pub external_tools: Vec<ExternalTool>,
+}
+
+/// An external program that is run when the TeX engine writes certain files.
+///
+/// The tool runs in a temporary directory containing the file that triggered
+/// it, as well as any other files from the build that are named in its
+/// arguments. Afterwards, the files that it produced are made available to
+/// later passes of the TeX engine.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ExternalTool {
+    /// The program to run, followed by its arguments.
+    ///
+    /// In each of these, `{trigger}` is replaced by the name of the file that
+    /// triggered the tool, and `{stem}` by that name without its extension.
+    pub command: Vec<String>,
+
+    /// A pattern matching the names of the files that trigger the tool.
+    ///
+    /// A `*` in the pattern matches any sequence of characters. The tool is run
+    /// once for each matching file written by the TeX engine, whenever the
+    /// contents of that file change.
+    pub trigger: String,
+
+    /// Patterns matching the names of the files produced by the tool.
+    ///
+    /// These use the same syntax as [`Self::trigger`], and may also use the
+    /// `{stem}` substitution. If empty, any new files that the tool creates
+    /// at the top level of its working directory are used.
+    pub produces: Vec<String>,
+
+    /// Whether the TeX engine should be rerun after the tool runs.
+    pub rerun_tex: bool,
 }
 
 /// The output target type of a document build.
@@ -301,10 +374,13 @@ pub(crate) fn default_outputs() -> HashMap<String, OutputProfile> {
             target_type: BuildTargetType::Pdf,
             tex_format: "latex".to_owned(),
             preamble_file: DEFAULT_PREAMBLE_FILE.to_owned(),
+            precompile_preamble: false,
             index_file: DEFAULT_INDEX_FILE.to_owned(),
             postamble_file: DEFAULT_POSTAMBLE_FILE.to_owned(),
             shell_escape: false,
             shell_escape_cwd: None,
+            shell_escape_commands: None,
+            external_tools: Vec::new(),
         },
     );
     outputs
@@ -328,7 +404,8 @@ mod syntax {
     #[serde(deny_unknown_fields)]
     pub struct DocSection {
         pub name: String,
-        pub bundle: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub bundle: Option<String>,
     }
 
     #[derive(Debug, Deserialize, Serialize)]
@@ -340,12 +417,50 @@ mod syntax {
         pub tex_format: Option<String>,
         #[serde(rename = "preamble")]
         pub preamble_file: Option<String>,
+        pub precompile_preamble: Option<bool>,
         #[serde(rename = "index")]
         pub index_file: Option<String>,
         #[serde(rename = "postamble")]
         pub postamble_file: Option<String>,
         pub shell_escape: Option<bool>,
         pub shell_escape_cwd: Option<String>,
+        pub shell_escape_commands: Option<Vec<String>>,
+        #[serde(
+            default,
+            rename = "external_tool",
+            skip_serializing_if = "Vec::is_empty"
+        )]
+        pub external_tools: Vec<ExternalTool>,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct ExternalTool {
+        pub command: Vec<String>,
+        pub trigger: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub produces: Vec<String>,
+        pub rerun_tex: Option<bool>,
+    }
+
+    impl ExternalTool {
+        pub fn from_runtime(rt: &super::ExternalTool) -> Self {
+            ExternalTool {
+                command: rt.command.clone(),
+                trigger: rt.trigger.clone(),
+                produces: rt.produces.clone(),
+                rerun_tex: if rt.rerun_tex { None } else { Some(false) },
+            }
+        }
+
+        pub fn to_runtime(&self) -> super::ExternalTool {
+            super::ExternalTool {
+                command: self.command.clone(),
+                trigger: self.trigger.clone(),
+                produces: self.produces.clone(),
+                rerun_tex: self.rerun_tex.unwrap_or(true),
+            }
+        }
     }
 
     impl OutputProfile {
@@ -362,6 +477,12 @@ mod syntax {
                 Some(rt.preamble_file.clone())
             };
 
+            let precompile_preamble = if !rt.precompile_preamble {
+                None
+            } else {
+                Some(true)
+            };
+
             let index_file = if rt.index_file == DEFAULT_INDEX_FILE {
                 None
             } else {
@@ -376,21 +497,31 @@ mod syntax {
 
             let shell_escape = if !rt.shell_escape { None } else { Some(true) };
             let shell_escape_cwd = rt.shell_escape_cwd.clone();
+            let shell_escape_commands = rt.shell_escape_commands.clone();
+            let external_tools = rt
+                .external_tools
+                .iter()
+                .map(ExternalTool::from_runtime)
+                .collect();
 
             OutputProfile {
                 name: rt.name.clone(),
                 target_type: BuildTargetType::from_runtime(&rt.target_type),
                 tex_format,
                 preamble_file,
+                precompile_preamble,
                 index_file,
                 postamble_file,
                 shell_escape,
                 shell_escape_cwd,
+                shell_escape_commands,
+                external_tools,
             }
         }
 
         pub fn to_runtime(&self) -> super::OutputProfile {
-            let shell_escape_default = self.shell_escape_cwd.is_some();
+            let shell_escape_default =
+                self.shell_escape_cwd.is_some() || self.shell_escape_commands.is_some();
 
             super::OutputProfile {
                 name: self.name.clone(),
@@ -405,6 +536,7 @@ mod syntax {
                     .preamble_file
                     .clone()
                     .unwrap_or_else(|| DEFAULT_PREAMBLE_FILE.to_owned()),
+                precompile_preamble: self.precompile_preamble.unwrap_or(false),
                 index_file: self
                     .index_file
                     .clone()
@@ -415,6 +547,12 @@ mod syntax {
                     .unwrap_or_else(|| DEFAULT_POSTAMBLE_FILE.to_owned()),
                 shell_escape: self.shell_escape.unwrap_or(shell_escape_default),
                 shell_escape_cwd: self.shell_escape_cwd.clone(),
+                shell_escape_commands: self.shell_escape_commands.clone(),
+                external_tools: self
+                    .external_tools
+                    .iter()
+                    .map(ExternalTool::to_runtime)
+                    .collect(),
             }
         }
     }
@@ -512,4 +650,95 @@ mod tests {
         let doc = Document::new_from_toml(".", ".", &mut c).unwrap();
         assert!(doc.outputs.get("o").unwrap().shell_escape);
     }
+
+    #[test]
+    fn shell_escape_commands_imply_shell_escape() {
+        const TOML: &str = r#"
+        [doc]
+        name = "test"
+        bundle = "na"
+
+        [[output]]
+        name = "o"
+        type = "pdf"
+        shell_escape_commands = ["pygmentize"]
+        "#;
+
+        let mut c = Cursor::new(TOML.as_bytes());
+        let doc = Document::new_from_toml(".", ".", &mut c).unwrap();
+        let profile = doc.outputs.get("o").unwrap();
+        assert!(profile.shell_escape);
+        assert_eq!(
+            profile.shell_escape_commands.as_deref(),
+            Some(&["pygmentize".to_owned()][..])
+        );
+    }
+
+    #[test]
+    fn precompile_preamble_option() {
+        const TOML: &str = r#"
+        [doc]
+        name = "test"
+        bundle = "na"
+
+        [[output]]
+        name = "o"
+        type = "pdf"
+        precompile_preamble = true
+        "#;
+
+        let mut c = Cursor::new(TOML.as_bytes());
+        let doc = Document::new_from_toml(".", ".", &mut c).unwrap();
+        assert!(doc.outputs.get("o").unwrap().precompile_preamble);
+    }
+
+    #[test]
+    fn external_tool_option() {
+        const TOML: &str = r#"
+        [doc]
+        name = "test"
+        bundle = "na"
+
+        [[output]]
+        name = "o"
+        type = "pdf"
+
+        [[output.external_tool]]
+        command = ["pythontex", "{stem}"]
+        trigger = "*.pytxcode"
+        produces = ["pythontex-files-{stem}/*"]
+
+        [[output.external_tool]]
+        command = ["asy", "{trigger}"]
+        trigger = "*.asy"
+        rerun_tex = false
+        "#;
+
+        let mut c = Cursor::new(TOML.as_bytes());
+        let doc = Document::new_from_toml(".", ".", &mut c).unwrap();
+        let tools = &doc.outputs.get("o").unwrap().external_tools;
+        assert_eq!(tools.len(), 2);
+        assert_eq!(tools[0].command, ["pythontex", "{stem}"]);
+        assert_eq!(tools[0].produces, ["pythontex-files-{stem}/*"]);
+        assert!(tools[0].rerun_tex);
+        assert_eq!(tools[1].trigger, "*.asy");
+        assert!(tools[1].produces.is_empty());
+        assert!(!tools[1].rerun_tex);
+    }
+
+    #[test]
+    fn bundle_inherited_from_workspace() {
+        const TOML: &str = r#"
+        [doc]
+        name = "test"
+
+        [[output]]
+        name = "o"
+        type = "pdf"
+        "#;
+
+        assert!(Document::new_from_toml_text(".", ".", TOML, None).is_err());
+        let doc = Document::new_from_toml_text(".", ".", TOML, Some("ws")).unwrap();
+        assert_eq!(doc.bundle_loc, "ws");
+    }
 }
@@ -3,16 +3,20 @@
 
 //! A Tectonic document-build workspace.
 //!
-//! For the time being, this is just a thin wrapper to provide access to a
-//! `Document` instance. This API exists to future-proof a bit for a potential
-//! world where one workspace can contain multiple documents.
+//! A workspace is a collection of one or more documents. In the simplest case,
+//! a workspace is defined by a single `Tectonic.toml` file describing a single
+//! document. Alternatively, a `Tectonic.toml` file containing a `[workspace]`
+//! section lists several member directories, each of which contains its own
+//! document. Similar to a Cargo workspace, the members may then share
+//! settings such as their backing bundle.
 
+use serde::Deserialize;
 use std::{
     env,
     error::Error,
     fmt, fs,
     io::{self, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 use tectonic_errors::prelude::*;
 
@@ -20,74 +24,205 @@ use crate::document::Document;
 
 /// A Tectonic workspace.
 ///
-/// For the time being, a Workspace is just a thin wrapper to provide access to
-/// a `Document` instance. In the future, it might become possible for one
-/// workspace to contain multiple documents.
-///
-/// In most cases, you will want to create a [`Workspace`] by opening an
-/// existing one using [`Workspace::open_from_environment`].
+/// A workspace contains one or more documents. In most cases, you will want
+/// to create a [`Workspace`] by opening an existing one using
+/// [`Workspace::open_from_environment`].
 #[derive(Debug)]
 pub struct Workspace {
     /// The root directory of the workspace.
     #[allow(dead_code)] // We expect to use this eventually.
     root_dir: PathBuf,
 
-    /// This workspace's document. In the future, there might be more than one.
-    doc: Document,
+    /// This workspace's documents, in the order in which they are listed in
+    /// the workspace manifest.
+    docs: Vec<Document>,
+
+    /// The index of the document that the workspace was opened from, if the
+    /// workspace was opened from within one of its member documents.
+    current: Option<usize>,
 }
 
 impl Workspace {
     /// Get the first document in the workspace.
     ///
-    /// Right now, workspaces in fact only include one document. That may change
-    /// in the future.
+    /// If the workspace was opened from within one of its member documents,
+    /// that document is returned. Otherwise, this is the first member listed
+    /// in the workspace manifest.
     pub fn first_document(&self) -> &Document {
-        &self.doc
+        &self.docs[self.current.unwrap_or(0)]
     }
 
     /// Get the first document in the workspace, mutably.
     ///
-    /// Right now, workspaces in fact only include one document. That may change
-    /// in the future.
+    /// See [`Self::first_document`] for the meaning of "first".
     pub fn first_document_mut(&mut self) -> &mut Document {
-        &mut self.doc
+        &mut self.docs[self.current.unwrap_or(0)]
+    }
+
+    /// Consume the workspace, returning its first document.
+    ///
+    /// See [`Self::first_document`] for the meaning of "first".
+    pub fn into_first_document(mut self) -> Document {
+        self.docs.swap_remove(self.current.unwrap_or(0))
+    }
+
+    /// Get all of the documents in the workspace.
+    pub fn documents(&self) -> &[Document] {
+        &self.docs[..]
+    }
+
+    /// Consume the workspace, returning all of its documents.
+    pub fn into_documents(self) -> Vec<Document> {
+        self.docs
+    }
+
+    /// Look up a document in the workspace by its name.
+    pub fn document(&self, name: &str) -> Option<&Document> {
+        self.docs.iter().find(|d| d.name == name)
+    }
+
+    /// Get the document that the workspace was opened from, if any.
+    ///
+    /// This is `None` if the workspace was opened from its root directory,
+    /// rather than from within one of its member documents.
+    pub fn current_document(&self) -> Option<&Document> {
+        self.current.map(|i| &self.docs[i])
     }
 
     /// Open up a workspace based on the current process environment.
     ///
     /// This function searches the current directory and its parents for a
-    /// `Tectonic.toml` file. Because workspaces can currently only contain a
-    /// single document, the search stops when the first such file is found. If
-    /// no such file is found, an error downcastable into
+    /// `Tectonic.toml` file. If that file is a workspace manifest, the
+    /// workspace is loaded from it. If it describes a single document, the
+    /// search continues upwards for a workspace manifest listing that
+    /// document as a member; if one is found, the whole workspace is loaded,
+    /// with the original document as its "current" document. If no
+    /// `Tectonic.toml` is found at all, an error downcastable into
     /// [`NoWorkspaceFoundError`] is returned.
     pub fn open_from_environment() -> Result<Self> {
         let initial_dir = env::current_dir()?;
 
-        let mut root_dir = initial_dir.clone();
-        root_dir.push("tmp"); // simplifies loop logic
+        for dir in initial_dir.ancestors() {
+            let toml_text = match read_manifest(dir)? {
+                Some(t) => t,
+                None => continue,
+            };
+
+            if is_workspace_manifest(&toml_text)? {
+                return Self::load(dir, &toml_text, None);
+            }
 
-        while root_dir.pop() {
-            root_dir.push("Tectonic.toml");
+            // We found a single document. Is it part of an enclosing workspace?
 
-            let mut doc_file = match fs::File::open(&root_dir) {
-                Ok(f) => f,
-                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
-                    root_dir.pop(); // remove "Tectonic.toml"
-                    continue; // this will pop up one directory and try again
+            for ws_dir in dir.ancestors().skip(1) {
+                let ws_text = match read_manifest(ws_dir)? {
+                    Some(t) => t,
+                    None => continue,
+                };
+
+                if !is_workspace_manifest(&ws_text)? {
+                    continue;
                 }
-                Err(e) => return Err(e.into()),
-            };
 
-            root_dir.pop();
-            let mut doc_build_dir = root_dir.clone();
-            doc_build_dir.push("build");
-            let doc = Document::new_from_toml(root_dir.clone(), doc_build_dir, &mut doc_file)?;
+                let manifest: syntax::WorkspaceManifest = toml::from_str(&ws_text)?;
+
+                if manifest
+                    .workspace
+                    .members
+                    .iter()
+                    .any(|m| same_dir(&ws_dir.join(m), dir))
+                {
+                    return Self::load(ws_dir, &ws_text, Some(dir));
+                }
+
+                break;
+            }
+
+            let doc = Document::new_from_toml_text(dir, dir.join("build"), &toml_text, None)?;
 
-            return Ok(Workspace { root_dir, doc });
+            return Ok(Workspace {
+                root_dir: dir.to_owned(),
+                docs: vec![doc],
+                current: Some(0),
+            });
         }
 
         Err(NoWorkspaceFoundError { initial_dir }.into())
     }
+
+    /// Load a workspace from the text of its workspace manifest.
+    fn load(root_dir: &Path, toml_text: &str, current_dir: Option<&Path>) -> Result<Self> {
+        let manifest: syntax::WorkspaceManifest = toml::from_str(toml_text)?;
+        let default_bundle = manifest.workspace.bundle.as_deref();
+        let mut docs: Vec<Document> = Vec::new();
+        let mut current = None;
+
+        for member in &manifest.workspace.members {
+            let src_dir = root_dir.join(member);
+            let toml_path = src_dir.join("Tectonic.toml");
+
+            let doc_text = atry!(
+                fs::read_to_string(&toml_path);
+                ["couldn\'t read workspace member manifest `{}`", toml_path.display()]
+            );
+
+            let doc = atry!(
+                Document::new_from_toml_text(&src_dir, src_dir.join("build"), &doc_text, default_bundle);
+                ["invalid workspace member manifest `{}`", toml_path.display()]
+            );
+
+            if docs.iter().any(|d| d.name == doc.name) {
+                bail!(
+                    "duplicated document name `{}` in workspace `{}`",
+                    doc.name,
+                    root_dir.display()
+                );
+            }
+
+            if current_dir.map(|c| same_dir(&src_dir, c)) == Some(true) {
+                current = Some(docs.len());
+            }
+
+            docs.push(doc);
+        }
+
+        if docs.is_empty() {
+            bail!(
+                "workspace manifest in `{}` must list at least one member",
+                root_dir.display()
+            );
+        }
+
+        Ok(Workspace {
+            root_dir: root_dir.to_owned(),
+            docs,
+            current,
+        })
+    }
+}
+
+/// Read the `Tectonic.toml` file in the specified directory, if it exists.
+fn read_manifest(dir: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(dir.join("Tectonic.toml")) {
+        Ok(t) => Ok(Some(t)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Determine whether `Tectonic.toml` text describes a workspace, rather than a
+/// single document.
+fn is_workspace_manifest(toml_text: &str) -> Result<bool> {
+    let table: toml::Table = toml::from_str(toml_text)?;
+    Ok(table.contains_key("workspace"))
+}
+
+/// Compare two directory paths, resolving symlinks and the like if possible.
+fn same_dir(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
 }
 
 /// An error for when the environment does not seem to contain a Tectonic
@@ -176,7 +311,26 @@ impl WorkspaceCreator {
 
         Ok(Workspace {
             root_dir: self.root_dir,
-            doc,
+            docs: vec![doc],
+            current: Some(0),
         })
     }
 }
+
+/// The concrete syntax for workspace manifests, wired up via serde.
+mod syntax {
+    use super::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct WorkspaceManifest {
+        pub workspace: WorkspaceSection,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct WorkspaceSection {
+        pub members: Vec<String>,
+        pub bundle: Option<String>,
+    }
+}
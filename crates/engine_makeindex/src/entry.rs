@@ -0,0 +1,410 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Reading raw index entries.
+
+use std::{cmp::Ordering, convert::TryFrom};
+
+use crate::style::Style;
+
+/// The deepest nesting of subentries that we support, as with `makeindex`.
+const MAX_LEVELS: usize = 3;
+
+/// One level of an index key.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct KeyLevel {
+    /// The text used to sort the entry.
+    pub sort: String,
+
+    /// The text printed in the index.
+    pub actual: String,
+}
+
+/// How an entry participates in an explicit page range.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub(crate) enum RangeMark {
+    Open,
+    None,
+    Close,
+}
+
+/// A parsed index entry.
+#[derive(Clone, Debug)]
+pub(crate) struct Entry {
+    pub levels: Vec<KeyLevel>,
+    pub page: Page,
+    pub encap: Option<String>,
+    pub range: RangeMark,
+}
+
+/// The kinds of page number, as identified in the `page_precedence` style
+/// setting.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum PageKind {
+    LowerRoman,
+    Arabic,
+    LowerAlpha,
+    UpperRoman,
+    UpperAlpha,
+}
+
+impl PageKind {
+    fn code(self) -> char {
+        match self {
+            PageKind::LowerRoman => 'r',
+            PageKind::Arabic => 'n',
+            PageKind::LowerAlpha => 'a',
+            PageKind::UpperRoman => 'R',
+            PageKind::UpperAlpha => 'A',
+        }
+    }
+}
+
+/// A page number, possibly composite (like `2-13`).
+#[derive(Clone, Debug)]
+pub(crate) struct Page {
+    /// The page number as written.
+    pub text: String,
+
+    /// The sort rank and value of each component.
+    components: Vec<(usize, u32)>,
+}
+
+impl Page {
+    fn parse(text: &str, style: &Style) -> Option<Page> {
+        let mut components = Vec::new();
+        let pieces: Vec<&str> = if style.page_compositor.is_empty() {
+            vec![text]
+        } else {
+            text.split(style.page_compositor.as_str()).collect()
+        };
+
+        for piece in pieces {
+            let (kind, value) = parse_page_component(piece)?;
+            let rank = style
+                .page_precedence
+                .find(kind.code())
+                .unwrap_or(style.page_precedence.len());
+            components.push((rank, value));
+        }
+
+        Some(Page {
+            text: text.to_owned(),
+            components,
+        })
+    }
+
+    /// Whether `other` is the page right after this one.
+    pub fn is_followed_by(&self, other: &Page) -> bool {
+        let n = self.components.len();
+
+        n == other.components.len()
+            && self.components[..n - 1] == other.components[..n - 1]
+            && self.components[n - 1].0 == other.components[n - 1].0
+            && self.components[n - 1].1 + 1 == other.components[n - 1].1
+    }
+}
+
+impl PartialEq for Page {
+    fn eq(&self, other: &Self) -> bool {
+        self.components == other.components
+    }
+}
+
+impl Eq for Page {}
+
+impl PartialOrd for Page {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Page {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.components.cmp(&other.components)
+    }
+}
+
+fn parse_page_component(s: &str) -> Option<(PageKind, u32)> {
+    if s.is_empty() {
+        return None;
+    }
+
+    if s.bytes().all(|b| b.is_ascii_digit()) {
+        return s.parse().ok().map(|v| (PageKind::Arabic, v));
+    }
+
+    if let Some(v) = parse_roman(s, false) {
+        return Some((PageKind::LowerRoman, v));
+    }
+
+    if let Some(v) = parse_roman(s, true) {
+        return Some((PageKind::UpperRoman, v));
+    }
+
+    let mut chars = s.chars();
+    let c = chars.next()?;
+
+    if chars.next().is_some() {
+        return None;
+    }
+
+    match c {
+        'a'..='z' => Some((PageKind::LowerAlpha, c as u32 - 'a' as u32 + 1)),
+        'A'..='Z' => Some((PageKind::UpperAlpha, c as u32 - 'A' as u32 + 1)),
+        _ => None,
+    }
+}
+
+fn parse_roman(s: &str, upper: bool) -> Option<u32> {
+    let mut total = 0;
+    let mut prev = 0;
+
+    for c in s.chars().rev() {
+        let c = if upper {
+            if !c.is_ascii_uppercase() {
+                return None;
+            }
+            c.to_ascii_lowercase()
+        } else {
+            c
+        };
+
+        let v = match c {
+            'i' => 1,
+            'v' => 5,
+            'x' => 10,
+            'l' => 50,
+            'c' => 100,
+            'd' => 500,
+            'm' => 1000,
+            _ => return None,
+        };
+
+        if v < prev {
+            total -= v;
+        } else {
+            total += v;
+            prev = v;
+        }
+    }
+
+    u32::try_from(total).ok().filter(|v| *v > 0)
+}
+
+/// Read all of the entries in an `.idx` file.
+///
+/// Entries that can't be understood are skipped, with a message for the
+/// transcript.
+pub(crate) fn read_entries(text: &str, style: &Style, messages: &mut Vec<String>) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut rest = text;
+
+    while let Some(i) = rest.find(style.keyword.as_str()) {
+        let line = 1 + text[..text.len() - rest.len() + i].matches('\n').count();
+        rest = &rest[i + style.keyword.len()..];
+
+        let result = read_arg(rest, style).and_then(|(key, after)| {
+            let (page, after) = read_arg(after.trim_start(), style)?;
+            rest = after;
+            parse_entry(&key, &page, style)
+        });
+
+        match result {
+            Ok(entry) => entries.push(entry),
+            Err(msg) => messages.push(format!("input line {line}: {msg}; entry rejected")),
+        }
+    }
+
+    entries
+}
+
+/// Read a delimited argument, returning it and the text that follows it.
+fn read_arg<'a>(text: &'a str, style: &Style) -> Result<(String, &'a str), String> {
+    let mut chars = text.char_indices();
+
+    match chars.next() {
+        Some((_, c)) if c == style.arg_open => {}
+        _ => return Err(format!("expected `{}`", style.arg_open)),
+    }
+
+    let mut depth = 0;
+    let mut arg = String::new();
+    let mut prev = None;
+
+    for (i, c) in chars {
+        // Quoted characters and escaped quotes don't count for nesting.
+        let literal = prev == Some(style.quote) || prev == Some(style.escape);
+
+        if c == style.arg_close && !literal {
+            if depth == 0 {
+                return Ok((arg, &text[i + c.len_utf8()..]));
+            }
+            depth -= 1;
+        } else if c == style.arg_open && !literal {
+            depth += 1;
+        }
+
+        arg.push(c);
+
+        // A doubled quote or escape is itself literal, and doesn't affect
+        // the next character.
+        prev = if literal && (c == style.quote || c == style.escape) {
+            None
+        } else {
+            Some(c)
+        };
+    }
+
+    Err(format!(
+        "unterminated argument (missing `{}`)",
+        style.arg_close
+    ))
+}
+
+/// Split the key argument of an entry into its parts. We keep track of brace
+/// nesting and quoting so that special characters are only recognized when
+/// they're meant to be.
+fn parse_entry(key: &str, page: &str, style: &Style) -> Result<Entry, String> {
+    let mut levels = Vec::new();
+    let mut sort = String::new();
+    let mut actual: Option<String> = None;
+    let mut encap: Option<String> = None;
+    let mut depth = 0;
+    let mut chars = key.chars();
+    let mut prev_escape = false;
+
+    while let Some(c) = chars.next() {
+        if let Some(ref mut e) = encap {
+            e.push(c);
+            continue;
+        }
+
+        if c == style.quote && !prev_escape {
+            match chars.next() {
+                Some(q) => actual.as_mut().unwrap_or(&mut sort).push(q),
+                None => return Err("quote character at end of key".to_owned()),
+            }
+            prev_escape = false;
+            continue;
+        }
+
+        let special = depth == 0 && !prev_escape;
+        prev_escape = c == style.escape && !prev_escape;
+
+        if c == style.arg_open {
+            depth += 1;
+        } else if c == style.arg_close && depth > 0 {
+            depth -= 1;
+        }
+
+        if special && c == style.level {
+            levels.push(finish_level(sort, actual.take())?);
+            sort = String::new();
+        } else if special && c == style.actual && actual.is_none() {
+            actual = Some(String::new());
+        } else if special && c == style.encap {
+            encap = Some(String::new());
+        } else {
+            actual.as_mut().unwrap_or(&mut sort).push(c);
+        }
+    }
+
+    levels.push(finish_level(sort, actual)?);
+
+    if levels.len() > MAX_LEVELS {
+        return Err(format!(
+            "too many levels (at most {MAX_LEVELS} are allowed)"
+        ));
+    }
+
+    let (range, encap) = match encap {
+        None => (RangeMark::None, String::new()),
+        Some(e) => {
+            let mut chars = e.chars();
+
+            match chars.next() {
+                Some(c) if c == style.range_open => (RangeMark::Open, chars.as_str().to_owned()),
+                Some(c) if c == style.range_close => (RangeMark::Close, chars.as_str().to_owned()),
+                _ => (RangeMark::None, e),
+            }
+        }
+    };
+
+    let page = Page::parse(page.trim(), style)
+        .ok_or_else(|| format!("illegal page number `{}`", page.trim()))?;
+
+    Ok(Entry {
+        levels,
+        page,
+        encap: if encap.is_empty() { None } else { Some(encap) },
+        range,
+    })
+}
+
+fn finish_level(sort: String, actual: Option<String>) -> Result<KeyLevel, String> {
+    let sort = sort.trim().to_owned();
+
+    if sort.is_empty() {
+        return Err("empty sort key".to_owned());
+    }
+
+    let actual = match actual {
+        Some(a) => a.trim().to_owned(),
+        None => sort.clone(),
+    };
+
+    Ok(KeyLevel { sort, actual })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_entries() {
+        let style = Style::default();
+        let mut messages = Vec::new();
+        let entries = read_entries(
+            "\\indexentry{alpha@\\textit{alpha}!beta|textbf}{iv}\n\
+             \\indexentry{a\"!b|(}{12}\n\
+             \\indexentry{{x!y}}{3-4}\n\
+             \\indexentry{bad}{?}\n",
+            &style,
+            &mut messages,
+        );
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(messages.len(), 1);
+
+        assert_eq!(entries[0].levels.len(), 2);
+        assert_eq!(entries[0].levels[0].sort, "alpha");
+        assert_eq!(entries[0].levels[0].actual, "\\textit{alpha}");
+        assert_eq!(entries[0].levels[1].actual, "beta");
+        assert_eq!(entries[0].encap.as_deref(), Some("textbf"));
+
+        assert_eq!(entries[1].levels[0].sort, "a!b");
+        assert_eq!(entries[1].range, RangeMark::Open);
+        assert_eq!(entries[1].encap, None);
+
+        assert_eq!(entries[2].levels[0].sort, "{x!y}");
+        assert!(entries[2].page < Page::parse("3-5", &style).unwrap());
+    }
+
+    #[test]
+    fn page_order() {
+        let style = Style::default();
+        let pages: Vec<Page> = ["xii", "3", "b", "IV", "A"]
+            .iter()
+            .map(|t| Page::parse(t, &style).unwrap())
+            .collect();
+
+        for w in pages.windows(2) {
+            assert!(w[0] < w[1]);
+        }
+
+        assert!(Page::parse("9", &style)
+            .unwrap()
+            .is_followed_by(&Page::parse("10", &style).unwrap()));
+    }
+}
@@ -0,0 +1,491 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Sorting index entries and generating the formatted index.
+
+use std::cmp::Ordering;
+
+use crate::{
+    entry::{Entry, KeyLevel, Page, RangeMark},
+    style::Style,
+};
+
+/// The classes of sort key, in the order that their groups appear in the
+/// index.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum KeyClass {
+    Symbol,
+    Number,
+    Letter,
+}
+
+impl KeyClass {
+    fn of(key: &str) -> KeyClass {
+        if key.bytes().all(|b| b.is_ascii_digit()) {
+            KeyClass::Number
+        } else if matches!(key.chars().next(), Some(c) if c.is_alphabetic()) {
+            KeyClass::Letter
+        } else {
+            KeyClass::Symbol
+        }
+    }
+}
+
+fn compare_keys(a: &str, b: &str) -> Ordering {
+    let (ca, cb) = (KeyClass::of(a), KeyClass::of(b));
+
+    ca.cmp(&cb).then_with(|| {
+        if ca == KeyClass::Number {
+            let na = a.trim_start_matches('0');
+            let nb = b.trim_start_matches('0');
+            na.len().cmp(&nb.len()).then_with(|| na.cmp(nb))
+        } else {
+            Ordering::Equal
+        }
+        .then_with(|| a.to_lowercase().cmp(&b.to_lowercase()))
+        .then_with(|| a.cmp(b))
+    })
+}
+
+fn compare_levels(a: &[KeyLevel], b: &[KeyLevel], letter_ordering: bool) -> Ordering {
+    for (la, lb) in a.iter().zip(b) {
+        let o = if letter_ordering {
+            compare_keys(&la.sort.replace(' ', ""), &lb.sort.replace(' ', ""))
+        } else {
+            Ordering::Equal
+        }
+        .then_with(|| compare_keys(&la.sort, &lb.sort))
+        .then_with(|| la.actual.cmp(&lb.actual));
+
+        if o != Ordering::Equal {
+            return o;
+        }
+    }
+
+    a.len().cmp(&b.len())
+}
+
+/// Sort entries into index order.
+///
+/// With *letter_ordering*, spaces in sort keys are ignored, like the `-l`
+/// option of `makeindex`.
+pub(crate) fn sort_entries(entries: &mut [Entry], letter_ordering: bool) {
+    entries.sort_by(|a, b| {
+        compare_levels(&a.levels, &b.levels, letter_ordering)
+            .then_with(|| a.page.cmp(&b.page))
+            .then_with(|| a.range.cmp(&b.range))
+    });
+}
+
+/// One item in a page list.
+#[derive(Debug)]
+enum Piece<'a> {
+    Single(&'a Page, Option<&'a str>),
+    Range(&'a Page, Option<&'a Page>, Option<&'a str>),
+    Suffixed(&'a Page, &'a str, Option<&'a str>),
+}
+
+/// Build up the page list for one item, whose references are sorted.
+fn page_pieces<'a>(
+    refs: &[&'a Entry],
+    style: &'a Style,
+    messages: &mut Vec<String>,
+) -> Vec<Piece<'a>> {
+    let mut pieces = Vec::new();
+    let mut open: Option<(usize, Option<&str>)> = None;
+    let mut prev: Option<(&Page, Option<&str>, RangeMark)> = None;
+
+    for e in refs {
+        let encap = e.encap.as_deref();
+
+        if prev == Some((&e.page, encap, e.range)) {
+            continue;
+        }
+
+        prev = Some((&e.page, encap, e.range));
+
+        match e.range {
+            RangeMark::Open => {
+                if open.is_some() {
+                    messages.push(format!(
+                        "extra range opening for page {}; ignored",
+                        e.page.text
+                    ));
+                } else {
+                    open = Some((pieces.len(), encap));
+                    pieces.push(Piece::Range(&e.page, None, encap));
+                }
+            }
+
+            RangeMark::Close => match open.take() {
+                Some((idx, open_encap)) => {
+                    if let Piece::Range(start, _, _) = pieces[idx] {
+                        pieces[idx] = if *start == e.page {
+                            Piece::Single(start, open_encap)
+                        } else {
+                            Piece::Range(start, Some(&e.page), open_encap)
+                        };
+                    }
+                }
+
+                None => {
+                    messages.push(format!(
+                        "unmatched range closing for page {}; treated as a single page",
+                        e.page.text
+                    ));
+                    pieces.push(Piece::Single(&e.page, encap));
+                }
+            },
+
+            RangeMark::None => {
+                // References inside an explicit range with the same encap are
+                // absorbed into it.
+                if let Some((_, open_encap)) = open {
+                    if open_encap == encap {
+                        continue;
+                    }
+                }
+
+                pieces.push(Piece::Single(&e.page, encap));
+            }
+        }
+    }
+
+    if let Some((idx, _)) = open {
+        if let Piece::Range(start, None, encap) = pieces[idx] {
+            messages.push(format!(
+                "range opened on page {} is never closed",
+                start.text
+            ));
+            pieces[idx] = Piece::Single(start, encap);
+        }
+    }
+
+    merge_runs(pieces, style)
+}
+
+/// Turn runs of successive single pages into implicit ranges.
+fn merge_runs<'a>(pieces: Vec<Piece<'a>>, style: &'a Style) -> Vec<Piece<'a>> {
+    let mut merged = Vec::new();
+    let mut run: Vec<(&Page, Option<&str>)> = Vec::new();
+
+    fn flush<'a>(
+        run: &mut Vec<(&'a Page, Option<&'a str>)>,
+        out: &mut Vec<Piece<'a>>,
+        style: &'a Style,
+    ) {
+        let n = run.len();
+
+        if n == 0 {
+            return;
+        }
+
+        let (first, encap) = run[0];
+        let last = run[n - 1].0;
+
+        if n == 2 && !style.suffix_2p.is_empty() {
+            out.push(Piece::Suffixed(first, &style.suffix_2p, encap));
+        } else if n == 3 && !style.suffix_3p.is_empty() {
+            out.push(Piece::Suffixed(first, &style.suffix_3p, encap));
+        } else if n >= 3 && !style.suffix_mp.is_empty() {
+            out.push(Piece::Suffixed(first, &style.suffix_mp, encap));
+        } else if n >= 3 {
+            out.push(Piece::Range(first, Some(last), encap));
+        } else {
+            out.extend(run.iter().map(|(p, e)| Piece::Single(p, *e)));
+        }
+
+        run.clear();
+    }
+
+    for piece in pieces {
+        match piece {
+            Piece::Single(page, encap) => {
+                if let Some(&(last, last_encap)) = run.last() {
+                    if last_encap != encap || !last.is_followed_by(page) {
+                        flush(&mut run, &mut merged, style);
+                    }
+                }
+
+                run.push((page, encap));
+            }
+
+            other => {
+                flush(&mut run, &mut merged, style);
+                merged.push(other);
+            }
+        }
+    }
+
+    flush(&mut run, &mut merged, style);
+    merged
+}
+
+fn format_piece(piece: &Piece, style: &Style) -> String {
+    let (text, encap) = match piece {
+        Piece::Single(p, e) => (p.text.clone(), e),
+        Piece::Range(p, Some(q), e) => (format!("{}{}{}", p.text, style.delim_r, q.text), e),
+        Piece::Range(p, None, e) => (p.text.clone(), e),
+        Piece::Suffixed(p, s, e) => (format!("{}{}", p.text, s), e),
+    };
+
+    match encap {
+        Some(e) => format!(
+            "{}{}{}{}{}",
+            style.encap_prefix, e, style.encap_infix, text, style.encap_suffix
+        ),
+        None => text,
+    }
+}
+
+/// A helper for writing output while tracking the line length, so that
+/// long page lists can be wrapped.
+struct Writer<'a> {
+    style: &'a Style,
+    text: String,
+    column: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn push(&mut self, s: &str) {
+        self.text.push_str(s);
+
+        match s.rfind('\n') {
+            Some(i) => self.column = s[i + 1..].chars().count(),
+            None => self.column += s.chars().count(),
+        }
+    }
+
+    /// Push text, first wrapping the line if it would get too long.
+    fn push_wrapped(&mut self, s: &str) {
+        if self.column > 0 && self.column + s.chars().count() > self.style.line_max {
+            self.text.push('\n');
+            self.text.push_str(&self.style.indent_space);
+            self.column = self.style.indent_length;
+        }
+
+        self.push(s);
+    }
+}
+
+fn heading(key: &str, style: &Style) -> String {
+    let positive = style.headings_flag > 0;
+
+    let text = match KeyClass::of(key) {
+        KeyClass::Symbol if positive => style.symhead_positive.clone(),
+        KeyClass::Symbol => style.symhead_negative.clone(),
+        KeyClass::Number if positive => style.numhead_positive.clone(),
+        KeyClass::Number => style.numhead_negative.clone(),
+        KeyClass::Letter => {
+            let c = key.chars().next().unwrap_or(' ');
+
+            if positive {
+                c.to_uppercase().collect()
+            } else {
+                c.to_lowercase().collect()
+            }
+        }
+    };
+
+    format!("{}{}{}", style.heading_prefix, text, style.heading_suffix)
+}
+
+/// The key that determines which group an entry goes into.
+fn group_key(levels: &[KeyLevel]) -> (KeyClass, String) {
+    let key = &levels[0].sort;
+    let class = KeyClass::of(key);
+
+    let letter = if class == KeyClass::Letter {
+        key.chars()
+            .next()
+            .map(|c| c.to_lowercase().collect())
+            .unwrap_or_default()
+    } else {
+        String::new()
+    };
+
+    (class, letter)
+}
+
+/// Generate the formatted index from a sorted list of entries.
+pub(crate) fn write_index(entries: &[Entry], style: &Style, messages: &mut Vec<String>) -> String {
+    let mut w = Writer {
+        style,
+        text: String::new(),
+        column: 0,
+    };
+
+    w.push(&style.preamble);
+
+    let mut group: Option<(KeyClass, String)> = None;
+    let mut prev_levels: &[KeyLevel] = &[];
+    let mut last_depth = 0;
+    let mut last_had_pages = false;
+    let mut i = 0;
+
+    while i < entries.len() {
+        let levels = &entries[i].levels;
+        let mut refs = Vec::new();
+
+        while i < entries.len() && entries[i].levels == *levels {
+            refs.push(&entries[i]);
+            i += 1;
+        }
+
+        let this_group = group_key(levels);
+
+        if group.as_ref() != Some(&this_group) {
+            if group.is_some() {
+                w.push(&style.group_skip);
+            }
+
+            if style.headings_flag != 0 {
+                w.push(&heading(&levels[0].sort, style));
+            }
+
+            group = Some(this_group);
+            prev_levels = &[];
+        }
+
+        // Emit any parent items that haven't appeared yet, then this item.
+
+        let common = prev_levels
+            .iter()
+            .zip(levels.iter())
+            .take_while(|(a, b)| a == b)
+            .count()
+            .min(levels.len() - 1);
+
+        for (depth, level) in levels.iter().enumerate().skip(common) {
+            let follows_parent = depth > 0 && last_depth == depth - 1;
+
+            let item = match depth {
+                0 => &style.item_0,
+                1 if follows_parent && last_had_pages => &style.item_01,
+                1 if follows_parent => &style.item_x1,
+                1 => &style.item_1,
+                _ if follows_parent && last_had_pages => &style.item_12,
+                _ if follows_parent => &style.item_x2,
+                _ => &style.item_2,
+            };
+
+            w.push(item);
+            w.push(&level.actual);
+            last_depth = depth;
+            last_had_pages = depth == levels.len() - 1;
+        }
+
+        let delim = match levels.len() {
+            1 => &style.delim_0,
+            2 => &style.delim_1,
+            _ => &style.delim_2,
+        };
+
+        let pieces = page_pieces(&refs, style, messages);
+        let n = pieces.len();
+
+        for (j, piece) in pieces.iter().enumerate() {
+            let mut s = if j == 0 { delim.clone() } else { String::new() };
+
+            s.push_str(&format_piece(piece, style));
+
+            if j + 1 < n {
+                s.push_str(&style.delim_n);
+            }
+
+            w.push_wrapped(&s);
+        }
+
+        w.push(&style.delim_t);
+        prev_levels = levels;
+    }
+
+    w.push(&style.postamble);
+    w.text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::read_entries;
+
+    fn run(idx: &str, style: &Style) -> String {
+        let mut messages = Vec::new();
+        let mut entries = read_entries(idx, style, &mut messages);
+        sort_entries(&mut entries, false);
+        write_index(&entries, style, &mut messages)
+    }
+
+    #[test]
+    fn basic_index() {
+        let style = Style::default();
+        let out = run(
+            "\\indexentry{beta}{2}\n\
+             \\indexentry{Alpha}{3}\n\
+             \\indexentry{beta}{3}\n\
+             \\indexentry{beta}{4}\n\
+             \\indexentry{beta}{4}\n\
+             \\indexentry{beta!gamma|textbf}{7}\n\
+             \\indexentry{delta!epsilon}{1}\n\
+             \\indexentry{zeta|(}{10}\n\
+             \\indexentry{zeta}{11}\n\
+             \\indexentry{zeta|)}{14}\n\
+             \\indexentry{10}{5}\n",
+            &style,
+        );
+
+        assert_eq!(
+            out,
+            "\\begin{theindex}\n\
+             \n  \\item 10, 5\
+             \n\n  \\indexspace\n\
+             \n  \\item Alpha, 3\
+             \n\n  \\indexspace\n\
+             \n  \\item beta, 2--4\
+             \n    \\subitem gamma, \\textbf{7}\
+             \n\n  \\indexspace\n\
+             \n  \\item delta\
+             \n    \\subitem epsilon, 1\
+             \n\n  \\indexspace\n\
+             \n  \\item zeta, 10--14\
+             \n\n\\end{theindex}\n"
+        );
+    }
+
+    #[test]
+    fn letter_ordering() {
+        let style = Style::default();
+        let mut messages = Vec::new();
+        let mut entries = read_entries(
+            "\\indexentry{sea lion}{1}\\indexentry{seal}{2}\n",
+            &style,
+            &mut messages,
+        );
+
+        sort_entries(&mut entries, false);
+        assert_eq!(entries[0].levels[0].sort, "sea lion");
+        sort_entries(&mut entries, true);
+        assert_eq!(entries[0].levels[0].sort, "seal");
+    }
+
+    #[test]
+    fn headings_and_suffixes() {
+        let mut style = Style::default();
+        let mut messages = Vec::new();
+        style.apply_file(
+            "headings_flag 1\nheading_prefix \"\\\\head{\"\nheading_suffix \"}\"\n\
+             suffix_2p \"f.\"\n",
+            &mut messages,
+        );
+
+        let out = run(
+            "\\indexentry{apple}{1}\\indexentry{apple}{2}\\indexentry{avocado}{9}\n",
+            &style,
+        );
+
+        assert_eq!(
+            out,
+            "\\begin{theindex}\n\\head{A}\n  \\item apple, 1f.\n  \\item avocado, 9\n\n\\end{theindex}\n"
+        );
+    }
+}
@@ -0,0 +1,225 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+#![deny(missing_docs)]
+
+//! A makeindex-compatible index processor.
+//!
+//! This crate reads the `.idx` files written by LaTeX's `\index` machinery and
+//! generates sorted, formatted `.ind` files, following the rules of the
+//! classic `makeindex` program. Index style (`.ist`) files are supported. All
+//! I/O goes through Tectonic's virtual I/O layer, so that the index processor
+//! can be run inside the usual processing loop without any external tools.
+
+use std::io::{Read, Write};
+use tectonic_bridge_core::DriverHooks;
+use tectonic_errors::prelude::*;
+use tectonic_status_base::{tt_warning, StatusBackend};
+
+mod entry;
+mod index;
+mod style;
+
+use self::style::Style;
+
+/// A possible outcome from a makeindex engine invocation.
+///
+/// Fatal problems, like a missing input file, are represented as an `Err`
+/// result rather than a [`MakeindexOutcome`].
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum MakeindexOutcome {
+    /// Nothing bad happened.
+    Spotless,
+
+    /// Warnings were issued. These are described in the transcript file.
+    Warnings,
+}
+
+/// A struct for invoking the makeindex engine.
+///
+/// This struct has a “builder” interface: you create it, apply any settings
+/// that you wish, and eventually run the [`process()`](Self::process) method.
+#[derive(Debug, Default)]
+pub struct MakeindexEngine {
+    style: Option<String>,
+    output_name: Option<String>,
+    log_name: Option<String>,
+    letter_ordering: bool,
+}
+
+impl MakeindexEngine {
+    /// Specify an index style (`.ist`) file to use.
+    ///
+    /// By default, the standard makeindex style settings are used.
+    pub fn style<S: ToString>(&mut self, name: S) -> &mut Self {
+        self.style = Some(name.to_string());
+        self
+    }
+
+    /// Specify the name of the output file.
+    ///
+    /// By default, this is the input name with its extension replaced by
+    /// `.ind`.
+    pub fn output_name<S: ToString>(&mut self, name: S) -> &mut Self {
+        self.output_name = Some(name.to_string());
+        self
+    }
+
+    /// Specify the name of the transcript file.
+    ///
+    /// By default, this is the input name with its extension replaced by
+    /// `.ilg`.
+    pub fn log_name<S: ToString>(&mut self, name: S) -> &mut Self {
+        self.log_name = Some(name.to_string());
+        self
+    }
+
+    /// Specify whether to use “letter ordering” when sorting entries.
+    ///
+    /// In letter ordering, spaces in sort keys are ignored, so that “seal”
+    /// sorts before “sea lion”. This is equivalent to the `-l` option of
+    /// `makeindex`. By default, word ordering is used, in which spaces are
+    /// significant.
+    pub fn letter_ordering(&mut self, letter_ordering: bool) -> &mut Self {
+        self.letter_ordering = letter_ordering;
+        self
+    }
+
+    /// Process an index file.
+    ///
+    /// The input, style, output, and transcript files are all accessed through
+    /// the I/O layer provided by *hooks*.
+    pub fn process(
+        &mut self,
+        hooks: &mut dyn DriverHooks,
+        status: &mut dyn StatusBackend,
+        idx: &str,
+    ) -> Result<MakeindexOutcome> {
+        let stem = match idx.rfind('.') {
+            Some(i) if !idx[i..].contains('/') => &idx[..i],
+            _ => idx,
+        };
+
+        let output_name = self
+            .output_name
+            .clone()
+            .unwrap_or_else(|| format!("{stem}.ind"));
+        let log_name = self
+            .log_name
+            .clone()
+            .unwrap_or_else(|| format!("{stem}.ilg"));
+
+        let mut log = String::new();
+        let mut n_warnings = 0;
+        log.push_str("This is Tectonic's makeindex-compatible index processor.\n");
+
+        let mut style = Style::default();
+
+        if let Some(ref style_name) = self.style {
+            let text = read_text(hooks, status, style_name)?;
+            let mut messages = Vec::new();
+            let n = style.apply_file(&text, &mut messages);
+            log.push_str(&format!(
+                "Scanning style file {style_name}...done ({n} attributes redefined, {} ignored).\n",
+                messages.len()
+            ));
+            n_warnings += log_messages(&mut log, &messages);
+        }
+
+        let text = read_text(hooks, status, idx)?;
+        let mut messages = Vec::new();
+        let mut entries = entry::read_entries(&text, &style, &mut messages);
+        log.push_str(&format!(
+            "Scanning input file {idx}...done ({} entries accepted, {} rejected).\n",
+            entries.len(),
+            messages.len()
+        ));
+        let n_rejected = messages.len();
+        n_warnings += log_messages(&mut log, &messages);
+
+        log.push_str("Sorting entries...done.\n");
+        index::sort_entries(&mut entries, self.letter_ordering);
+
+        let mut messages = Vec::new();
+        let output = index::write_index(&entries, &style, &mut messages);
+        log.push_str(&format!(
+            "Generating output file {output_name}...done ({} lines written, {} warnings).\n",
+            output.matches('\n').count(),
+            messages.len()
+        ));
+        n_warnings += log_messages(&mut log, &messages);
+
+        write_text(hooks, status, &output_name, &output)?;
+        log.push_str(&format!("Output written in {output_name}.\n"));
+        log.push_str(&format!("Transcript written in {log_name}.\n"));
+        write_text(hooks, status, &log_name, &log)?;
+
+        if n_rejected > 0 {
+            tt_warning!(
+                status,
+                "{} index entries in `{}` were rejected; see `{}` for details",
+                n_rejected,
+                idx,
+                log_name
+            );
+        }
+
+        Ok(if n_warnings > 0 {
+            MakeindexOutcome::Warnings
+        } else {
+            MakeindexOutcome::Spotless
+        })
+    }
+}
+
+fn log_messages(log: &mut String, messages: &[String]) -> usize {
+    for msg in messages {
+        log.push_str("## ");
+        log.push_str(msg);
+        log.push('\n');
+    }
+
+    messages.len()
+}
+
+fn read_text(
+    hooks: &mut dyn DriverHooks,
+    status: &mut dyn StatusBackend,
+    name: &str,
+) -> Result<String> {
+    let mut ih = atry!(
+        hooks.io().input_open_name(name, status).must_exist();
+        ["unable to open index input `{}`", name]
+    );
+
+    let mut data = Vec::new();
+    atry!(
+        ih.read_to_end(&mut data);
+        ["failed to read index input `{}`", name]
+    );
+
+    let (name, digest_opt) = ih.into_name_digest();
+    hooks.event_input_closed(name, digest_opt, status);
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+fn write_text(
+    hooks: &mut dyn DriverHooks,
+    status: &mut dyn StatusBackend,
+    name: &str,
+    text: &str,
+) -> Result<()> {
+    let mut output = atry!(
+        hooks.io().output_open_name(name).must_exist();
+        ["unable to open index output `{}`", name]
+    );
+
+    atry!(
+        output.write_all(text.as_bytes());
+        ["failed to write index output `{}`", name]
+    );
+
+    let (name, digest) = output.into_name_digest();
+    hooks.event_output_closed(name, digest, status);
+    Ok(())
+}
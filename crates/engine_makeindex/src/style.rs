@@ -0,0 +1,394 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Index style settings and the parser for `.ist` style files.
+
+/// The settings that control how index entries are read and written.
+///
+/// The field names match the keys used in `.ist` files, and the defaults match
+/// those of the classic `makeindex` program.
+#[derive(Clone, Debug)]
+pub(crate) struct Style {
+    // Input settings.
+    pub keyword: String,
+    pub arg_open: char,
+    pub arg_close: char,
+    pub range_open: char,
+    pub range_close: char,
+    pub level: char,
+    pub actual: char,
+    pub encap: char,
+    pub quote: char,
+    pub escape: char,
+    pub page_compositor: String,
+
+    // Output settings.
+    pub preamble: String,
+    pub postamble: String,
+    pub group_skip: String,
+    pub headings_flag: i32,
+    pub heading_prefix: String,
+    pub heading_suffix: String,
+    pub symhead_positive: String,
+    pub symhead_negative: String,
+    pub numhead_positive: String,
+    pub numhead_negative: String,
+    pub item_0: String,
+    pub item_1: String,
+    pub item_2: String,
+    pub item_01: String,
+    pub item_x1: String,
+    pub item_12: String,
+    pub item_x2: String,
+    pub delim_0: String,
+    pub delim_1: String,
+    pub delim_2: String,
+    pub delim_n: String,
+    pub delim_r: String,
+    pub delim_t: String,
+    pub encap_prefix: String,
+    pub encap_infix: String,
+    pub encap_suffix: String,
+    pub page_precedence: String,
+    pub line_max: usize,
+    pub indent_space: String,
+    pub indent_length: usize,
+    pub suffix_2p: String,
+    pub suffix_3p: String,
+    pub suffix_mp: String,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            keyword: "\\indexentry".to_owned(),
+            arg_open: '{',
+            arg_close: '}',
+            range_open: '(',
+            range_close: ')',
+            level: '!',
+            actual: '@',
+            encap: '|',
+            quote: '"',
+            escape: '\\',
+            page_compositor: "-".to_owned(),
+
+            preamble: "\\begin{theindex}\n".to_owned(),
+            postamble: "\n\n\\end{theindex}\n".to_owned(),
+            group_skip: "\n\n  \\indexspace\n".to_owned(),
+            headings_flag: 0,
+            heading_prefix: String::new(),
+            heading_suffix: String::new(),
+            symhead_positive: "Symbols".to_owned(),
+            symhead_negative: "symbols".to_owned(),
+            numhead_positive: "Numbers".to_owned(),
+            numhead_negative: "numbers".to_owned(),
+            item_0: "\n  \\item ".to_owned(),
+            item_1: "\n    \\subitem ".to_owned(),
+            item_2: "\n      \\subsubitem ".to_owned(),
+            item_01: "\n    \\subitem ".to_owned(),
+            item_x1: "\n    \\subitem ".to_owned(),
+            item_12: "\n      \\subsubitem ".to_owned(),
+            item_x2: "\n      \\subsubitem ".to_owned(),
+            delim_0: ", ".to_owned(),
+            delim_1: ", ".to_owned(),
+            delim_2: ", ".to_owned(),
+            delim_n: ", ".to_owned(),
+            delim_r: "--".to_owned(),
+            delim_t: String::new(),
+            encap_prefix: "\\".to_owned(),
+            encap_infix: "{".to_owned(),
+            encap_suffix: "}".to_owned(),
+            page_precedence: "rnaRA".to_owned(),
+            line_max: 72,
+            indent_space: "\t\t".to_owned(),
+            indent_length: 16,
+            suffix_2p: String::new(),
+            suffix_3p: String::new(),
+            suffix_mp: String::new(),
+        }
+    }
+}
+
+/// A value in a style file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Value {
+    Str(String),
+    Char(char),
+    Number(i32),
+}
+
+impl Style {
+    /// Apply the settings in a style file.
+    ///
+    /// Problems are reported as messages for the transcript, but don't stop
+    /// processing. Returns the number of settings that were applied.
+    pub fn apply_file(&mut self, text: &str, messages: &mut Vec<String>) -> usize {
+        let mut chars = text.chars().peekable();
+        let mut line = 1;
+        let mut n_applied = 0;
+
+        loop {
+            // Skip whitespace and comments.
+
+            while let Some(&c) = chars.peek() {
+                if c == '%' {
+                    for c in chars.by_ref() {
+                        if c == '\n' {
+                            line += 1;
+                            break;
+                        }
+                    }
+                } else if c.is_whitespace() {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+
+            if chars.peek().is_none() {
+                break;
+            }
+
+            let mut key = String::new();
+
+            while let Some(&c) = chars.peek() {
+                if c.is_alphanumeric() || c == '_' {
+                    key.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+
+            if key.is_empty() {
+                messages.push(format!(
+                    "style line {line}: unexpected character; skipping line"
+                ));
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line += 1;
+                        break;
+                    }
+                }
+                continue;
+            }
+
+            while let Some(&c) = chars.peek() {
+                if c == ' ' || c == '\t' {
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+
+            let value = match chars.peek() {
+                Some('"') => {
+                    chars.next();
+                    let mut s = String::new();
+                    let mut closed = false;
+
+                    while let Some(c) = chars.next() {
+                        match c {
+                            '"' => {
+                                closed = true;
+                                break;
+                            }
+                            '\\' => s.push(unescape(chars.next())),
+                            c => {
+                                if c == '\n' {
+                                    line += 1;
+                                }
+                                s.push(c);
+                            }
+                        }
+                    }
+
+                    if !closed {
+                        messages.push(format!("style line {line}: unterminated string"));
+                    }
+
+                    Some(Value::Str(s))
+                }
+
+                Some('\'') => {
+                    chars.next();
+                    let c = match chars.next() {
+                        Some('\\') => Some(unescape(chars.next())),
+                        c => c,
+                    };
+
+                    if chars.next() != Some('\'') {
+                        None
+                    } else {
+                        c.map(Value::Char)
+                    }
+                }
+
+                Some(&c) if c == '-' || c.is_ascii_digit() => {
+                    let mut s = String::new();
+                    s.push(c);
+                    chars.next();
+
+                    while let Some(&c) = chars.peek() {
+                        if c.is_ascii_digit() {
+                            s.push(c);
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+
+                    s.parse().ok().map(Value::Number)
+                }
+
+                _ => None,
+            };
+
+            let value = match value {
+                Some(v) => v,
+                None => {
+                    messages.push(format!(
+                        "style line {line}: missing or malformed value for `{key}`"
+                    ));
+                    continue;
+                }
+            };
+
+            match self.set(&key, value) {
+                Ok(()) => n_applied += 1,
+                Err(msg) => messages.push(format!("style line {line}: {msg}")),
+            }
+        }
+
+        n_applied
+    }
+
+    /// Apply one setting.
+    fn set(&mut self, key: &str, value: Value) -> Result<(), String> {
+        fn string(key: &str, dest: &mut String, value: Value) -> Result<(), String> {
+            match value {
+                Value::Str(s) => {
+                    *dest = s;
+                    Ok(())
+                }
+                _ => Err(format!("`{key}` requires a string value")),
+            }
+        }
+
+        fn character(key: &str, dest: &mut char, value: Value) -> Result<(), String> {
+            match value {
+                Value::Char(c) => {
+                    *dest = c;
+                    Ok(())
+                }
+                _ => Err(format!("`{key}` requires a character value")),
+            }
+        }
+
+        fn number(key: &str, value: Value) -> Result<i32, String> {
+            match value {
+                Value::Number(n) => Ok(n),
+                _ => Err(format!("`{key}` requires a numeric value")),
+            }
+        }
+
+        match key {
+            "keyword" => string(key, &mut self.keyword, value),
+            "arg_open" => character(key, &mut self.arg_open, value),
+            "arg_close" => character(key, &mut self.arg_close, value),
+            "range_open" => character(key, &mut self.range_open, value),
+            "range_close" => character(key, &mut self.range_close, value),
+            "level" => character(key, &mut self.level, value),
+            "actual" => character(key, &mut self.actual, value),
+            "encap" => character(key, &mut self.encap, value),
+            "quote" => character(key, &mut self.quote, value),
+            "escape" => character(key, &mut self.escape, value),
+            "page_compositor" => string(key, &mut self.page_compositor, value),
+
+            "preamble" => string(key, &mut self.preamble, value),
+            "postamble" => string(key, &mut self.postamble, value),
+            "group_skip" => string(key, &mut self.group_skip, value),
+            "headings_flag" | "lethead_flag" => {
+                self.headings_flag = number(key, value)?;
+                Ok(())
+            }
+            "heading_prefix" | "lethead_prefix" => string(key, &mut self.heading_prefix, value),
+            "heading_suffix" | "lethead_suffix" => string(key, &mut self.heading_suffix, value),
+            "symhead_positive" => string(key, &mut self.symhead_positive, value),
+            "symhead_negative" => string(key, &mut self.symhead_negative, value),
+            "numhead_positive" => string(key, &mut self.numhead_positive, value),
+            "numhead_negative" => string(key, &mut self.numhead_negative, value),
+            "item_0" => string(key, &mut self.item_0, value),
+            "item_1" => string(key, &mut self.item_1, value),
+            "item_2" => string(key, &mut self.item_2, value),
+            "item_01" => string(key, &mut self.item_01, value),
+            "item_x1" => string(key, &mut self.item_x1, value),
+            "item_12" => string(key, &mut self.item_12, value),
+            "item_x2" => string(key, &mut self.item_x2, value),
+            "delim_0" => string(key, &mut self.delim_0, value),
+            "delim_1" => string(key, &mut self.delim_1, value),
+            "delim_2" => string(key, &mut self.delim_2, value),
+            "delim_n" => string(key, &mut self.delim_n, value),
+            "delim_r" => string(key, &mut self.delim_r, value),
+            "delim_t" => string(key, &mut self.delim_t, value),
+            "encap_prefix" => string(key, &mut self.encap_prefix, value),
+            "encap_infix" => string(key, &mut self.encap_infix, value),
+            "encap_suffix" => string(key, &mut self.encap_suffix, value),
+            "page_precedence" => string(key, &mut self.page_precedence, value),
+            "line_max" => {
+                self.line_max = number(key, value)?.max(1) as usize;
+                Ok(())
+            }
+            "indent_space" => string(key, &mut self.indent_space, value),
+            "indent_length" => {
+                self.indent_length = number(key, value)?.max(0) as usize;
+                Ok(())
+            }
+            "suffix_2p" => string(key, &mut self.suffix_2p, value),
+            "suffix_3p" => string(key, &mut self.suffix_3p, value),
+            "suffix_mp" => string(key, &mut self.suffix_mp, value),
+
+            // We don't support these, but they're harmless to ignore.
+            "setpage_prefix" | "setpage_suffix" => Ok(()),
+
+            _ => Err(format!("unknown specifier `{key}`")),
+        }
+    }
+}
+
+/// Interpret the character after a backslash in a style file string.
+fn unescape(c: Option<char>) -> char {
+    match c {
+        Some('n') => '\n',
+        Some('t') => '\t',
+        Some(c) => c,
+        None => '\\',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_style() {
+        let mut style = Style::default();
+        let mut messages = Vec::new();
+        let n = style.apply_file(
+            "% A comment\nheadings_flag 1\nheading_prefix \"\\\\textbf{\"\n\
+             delim_0 \"\\t\" quote '+'\nbogus \"x\"\n",
+            &mut messages,
+        );
+
+        assert_eq!(n, 4);
+        assert_eq!(style.headings_flag, 1);
+        assert_eq!(style.heading_prefix, "\\textbf{");
+        assert_eq!(style.delim_0, "\t");
+        assert_eq!(style.quote, '+');
+        assert_eq!(messages.len(), 1);
+    }
+}
@@ -8,7 +8,7 @@
 
 use std::{collections::HashMap, fmt::Write, io::Read, path::Path};
 use tectonic_errors::prelude::*;
-use tectonic_io_base::InputHandle;
+use tectonic_io_base::{InputHandle, OpenResult};
 use tectonic_status_base::{tt_warning, StatusBackend};
 
 use crate::{
@@ -101,6 +101,61 @@ impl FontEnsemble {
         Ok(())
     }
 
+    /// Find the file for a "native" font defined in an XDV or SPX file and
+    /// register the font with this data structure.
+    ///
+    /// The font *name* comes directly from the input file and currently
+    /// corresponds to the TeX path of a font file that can be opened as an
+    /// input, potentially without its extension. In the future, it is possible
+    /// that the font name might be something symbolic like "Times New Roman"
+    /// that might not work well as a file path.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn register_native_font(
+        &mut self,
+        name: &str,
+        font_num: TexFontNum,
+        size: FixedPoint,
+        face_index: u32,
+        color_rgba: Option<u32>,
+        extend: Option<u32>,
+        slant: Option<u32>,
+        embolden: Option<u32>,
+        common: &mut Common,
+    ) -> Result<()> {
+        // Figure out the TeX path of the font source. At the moment, this is
+        // just the name or something similar, but in principle we might do a
+        // lookup based on something like symbolic name.
+
+        let io = common.hooks.io();
+        let mut texpath = String::default();
+        let mut ih = None;
+
+        for ext in &["", ".otf"] {
+            texpath = format!("{name}{ext}");
+
+            match io.input_open_name(&texpath, common.status) {
+                OpenResult::Ok(h) => {
+                    ih = Some(h);
+                    break;
+                }
+
+                OpenResult::NotAvailable => continue,
+
+                OpenResult::Err(e) => return Err(e),
+            };
+        }
+
+        let ih = a_ok_or!(ih;
+            ["failed to find a font file associated with the name `{}`", name]
+        );
+
+        // Now that we have that, we can pass off to the font manager.
+
+        self.register_tex_font(
+            font_num, size, face_index, color_rgba, extend, slant, embolden, texpath, ih, common,
+        )
+    }
+
     /// Make sure that a font file is loaded. Font files are uniquely identified
     /// by their source TeX paths and face indices.
     fn ensure_font_file(
@@ -3,10 +3,14 @@
 
 #![deny(missing_docs)]
 
-//! Convert Tectonic’s SPX format to HTML.
+//! Convert Tectonic’s SPX format to HTML, and XDV to SVG.
 //!
 //! SPX is essentially the same thing as XDV, but we identify it differently to
 //! mark that the semantics of the content wil be set up for HTML output.
+//!
+//! The [`Xdv2SvgEngine`] reuses the font handling of the HTML output to convert
+//! ordinary XDV files into SVG images, one per page. It only supports text set
+//! in OpenType and TrueType fonts.
 
 use std::path::{Path, PathBuf};
 use tectonic_bridge_core::DriverHooks;
@@ -22,6 +26,7 @@ mod fonts;
 mod html;
 mod initialization;
 mod specials;
+mod svg;
 mod templating;
 
 use self::{
@@ -167,6 +172,7 @@ impl Spx2HtmlEngine {
 }
 
 pub use assets::AssetSpecification;
+pub use svg::Xdv2SvgEngine;
 
 struct EngineState<'a> {
     common: Common<'a>,
@@ -0,0 +1,490 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Convert XDV to SVG, with one output file per page.
+//!
+//! Glyphs are drawn as SVG text, using the same machinery as the HTML output
+//! to map each glyph back to a Unicode character and to emit modified font
+//! files when a glyph can't be reached that way. The font files are written
+//! next to the SVG files and referenced from `@font-face` rules in each of
+//! them.
+
+use std::{
+    fmt::Write as FmtWrite,
+    path::{Path, PathBuf},
+};
+use tectonic_bridge_core::DriverHooks;
+use tectonic_errors::prelude::*;
+use tectonic_status_base::{tt_warning, StatusBackend};
+use tectonic_xdv::{FileType, XdvEvents, XdvParser};
+
+use crate::{fonts::FontEnsemble, Common, FixedPoint, OutputState, TexFontNum};
+
+/// The number of big points (the user unit of the SVG output) per TeX point.
+const BP_PER_PT: f64 = 72. / 72.27;
+
+/// The size of TeX's origin offset from the top left corner of the page.
+const ORIGIN_OFFSET: f64 = 72.;
+
+/// The default page size, U.S. letter, as in the PDF output.
+const DEFAULT_PAGE_SIZE: (f64, f64) = (612., 792.);
+
+/// An engine that converts XDV to SVG.
+#[derive(Debug, Default)]
+pub struct Xdv2SvgEngine {
+    output: OutputState,
+}
+
+impl Xdv2SvgEngine {
+    /// Specify that output files should not actually be created.
+    ///
+    /// This mode can be useful to check that a file can be converted.
+    pub fn do_not_emit_files(&mut self) -> &mut Self {
+        self.output = OutputState::NoOutput;
+        self
+    }
+
+    /// Specify the directory in which to create the output files.
+    ///
+    /// Like the HTML output, and for the same reasons, the SVG and font files
+    /// are written directly to disk rather than through the I/O layer.
+    pub fn output_base(&mut self, out_base: impl Into<PathBuf>) -> &mut Self {
+        self.output = OutputState::Path(out_base.into());
+        self
+    }
+
+    /// Process XDV into SVG.
+    ///
+    /// If the input has only one page, the output is named after it with an
+    /// `.svg` extension. Otherwise, the page number is appended to the name of
+    /// each output, so that `doc.xdv` becomes `doc-1.svg`, `doc-2.svg`, and so
+    /// on.
+    ///
+    /// Before calling this function, you must explicitly specify the output
+    /// mode by calling either [`Self::do_not_emit_files`] or
+    /// [`Self::output_base`]. If you do not, this function will panic.
+    pub fn process_to_filesystem(
+        &mut self,
+        hooks: &mut dyn DriverHooks,
+        status: &mut dyn StatusBackend,
+        xdv: &str,
+    ) -> Result<()> {
+        let mut input = hooks.io().input_open_name(xdv, status).must_exist()?;
+
+        let out_base = match self.output {
+            OutputState::NoOutput => None,
+            OutputState::Path(ref p) => Some(p.as_ref()),
+            OutputState::Undefined => panic!("xdv2svg output mode not specified"),
+        };
+
+        {
+            let state = SvgState::new(hooks, status, out_base);
+            let state = XdvParser::process_with_seeks(&mut input, state)?;
+            state.finish(xdv)?;
+        }
+
+        let (name, digest_opt) = input.into_name_digest();
+        hooks.event_input_closed(name, digest_opt, status);
+        Ok(())
+    }
+}
+
+/// Convert a TeX dimension to big points.
+fn bp(sp: FixedPoint) -> f64 {
+    sp as f64 / 65536. * BP_PER_PT
+}
+
+/// Format a length for the output, without needless digits.
+fn fmt_len(value: f64) -> String {
+    let s = format!("{value:.3}");
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+
+    if s == "-0" {
+        "0".to_owned()
+    } else {
+        s.to_owned()
+    }
+}
+
+/// Parse a length as used in the page size specials, returning it in big
+/// points.
+fn parse_length(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+'))
+        .unwrap_or(text.len());
+    let value = text[..split].parse::<f64>().ok()?;
+    let unit = text[split..].trim_start();
+    let unit = unit.strip_prefix("true").unwrap_or(unit);
+
+    let factor = match unit {
+        "pt" => BP_PER_PT,
+        "bp" => 1.,
+        "in" => 72.,
+        "cm" => 72. / 2.54,
+        "mm" => 72. / 25.4,
+        "pc" => 12. * BP_PER_PT,
+        "dd" => 1238. / 1157. * BP_PER_PT,
+        "cc" => 12. * 1238. / 1157. * BP_PER_PT,
+        "sp" => BP_PER_PT / 65536.,
+        _ => return None,
+    };
+
+    Some(value * factor)
+}
+
+/// Parse a color specification as used in the `color` specials, such as `rgb 1
+/// 0 0` or `gray 0.5`, into an SVG color.
+fn parse_color(spec: &str) -> Option<String> {
+    let mut words = spec.split_whitespace();
+    let model = words.next()?;
+    let values = words
+        .map(|w| w.parse::<f64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    let (r, g, b) = match (model, &values[..]) {
+        ("rgb", &[r, g, b]) => (r, g, b),
+        ("gray", &[g]) => (g, g, g),
+        ("cmyk", &[c, m, y, k]) => (
+            (1. - c) * (1. - k),
+            (1. - m) * (1. - k),
+            (1. - y) * (1. - k),
+        ),
+        _ => return None,
+    };
+
+    let byte = |v: f64| (v.clamp(0., 1.) * 255.).round() as u8;
+    Some(format!("#{:02x}{:02x}{:02x}", byte(r), byte(g), byte(b)))
+}
+
+/// A page that has been converted, except for the font definitions, which
+/// are only known once the whole file has been processed.
+#[derive(Debug)]
+struct Page {
+    size: (f64, f64),
+    body: String,
+}
+
+struct SvgState<'a> {
+    common: Common<'a>,
+    fonts: FontEnsemble,
+    pages: Vec<Page>,
+    body: String,
+    page_size: (f64, f64),
+    color_stack: Vec<Option<String>>,
+    warned_tfm: bool,
+}
+
+impl<'a> SvgState<'a> {
+    fn new(
+        hooks: &'a mut dyn DriverHooks,
+        status: &'a mut dyn StatusBackend,
+        out_base: Option<&'a Path>,
+    ) -> Self {
+        SvgState {
+            common: Common {
+                hooks,
+                status,
+                out_base,
+                precomputed_assets: None,
+            },
+            fonts: FontEnsemble::default(),
+            pages: Vec::new(),
+            body: String::new(),
+            page_size: DEFAULT_PAGE_SIZE,
+            color_stack: Vec::new(),
+            warned_tfm: false,
+        }
+    }
+
+    /// Get an attribute setting the current color, if one has been set.
+    fn fill_attr(&self) -> String {
+        match self.color_stack.last() {
+            Some(Some(c)) => format!(r#" fill="{c}""#),
+            _ => String::new(),
+        }
+    }
+
+    /// Draw glyphs as text, in as few `text` elements as possible.
+    fn add_glyphs(
+        &mut self,
+        font_num: TexFontNum,
+        glyphs: &[u16],
+        x: &[i32],
+        y: &[i32],
+    ) -> Result<()> {
+        let fill = self.fill_attr();
+        let mut cur_style = String::new();
+        let mut text = String::new();
+        let mut xs = Vec::new();
+        let mut ys = Vec::new();
+
+        for (i, glyph) in glyphs.iter().enumerate() {
+            let (text_info, size, _) =
+                self.fonts
+                    .process_glyph_for_canvas(font_num, *glyph, self.common.status);
+
+            let (ch, style) = match text_info {
+                Some(ti) => ti,
+                None => continue,
+            };
+
+            let style = format!(r#"font-size="{}" style="{}""#, fmt_len(bp(size)), style);
+
+            // Positions are given for each character, but some software counts
+            // characters outside of the Basic Multilingual Plane twice, so
+            // those get text elements of their own.
+            if style != cur_style || (ch as u32) > 0xFFFF {
+                self.flush_text(&cur_style, &fill, &mut text, &mut xs, &mut ys);
+                cur_style = style;
+            }
+
+            html_escape::encode_text_to_string(ch.encode_utf8(&mut [0; 4]), &mut text);
+            xs.push(ORIGIN_OFFSET + bp(x[i]));
+            ys.push(ORIGIN_OFFSET + bp(y[i]));
+
+            if (ch as u32) > 0xFFFF {
+                self.flush_text(&cur_style, &fill, &mut text, &mut xs, &mut ys);
+            }
+        }
+
+        self.flush_text(&cur_style, &fill, &mut text, &mut xs, &mut ys);
+        Ok(())
+    }
+
+    fn flush_text(
+        &mut self,
+        style: &str,
+        fill: &str,
+        text: &mut String,
+        xs: &mut Vec<f64>,
+        ys: &mut Vec<f64>,
+    ) {
+        if text.is_empty() {
+            return;
+        }
+
+        let join = |v: &[f64]| v.iter().map(|l| fmt_len(*l)).collect::<Vec<_>>().join(" ");
+        let ys_text = if ys.iter().all(|y| *y == ys[0]) {
+            fmt_len(ys[0])
+        } else {
+            join(ys)
+        };
+
+        writeln!(
+            self.body,
+            r#"<text x="{}" y="{}" {}{}>{}</text>"#,
+            join(xs),
+            ys_text,
+            style,
+            fill,
+            text
+        )
+        .unwrap();
+
+        text.clear();
+        xs.clear();
+        ys.clear();
+    }
+
+    /// Handle the specials that set the page size.
+    fn handle_page_size(&mut self, contents: &str) {
+        if let Some(rest) = contents.strip_prefix("pdf:pagesize") {
+            let mut words = rest.split_whitespace();
+            let (mut width, mut height) = self.page_size;
+
+            while let Some(word) = words.next() {
+                match word {
+                    "width" => width = words.next().and_then(parse_length).unwrap_or(width),
+                    "height" => height = words.next().and_then(parse_length).unwrap_or(height),
+                    "default" => (width, height) = DEFAULT_PAGE_SIZE,
+                    _ => {}
+                }
+            }
+
+            self.page_size = (width, height);
+        } else if let Some(rest) = contents.strip_prefix("papersize") {
+            let rest = rest.trim_start().trim_start_matches('=').trim();
+            let rest = rest.trim_matches(|c| c == '"' || c == '\'');
+
+            if let Some((w, h)) = rest.split_once(',') {
+                if let (Some(w), Some(h)) = (parse_length(w), parse_length(h)) {
+                    self.page_size = (w, h);
+                }
+            }
+        }
+    }
+
+    /// Write out the pages, along with the font files that they use.
+    fn finish(mut self, xdv: &str) -> Result<()> {
+        let faces = self.fonts.emit(self.common.out_base)?;
+
+        let out_base = match self.common.out_base {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+
+        let stem = Path::new(xdv)
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "page".to_owned());
+        let n_pages = self.pages.len();
+
+        for (index, page) in self.pages.into_iter().enumerate() {
+            let name = if n_pages == 1 {
+                format!("{stem}.svg")
+            } else {
+                format!("{stem}-{}.svg", index + 1)
+            };
+
+            let (width, height) = (fmt_len(page.size.0), fmt_len(page.size.1));
+            let svg = format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<svg xmlns="http://www.w3.org/2000/svg" version="1.1" xml:space="preserve" width="{width}pt" height="{height}pt" viewBox="0 0 {width} {height}">
+<style><![CDATA[
+{faces}]]></style>
+{}</svg>
+"#,
+                page.body
+            );
+
+            let path = out_base.join(&name);
+            atry!(
+                std::fs::write(&path, svg);
+                ["cannot write output file `{}`", path.display()]
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> XdvEvents for SvgState<'a> {
+    type Error = Error;
+
+    fn handle_header(&mut self, filetype: FileType, _comment: &[u8]) -> Result<()> {
+        if filetype != FileType::Xdv {
+            bail!("file should be XDV format but got {}", filetype);
+        }
+
+        Ok(())
+    }
+
+    fn handle_begin_page(&mut self, _counters: &[i32], _previous_bop: i32) -> Result<()> {
+        self.body.clear();
+        Ok(())
+    }
+
+    fn handle_end_page(&mut self) -> Result<()> {
+        self.pages.push(Page {
+            size: self.page_size,
+            body: std::mem::take(&mut self.body),
+        });
+        Ok(())
+    }
+
+    fn handle_special(&mut self, _x: i32, _y: i32, contents: &[u8]) -> Result<()> {
+        let contents = match std::str::from_utf8(contents) {
+            Ok(c) => c.trim(),
+            Err(_) => return Ok(()),
+        };
+
+        if let Some(spec) = contents.strip_prefix("color push") {
+            let color = parse_color(spec);
+
+            if color.is_none() {
+                tt_warning!(self.common.status, "unsupported color `{}`", spec.trim());
+            }
+
+            self.color_stack.push(color);
+        } else if contents == "color pop" {
+            self.color_stack.pop();
+        } else {
+            self.handle_page_size(contents);
+        }
+
+        Ok(())
+    }
+
+    fn handle_char_run(&mut self, _font_num: i32, _chars: &[i32]) -> Result<()> {
+        if !self.warned_tfm {
+            tt_warning!(
+                self.common.status,
+                "SVG output only supports OpenType and TrueType fonts; \
+                 text in traditional TeX fonts will be missing"
+            );
+            self.warned_tfm = true;
+        }
+
+        Ok(())
+    }
+
+    fn handle_define_native_font(
+        &mut self,
+        name: &str,
+        font_num: TexFontNum,
+        size: FixedPoint,
+        face_index: u32,
+        color_rgba: Option<u32>,
+        extend: Option<u32>,
+        slant: Option<u32>,
+        embolden: Option<u32>,
+    ) -> Result<()> {
+        if self.fonts.contains(font_num) {
+            return Ok(());
+        }
+
+        self.fonts.register_native_font(
+            name,
+            font_num,
+            size,
+            face_index,
+            color_rgba,
+            extend,
+            slant,
+            embolden,
+            &mut self.common,
+        )
+    }
+
+    fn handle_glyph_run(
+        &mut self,
+        font_num: TexFontNum,
+        glyphs: &[u16],
+        x: &[i32],
+        y: &[i32],
+    ) -> Result<()> {
+        self.add_glyphs(font_num, glyphs, x, y)
+    }
+
+    fn handle_text_and_glyphs(
+        &mut self,
+        font_num: TexFontNum,
+        _text: &str,
+        _width: i32,
+        glyphs: &[u16],
+        x: &[i32],
+        y: &[i32],
+    ) -> Result<()> {
+        self.add_glyphs(font_num, glyphs, x, y)
+    }
+
+    fn handle_rule(&mut self, x: i32, y: i32, height: i32, width: i32) -> Result<()> {
+        if height <= 0 || width <= 0 {
+            return Ok(());
+        }
+
+        // The position is that of the bottom left corner of the rule.
+        writeln!(
+            self.body,
+            r#"<rect x="{}" y="{}" width="{}" height="{}"{}/>"#,
+            fmt_len(ORIGIN_OFFSET + bp(x)),
+            fmt_len(ORIGIN_OFFSET + bp(y - height)),
+            fmt_len(bp(width)),
+            fmt_len(bp(height)),
+            self.fill_attr()
+        )
+        .unwrap();
+        Ok(())
+    }
+}
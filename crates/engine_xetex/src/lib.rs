@@ -81,9 +81,11 @@ pub struct TexEngine {
     // though, it's just a proxy for the global constants in the C code.
     halt_on_error: bool,
     initex_mode: bool,
+    initex_load_format: bool,
     synctex_enabled: bool,
     semantic_pagination_enabled: bool,
     shell_escape_enabled: bool,
+    shell_escape_restricted: bool,
     build_date: SystemTime,
 }
 
@@ -92,9 +94,11 @@ impl Default for TexEngine {
         TexEngine {
             halt_on_error: true,
             initex_mode: false,
+            initex_load_format: false,
             synctex_enabled: false,
             semantic_pagination_enabled: false,
             shell_escape_enabled: false,
+            shell_escape_restricted: false,
             build_date: SystemTime::UNIX_EPOCH,
         }
     }
@@ -119,6 +123,18 @@ impl TexEngine {
         self
     }
 
+    /// Configure whether the engine will load a format file in "initex" mode.
+    ///
+    /// Normally, "initex" mode starts from a blank slate. If this setting is
+    /// true, the engine will first load the format file passed to
+    /// [`Self::process`], so that a new format can be built on top of it, in
+    /// the same way as the classic `tex -ini &base` invocation. This has no
+    /// effect unless "initex" mode is activated. The default is false.
+    pub fn initex_load_format(&mut self, load: bool) -> &mut Self {
+        self.initex_load_format = load;
+        self
+    }
+
     /// Configure the engine to produce SyncTeX data.
     ///
     /// The default is false.
@@ -150,6 +166,18 @@ impl TexEngine {
         self
     }
 
+    /// Configure whether the "shell escape" feature should be reported as
+    /// restricted.
+    ///
+    /// If true and shell escape is enabled, `\pdfshellescape` evaluates to 2,
+    /// as it does in TeX Live's restricted mode, so that packages know that
+    /// only some programs may be run. The restrictions themselves are enforced
+    /// by the driver. The default is false.
+    pub fn shell_escape_restricted(&mut self, restricted: bool) -> &mut Self {
+        self.shell_escape_restricted = restricted;
+        self
+    }
+
     /// Sets the date and time used by the TeX engine. This affects things like
     /// LaTeX's \today command.
     ///
@@ -170,7 +198,8 @@ impl TexEngine {
     /// preloaded engine state. It must be findable in the I/O stack, using the
     /// special hooks that are provided for handing format files, which allow
     /// updates to the file format to be handed (see [`FORMAT_SERIAL`]). If in
-    /// “initex” mode, this parameter will be ignored.
+    /// “initex” mode, this parameter will be ignored, unless
+    /// [`Self::initex_load_format`] is activated.
     ///
     /// The *input_file_name* is used to name the “primary input file”. The I/O
     /// system has special hooks for opening this primary input, so be aware
@@ -197,6 +226,10 @@ impl TexEngine {
                     b"shell_escape_enabled\0".as_ptr() as _,
                     self.shell_escape_enabled.into(),
                 );
+                tt_xetex_set_int_variable(
+                    b"shell_escape_restricted\0".as_ptr() as _,
+                    self.shell_escape_restricted.into(),
+                );
                 tt_xetex_set_int_variable(
                     b"halt_on_error_p\0".as_ptr() as _,
                     self.halt_on_error.into(),
@@ -205,6 +238,10 @@ impl TexEngine {
                     b"in_initex_mode\0".as_ptr() as _,
                     self.initex_mode.into(),
                 );
+                tt_xetex_set_int_variable(
+                    b"initex_load_format\0".as_ptr() as _,
+                    self.initex_load_format.into(),
+                );
                 tt_xetex_set_int_variable(
                     b"synctex_enabled\0".as_ptr() as _,
                     self.synctex_enabled.into(),
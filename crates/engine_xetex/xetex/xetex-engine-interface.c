@@ -28,12 +28,16 @@ tt_xetex_set_int_variable (const char *var_name, int value)
         halt_on_error_p = value;
     else if (streq_ptr(var_name, "in_initex_mode"))
         in_initex_mode = (value != 0);
+    else if (streq_ptr(var_name, "initex_load_format"))
+        initex_load_format = (value != 0);
     else if (streq_ptr(var_name, "synctex_enabled"))
         synctex_enabled = (value != 0);
     else if (streq_ptr(var_name, "semantic_pagination_enabled"))
         semantic_pagination_enabled = (value != 0);
     else if (streq_ptr(var_name, "shell_escape_enabled"))
         shell_escape_enabled = (value != 0);
+    else if (streq_ptr(var_name, "shell_escape_restricted"))
+        shell_escape_restricted = (value != 0);
     else
         return 1; /* Uh oh: unrecognized variable */
 
@@ -62,6 +66,8 @@ tt_engine_xetex_main(
         return HISTORY_FATAL_ERROR;
     }
 
+    ttbc_global_set_input_location_func(current_input_location);
+
     /* See ttstub_input_get_mtime() in tectonic_bridge_core about bridging time_t
     * over FFI. */
     rv = tt_run_engine(dump_name, input_file_name, (time_t) build_date);
@@ -18,6 +18,7 @@
 
 /* All the following variables are declared in xetex-xetexd.h */
 bool shell_escape_enabled = false;
+bool shell_escape_restricted = false;
 memory_word *eqtb;
 int32_t bad;
 char *name_of_file;
@@ -29,6 +30,7 @@ int32_t first;
 int32_t last;
 int32_t max_buf_stack;
 bool in_initex_mode;
+bool initex_load_format;
 int32_t error_line;
 int32_t half_error_line;
 int32_t max_print_line;
@@ -3755,7 +3757,10 @@ tt_run_engine(const char *dump_name, const char *input_file_name, time_t build_d
 
     no_new_control_sequence = true;
 
-    if (!in_initex_mode) {
+    /* In initex mode, we usually start from scratch, but we can also build a
+     * new format on top of an existing one, like `tex -ini &fmt` used to. */
+
+    if (!in_initex_mode || initex_load_format) {
         if (!load_fmt_file())
             return history;
     }
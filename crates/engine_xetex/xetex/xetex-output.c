@@ -20,26 +20,39 @@ capture_to_diagnostic(ttbc_diagnostic_t *diagnostic)
     current_diagnostic = diagnostic;
 }
 
-static void
-diagnostic_print_file_line(ttbc_diagnostic_t *diagnostic)
+char *
+current_input_location(int *line_out)
 {
-    // Add file/line number information
     // This duplicates logic from print_file_line
 
     int32_t level = in_open;
     while (level > 0 && full_source_filename_stack[level] == 0)
         level--;
 
-    if (level == 0) {
+    if (level == 0)
+        return NULL;
+
+    *line_out = line;
+    if (level != in_open) {
+        *line_out = line_stack[level + 1];
+    }
+
+    return gettexstring(full_source_filename_stack[level]);
+}
+
+static void
+diagnostic_print_file_line(ttbc_diagnostic_t *diagnostic)
+{
+    // Add file/line number information
+
+    int source_line;
+    char* filename = current_input_location(&source_line);
+
+    if (filename == NULL) {
         ttbc_diag_append(diagnostic, "!");
     } else {
-        int32_t source_line = line;
-        if (level != in_open) {
-            source_line = line_stack[level + 1];
-        }
-
-        char* filename = gettexstring(full_source_filename_stack[level]);
         ttstub_diag_printf(diagnostic, "%s:%d: ", filename, source_line);
+        ttbc_diag_set_location(diagnostic, filename, source_line);
         free(filename);
     }
 }
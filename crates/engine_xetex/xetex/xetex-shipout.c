@@ -76,6 +76,25 @@ dvi_out(eight_bits c)
 }
 
 
+/* Tell the driver that a page has been shipped out, along with the name of
+ * the innermost input file that is being read, if any. */
+static void
+report_page_progress(void)
+{
+    int32_t level = in_open;
+    char *filename = NULL;
+
+    while (level > 0 && full_source_filename_stack[level] == 0)
+        level--;
+
+    if (level > 0)
+        filename = gettexstring(full_source_filename_stack[level]);
+
+    ttstub_report_page_shipped_out(total_pages, filename);
+    free(filename);
+}
+
+
 /*660: output the box `p` */
 void
 ship_out(int32_t p)
@@ -87,6 +106,7 @@ ship_out(int32_t p)
     unsigned char l;
     const char *output_comment = "tectonic";
 
+    ttstub_check_interrupt();
     synctex_sheet(INTPAR(mag));
 
     if (job_name == 0)
@@ -262,6 +282,7 @@ ship_out(int32_t p)
     dvi_out(EOP);
     total_pages++;
     cur_s = -1;
+    report_page_progress();
 
 done:
     /*1518: "Check for LR anomalies at the end of ship_out" */
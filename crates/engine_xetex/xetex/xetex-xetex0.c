@@ -5155,6 +5155,7 @@ check_outer_validity(void)
 void
 get_next(void)
 {
+    static int interrupt_check_counter = 0;
     int32_t k;
     int32_t t;
     unsigned char /*max_char_code */ cat;
@@ -5164,6 +5165,14 @@ get_next(void)
     small_number sup_count;
 
 restart:
+    /* Every so often, give the driver a chance to stop runaway processing.
+     * This is the one place that even the tightest macro loop must pass
+     * through. */
+    if (++interrupt_check_counter >= 4096) {
+        interrupt_check_counter = 0;
+        ttstub_check_interrupt();
+    }
+
     cur_cs = 0;
 
     if (cur_input.state != TOKEN_LIST) { /*355:*/
@@ -7615,8 +7624,10 @@ restart:
                 case PDF_SHELL_ESCAPE_CODE:
                     // 0 if shellescape disabled
                     // 1 if enabled & unrestricted
-                    // 2 if enabled but restricted (which isn't currently supported)
-                    if (shell_escape_enabled) {
+                    // 2 if enabled but restricted
+                    if (shell_escape_enabled && shell_escape_restricted) {
+                        cur_val = 2;
+                    } else if (shell_escape_enabled) {
                         cur_val = 1;
                     } else {
                         cur_val = 0;
@@ -11025,6 +11036,12 @@ start_input(const char *primary_input_name)
     print(full_source_filename_stack[in_open]);
     ttstub_output_flush(rust_stdout);
 
+    {
+        char *filename = gettexstring(full_source_filename_stack[in_open]);
+        ttstub_report_input_opened(filename);
+        free(filename);
+    }
+
     if (INTPAR(tracing_stack_levels) > 0) {
         int32_t v;
 
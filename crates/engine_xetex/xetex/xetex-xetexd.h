@@ -383,6 +383,7 @@ void remember_source_info(str_number, int);
 
 /* All the following variables are defined in xetexini.c */
 extern bool shell_escape_enabled;
+extern bool shell_escape_restricted;
 extern memory_word *eqtb;
 extern int32_t bad;
 extern char *name_of_file;
@@ -394,6 +395,7 @@ extern int32_t first;
 extern int32_t last;
 extern int32_t max_buf_stack;
 extern bool in_initex_mode;
+extern bool initex_load_format;
 extern int32_t error_line;
 extern int32_t half_error_line;
 extern int32_t max_print_line;
@@ -1078,6 +1080,13 @@ ttbc_diagnostic_t *diagnostic_begin_capture_warning_here(void);
 // progress when this function is called, it will be completed and reported.
 void capture_to_diagnostic(ttbc_diagnostic_t *diagnostic);
 
+// Get the name of the current input file, as used in file:line error messages,
+// and store the current line number in *line_out. The name must be freed; if
+// there is no current input file, NULL is returned instead. This is registered
+// with ttbc_global_set_input_location_func() so that the warnings and errors
+// issued with ttstub_issue_warning() and ttstub_issue_error() are located too.
+char *current_input_location(int *line_out);
+
 // A replacement for xetex print_file_line+print_nl_ctr blocks. e.g. Replace
 //
 //     if (file_line_error_style_p)
@@ -29,6 +29,43 @@ pub enum MessageKind {
     Error,
 }
 
+/// A location in a source file that a message pertains to.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct SourceLocation {
+    /// The name of the file, as it was given to the engine's I/O layer when
+    /// the file was opened.
+    pub file: String,
+
+    /// The line number within the file, counting from one, if known.
+    pub line: Option<u32>,
+}
+
+/// Progress made by a processing engine.
+///
+/// Engines report these events through [`StatusBackend::report_progress`] so
+/// that long-running jobs can display how far along they are.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Progress<'a> {
+    /// The engine started reading an input file of the document, such as one
+    /// loaded with `\input`. Support files from the bundle aren't reported.
+    InputOpened {
+        /// The name of the file, as it was given to the engine's I/O layer.
+        name: &'a str,
+    },
+
+    /// The engine finished a page of output.
+    PageShippedOut {
+        /// The number of pages that have been shipped out by this run of the
+        /// engine, including this one.
+        page: u32,
+
+        /// The name of the input file that the engine was reading when the
+        /// page was shipped out, if any.
+        file: Option<&'a str>,
+    },
+}
+
 /// A setting regarding which messages to display.
 #[repr(usize)]
 #[non_exhaustive]
@@ -121,6 +158,43 @@ pub trait StatusBackend {
         )
     }
 
+    /// Report a diagnostic message issued by a processing engine.
+    ///
+    /// Engines such as XeTeX use this method to report warnings and errors
+    /// about the document being processed, as opposed to problems with the
+    /// processing infrastructure itself. If the engine knows which input file
+    /// and line the message pertains to, it is provided as *location*. The
+    /// message text will generally include this information in a
+    /// human-readable form as well, so the default implementation delegates to
+    /// `report()` and ignores *location*.
+    fn report_diagnostic(
+        &mut self,
+        kind: MessageKind,
+        args: Arguments,
+        _location: Option<&SourceLocation>,
+    ) {
+        self.report(kind, args, None)
+    }
+
+    /// Indicate that a processing pass, such as a run of the TeX engine, is
+    /// beginning.
+    ///
+    /// Drivers call this method before launching each engine so that backends
+    /// producing structured output can associate subsequent messages with the
+    /// pass. Drivers also issue human-readable notes about their passes, so the
+    /// default implementation does nothing.
+    fn pass_begin(&mut self, _pass: &str) {}
+
+    /// Indicate that the processing pass most recently announced with
+    /// [`Self::pass_begin`] has ended. The default implementation does nothing.
+    fn pass_end(&mut self, _pass: &str, _success: bool) {}
+
+    /// Report the progress of the current processing pass.
+    ///
+    /// These events can be frequent, so backends should handle them quickly.
+    /// The default implementation does nothing.
+    fn report_progress(&mut self, _progress: &Progress) {}
+
     /// This is used to print TeX engine logs after it encountered errors. This
     /// should print the provided output, which may span many lines, with some
     /// clear delineation.
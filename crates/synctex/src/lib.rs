@@ -0,0 +1,501 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+#![deny(missing_docs)]
+
+//! A reader for SyncTeX files.
+//!
+//! When SyncTeX output is enabled, the TeX engine writes a `.synctex.gz` file
+//! recording where each box, glue, and kern of the typeset document came from
+//! in the input files. This crate parses those files and answers the two
+//! questions that editors need answered to link source and output:
+//!
+//! - *Forward search:* which parts of which pages were typeset from a certain
+//!   line of an input file? See [`SynctexFile::forward_search`].
+//! - *Inverse search:* which input file and line produced the material at a
+//!   certain point on a page? See [`SynctexFile::inverse_search`].
+//!
+//! All positions are measured in PDF points (“big points”, 1/72 of an inch)
+//! from the top left corner of the page, as in the classic `synctex` program.
+
+use flate2::read::MultiGzDecoder;
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::Read,
+    path::{Component, Path},
+};
+use tectonic_errors::prelude::*;
+
+mod parse;
+
+/// The number of TeX scaled points in a PDF point.
+const SP_PER_BP: f64 = 65536.0 * 72.27 / 72.0;
+
+/// The parsed contents of a SyncTeX file.
+#[derive(Clone, Debug)]
+pub struct SynctexFile {
+    inputs: BTreeMap<u32, String>,
+    magnification: i32,
+    unit: i32,
+    pages: Vec<Page>,
+}
+
+/// A region of a page that was typeset from a location in the input.
+///
+/// Coordinates are in PDF points, measured from the top left corner of the
+/// page. The region extends *height* above and *depth* below its baseline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PageBox {
+    /// The page number, starting at 1.
+    pub page: u32,
+
+    /// The position of the left edge of the region.
+    pub x: f64,
+
+    /// The position of the baseline of the region.
+    pub y: f64,
+
+    /// The width of the region.
+    pub width: f64,
+
+    /// The height of the region above its baseline.
+    pub height: f64,
+
+    /// The depth of the region below its baseline.
+    pub depth: f64,
+}
+
+/// A location in one of the input files.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SourceLocation {
+    /// The name of the input file, as recorded by the engine.
+    pub file: String,
+
+    /// The line number, starting at 1.
+    pub line: u32,
+}
+
+#[derive(Clone, Debug)]
+struct Page {
+    number: u32,
+    nodes: Vec<Node>,
+}
+
+/// A record of the page content. Positions and sizes are in SyncTeX units.
+#[derive(Clone, Debug)]
+struct Node {
+    kind: NodeKind,
+    tag: u32,
+    line: u32,
+    h: i32,
+    v: i32,
+    width: i32,
+    height: i32,
+    depth: i32,
+    parent: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum NodeKind {
+    VBox,
+    HBox,
+    VoidVBox,
+    VoidHBox,
+    Rule,
+    Kern,
+    Glue,
+    Math,
+    Current,
+}
+
+/// The extent of a node on the page: left, right, top, and bottom.
+#[derive(Clone, Copy, Debug)]
+struct Extent(i64, i64, i64, i64);
+
+impl Node {
+    fn is_box(&self) -> bool {
+        matches!(
+            self.kind,
+            NodeKind::VBox | NodeKind::HBox | NodeKind::VoidVBox | NodeKind::VoidHBox
+        )
+    }
+
+    fn is_hbox(&self) -> bool {
+        matches!(self.kind, NodeKind::HBox | NodeKind::VoidHBox)
+    }
+
+    fn extent(&self) -> Extent {
+        let h = self.h as i64;
+        let v = self.v as i64;
+        let w = self.width as i64;
+
+        let (a, b, top, bottom) = match self.kind {
+            // Kerns are recorded at the position where they end.
+            NodeKind::Kern => (h - w, h, v, v),
+            NodeKind::Glue | NodeKind::Math | NodeKind::Current => (h, h, v, v),
+            _ => (h, h + w, v - self.height as i64, v + self.depth as i64),
+        };
+
+        // Boxes typeset right-to-left have negative widths.
+        Extent(a.min(b), a.max(b), top, bottom)
+    }
+}
+
+impl Extent {
+    fn contains(&self, x: i64, y: i64) -> bool {
+        x >= self.0 && x <= self.1 && y >= self.2 && y <= self.3
+    }
+
+    fn area(&self) -> i64 {
+        (self.1 - self.0) * (self.3 - self.2)
+    }
+
+    fn x_distance(&self, x: i64) -> i64 {
+        (self.0 - x).max(x - self.1).max(0)
+    }
+
+    fn y_distance(&self, y: i64) -> i64 {
+        (self.2 - y).max(y - self.3).max(0)
+    }
+}
+
+impl Page {
+    /// Get the index of the box that best represents the node with index
+    /// *index*: the node itself if it is a box, otherwise the innermost
+    /// horizontal box containing it.
+    fn representative_box(&self, index: usize) -> usize {
+        if self.nodes[index].is_box() {
+            return index;
+        }
+
+        let mut cur = self.nodes[index].parent;
+
+        while let Some(i) = cur {
+            if self.nodes[i].is_hbox() {
+                return i;
+            }
+
+            cur = self.nodes[i].parent;
+        }
+
+        index
+    }
+}
+
+impl SynctexFile {
+    /// Read a SyncTeX file from disk.
+    ///
+    /// Both compressed (`.synctex.gz`) and uncompressed (`.synctex`) files are
+    /// supported.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = atry!(
+            File::open(path);
+            ["failed to open SyncTeX file `{}`", path.display()]
+        );
+        Ok(atry!(
+            Self::from_reader(file);
+            ["failed to read SyncTeX file `{}`", path.display()]
+        ))
+    }
+
+    /// Read SyncTeX data from a stream, which may be gzip-compressed.
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        if data.starts_with(&[0x1f, 0x8b]) {
+            let mut decompressed = Vec::new();
+            atry!(
+                MultiGzDecoder::new(&data[..]).read_to_end(&mut decompressed);
+                ["failed to decompress SyncTeX data"]
+            );
+            data = decompressed;
+        }
+
+        parse::parse(&String::from_utf8_lossy(&data))
+    }
+
+    /// Get the names of the input files, as recorded by the engine.
+    pub fn inputs(&self) -> impl Iterator<Item = &str> {
+        self.inputs.values().map(|s| s.as_str())
+    }
+
+    /// Get the number of pages described by the file.
+    pub fn n_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Find the regions of the output that were typeset from line *line* of
+    /// the input file *file*.
+    ///
+    /// The file name is matched against the names recorded by the engine. If
+    /// none of them is identical, names whose trailing path components match
+    /// are accepted, so that `chapter.tex` will find `/path/to/chapter.tex`.
+    /// If nothing was typeset from the requested line, the nearest line that
+    /// has output is used instead. The result is empty if no match is found.
+    pub fn forward_search(&self, file: &str, line: u32) -> Vec<PageBox> {
+        let tags = self.tags_for(file);
+
+        // Prefer later lines on ties, since TeX generally only processes text
+        // once it has read the line that follows it.
+        let best = match self
+            .pages
+            .iter()
+            .flat_map(|p| &p.nodes)
+            .filter(|n| tags.contains(&n.tag))
+            .map(|n| n.line)
+            .min_by_key(|&l| (l.abs_diff(line), l < line))
+        {
+            Some(l) => l,
+            None => return Vec::new(),
+        };
+
+        let mut results = Vec::new();
+
+        for page in &self.pages {
+            let mut seen = Vec::new();
+
+            for (i, node) in page.nodes.iter().enumerate() {
+                if node.line != best || !tags.contains(&node.tag) {
+                    continue;
+                }
+
+                let b = page.representative_box(i);
+
+                if !seen.contains(&b) {
+                    seen.push(b);
+                    results.push(self.page_box(page.number, &page.nodes[b]));
+                }
+            }
+        }
+
+        results
+    }
+
+    /// Find the input location that produced the material at the point
+    /// (*x*, *y*) of page *page*.
+    ///
+    /// Page numbers start at 1. If the point is not inside any line of text,
+    /// the nearest material on the page is used. The result is `None` if the
+    /// page doesn't exist or has no content from the input files.
+    pub fn inverse_search(&self, page: u32, x: f64, y: f64) -> Option<SourceLocation> {
+        let page = self.pages.iter().find(|p| p.number == page)?;
+        let scale = self.scale();
+        let px = (x / scale).round() as i64;
+        let py = (y / scale).round() as i64;
+        let known = |n: &&Node| self.inputs.contains_key(&n.tag);
+
+        // Find the smallest line of text containing the point, then the
+        // material within it that is closest to the point.
+        let line_box = page
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| n.is_hbox() && n.extent().contains(px, py))
+            .min_by_key(|(_, n)| n.extent().area());
+
+        let node = if let Some((index, hbox)) = line_box {
+            page.nodes
+                .iter()
+                .filter(|n| n.parent == Some(index))
+                .filter(known)
+                .min_by_key(|n| n.extent().x_distance(px))
+                .or_else(|| Some(hbox).filter(known))
+        } else {
+            None
+        };
+
+        let node = node.or_else(|| {
+            page.nodes.iter().filter(known).min_by_key(|n| {
+                let e = n.extent();
+                e.x_distance(px) + e.y_distance(py)
+            })
+        })?;
+
+        Some(SourceLocation {
+            file: self.inputs[&node.tag].clone(),
+            line: node.line,
+        })
+    }
+
+    /// The size of a SyncTeX unit in PDF points.
+    fn scale(&self) -> f64 {
+        self.unit as f64 * self.magnification as f64 / 1000.0 / SP_PER_BP
+    }
+
+    fn page_box(&self, page: u32, node: &Node) -> PageBox {
+        let scale = self.scale();
+        let Extent(left, right, top, bottom) = node.extent();
+        let v = node.v as i64;
+
+        PageBox {
+            page,
+            x: left as f64 * scale,
+            y: v as f64 * scale,
+            width: (right - left) as f64 * scale,
+            height: (v - top) as f64 * scale,
+            depth: (bottom - v) as f64 * scale,
+        }
+    }
+
+    /// Get the tags of the input files matching *file*.
+    fn tags_for(&self, file: &str) -> Vec<u32> {
+        let exact: Vec<u32> = self
+            .inputs
+            .iter()
+            .filter(|(_, name)| *name == file)
+            .map(|(tag, _)| *tag)
+            .collect();
+
+        if !exact.is_empty() {
+            return exact;
+        }
+
+        self.inputs
+            .iter()
+            .filter(|(_, name)| same_trailing_components(Path::new(name), Path::new(file)))
+            .map(|(tag, _)| *tag)
+            .collect()
+    }
+}
+
+/// Check whether the components of the shorter of two paths match the final
+/// components of the longer one.
+fn same_trailing_components(a: &Path, b: &Path) -> bool {
+    fn components(p: &Path) -> Vec<Component<'_>> {
+        p.components()
+            .filter(|c| !matches!(c, Component::CurDir))
+            .collect()
+    }
+
+    let a = components(a);
+    let b = components(b);
+    let n = a.len().min(b.len());
+    n > 0 && a[a.len() - n..] == b[b.len() - n..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    const SAMPLE: &str = "SyncTeX Version:1
+Input:1:/doc/main.tex
+Input:2:/doc/chapter.tex
+Output:pdf
+Magnification:1000
+Unit:1
+X Offset:0
+Y Offset:0
+Content:
+!100
+{1
+[1,10:4736287,48462073:30785863,43725786,0
+(2,3:4736287,5391647:30785863,282168,0
+x2,3:6374688,5391647
+k2,3:35522150,5391647:29147462
+)
+(2,5:4736287,6391647:30785863,282168,0
+x2,5:6374688,6391647
+g2,6:20000000,6391647
+)
+]
+!200
+}1
+{2
+[1,12:4736287,48462073:30785863,43725786,0
+(1,12:4736287,5391647:30785863,282168,0
+x1,12:6374688,5391647
+)
+]
+}2
+Postamble:
+Count:10
+Post scriptum:
+";
+
+    fn sample() -> SynctexFile {
+        SynctexFile::from_reader(SAMPLE.as_bytes()).unwrap()
+    }
+
+    fn to_bp(sp: i32) -> f64 {
+        sp as f64 / SP_PER_BP
+    }
+
+    #[test]
+    fn parse_basic() {
+        let s = sample();
+        assert_eq!(
+            s.inputs().collect::<Vec<_>>(),
+            ["/doc/main.tex", "/doc/chapter.tex"]
+        );
+        assert_eq!(s.n_pages(), 2);
+        assert_eq!(s.pages[0].nodes.len(), 7);
+        assert_eq!(s.pages[0].nodes[2].parent, Some(1));
+        assert_eq!(s.pages[0].nodes[4].parent, Some(0));
+    }
+
+    #[test]
+    fn parse_gzipped() {
+        let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+        enc.write_all(SAMPLE.as_bytes()).unwrap();
+        let data = enc.finish().unwrap();
+        let s = SynctexFile::from_reader(&data[..]).unwrap();
+        assert_eq!(s.n_pages(), 2);
+    }
+
+    #[test]
+    fn parse_bad() {
+        assert!(SynctexFile::from_reader(&b"hello\n"[..]).is_err());
+        assert!(
+            SynctexFile::from_reader(&b"SyncTeX Version:1\nContent:\n{1\n(1,x:0,0\n"[..]).is_err()
+        );
+    }
+
+    #[test]
+    fn forward() {
+        let s = sample();
+        let boxes = s.forward_search("chapter.tex", 5);
+        assert_eq!(boxes.len(), 1);
+        assert_eq!(boxes[0].page, 1);
+        assert!((boxes[0].x - 72.0).abs() < 1e-3);
+        assert!((boxes[0].y - to_bp(6391647)).abs() < 1e-6);
+        assert!((boxes[0].width - to_bp(30785863)).abs() < 1e-6);
+        assert!((boxes[0].height - to_bp(282168)).abs() < 1e-6);
+
+        // Line 4 has no output, and line 5 is preferred over line 3.
+        assert_eq!(s.forward_search("/doc/chapter.tex", 4), boxes);
+
+        let boxes = s.forward_search("./main.tex", 12);
+        assert_eq!(boxes.len(), 2);
+        assert!(boxes.iter().all(|b| b.page == 2));
+
+        assert!(s.forward_search("other.tex", 1).is_empty());
+    }
+
+    #[test]
+    fn inverse() {
+        let s = sample();
+        let y = to_bp(6391647) - 1.0;
+
+        let loc = s.inverse_search(1, to_bp(6374688) + 1.0, y).unwrap();
+        assert_eq!(loc.file, "/doc/chapter.tex");
+        assert_eq!(loc.line, 5);
+
+        let loc = s.inverse_search(1, to_bp(20000000) + 1.0, y).unwrap();
+        assert_eq!(loc.line, 6);
+
+        // Outside of any line: the nearest material wins.
+        let loc = s.inverse_search(1, 10.0, 10.0).unwrap();
+        assert_eq!(loc.line, 10);
+
+        let loc = s.inverse_search(2, 100.0, 80.0).unwrap();
+        assert_eq!(loc.file, "/doc/main.tex");
+        assert_eq!(loc.line, 12);
+
+        assert!(s.inverse_search(3, 100.0, 80.0).is_none());
+    }
+}
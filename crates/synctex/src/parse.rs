@@ -0,0 +1,179 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Parsing the SyncTeX file format.
+//!
+//! The format is line-oriented. A short header lists the input files and the
+//! units used, then the `Content:` section describes each page as a tree of
+//! boxes containing glue, kerns, and other nodes. Each record gives the input
+//! file and line that the node came from, and its position on the page.
+
+use std::collections::BTreeMap;
+use tectonic_errors::prelude::*;
+
+use crate::{Node, NodeKind, Page, SynctexFile};
+
+pub(crate) fn parse(text: &str) -> Result<SynctexFile> {
+    let mut lines = text.lines().enumerate();
+
+    match lines.next() {
+        Some((_, l)) if l.starts_with("SyncTeX Version:") => {}
+        _ => bail!("this is not a SyncTeX file"),
+    }
+
+    let mut inputs = BTreeMap::new();
+    let mut magnification = 1000;
+    let mut unit = 1;
+    let mut in_content = false;
+    let mut pages = Vec::new();
+    let mut cur_page: Option<Page> = None;
+    let mut box_stack: Vec<usize> = Vec::new();
+
+    for (index, line) in lines {
+        let lineno = index + 1;
+
+        if let Some(rest) = line.strip_prefix("Input:") {
+            let (tag, name) = a_ok_or!(
+                rest.split_once(':');
+                ["malformed input record on line {} of SyncTeX file", lineno]
+            );
+            let tag = atry!(
+                tag.parse::<u32>();
+                ["malformed input tag on line {} of SyncTeX file", lineno]
+            );
+            inputs.insert(tag, name.to_owned());
+            continue;
+        }
+
+        if !in_content {
+            if let Some(rest) = line.strip_prefix("Magnification:") {
+                magnification = atry!(
+                    rest.trim().parse::<i32>();
+                    ["malformed magnification on line {} of SyncTeX file", lineno]
+                );
+            } else if let Some(rest) = line.strip_prefix("Unit:") {
+                unit = atry!(
+                    rest.trim().parse::<i32>();
+                    ["malformed unit on line {} of SyncTeX file", lineno]
+                );
+            } else if line == "Content:" {
+                in_content = true;
+            }
+
+            continue;
+        }
+
+        if line == "Postamble:" {
+            break;
+        }
+
+        let mut chars = line.chars();
+        let code = match chars.next() {
+            Some(c) => c,
+            None => continue,
+        };
+        let rest = chars.as_str();
+
+        let (kind, n_sizes) = match code {
+            '{' => {
+                let number = atry!(
+                    rest.parse::<u32>();
+                    ["malformed sheet record on line {} of SyncTeX file", lineno]
+                );
+                cur_page = Some(Page {
+                    number,
+                    nodes: Vec::new(),
+                });
+                box_stack.clear();
+                continue;
+            }
+
+            '}' => {
+                if let Some(page) = cur_page.take() {
+                    pages.push(page);
+                }
+                continue;
+            }
+
+            ']' | ')' => {
+                box_stack.pop();
+                continue;
+            }
+
+            '[' => (NodeKind::VBox, 3),
+            '(' => (NodeKind::HBox, 3),
+            'v' => (NodeKind::VoidVBox, 3),
+            'h' => (NodeKind::VoidHBox, 3),
+            'r' => (NodeKind::Rule, 3),
+            'k' => (NodeKind::Kern, 1),
+            'g' => (NodeKind::Glue, 0),
+            '$' => (NodeKind::Math, 0),
+            'x' => (NodeKind::Current, 0),
+
+            // Anchors, forms, and anything that we don't know about.
+            _ => continue,
+        };
+
+        let page = match cur_page.as_mut() {
+            Some(p) => p,
+            None => continue,
+        };
+
+        let mut node = a_ok_or!(
+            parse_record(kind, rest, n_sizes);
+            ["malformed content record on line {} of SyncTeX file", lineno]
+        );
+        node.parent = box_stack.last().copied();
+        page.nodes.push(node);
+
+        if matches!(kind, NodeKind::VBox | NodeKind::HBox) {
+            box_stack.push(page.nodes.len() - 1);
+        }
+    }
+
+    ensure!(in_content, "the SyncTeX file has no content section");
+
+    Ok(SynctexFile {
+        inputs,
+        magnification,
+        unit,
+        pages,
+    })
+}
+
+/// Parse the part of a content record after its type code, which looks like
+/// `tag,line:h,v` followed by *n_sizes* comma-separated sizes. Newer versions
+/// of SyncTeX may add a column number after the line, which we ignore.
+fn parse_record(kind: NodeKind, text: &str, n_sizes: usize) -> Option<Node> {
+    let mut fields = text.split(':');
+
+    let mut link = fields.next()?.split(',');
+    let tag = link.next()?.parse().ok()?;
+    let line = link.next()?.parse().ok()?;
+
+    let mut pos = fields.next()?.split(',');
+    let h = pos.next()?.parse().ok()?;
+    let v = pos.next()?.parse().ok()?;
+
+    let mut sizes = [0; 3];
+
+    if n_sizes > 0 {
+        let mut values = fields.next()?.split(',');
+
+        for size in &mut sizes[..n_sizes] {
+            *size = values.next()?.parse().ok()?;
+        }
+    }
+
+    Some(Node {
+        kind,
+        tag,
+        line,
+        h,
+        v,
+        width: sizes[0],
+        height: sizes[1],
+        depth: sizes[2],
+        parent: None,
+    })
+}
@@ -0,0 +1,361 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Random access to the pages of XDV and SPX files.
+
+use byteorder::{BigEndian, ByteOrder};
+use std::{
+    collections::HashMap,
+    io::{Error as IoError, Read, Seek, SeekFrom},
+};
+
+use crate::{FileType, IdByte, Opcode, ParserMode, ParserState, XdvError, XdvEvents, XdvParser};
+
+/// The number of bytes at the end of the file that are examined to find the
+/// post-postamble.
+const EOF_WORK_SIZE: usize = 16;
+
+/// An index of the pages of an XDV or SPX file.
+///
+/// Building the index only requires reading the beginning and end of the
+/// file: the postamble points to the last page, and each page points to the
+/// one before it. Individual pages can then be processed with
+/// [`Self::process_page`] without parsing the rest of the file, which makes
+/// it cheap to preview or convert a few pages of a long document.
+#[derive(Clone, Debug)]
+pub struct XdvPageIndex {
+    filetype: FileType,
+    comment: Vec<u8>,
+    pages: Vec<PageInfo>,
+    fonts: HashMap<i32, (u64, Vec<u8>)>,
+}
+
+/// Information about one page of the file.
+#[derive(Clone, Debug)]
+struct PageInfo {
+    offset: u64,
+    counters: [i32; 10],
+}
+
+impl XdvPageIndex {
+    /// Build the index of a seekable XDV/SPX stream.
+    ///
+    /// This reads the preamble, the postamble, and the header of each page.
+    pub fn new<R: Read + Seek>(mut stream: R) -> Result<Self, IoError> {
+        // The preamble gives us the file type and the comment.
+
+        stream.rewind()?;
+        let mut buf = [0; 15];
+        stream.read_exact(&mut buf)?;
+
+        if buf[0] != Opcode::Preamble as u8 {
+            return Err(XdvError::Malformed(0).into());
+        }
+
+        let filetype = match buf[1] {
+            b if b == IdByte::Xdv as u8 => FileType::Xdv,
+            b if b == IdByte::Spx as u8 => FileType::Spx,
+            _ => return Err(XdvError::Malformed(1).into()),
+        };
+
+        let mut comment = vec![0; buf[14] as usize];
+        stream.read_exact(&mut comment)?;
+
+        // The post-postamble gives us the location of the postamble.
+
+        let mut buf = [0; EOF_WORK_SIZE];
+        let offset = stream.seek(SeekFrom::End(-(EOF_WORK_SIZE as i64)))?;
+        stream.read_exact(&mut buf)?;
+
+        let mut delta = EOF_WORK_SIZE - 1;
+
+        while buf[delta] == 0xDF && delta > 0 {
+            delta -= 1;
+        }
+
+        if !(6..=EOF_WORK_SIZE - 4).contains(&delta) {
+            return Err(XdvError::Malformed(offset + delta as u64).into());
+        }
+
+        delta -= 5; // 4 bytes for offset, 1 for opcode
+
+        if buf[delta] != Opcode::DoublePostamble as u8 {
+            return Err(XdvError::Malformed(offset + delta as u64).into());
+        }
+
+        let postamble_offset = u64::from(BigEndian::read_u32(&buf[delta + 1..]));
+
+        // The postamble gives us the font definitions, and the location of
+        // the last page.
+
+        stream.seek(SeekFrom::Start(postamble_offset))?;
+        let mut parser = XdvParser::new(FontCollector::default());
+        parser.filetype = filetype;
+        parser.offset = postamble_offset;
+        parser.mode = ParserMode::UntilDoublePostamble;
+        parser.state = ParserState::BetweenPages;
+        parser.process_part(&mut stream)?;
+
+        if parser.state != ParserState::PostambleFontDefinitions {
+            return Err(XdvError::Malformed(parser.offset).into());
+        }
+
+        let fonts = parser.events.fonts;
+
+        let mut buf = [0; 5];
+        stream.seek(SeekFrom::Start(postamble_offset))?;
+        stream.read_exact(&mut buf)?;
+        let mut bop = BigEndian::read_i32(&buf[1..]);
+
+        // Finally, follow the chain of pointers from each page to the
+        // previous one.
+
+        let mut pages = Vec::new();
+        let mut limit = postamble_offset;
+        let mut buf = [0; 45];
+
+        while bop != -1 {
+            let offset = bop as u64;
+
+            // The pointers must go backwards, or we could loop forever.
+            if bop < 0 || offset >= limit {
+                return Err(XdvError::Malformed(limit).into());
+            }
+
+            stream.seek(SeekFrom::Start(offset))?;
+            stream.read_exact(&mut buf)?;
+
+            if buf[0] != Opcode::BeginningOfPage as u8 {
+                return Err(XdvError::Malformed(offset).into());
+            }
+
+            let mut counters = [0; 10];
+            BigEndian::read_i32_into(&buf[1..41], &mut counters);
+            pages.push(PageInfo { offset, counters });
+
+            limit = offset;
+            bop = BigEndian::read_i32(&buf[41..]);
+        }
+
+        pages.reverse();
+
+        Ok(XdvPageIndex {
+            filetype,
+            comment,
+            pages,
+            fonts,
+        })
+    }
+
+    /// Get the type of the file.
+    pub fn filetype(&self) -> FileType {
+        self.filetype
+    }
+
+    /// Get the number of pages in the file.
+    pub fn n_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Get the byte offset of the page with the zero-based index *page*.
+    pub fn page_offset(&self, page: usize) -> Option<u64> {
+        self.pages.get(page).map(|p| p.offset)
+    }
+
+    /// Get the values of the ten TeX counters `\count0` through `\count9`
+    /// recorded for the page with the zero-based index *page*. The first of
+    /// these is usually the page number that is printed on the page.
+    pub fn page_counters(&self, page: usize) -> Option<&[i32]> {
+        self.pages.get(page).map(|p| &p.counters[..])
+    }
+
+    /// Process the page with the zero-based index *page* of *stream*, which
+    /// must be the stream that was used to build this index.
+    ///
+    /// The events are reported as if the file only contained that page: first
+    /// the header, then the page itself. Fonts that are used on the page but
+    /// defined earlier in the file are reported when they are first selected,
+    /// so that *events* sees the definitions of exactly the fonts that the page
+    /// uses.
+    ///
+    /// Returns the input “events” variable.
+    pub fn process_page<R: Read + Seek, T: XdvEvents>(
+        &self,
+        mut stream: R,
+        page: usize,
+        events: T,
+    ) -> Result<T, T::Error>
+    where
+        T::Error: From<IoError>,
+    {
+        let offset = match self.page_offset(page) {
+            Some(o) => o,
+            None => return Err(XdvError::NoSuchPage(page).into()),
+        };
+
+        let mut parser = XdvParser::new(events);
+        parser.filetype = self.filetype;
+        parser.events.handle_header(self.filetype, &self.comment)?;
+
+        stream.seek(SeekFrom::Start(offset))?;
+        parser.offset = offset;
+        parser.mode = ParserMode::UntilEndOfPage;
+        parser.state = ParserState::BetweenPages;
+        parser.pending_fonts = self.fonts.clone();
+        parser.process_part(&mut stream)?;
+
+        if parser.state != ParserState::BetweenPages {
+            return Err(XdvError::UnexpectedEndOfStream.into());
+        }
+
+        parser.state = ParserState::Finished;
+        parser.finish()
+    }
+}
+
+/// Collects the raw font definitions in the postamble.
+#[derive(Debug, Default)]
+struct FontCollector {
+    fonts: HashMap<i32, (u64, Vec<u8>)>,
+    font_num: i32,
+}
+
+impl XdvEvents for FontCollector {
+    type Error = IoError;
+
+    fn handle_define_font(
+        &mut self,
+        _area: &str,
+        _name: &str,
+        font_num: i32,
+        _checksum: u32,
+        _scale_factor: u32,
+        _design_size: u32,
+    ) -> Result<(), Self::Error> {
+        self.font_num = font_num;
+        Ok(())
+    }
+
+    fn handle_define_native_font(
+        &mut self,
+        _name: &str,
+        font_num: i32,
+        _size: i32,
+        _face_index: u32,
+        _color_rgba: Option<u32>,
+        _extend: Option<u32>,
+        _slant: Option<u32>,
+        _embolden: Option<u32>,
+    ) -> Result<(), Self::Error> {
+        self.font_num = font_num;
+        Ok(())
+    }
+
+    fn handle_command(
+        &mut self,
+        offset: u64,
+        command: &[u8],
+        _h: i32,
+        _v: i32,
+    ) -> Result<(), Self::Error> {
+        let opcode = command[0];
+
+        if (opcode >= Opcode::DefineFont1 as u8 && opcode <= Opcode::DefineFont4 as u8)
+            || opcode == Opcode::DefineNativeFont as u8
+        {
+            self.fonts
+                .insert(self.font_num, (offset, command.to_owned()));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{sample, Recorder};
+    use std::io::Cursor;
+
+    fn page_events(data: &[u8], index: &XdvPageIndex, page: usize) -> Vec<String> {
+        index
+            .process_page(Cursor::new(data), page, Recorder::default())
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn index() {
+        let data = sample();
+        let index = XdvPageIndex::new(Cursor::new(&data)).unwrap();
+        assert_eq!(index.filetype(), FileType::Xdv);
+        assert_eq!(index.n_pages(), 3);
+        assert_eq!(index.page_counters(1).unwrap()[..3], [2, 0, 7]);
+        assert_eq!(index.page_offset(3), None);
+
+        for page in 0..3 {
+            let offset = index.page_offset(page).unwrap() as usize;
+            assert_eq!(data[offset], Opcode::BeginningOfPage as u8);
+        }
+    }
+
+    #[test]
+    fn single_pages() {
+        let data = sample();
+        let index = XdvPageIndex::new(Cursor::new(&data)).unwrap();
+        let cmr10 = "font cmr10 0 1234 655360 655360";
+        let lmroman12 = "native lmroman12-regular.otf 301 786432 0 None None None None";
+
+        // The fonts defined before the first page are reported when they are
+        // selected.
+        let events = page_events(&data, &index, 0);
+        assert_eq!(events[0], "header XDV [116, 101, 115, 116]");
+        assert_eq!(events[1], "bop [1, 0, 0, 0, 0, 0, 0, 0, 0, 0] -1");
+        assert_eq!(events[2], cmr10);
+        assert_eq!(events[3], "chars 0 [72, 105, 200] 100 2000");
+        assert_eq!(
+            events[8],
+            "native lmroman10-regular.otf 300 655360 0 None Some(70000) None None"
+        );
+        assert_eq!(events[9], "glyphs 300 [5, 6] [100, 550] [42000, 42000]");
+
+        // Fonts defined on the page are only reported once.
+        let events = page_events(&data, &index, 1);
+        assert_eq!(events[2], cmr10);
+        assert_eq!(events.iter().filter(|e| *e == lmroman12).count(), 1);
+        assert_eq!(events.iter().filter(|e| e.starts_with("native")).count(), 1);
+
+        // And fonts defined on earlier pages are available.
+        assert_eq!(
+            page_events(&data, &index, 2)[1..],
+            [
+                &format!(
+                    "bop [3, 0, 0, 0, 0, 0, 0, 0, 0, 0] {}",
+                    index.page_offset(1).unwrap()
+                ),
+                lmroman12,
+                "glyphs 301 [10] [100] [2000]",
+                "eop",
+            ]
+        );
+
+        let err = index
+            .process_page(Cursor::new(&data), 3, Recorder::default())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("no page with index 3"));
+    }
+
+    #[test]
+    fn malformed() {
+        let mut data = sample();
+        assert!(XdvPageIndex::new(Cursor::new(&data[..40])).is_err());
+
+        // Make the last page point to itself.
+        let index = XdvPageIndex::new(Cursor::new(&data)).unwrap();
+        let last = index.page_offset(2).unwrap() as usize;
+        let me = (last as u32).to_be_bytes();
+        data[last + 41..last + 45].copy_from_slice(&me);
+        assert!(XdvPageIndex::new(Cursor::new(&data)).is_err());
+    }
+}
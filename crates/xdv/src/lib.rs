@@ -3,7 +3,8 @@
 
 #![deny(missing_docs)]
 
-//! A decoder for the XDV and SPX file formats used by Tectonic and XeTeX.
+//! A decoder and encoder for the XDV and SPX file formats used by Tectonic and
+//! XeTeX.
 //!
 //! Both of these file formats are derived from the venerable “device
 //! independent” (DVI) format used by TeX. The XDV format (name presumably
@@ -12,9 +13,19 @@
 //! (“semantically-paginated XDV”) is essentially the same as XDV, but
 //! expresses output that is not paginated for print — this is what Tectonic
 //! uses to produce its HTML output.
+//!
+//! Files are decoded with [`XdvParser`], which reports their contents to an
+//! implementation of [`XdvEvents`]. They can be written with [`XdvWriter`],
+//! which itself implements [`XdvEvents`] so that a file can be filtered or
+//! rewritten by passing the parser's events through to a writer.
+//!
+//! To work with individual pages of a long file, [`XdvPageIndex`] locates the
+//! pages using the pointers stored in the file, so that any one of them can be
+//! processed without parsing the others.
 
 use byteorder::{BigEndian, ByteOrder};
 use std::{
+    collections::HashMap,
     error,
     fmt::{Debug, Display, Error as FmtError, Formatter},
     io::{Error as IoError, Read, Seek, SeekFrom},
@@ -22,6 +33,14 @@ use std::{
     mem,
 };
 
+mod index;
+#[cfg(test)]
+mod test_util;
+mod writer;
+
+pub use index::XdvPageIndex;
+pub use writer::XdvWriter;
+
 /// Errors that can occur when parsing XDV/SPX files.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum XdvError {
@@ -41,6 +60,10 @@ pub enum XdvError {
 
     /// A sequence that should have been UTF16 text was invalid.
     FromUTF16(u64),
+
+    /// A page was requested that the file doesn't contain. The value is the
+    /// zero-based index of the page.
+    NoSuchPage(usize),
 }
 
 impl Display for XdvError {
@@ -59,6 +82,9 @@ impl Display for XdvError {
             XdvError::FromUTF16(offset) => {
                 write!(f, "illegal UTF16 sequence starting at byte offset {offset}")
             }
+            XdvError::NoSuchPage(index) => {
+                write!(f, "there is no page with index {index} in the XDV data")
+            }
         }
     }
 }
@@ -71,6 +97,7 @@ impl error::Error for XdvError {
             XdvError::UnexpectedEndOfStream => "stream ended unexpectedly soon",
             XdvError::FromUTF8(_) => "illegal UTF8 sequence",
             XdvError::FromUTF16(_) => "illegal UTF16 sequence",
+            XdvError::NoSuchPage(_) => "no such page",
         }
     }
 
@@ -86,6 +113,13 @@ impl From<XdvError> for String {
     }
 }
 
+/// This lets [`XdvWriter`] be used as an event handler for [`XdvParser`].
+impl From<XdvError> for IoError {
+    fn from(e: XdvError) -> Self {
+        IoError::new(std::io::ErrorKind::InvalidData, e)
+    }
+}
+
 impl XdvError {
     /// We can't implement this as a From trait on InternalError because it
     /// conflicts with the generic From: XdvError satisfies `Debug +
@@ -136,6 +170,24 @@ pub trait XdvEvents {
         Ok(())
     }
 
+    /// End the current page.
+    #[allow(unused)]
+    fn handle_end_page(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Save the current position on the stack.
+    #[allow(unused)]
+    fn handle_push(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Restore the position most recently saved on the stack.
+    #[allow(unused)]
+    fn handle_pop(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
     /// Handle a `\special`.
     #[allow(unused)]
     fn handle_special(&mut self, x: i32, y: i32, contents: &[u8]) -> Result<(), Self::Error> {
@@ -148,6 +200,23 @@ pub trait XdvEvents {
         Ok(())
     }
 
+    /// Handle a sequence of characters without intervening commands, starting
+    /// at the position (*x*, *y*).
+    ///
+    /// The parser doesn't know the widths of the characters, so the current
+    /// position is not advanced as they are set. By default, this method calls
+    /// [`Self::handle_char_run`].
+    #[allow(unused)]
+    fn handle_positioned_char_run(
+        &mut self,
+        font_num: i32,
+        chars: &[i32],
+        x: i32,
+        y: i32,
+    ) -> Result<(), Self::Error> {
+        self.handle_char_run(font_num, chars)
+    }
+
     /// Handle a sequence of glyphs.
     #[allow(unused)]
     fn handle_glyph_run(
@@ -174,6 +243,20 @@ pub trait XdvEvents {
         Ok(())
     }
 
+    /// Handle the definition of a traditional TeX font
+    #[allow(unused)]
+    fn handle_define_font(
+        &mut self,
+        area: &str,
+        name: &str,
+        font_num: i32,
+        checksum: u32,
+        scale_factor: u32,
+        design_size: u32,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
     /// Handle the definition of a native font
     #[allow(unused)]
     #[allow(clippy::too_many_arguments)]
@@ -196,6 +279,67 @@ pub trait XdvEvents {
     fn handle_rule(&mut self, x: i32, y: i32, height: i32, width: i32) -> Result<(), Self::Error> {
         Ok(())
     }
+
+    /// Handle any command, once it has been processed.
+    ///
+    /// This is called for every command in the file, after any of the more
+    /// specific handlers above. *offset* is the position of the command in the
+    /// file, *command* contains all of its bytes, starting with the opcode, and
+    /// *h* and *v* give the current position after the command has taken
+    /// effect, or zero outside of pages. This is mainly useful for tools that
+    /// need to show the low-level structure of a file.
+    #[allow(unused)]
+    fn handle_command(
+        &mut self,
+        offset: u64,
+        command: &[u8],
+        h: i32,
+        v: i32,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Get the name of an XDV opcode, as used by the `dvitype` program.
+///
+/// The opcodes that XeTeX adds are named after the constants in its source
+/// code. Returns `None` if the opcode is undefined.
+pub fn opcode_name(opcode: u8) -> Option<String> {
+    let sized = |base: &str, first: u8| format!("{}{}", base, opcode - first + 1);
+
+    Some(match opcode {
+        0..=127 => format!("setchar{}", opcode),
+        128..=131 => sized("set", 128),
+        132 => "setrule".to_owned(),
+        133..=136 => sized("put", 133),
+        137 => "putrule".to_owned(),
+        138 => "nop".to_owned(),
+        139 => "bop".to_owned(),
+        140 => "eop".to_owned(),
+        141 => "push".to_owned(),
+        142 => "pop".to_owned(),
+        143..=146 => sized("right", 143),
+        147 => "w0".to_owned(),
+        148..=151 => sized("w", 148),
+        152 => "x0".to_owned(),
+        153..=156 => sized("x", 153),
+        157..=160 => sized("down", 157),
+        161 => "y0".to_owned(),
+        162..=165 => sized("y", 162),
+        166 => "z0".to_owned(),
+        167..=170 => sized("z", 167),
+        171..=234 => format!("fntnum{}", opcode - 171),
+        235..=238 => sized("fnt", 235),
+        239..=242 => sized("xxx", 239),
+        243..=246 => sized("fntdef", 243),
+        247 => "pre".to_owned(),
+        248 => "post".to_owned(),
+        249 => "post_post".to_owned(),
+        252 => "native_font_def".to_owned(),
+        253 => "glyphs".to_owned(),
+        254 => "text_and_glyphs".to_owned(),
+        _ => return None,
+    })
 }
 
 /// State for parsing an XDV file.
@@ -209,6 +353,8 @@ pub struct XdvParser<T: XdvEvents> {
     cur_font_num: i32,
     offset: u64,
     cur_char_run: Vec<i32>,
+    cur_char_run_pos: (i32, i32),
+    pending_fonts: HashMap<i32, (u64, Vec<u8>)>,
 }
 
 /// Which type of file is being parsed.
@@ -239,6 +385,7 @@ impl Display for FileType {
 enum ParserMode {
     AllTheWayThrough,
     UntilDoublePostamble,
+    UntilEndOfPage,
     UntilPostamble,
 }
 
@@ -276,6 +423,8 @@ impl<T: XdvEvents> XdvParser<T> {
             cur_font_num: 0,
             offset: 0,
             cur_char_run: Vec::new(),
+            cur_char_run_pos: (0, 0),
+            pending_fonts: HashMap::new(),
         }
     }
 
@@ -460,24 +609,33 @@ impl<T: XdvEvents> XdvParser<T> {
 
             // OK, no early exit.
 
+            let start = cursor.offset;
             cursor.get_u8().unwrap(); // consume the opcode
-            let mut char_run_ended = true; // most commands end runs of characters
+
+            // Most commands end runs of characters. The run is reported before
+            // the command is processed, so that events arrive in file order.
+            // This includes pushes: a writer driven by the events must set the
+            // characters before it saves the position that they moved.
+            let char_run_continues = opcode == Opcode::Noop as u8
+                || (opcode >= Opcode::DefineFont1 as u8 && opcode <= Opcode::DefineFont4 as u8)
+                || opcode == Opcode::DefineNativeFont as u8
+                || opcode <= Opcode::SetCharNumber127 as u8
+                || (opcode >= Opcode::SetChar1 as u8 && opcode <= Opcode::SetChar4 as u8);
+
+            if !char_run_continues {
+                self.flush_char_run()?;
+            }
 
             let rv = match opcode {
                 // This is the least ugly way I've found to map the u8 to the
                 // symbolic enum values.
-                oc if oc == Opcode::Noop as u8 => {
-                    char_run_ended = false;
-                    Ok(())
-                }
+                oc if oc == Opcode::Noop as u8 => Ok(()),
 
                 oc if oc >= Opcode::DefineFont1 as u8 && oc <= Opcode::DefineFont4 as u8 => {
-                    char_run_ended = false;
                     self.do_define_font(oc, &mut cursor)
                 }
 
                 oc if oc == Opcode::DefineNativeFont as u8 => {
-                    char_run_ended = false;
                     self.do_define_native_font(oc, &mut cursor)
                 }
 
@@ -487,10 +645,7 @@ impl<T: XdvEvents> XdvParser<T> {
 
                 oc if oc == Opcode::EndOfPage as u8 => self.do_end_of_page(oc, &mut cursor),
 
-                oc if oc == Opcode::PushStack as u8 => {
-                    char_run_ended = false;
-                    self.do_push_stack(oc, &mut cursor)
-                }
+                oc if oc == Opcode::PushStack as u8 => self.do_push_stack(oc, &mut cursor),
 
                 oc if oc == Opcode::PopStack as u8 => self.do_pop_stack(oc, &mut cursor),
 
@@ -537,12 +692,10 @@ impl<T: XdvEvents> XdvParser<T> {
                 oc if oc >= Opcode::SetCharNumber0 as u8
                     && oc <= Opcode::SetCharNumber127 as u8 =>
                 {
-                    char_run_ended = false;
                     self.do_set_char_number(oc, &mut cursor)
                 }
 
                 oc if oc >= Opcode::SetChar1 as u8 && oc <= Opcode::SetChar4 as u8 => {
-                    char_run_ended = false;
                     self.do_set_char(oc, &mut cursor)
                 }
 
@@ -575,7 +728,23 @@ impl<T: XdvEvents> XdvParser<T> {
 
             match rv {
                 Ok(()) => {
+                    let (h, v) = match (self.state, self.stack.last()) {
+                        (ParserState::InPage, Some(state)) => (state.h, state.v),
+                        _ => (0, 0),
+                    };
+                    self.events.handle_command(
+                        self.offset + start as u64,
+                        &chunk[start..cursor.offset],
+                        h,
+                        v,
+                    )?;
                     cursor.checkpoint(); // Opcode was successfully processed.
+
+                    if opcode == Opcode::EndOfPage as u8 && self.mode == ParserMode::UntilEndOfPage
+                    {
+                        keep_going = false;
+                        break;
+                    }
                 }
                 Err(InternalError::NeedMoreData) => {
                     break;
@@ -584,18 +753,24 @@ impl<T: XdvEvents> XdvParser<T> {
                     return Err(e);
                 }
             }
-
-            if char_run_ended && !self.cur_char_run.is_empty() {
-                self.events
-                    .handle_char_run(self.cur_font_num, &self.cur_char_run)?;
-                self.cur_char_run.clear();
-            }
         }
 
         self.offset += cursor.checkpoint as u64;
         Ok((cursor.checkpoint, keep_going))
     }
 
+    /// Report the pending run of characters, if any.
+    fn flush_char_run(&mut self) -> Result<(), T::Error> {
+        if !self.cur_char_run.is_empty() {
+            let (x, y) = self.cur_char_run_pos;
+            self.events
+                .handle_positioned_char_run(self.cur_font_num, &self.cur_char_run, x, y)?;
+            self.cur_char_run.clear();
+        }
+
+        Ok(())
+    }
+
     fn do_preamble(&mut self, opcode: u8, cursor: &mut Cursor<T>) -> InternalResult<(), T::Error> {
         if self.state != ParserState::Preamble {
             return Err(XdvError::IllegalOpcode(opcode, cursor.global_offset()).into_internal());
@@ -629,26 +804,37 @@ impl<T: XdvEvents> XdvParser<T> {
             return Err(XdvError::IllegalOpcode(opcode, cursor.global_offset()).into_internal());
         }
 
-        let _font_num = cursor.get_compact_i32_smpos(opcode - Opcode::DefineFont1 as u8)?;
-        let _checksum = cursor.get_u32()?;
-        let _scale_factor = cursor.get_u32()?;
-        let _design_size = cursor.get_u32()?;
+        let font_num = cursor.get_compact_i32_smpos(opcode - Opcode::DefineFont1 as u8)?;
+        let checksum = cursor.get_u32()?;
+        let scale_factor = cursor.get_u32()?;
+        let design_size = cursor.get_u32()?;
         let area_len = cursor.get_u8()?;
         let name_len = cursor.get_u8()?;
 
-        // TODO: figure out what to do with these. In Tectonic's context,
-        // non-"native" font definitions are a bad sign, since they correspond
-        // to fonts that we wouldn't be able to express in HTML. But note that
-        // this crate should support generic XDV decoding, not necessarily
-        // targeting HTML, as best it can.
+        // In Tectonic's context, non-"native" font definitions are a bad
+        // sign, since they correspond to fonts that we wouldn't be able to
+        // express in HTML. But this crate should support generic XDV decoding,
+        // not necessarily targeting HTML, as best it can.
 
         use std::str::from_utf8;
-        let _area_str = from_utf8(cursor.get_slice(area_len as usize)?)
-            .unwrap()
+        let offset = cursor.global_offset();
+        let area_str = from_utf8(cursor.get_slice(area_len as usize)?)
+            .map_err(|_| XdvError::FromUTF8(offset).into_internal())?
             .to_owned();
-        let _name_str = from_utf8(cursor.get_slice(name_len as usize)?)
-            .unwrap()
+        let offset = cursor.global_offset();
+        let name_str = from_utf8(cursor.get_slice(name_len as usize)?)
+            .map_err(|_| XdvError::FromUTF8(offset).into_internal())?
             .to_owned();
+
+        self.pending_fonts.remove(&font_num);
+        self.events.handle_define_font(
+            &area_str,
+            &name_str,
+            font_num,
+            checksum,
+            scale_factor,
+            design_size,
+        )?;
         Ok(())
     }
 
@@ -697,6 +883,7 @@ impl<T: XdvEvents> XdvParser<T> {
             None
         };
 
+        self.pending_fonts.remove(&font_num);
         self.events.handle_define_native_font(
             &name_str, font_num, size, face_index, color_rgba, extend, slant, embolden,
         )?;
@@ -741,6 +928,7 @@ impl<T: XdvEvents> XdvParser<T> {
             return Err(XdvError::Malformed(cursor.global_offset()).into_internal());
         }
 
+        self.events.handle_end_page()?;
         self.state = ParserState::BetweenPages;
         Ok(())
     }
@@ -756,6 +944,7 @@ impl<T: XdvEvents> XdvParser<T> {
 
         let dup = self.stack.last().unwrap().clone();
         self.stack.push(dup);
+        self.events.handle_push()?;
         Ok(())
     }
 
@@ -769,6 +958,7 @@ impl<T: XdvEvents> XdvParser<T> {
         }
 
         self.stack.pop();
+        self.events.handle_pop()?;
         Ok(())
     }
 
@@ -898,16 +1088,8 @@ impl<T: XdvEvents> XdvParser<T> {
             return Err(XdvError::IllegalOpcode(opcode, cursor.global_offset()).into_internal());
         }
 
-        let new_font_num = i32::from(opcode - Opcode::SetFontNumber0 as u8);
-
-        if new_font_num != self.cur_font_num && !self.cur_char_run.is_empty() {
-            self.events
-                .handle_char_run(self.cur_font_num, &self.cur_char_run)?;
-            self.cur_char_run.clear();
-        }
-
-        self.cur_font_num = new_font_num;
-        Ok(())
+        self.cur_font_num = i32::from(opcode - Opcode::SetFontNumber0 as u8);
+        self.define_pending_font(self.cur_font_num)
     }
 
     /// This variant takes an argument that encodes the font number.
@@ -916,16 +1098,35 @@ impl<T: XdvEvents> XdvParser<T> {
             return Err(XdvError::IllegalOpcode(opcode, cursor.global_offset()).into_internal());
         }
 
-        let new_font_num = cursor.get_compact_i32_smpos(opcode - Opcode::SetFont1 as u8)?;
+        self.cur_font_num = cursor.get_compact_i32_smpos(opcode - Opcode::SetFont1 as u8)?;
+        self.define_pending_font(self.cur_font_num)
+    }
 
-        if new_font_num != self.cur_font_num && !self.cur_char_run.is_empty() {
-            self.events
-                .handle_char_run(self.cur_font_num, &self.cur_char_run)?;
-            self.cur_char_run.clear();
-        }
+    /// When a single page is being processed, the fonts that it uses may have
+    /// been defined on earlier pages. Their definitions are copied from the
+    /// postamble, and are reported the first time that each font is selected.
+    fn define_pending_font(&mut self, font_num: i32) -> InternalResult<(), T::Error> {
+        let (offset, def) = match self.pending_fonts.remove(&font_num) {
+            Some(d) => d,
+            None => return Ok(()),
+        };
 
-        self.cur_font_num = new_font_num;
-        Ok(())
+        let mut cursor = Cursor::<T>::new(&def, offset);
+
+        let rv = match cursor.get_u8() {
+            Ok(oc) if oc == Opcode::DefineNativeFont as u8 => {
+                self.do_define_native_font(oc, &mut cursor)
+            }
+            Ok(oc) => self.do_define_font(oc, &mut cursor),
+            Err(e) => Err(e),
+        };
+
+        // The definition is complete, so running out of data means that it's
+        // malformed, not that more needs to be read.
+        match rv {
+            Err(InternalError::NeedMoreData) => Err(XdvError::Malformed(offset).into_internal()),
+            other => other,
+        }
     }
 
     /// This variant uses the opcode to encode the character number.
@@ -939,7 +1140,7 @@ impl<T: XdvEvents> XdvParser<T> {
         }
 
         let char_num = opcode - Opcode::SetCharNumber0 as u8;
-        self.cur_char_run.push(i32::from(char_num));
+        self.start_char(i32::from(char_num));
         Ok(())
     }
 
@@ -950,10 +1151,21 @@ impl<T: XdvEvents> XdvParser<T> {
         }
 
         let char_num = cursor.get_compact_i32_smpos(opcode - Opcode::SetChar1 as u8)?;
-        self.cur_char_run.push(char_num);
+        self.start_char(char_num);
         Ok(())
     }
 
+    /// Add a character to the current run, noting the position where the run
+    /// starts.
+    fn start_char(&mut self, char_num: i32) {
+        if self.cur_char_run.is_empty() {
+            let state = self.stack.last().unwrap();
+            self.cur_char_run_pos = (state.h, state.v);
+        }
+
+        self.cur_char_run.push(char_num);
+    }
+
     fn do_set_glyphs(
         &mut self,
         opcode: u8,
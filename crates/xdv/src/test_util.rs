@@ -0,0 +1,184 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Helpers shared by the tests of this crate.
+
+use std::io::Error as IoError;
+
+use crate::{FileType, XdvEvents, XdvWriter};
+
+/// Records the events reported by the parser.
+#[derive(Default)]
+pub(crate) struct Recorder(pub Vec<String>);
+
+impl XdvEvents for Recorder {
+    type Error = IoError;
+
+    fn handle_header(&mut self, filetype: FileType, comment: &[u8]) -> Result<(), IoError> {
+        self.0.push(format!("header {filetype} {comment:?}"));
+        Ok(())
+    }
+
+    fn handle_begin_page(&mut self, counters: &[i32], previous_bop: i32) -> Result<(), IoError> {
+        self.0.push(format!("bop {counters:?} {previous_bop}"));
+        Ok(())
+    }
+
+    fn handle_end_page(&mut self) -> Result<(), IoError> {
+        self.0.push("eop".to_owned());
+        Ok(())
+    }
+
+    fn handle_push(&mut self) -> Result<(), IoError> {
+        self.0.push("push".to_owned());
+        Ok(())
+    }
+
+    fn handle_pop(&mut self) -> Result<(), IoError> {
+        self.0.push("pop".to_owned());
+        Ok(())
+    }
+
+    fn handle_special(&mut self, x: i32, y: i32, contents: &[u8]) -> Result<(), IoError> {
+        self.0.push(format!("special {x} {y} {contents:?}"));
+        Ok(())
+    }
+
+    fn handle_positioned_char_run(
+        &mut self,
+        font_num: i32,
+        chars: &[i32],
+        x: i32,
+        y: i32,
+    ) -> Result<(), IoError> {
+        self.0.push(format!("chars {font_num} {chars:?} {x} {y}"));
+        Ok(())
+    }
+
+    fn handle_glyph_run(
+        &mut self,
+        font_num: i32,
+        glyphs: &[u16],
+        x: &[i32],
+        y: &[i32],
+    ) -> Result<(), IoError> {
+        self.0
+            .push(format!("glyphs {font_num} {glyphs:?} {x:?} {y:?}"));
+        Ok(())
+    }
+
+    fn handle_text_and_glyphs(
+        &mut self,
+        font_num: i32,
+        text: &str,
+        width: i32,
+        glyphs: &[u16],
+        x: &[i32],
+        y: &[i32],
+    ) -> Result<(), IoError> {
+        self.0.push(format!(
+            "text {font_num} {text} {width} {glyphs:?} {x:?} {y:?}"
+        ));
+        Ok(())
+    }
+
+    fn handle_define_font(
+        &mut self,
+        area: &str,
+        name: &str,
+        font_num: i32,
+        checksum: u32,
+        scale_factor: u32,
+        design_size: u32,
+    ) -> Result<(), IoError> {
+        self.0.push(format!(
+            "font {area}{name} {font_num} {checksum} {scale_factor} {design_size}"
+        ));
+        Ok(())
+    }
+
+    fn handle_define_native_font(
+        &mut self,
+        name: &str,
+        font_num: i32,
+        size: i32,
+        face_index: u32,
+        color_rgba: Option<u32>,
+        extend: Option<u32>,
+        slant: Option<u32>,
+        embolden: Option<u32>,
+    ) -> Result<(), IoError> {
+        self.0.push(format!(
+            "native {name} {font_num} {size} {face_index} {color_rgba:?} {extend:?} {slant:?} {embolden:?}"
+        ));
+        Ok(())
+    }
+
+    fn handle_rule(&mut self, x: i32, y: i32, height: i32, width: i32) -> Result<(), IoError> {
+        self.0.push(format!("rule {x} {y} {height} {width}"));
+        Ok(())
+    }
+}
+
+/// A three-page XDV file. Two fonts are defined before the first page, and a
+/// third is defined on the second page and used again on the third.
+pub(crate) fn sample() -> Vec<u8> {
+    let mut w = XdvWriter::new(Vec::new());
+    w.preamble(FileType::Xdv, b"test").unwrap();
+    w.define_font("", "cmr10", 0, 1234, 655360, 655360).unwrap();
+    w.define_native_font(
+        "lmroman10-regular.otf",
+        300,
+        655360,
+        0,
+        None,
+        Some(70000),
+        None,
+        None,
+    )
+    .unwrap();
+
+    w.begin_page(&[1]).unwrap();
+    w.move_to(100, 2000).unwrap();
+    w.select_font(0).unwrap();
+    w.set_chars(&[72, 105, 200]).unwrap();
+    w.push().unwrap();
+    w.right(-70000).unwrap();
+    w.special(b"color push Black").unwrap();
+    w.set_rule(10, 500).unwrap();
+    w.pop().unwrap();
+    w.down(40000).unwrap();
+    w.select_font(300).unwrap();
+    w.set_glyphs(900, &[5, 6], &[0, 450], &[0, 0]).unwrap();
+    w.set_text_and_glyphs("ab", 800, &[7, 8], &[0, 400], &[0, -3])
+        .unwrap();
+    w.end_page().unwrap();
+
+    w.begin_page(&[2, 0, 7]).unwrap();
+    w.move_to(100, 2000).unwrap();
+    w.select_font(0).unwrap();
+    w.set_chars(&[65]).unwrap();
+    w.put_rule(20, 20).unwrap();
+    w.define_native_font(
+        "lmroman12-regular.otf",
+        301,
+        786432,
+        0,
+        None,
+        None,
+        None,
+        None,
+    )
+    .unwrap();
+    w.select_font(301).unwrap();
+    w.set_glyphs(900, &[9], &[0], &[0]).unwrap();
+    w.end_page().unwrap();
+
+    w.begin_page(&[3]).unwrap();
+    w.move_to(100, 2000).unwrap();
+    w.select_font(301).unwrap();
+    w.set_glyphs(900, &[10], &[0], &[0]).unwrap();
+    w.end_page().unwrap();
+
+    w.finish().unwrap()
+}
@@ -0,0 +1,1005 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Writing XDV and SPX files.
+
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    io::{Error as IoError, ErrorKind, Write},
+};
+
+use crate::{FileType, IdByte, NativeFontFlags, Opcode, XdvEvents};
+
+/// The numerator of the units of the file, as always used by TeX.
+const UNIT_NUMERATOR: u32 = 25_400_000;
+
+/// The denominator of the units of the file, as always used by TeX.
+const UNIT_DENOMINATOR: u32 = 473_628_672;
+
+/// The magnification of the file.
+const MAGNIFICATION: u32 = 1000;
+
+/// A writer for XDV and SPX files.
+///
+/// The writer has a method for each kind of command in the file format. It
+/// takes care of the bookkeeping that the format requires: the pointers
+/// linking each page to the previous one, the repetition of font definitions
+/// in the postamble, and the postamble itself, which is written by
+/// [`Self::finish`].
+///
+/// Like [`XdvParser`](crate::XdvParser), the writer tracks the current
+/// position on the page, except that it doesn't know the widths of the
+/// characters of traditional TeX fonts, so setting them doesn't advance the
+/// position.
+///
+/// The writer also implements [`XdvEvents`], so that it can be used with
+/// [`XdvParser`](crate::XdvParser) to copy a file. To filter a file, for
+/// instance to select some of its pages or to remove some specials, wrap the
+/// writer in an event handler that passes on only the events to be kept. The
+/// events give absolute positions, so movements are written as needed to get
+/// to them. The pointers in the file are recomputed, so they remain correct
+/// whatever is removed.
+#[derive(Debug)]
+pub struct XdvWriter<W: Write> {
+    out: W,
+    offset: u64,
+    state: WriterState,
+    filetype: FileType,
+    last_bop: Option<u32>,
+    n_pages: u16,
+    h: i32,
+    v: i32,
+    stack: Vec<(i32, i32)>,
+    max_stack_depth: u16,
+    max_h: i32,
+    max_v: i32,
+    cur_font_num: Option<i32>,
+    fonts: BTreeMap<i32, FontDefinition>,
+}
+
+/// What the writer is expecting next.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum WriterState {
+    Preamble,
+    BetweenPages,
+    InPage,
+}
+
+/// An encoded font definition.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct FontDefinition {
+    data: Vec<u8>,
+    written: bool,
+}
+
+fn usage_error(message: &str) -> IoError {
+    IoError::new(ErrorKind::InvalidInput, message)
+}
+
+/// Get the number of bytes needed to encode *value* as a signed quantity.
+fn signed_len(value: i32) -> u8 {
+    if (-0x80..0x80).contains(&value) {
+        1
+    } else if (-0x8000..0x8000).contains(&value) {
+        2
+    } else if (-0x80_0000..0x80_0000).contains(&value) {
+        3
+    } else {
+        4
+    }
+}
+
+/// Get the number of bytes needed to encode *value* as an unsigned quantity,
+/// with four-byte values being signed.
+fn unsigned_len(value: i32) -> u8 {
+    if value < 0 {
+        4
+    } else if value < 0x100 {
+        1
+    } else if value < 0x1_0000 {
+        2
+    } else if value < 0x100_0000 {
+        3
+    } else {
+        4
+    }
+}
+
+fn byte_len(s: &[u8], what: &str) -> Result<u8, IoError> {
+    u8::try_from(s.len()).map_err(|_| usage_error(&format!("{what} is too long")))
+}
+
+impl<W: Write> XdvWriter<W> {
+    /// Create a new writer that will write to *out*.
+    ///
+    /// The first thing written must be the preamble, with
+    /// [`Self::preamble`].
+    pub fn new(out: W) -> Self {
+        XdvWriter {
+            out,
+            offset: 0,
+            state: WriterState::Preamble,
+            filetype: FileType::Xdv,
+            last_bop: None,
+            n_pages: 0,
+            h: 0,
+            v: 0,
+            stack: Vec::new(),
+            max_stack_depth: 0,
+            max_h: 0,
+            max_v: 0,
+            cur_font_num: None,
+            fonts: BTreeMap::new(),
+        }
+    }
+
+    /// Get the number of bytes written so far.
+    pub fn current_offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Get the current position on the page.
+    pub fn position(&self) -> (i32, i32) {
+        (self.h, self.v)
+    }
+
+    fn write_bytes(&mut self, data: &[u8]) -> Result<(), IoError> {
+        self.out.write_all(data)?;
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+
+    fn write_u8(&mut self, value: u8) -> Result<(), IoError> {
+        self.write_bytes(&[value])
+    }
+
+    fn write_u16(&mut self, value: u16) -> Result<(), IoError> {
+        self.write_bytes(&value.to_be_bytes())
+    }
+
+    fn write_u32(&mut self, value: u32) -> Result<(), IoError> {
+        self.write_bytes(&value.to_be_bytes())
+    }
+
+    fn write_i32(&mut self, value: i32) -> Result<(), IoError> {
+        self.write_bytes(&value.to_be_bytes())
+    }
+
+    /// Write a command whose argument is encoded in one to four bytes, as
+    /// indicated by its opcode.
+    fn write_sized(&mut self, first_opcode: Opcode, value: i32, len: u8) -> Result<(), IoError> {
+        self.write_u8(first_opcode as u8 + len - 1)?;
+        self.write_bytes(&value.to_be_bytes()[4 - len as usize..])
+    }
+
+    fn check_in_page(&self) -> Result<(), IoError> {
+        if self.state != WriterState::InPage {
+            return Err(usage_error("XDV content can only be written inside a page"));
+        }
+
+        Ok(())
+    }
+
+    fn note_position(&mut self) {
+        self.max_h = self.max_h.max(self.h);
+        self.max_v = self.max_v.max(self.v);
+    }
+
+    /// Write the preamble of the file.
+    ///
+    /// The *comment* may be up to 255 bytes long.
+    pub fn preamble(&mut self, filetype: FileType, comment: &[u8]) -> Result<(), IoError> {
+        if self.state != WriterState::Preamble {
+            return Err(usage_error("the XDV preamble has already been written"));
+        }
+
+        let n_comment = byte_len(comment, "XDV comment")?;
+        self.filetype = filetype;
+        self.write_u8(Opcode::Preamble as u8)?;
+        self.write_u8(id_byte(filetype))?;
+        self.write_u32(UNIT_NUMERATOR)?;
+        self.write_u32(UNIT_DENOMINATOR)?;
+        self.write_u32(MAGNIFICATION)?;
+        self.write_u8(n_comment)?;
+        self.write_bytes(comment)?;
+        self.state = WriterState::BetweenPages;
+
+        // Fonts may have been defined before the preamble was written, if
+        // the definitions came from the postamble of a file being copied.
+        let pending: Vec<Vec<u8>> = self
+            .fonts
+            .values_mut()
+            .filter(|f| !f.written)
+            .map(|f| {
+                f.written = true;
+                f.data.clone()
+            })
+            .collect();
+
+        for data in pending {
+            self.write_bytes(&data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Begin a new page.
+    ///
+    /// Up to ten *counters* may be given; those not given are zero.
+    pub fn begin_page(&mut self, counters: &[i32]) -> Result<(), IoError> {
+        if self.state != WriterState::BetweenPages {
+            return Err(usage_error("an XDV page can only begin between pages"));
+        }
+
+        if counters.len() > 10 {
+            return Err(usage_error("XDV pages have at most ten counters"));
+        }
+
+        let bop =
+            u32::try_from(self.offset).map_err(|_| usage_error("the XDV output is too large"))?;
+        self.write_u8(Opcode::BeginningOfPage as u8)?;
+
+        for i in 0..10 {
+            self.write_i32(counters.get(i).copied().unwrap_or(0))?;
+        }
+
+        self.write_i32(self.last_bop.map(|p| p as i32).unwrap_or(-1))?;
+        self.last_bop = Some(bop);
+        self.n_pages = self.n_pages.saturating_add(1);
+        self.state = WriterState::InPage;
+        self.h = 0;
+        self.v = 0;
+        self.stack.clear();
+        self.cur_font_num = None;
+        Ok(())
+    }
+
+    /// End the current page.
+    pub fn end_page(&mut self) -> Result<(), IoError> {
+        self.check_in_page()?;
+
+        if !self.stack.is_empty() {
+            return Err(usage_error("an XDV page must end with an empty stack"));
+        }
+
+        self.write_u8(Opcode::EndOfPage as u8)?;
+        self.state = WriterState::BetweenPages;
+        Ok(())
+    }
+
+    /// Save the current position on the stack.
+    pub fn push(&mut self) -> Result<(), IoError> {
+        self.check_in_page()?;
+        self.write_u8(Opcode::PushStack as u8)?;
+        self.stack.push((self.h, self.v));
+        let depth = u16::try_from(self.stack.len()).unwrap_or(u16::MAX);
+        self.max_stack_depth = self.max_stack_depth.max(depth);
+        Ok(())
+    }
+
+    /// Restore the position most recently saved on the stack.
+    pub fn pop(&mut self) -> Result<(), IoError> {
+        self.check_in_page()?;
+
+        let (h, v) = self
+            .stack
+            .pop()
+            .ok_or_else(|| usage_error("the XDV stack is empty"))?;
+        self.write_u8(Opcode::PopStack as u8)?;
+        self.h = h;
+        self.v = v;
+        Ok(())
+    }
+
+    /// Move right by *dx*.
+    pub fn right(&mut self, dx: i32) -> Result<(), IoError> {
+        self.check_in_page()?;
+        self.write_sized(Opcode::Right1, dx, signed_len(dx))?;
+        self.h += dx;
+        self.note_position();
+        Ok(())
+    }
+
+    /// Move down by *dy*.
+    pub fn down(&mut self, dy: i32) -> Result<(), IoError> {
+        self.check_in_page()?;
+        self.write_sized(Opcode::Down1, dy, signed_len(dy))?;
+        self.v += dy;
+        self.note_position();
+        Ok(())
+    }
+
+    /// Move to the position (*x*, *y*), if not already there.
+    pub fn move_to(&mut self, x: i32, y: i32) -> Result<(), IoError> {
+        if x != self.h {
+            self.right(x - self.h)?;
+        }
+
+        if y != self.v {
+            self.down(y - self.v)?;
+        }
+
+        Ok(())
+    }
+
+    /// Select the font used by subsequent characters and glyphs, if it isn't
+    /// already selected.
+    pub fn select_font(&mut self, font_num: i32) -> Result<(), IoError> {
+        self.check_in_page()?;
+
+        if self.cur_font_num == Some(font_num) {
+            return Ok(());
+        }
+
+        if (0..64).contains(&font_num) {
+            self.write_u8(Opcode::SetFontNumber0 as u8 + font_num as u8)?;
+        } else {
+            self.write_sized(Opcode::SetFont1, font_num, unsigned_len(font_num))?;
+        }
+
+        self.cur_font_num = Some(font_num);
+        Ok(())
+    }
+
+    /// Set characters of a traditional TeX font.
+    pub fn set_chars(&mut self, chars: &[i32]) -> Result<(), IoError> {
+        self.check_in_page()?;
+
+        for &c in chars {
+            if (0..128).contains(&c) {
+                self.write_u8(Opcode::SetCharNumber0 as u8 + c as u8)?;
+            } else {
+                self.write_sized(Opcode::SetChar1, c, unsigned_len(c))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Set glyphs of a native font, then move right by *width*.
+    ///
+    /// The glyph positions are given relative to the current position.
+    pub fn set_glyphs(
+        &mut self,
+        width: i32,
+        glyphs: &[u16],
+        dx: &[i32],
+        dy: &[i32],
+    ) -> Result<(), IoError> {
+        self.check_in_page()?;
+        self.write_u8(Opcode::SetGlyphs as u8)?;
+        self.write_i32(width)?;
+        self.write_glyph_data(glyphs, dx, dy)?;
+        self.h += width;
+        self.note_position();
+        Ok(())
+    }
+
+    /// Set glyphs of a native font along with the text that they represent,
+    /// then move right by *width*.
+    ///
+    /// The glyph positions are given relative to the current position.
+    pub fn set_text_and_glyphs(
+        &mut self,
+        text: &str,
+        width: i32,
+        glyphs: &[u16],
+        dx: &[i32],
+        dy: &[i32],
+    ) -> Result<(), IoError> {
+        self.check_in_page()?;
+
+        let chars: Vec<u16> = text.encode_utf16().collect();
+        let n_chars =
+            u16::try_from(chars.len()).map_err(|_| usage_error("XDV text is too long"))?;
+
+        self.write_u8(Opcode::SetTextAndGlyphs as u8)?;
+        self.write_u16(n_chars)?;
+
+        for c in chars {
+            self.write_u16(c)?;
+        }
+
+        self.write_i32(width)?;
+        self.write_glyph_data(glyphs, dx, dy)?;
+        self.h += width;
+        self.note_position();
+        Ok(())
+    }
+
+    fn write_glyph_data(&mut self, glyphs: &[u16], dx: &[i32], dy: &[i32]) -> Result<(), IoError> {
+        if dx.len() != glyphs.len() || dy.len() != glyphs.len() {
+            return Err(usage_error("every XDV glyph needs one position"));
+        }
+
+        let n_glyphs =
+            u16::try_from(glyphs.len()).map_err(|_| usage_error("too many XDV glyphs"))?;
+        self.write_u16(n_glyphs)?;
+
+        for (x, y) in dx.iter().zip(dy) {
+            self.write_i32(*x)?;
+            self.write_i32(*y)?;
+        }
+
+        for g in glyphs {
+            self.write_u16(*g)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write a `\special` at the current position.
+    pub fn special(&mut self, contents: &[u8]) -> Result<(), IoError> {
+        self.check_in_page()?;
+
+        let n =
+            i32::try_from(contents.len()).map_err(|_| usage_error("XDV special is too long"))?;
+        self.write_sized(Opcode::Special1, n, unsigned_len(n))?;
+        self.write_bytes(contents)
+    }
+
+    /// Draw a rule with its bottom left corner at the current position, then
+    /// move right by its width.
+    pub fn set_rule(&mut self, height: i32, width: i32) -> Result<(), IoError> {
+        self.check_in_page()?;
+        self.write_u8(Opcode::SetRule as u8)?;
+        self.write_i32(height)?;
+        self.write_i32(width)?;
+        self.h += width;
+        self.note_position();
+        Ok(())
+    }
+
+    /// Draw a rule with its bottom left corner at the current position,
+    /// without moving.
+    pub fn put_rule(&mut self, height: i32, width: i32) -> Result<(), IoError> {
+        self.check_in_page()?;
+        self.write_u8(Opcode::PutRule as u8)?;
+        self.write_i32(height)?;
+        self.write_i32(width)
+    }
+
+    /// Define a traditional TeX font.
+    ///
+    /// The *area* and *name* may each be up to 255 bytes long.
+    pub fn define_font(
+        &mut self,
+        area: &str,
+        name: &str,
+        font_num: i32,
+        checksum: u32,
+        scale_factor: u32,
+        design_size: u32,
+    ) -> Result<(), IoError> {
+        let len = unsigned_len(font_num);
+        let mut data = vec![Opcode::DefineFont1 as u8 + len - 1];
+        data.extend_from_slice(&font_num.to_be_bytes()[4 - len as usize..]);
+        data.extend_from_slice(&checksum.to_be_bytes());
+        data.extend_from_slice(&scale_factor.to_be_bytes());
+        data.extend_from_slice(&design_size.to_be_bytes());
+        data.push(byte_len(area.as_bytes(), "XDV font area")?);
+        data.push(byte_len(name.as_bytes(), "XDV font name")?);
+        data.extend_from_slice(area.as_bytes());
+        data.extend_from_slice(name.as_bytes());
+        self.define_any_font(font_num, data)
+    }
+
+    /// Define a native font.
+    ///
+    /// The *name* may be up to 255 bytes long.
+    #[allow(clippy::too_many_arguments)]
+    pub fn define_native_font(
+        &mut self,
+        name: &str,
+        font_num: i32,
+        size: i32,
+        face_index: u32,
+        color_rgba: Option<u32>,
+        extend: Option<u32>,
+        slant: Option<u32>,
+        embolden: Option<u32>,
+    ) -> Result<(), IoError> {
+        let mut flags = 0;
+        let mut extra = Vec::new();
+
+        for (value, flag) in [
+            (color_rgba, NativeFontFlags::Colored),
+            (extend, NativeFontFlags::Extend),
+            (slant, NativeFontFlags::Slant),
+            (embolden, NativeFontFlags::Embolden),
+        ] {
+            if let Some(v) = value {
+                flags |= flag as u16;
+                extra.extend_from_slice(&v.to_be_bytes());
+            }
+        }
+
+        let mut data = vec![Opcode::DefineNativeFont as u8];
+        data.extend_from_slice(&font_num.to_be_bytes());
+        data.extend_from_slice(&size.to_be_bytes());
+        data.extend_from_slice(&flags.to_be_bytes());
+        data.push(byte_len(name.as_bytes(), "XDV font name")?);
+        data.extend_from_slice(name.as_bytes());
+        data.extend_from_slice(&face_index.to_be_bytes());
+        data.extend_from_slice(&extra);
+        self.define_any_font(font_num, data)
+    }
+
+    /// Record a font definition, writing it out if possible. Repeated
+    /// definitions are only written once.
+    fn define_any_font(&mut self, font_num: i32, data: Vec<u8>) -> Result<(), IoError> {
+        if let Some(existing) = self.fonts.get(&font_num) {
+            if existing.data != data {
+                return Err(usage_error(&format!(
+                    "XDV font {font_num} has already been defined differently"
+                )));
+            }
+
+            return Ok(());
+        }
+
+        let written = self.state != WriterState::Preamble;
+
+        if written {
+            self.write_bytes(&data)?;
+        }
+
+        self.fonts
+            .insert(font_num, FontDefinition { data, written });
+        Ok(())
+    }
+
+    /// Write the postamble, completing the file, and return the underlying
+    /// stream.
+    pub fn finish(mut self) -> Result<W, IoError> {
+        match self.state {
+            WriterState::Preamble => {
+                return Err(usage_error("the XDV preamble has not been written"))
+            }
+            WriterState::InPage => return Err(usage_error("the last XDV page has not ended")),
+            WriterState::BetweenPages => {}
+        }
+
+        let postamble =
+            u32::try_from(self.offset).map_err(|_| usage_error("the XDV output is too large"))?;
+        self.write_u8(Opcode::Postamble as u8)?;
+        self.write_u32(self.last_bop.unwrap_or(u32::MAX))?;
+        self.write_u32(UNIT_NUMERATOR)?;
+        self.write_u32(UNIT_DENOMINATOR)?;
+        self.write_u32(MAGNIFICATION)?;
+        self.write_i32(self.max_v)?;
+        self.write_i32(self.max_h)?;
+        self.write_u16(self.max_stack_depth)?;
+        self.write_u16(self.n_pages)?;
+
+        let definitions: Vec<u8> = self
+            .fonts
+            .values()
+            .flat_map(|f| f.data.iter().copied())
+            .collect();
+        self.write_bytes(&definitions)?;
+
+        self.write_u8(Opcode::DoublePostamble as u8)?;
+        self.write_u32(postamble)?;
+        self.write_u8(id_byte(self.filetype))?;
+
+        // At least four filler bytes, bringing the total length to a multiple
+        // of four.
+        let n_filler = 4 + (4 - self.offset % 4) % 4;
+
+        for _ in 0..n_filler {
+            self.write_u8(0xDF)?;
+        }
+
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn id_byte(filetype: FileType) -> u8 {
+    match filetype {
+        FileType::Xdv => IdByte::Xdv as u8,
+        FileType::Spx => IdByte::Spx as u8,
+    }
+}
+
+impl<W: Write> XdvEvents for XdvWriter<W> {
+    type Error = IoError;
+
+    fn handle_header(&mut self, filetype: FileType, comment: &[u8]) -> Result<(), Self::Error> {
+        self.preamble(filetype, comment)
+    }
+
+    fn handle_begin_page(
+        &mut self,
+        counters: &[i32],
+        _previous_bop: i32,
+    ) -> Result<(), Self::Error> {
+        self.begin_page(counters)
+    }
+
+    fn handle_end_page(&mut self) -> Result<(), Self::Error> {
+        self.end_page()
+    }
+
+    fn handle_push(&mut self) -> Result<(), Self::Error> {
+        self.push()
+    }
+
+    fn handle_pop(&mut self) -> Result<(), Self::Error> {
+        self.pop()
+    }
+
+    fn handle_special(&mut self, x: i32, y: i32, contents: &[u8]) -> Result<(), Self::Error> {
+        self.move_to(x, y)?;
+        self.special(contents)
+    }
+
+    fn handle_positioned_char_run(
+        &mut self,
+        font_num: i32,
+        chars: &[i32],
+        x: i32,
+        y: i32,
+    ) -> Result<(), Self::Error> {
+        self.move_to(x, y)?;
+        self.select_font(font_num)?;
+        self.set_chars(chars)
+    }
+
+    fn handle_glyph_run(
+        &mut self,
+        font_num: i32,
+        glyphs: &[u16],
+        x: &[i32],
+        y: &[i32],
+    ) -> Result<(), Self::Error> {
+        // The width of the run isn't known, so we don't move. Anything that
+        // comes later will move to its own position.
+        self.select_font(font_num)?;
+        let dx: Vec<i32> = x.iter().map(|x| x - self.h).collect();
+        let dy: Vec<i32> = y.iter().map(|y| y - self.v).collect();
+        self.set_glyphs(0, glyphs, &dx, &dy)
+    }
+
+    fn handle_text_and_glyphs(
+        &mut self,
+        font_num: i32,
+        text: &str,
+        width: i32,
+        glyphs: &[u16],
+        x: &[i32],
+        y: &[i32],
+    ) -> Result<(), Self::Error> {
+        self.select_font(font_num)?;
+        let dx: Vec<i32> = x.iter().map(|x| x - self.h).collect();
+        let dy: Vec<i32> = y.iter().map(|y| y - self.v).collect();
+        self.set_text_and_glyphs(text, width, glyphs, &dx, &dy)
+    }
+
+    fn handle_define_font(
+        &mut self,
+        area: &str,
+        name: &str,
+        font_num: i32,
+        checksum: u32,
+        scale_factor: u32,
+        design_size: u32,
+    ) -> Result<(), Self::Error> {
+        self.define_font(area, name, font_num, checksum, scale_factor, design_size)
+    }
+
+    fn handle_define_native_font(
+        &mut self,
+        name: &str,
+        font_num: i32,
+        size: i32,
+        face_index: u32,
+        color_rgba: Option<u32>,
+        extend: Option<u32>,
+        slant: Option<u32>,
+        embolden: Option<u32>,
+    ) -> Result<(), Self::Error> {
+        self.define_native_font(
+            name, font_num, size, face_index, color_rgba, extend, slant, embolden,
+        )
+    }
+
+    fn handle_rule(&mut self, x: i32, y: i32, height: i32, width: i32) -> Result<(), Self::Error> {
+        self.move_to(x, y)?;
+        self.put_rule(height, width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_util::{sample, Recorder},
+        XdvParser,
+    };
+    use std::io::Cursor;
+
+    /// Passes on everything but specials and the first page.
+    struct Filter {
+        inner: XdvWriter<Vec<u8>>,
+        n_pages: usize,
+    }
+
+    impl XdvEvents for Filter {
+        type Error = IoError;
+
+        fn handle_header(&mut self, filetype: FileType, comment: &[u8]) -> Result<(), IoError> {
+            self.inner.handle_header(filetype, comment)
+        }
+
+        fn handle_begin_page(
+            &mut self,
+            counters: &[i32],
+            previous_bop: i32,
+        ) -> Result<(), IoError> {
+            self.n_pages += 1;
+
+            if self.n_pages > 1 {
+                self.inner.handle_begin_page(counters, previous_bop)?;
+            }
+
+            Ok(())
+        }
+
+        fn handle_end_page(&mut self) -> Result<(), IoError> {
+            if self.n_pages > 1 {
+                self.inner.handle_end_page()?;
+            }
+
+            Ok(())
+        }
+
+        fn handle_positioned_char_run(
+            &mut self,
+            font_num: i32,
+            chars: &[i32],
+            x: i32,
+            y: i32,
+        ) -> Result<(), IoError> {
+            if self.n_pages > 1 {
+                self.inner
+                    .handle_positioned_char_run(font_num, chars, x, y)?;
+            }
+
+            Ok(())
+        }
+
+        fn handle_define_font(
+            &mut self,
+            area: &str,
+            name: &str,
+            font_num: i32,
+            checksum: u32,
+            scale_factor: u32,
+            design_size: u32,
+        ) -> Result<(), IoError> {
+            self.inner
+                .handle_define_font(area, name, font_num, checksum, scale_factor, design_size)
+        }
+    }
+
+    fn events(data: &[u8]) -> Vec<String> {
+        XdvParser::process(data, Recorder::default()).unwrap().0 .0
+    }
+
+    #[test]
+    fn write_and_parse() {
+        let data = sample();
+        assert_eq!(data.len() % 4, 0);
+
+        let events = events(&data);
+        let last_bop = data.len()
+            - data
+                .iter()
+                .rev()
+                .position(|b| *b == Opcode::BeginningOfPage as u8)
+                .unwrap();
+        assert!(last_bop > 0);
+
+        assert_eq!(events[0], "header XDV [116, 101, 115, 116]");
+        assert_eq!(events[1], "font cmr10 0 1234 655360 655360");
+        assert_eq!(
+            events[2],
+            "native lmroman10-regular.otf 300 655360 0 None Some(70000) None None"
+        );
+        assert_eq!(events[3], "bop [1, 0, 0, 0, 0, 0, 0, 0, 0, 0] -1");
+        assert_eq!(events[4], "chars 0 [72, 105, 200] 100 2000");
+        assert_eq!(events[5], "push");
+        assert_eq!(events[6], "special -69900 2000 [99, 111, 108, 111, 114, 32, 112, 117, 115, 104, 32, 66, 108, 97, 99, 107]");
+        assert_eq!(events[7], "rule -69900 2000 10 500");
+        assert_eq!(events[8], "pop");
+        assert_eq!(events[9], "glyphs 300 [5, 6] [100, 550] [42000, 42000]");
+        assert_eq!(
+            events[10],
+            "text 300 ab 800 [7, 8] [1000, 1400] [42000, 41997]"
+        );
+        assert_eq!(events[11], "eop");
+        assert!(events[12].starts_with("bop [2, 0, 7, 0, 0, 0, 0, 0, 0, 0] "));
+        assert_eq!(events[13], "chars 0 [65] 100 2000");
+        assert_eq!(events[14], "rule 100 2000 20 20");
+        assert_eq!(
+            events[15],
+            "native lmroman12-regular.otf 301 786432 0 None None None None"
+        );
+        assert_eq!(events[16], "glyphs 301 [9] [100] [2000]");
+        assert_eq!(events[17], "eop");
+        assert!(events[18].starts_with("bop [3, 0, 0, 0, 0, 0, 0, 0, 0, 0] "));
+        assert_eq!(events[19], "glyphs 301 [10] [100] [2000]");
+        assert_eq!(events[20], "eop");
+
+        // The postamble repeats the font definitions, including the one made
+        // on a page.
+        assert_eq!(events[21], events[1]);
+        assert_eq!(events[22], events[2]);
+        assert_eq!(events[23], events[15]);
+        assert_eq!(events.len(), 24);
+
+        // The postamble must be valid for this to work.
+        let seeking = XdvParser::process_with_seeks(Cursor::new(&data), Recorder::default())
+            .unwrap()
+            .0;
+        assert_eq!(seeking[..3], events[21..]);
+        assert_eq!(seeking[3..], events[..21]);
+    }
+
+    #[test]
+    fn back_pointers() {
+        let data = sample();
+        let events = events(&data);
+        let first_bop = data
+            .iter()
+            .position(|b| *b == Opcode::BeginningOfPage as u8)
+            .unwrap();
+        assert!(events[12].ends_with(&format!(" {first_bop}")));
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = sample();
+        let (writer, _) = XdvParser::process(&data[..], XdvWriter::new(Vec::new())).unwrap();
+        let copy = writer.finish().unwrap();
+
+        // The copy may encode movements differently, so the pages can move
+        // and the back pointers can change.
+        let without_pointers = |data: &[u8]| -> Vec<String> {
+            events(data)
+                .into_iter()
+                .map(|e| match e.strip_prefix("bop ") {
+                    Some(rest) => rest[..rest.rfind(' ').unwrap()].to_owned(),
+                    None => e,
+                })
+                .collect()
+        };
+        assert_eq!(without_pointers(&copy), without_pointers(&data));
+    }
+
+    #[test]
+    fn filter() {
+        let data = sample();
+        let filter = Filter {
+            inner: XdvWriter::new(Vec::new()),
+            n_pages: 0,
+        };
+        let (filter, _) = XdvParser::process(&data[..], filter).unwrap();
+        let filtered = filter.inner.finish().unwrap();
+        let events = events(&filtered);
+
+        assert_eq!(events[1], "font cmr10 0 1234 655360 655360");
+        assert_eq!(events[2], "bop [2, 0, 7, 0, 0, 0, 0, 0, 0, 0] -1");
+        assert_eq!(events[3], "chars 0 [65] 100 2000");
+        assert_eq!(events[4], "eop");
+        assert!(events[5].starts_with("bop [3, 0, 0, 0, 0, 0, 0, 0, 0, 0] "));
+        assert_eq!(events[6], "eop");
+        assert_eq!(events.len(), 8);
+    }
+
+    /// Records the commands reported by the parser.
+    #[derive(Default)]
+    struct Commands(Vec<(u64, Vec<u8>, i32, i32)>);
+
+    impl XdvEvents for Commands {
+        type Error = IoError;
+
+        fn handle_command(
+            &mut self,
+            offset: u64,
+            command: &[u8],
+            h: i32,
+            v: i32,
+        ) -> Result<(), IoError> {
+            self.0.push((offset, command.to_owned(), h, v));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn commands() {
+        let data = sample();
+        let commands = XdvParser::process(&data[..], Commands::default())
+            .unwrap()
+            .0
+             .0;
+
+        // The commands tile the file, apart from the final padding.
+        let mut offset = 0;
+
+        for (cmd_offset, bytes, _, _) in &commands {
+            assert_eq!(*cmd_offset, offset as u64);
+            assert_eq!(bytes[..], data[offset..offset + bytes.len()]);
+            assert!(crate::opcode_name(bytes[0]).is_some());
+            offset += bytes.len();
+        }
+
+        assert!(data[offset..].iter().all(|b| *b == 0xDF));
+
+        let names: Vec<_> = commands
+            .iter()
+            .map(|c| crate::opcode_name(c.1[0]).unwrap())
+            .collect();
+        assert_eq!(names[0], "pre");
+        assert_eq!(names.last().unwrap(), "post_post");
+
+        // Positions are reported after the command takes effect.
+        let down = names.iter().position(|n| n.starts_with("down")).unwrap();
+        assert_eq!(commands[down].3, 2000);
+        assert_eq!(crate::opcode_name(250), None);
+    }
+
+    /// The parser's events don't show where pushes and pops fall relative to
+    /// the commands that move the current point, so compare the sequences of
+    /// commands too.
+    #[test]
+    fn round_trip_commands() {
+        fn drawing_commands(data: &[u8]) -> Vec<String> {
+            XdvParser::process(data, Commands::default())
+                .unwrap()
+                .0
+                 .0
+                .into_iter()
+                .filter_map(|(_, bytes, _, _)| match bytes[0] {
+                    // Movements and font selections can legitimately be
+                    // encoded differently, and the writer always draws rules
+                    // in place and then moves.
+                    oc if oc >= Opcode::Right1 as u8 && oc <= Opcode::SetFont4 as u8 => None,
+                    oc if oc == Opcode::SetRule as u8 => Some("putrule".to_owned()),
+                    oc => crate::opcode_name(oc),
+                })
+                .collect()
+        }
+
+        let data = sample();
+        let (writer, _) = XdvParser::process(&data[..], XdvWriter::new(Vec::new())).unwrap();
+        let copy = writer.finish().unwrap();
+        let commands = drawing_commands(&copy);
+        assert_eq!(commands, drawing_commands(&data));
+
+        // In particular, the characters set before a push stay before it, so
+        // that the widths that they add aren't lost at the matching pop.
+        let push = commands.iter().position(|n| n == "push").unwrap();
+        assert_eq!(
+            commands[push - 3..push],
+            ["setchar72", "setchar105", "set1"]
+        );
+    }
+
+    #[test]
+    fn misuse() {
+        let mut w = XdvWriter::new(Vec::new());
+        assert!(w.begin_page(&[]).is_err());
+        w.preamble(FileType::Spx, b"").unwrap();
+        assert!(w.right(1).is_err());
+        w.begin_page(&[]).unwrap();
+        assert!(w.pop().is_err());
+        w.push().unwrap();
+        assert!(w.end_page().is_err());
+        w.define_font("", "cmr10", 1, 0, 0, 0).unwrap();
+        assert!(w.define_font("", "cmr12", 1, 0, 0, 0).is_err());
+    }
+}
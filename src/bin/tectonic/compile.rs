@@ -46,7 +46,7 @@ pub struct CompileOptions {
     only_cached: bool,
 
     /// The kind of output to generate
-    #[structopt(long, name = "format", default_value = "pdf", possible_values(&["pdf", "html", "xdv", "aux", "fmt"]))]
+    #[structopt(long, name = "format", default_value = "pdf", possible_values(&["pdf", "html", "xdv", "aux", "fmt", "svg"]))]
     outfmt: String,
 
     /// Write Makefile-format rules expressing the dependencies of this run to <dest_path>
@@ -0,0 +1,432 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Dumping the contents of XDV and SPX files, for `tectonic -X dump-xdv`.
+//!
+//! There are two styles of output. The default lists every command in the
+//! file, one per line, in the style of the classic `dvitype` program. The JSON
+//! style instead prints one object per page, describing the things that it
+//! draws: glyph runs, rules, and specials, along with the fonts that are
+//! defined on the page.
+
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufReader, Write},
+    path::Path,
+};
+use tectonic::{ctry, errors::Error, errors::Result};
+use tectonic_xdv::{opcode_name, FileType, XdvEvents, XdvParser};
+
+/// Dump the XDV or SPX file at *path* to *out*.
+pub fn dump<W: Write>(path: &Path, json: bool, out: W) -> Result<()> {
+    let file = ctry!(File::open(path); "failed to open `{}`", path.display());
+    let stream = BufReader::new(file);
+
+    if json {
+        XdvParser::process(stream, JsonDumper::new(out))?;
+    } else {
+        XdvParser::process(stream, TextDumper::new(out))?;
+    }
+
+    Ok(())
+}
+
+/// Decode the parameter that follows the opcode of a command whose size is
+/// given by its opcode, such as `set2` or `fnt1`.
+fn sized_param(command: &[u8]) -> i32 {
+    command[1..]
+        .iter()
+        .fold(0, |acc, b| (acc << 8) | i32::from(*b))
+}
+
+/// Format a list of glyphs and their positions.
+fn format_glyphs(glyphs: &[u16], x: &[i32], y: &[i32]) -> String {
+    let items: Vec<_> = glyphs
+        .iter()
+        .zip(x.iter().zip(y))
+        .map(|(g, (x, y))| format!("{g}@({x},{y})"))
+        .collect();
+    format!("{} glyphs: {}", glyphs.len(), items.join(" "))
+}
+
+/// Prints every command in `dvitype` style.
+///
+/// The specific event handlers are called before the generic command handler,
+/// so they stash the details of their commands, which are printed along with
+/// the position information once the command is reported.
+struct TextDumper<W: Write> {
+    out: W,
+    detail: String,
+    fonts: HashMap<i32, String>,
+    depth: usize,
+    h: i32,
+    v: i32,
+}
+
+impl<W: Write> TextDumper<W> {
+    fn new(out: W) -> Self {
+        TextDumper {
+            out,
+            detail: String::new(),
+            fonts: HashMap::new(),
+            depth: 0,
+            h: 0,
+            v: 0,
+        }
+    }
+}
+
+impl<W: Write> XdvEvents for TextDumper<W> {
+    type Error = Error;
+
+    fn handle_header(&mut self, filetype: FileType, comment: &[u8]) -> Result<()> {
+        self.detail = format!(" {} '{}'", filetype, String::from_utf8_lossy(comment));
+        Ok(())
+    }
+
+    fn handle_begin_page(&mut self, counters: &[i32], previous_bop: i32) -> Result<()> {
+        // Like `dvitype`, leave off the counters that are zero.
+        let n = counters.iter().rposition(|c| *c != 0).unwrap_or(0) + 1;
+        let counters: Vec<_> = counters[..n].iter().map(|c| c.to_string()).collect();
+        self.detail = format!(" {} prev {}", counters.join("."), previous_bop);
+        self.depth = 0;
+        Ok(())
+    }
+
+    fn handle_special(&mut self, _x: i32, _y: i32, contents: &[u8]) -> Result<()> {
+        self.detail = format!(" '{}'", String::from_utf8_lossy(contents));
+        Ok(())
+    }
+
+    fn handle_glyph_run(
+        &mut self,
+        _font_num: i32,
+        glyphs: &[u16],
+        x: &[i32],
+        y: &[i32],
+    ) -> Result<()> {
+        self.detail = format!(" {}", format_glyphs(glyphs, x, y));
+        Ok(())
+    }
+
+    fn handle_text_and_glyphs(
+        &mut self,
+        _font_num: i32,
+        text: &str,
+        width: i32,
+        glyphs: &[u16],
+        x: &[i32],
+        y: &[i32],
+    ) -> Result<()> {
+        self.detail = format!(
+            " {:?} width {}, {}",
+            text,
+            width,
+            format_glyphs(glyphs, x, y)
+        );
+        Ok(())
+    }
+
+    fn handle_define_font(
+        &mut self,
+        area: &str,
+        name: &str,
+        font_num: i32,
+        checksum: u32,
+        scale_factor: u32,
+        design_size: u32,
+    ) -> Result<()> {
+        let full_name = format!("{area}{name}");
+        self.detail = format!(
+            " {font_num}: {full_name} checksum {checksum:08X} at {scale_factor} design size {design_size}"
+        );
+        self.fonts.insert(font_num, full_name);
+        Ok(())
+    }
+
+    fn handle_define_native_font(
+        &mut self,
+        name: &str,
+        font_num: i32,
+        size: i32,
+        face_index: u32,
+        color_rgba: Option<u32>,
+        extend: Option<u32>,
+        slant: Option<u32>,
+        embolden: Option<u32>,
+    ) -> Result<()> {
+        self.detail = format!(" {font_num}: {name} at {size} index {face_index}");
+
+        if let Some(c) = color_rgba {
+            self.detail.push_str(&format!(" color {c:08X}"));
+        }
+
+        if let Some(e) = extend {
+            self.detail.push_str(&format!(" extend {}", e as i32));
+        }
+
+        if let Some(s) = slant {
+            self.detail.push_str(&format!(" slant {}", s as i32));
+        }
+
+        if let Some(e) = embolden {
+            self.detail.push_str(&format!(" embolden {}", e as i32));
+        }
+
+        self.fonts.insert(font_num, name.to_owned());
+        Ok(())
+    }
+
+    fn handle_rule(&mut self, _x: i32, _y: i32, height: i32, width: i32) -> Result<()> {
+        self.detail = format!(" height {height}, width {width}");
+        Ok(())
+    }
+
+    fn handle_command(&mut self, offset: u64, command: &[u8], h: i32, v: i32) -> Result<()> {
+        let opcode = command[0];
+        let name = opcode_name(opcode).unwrap_or_else(|| format!("opcode{opcode}"));
+        let mut detail = std::mem::take(&mut self.detail);
+
+        match opcode {
+            // set1 through set4
+            128..=131 => detail = format!(" {}", sized_param(command)),
+
+            139 => writeln!(self.out)?,
+
+            141 => {
+                detail = format!(" level {}", self.depth);
+                self.depth += 1;
+            }
+
+            142 => {
+                self.depth = self.depth.saturating_sub(1);
+                detail = format!(" level {}", self.depth);
+            }
+
+            // right, w, and x
+            143..=156 => detail = format!(" {} h:={}", h.wrapping_sub(self.h), h),
+
+            // down, y, and z
+            157..=170 => detail = format!(" {} v:={}", v.wrapping_sub(self.v), v),
+
+            // fntnum0 through fntnum63, and fnt1 through fnt4
+            171..=238 => {
+                let num = if opcode < 235 {
+                    i32::from(opcode - 171)
+                } else {
+                    detail = format!(" {}", sized_param(command));
+                    sized_param(command)
+                };
+
+                match self.fonts.get(&num) {
+                    Some(name) => detail.push_str(&format!(" current font is {name}")),
+                    None => detail.push_str(" current font is undefined"),
+                }
+            }
+
+            248 if command.len() >= 29 => {
+                let last_bop = sized_param(&command[..5]);
+                let max_depth = sized_param(&command[24..27]);
+                let n_pages = sized_param(&command[26..29]);
+                detail = format!(" last bop {last_bop}, max depth {max_depth}, {n_pages} pages");
+            }
+
+            249 if command.len() >= 6 => {
+                detail = format!(" post at {}, id {}", sized_param(&command[..5]), command[5]);
+            }
+
+            _ => {}
+        }
+
+        // Rules and glyph runs move the current point.
+        if matches!(opcode, 132 | 253 | 254) && h != self.h {
+            detail.push_str(&format!(" h:={h}"));
+        }
+
+        writeln!(self.out, "{offset}: {name}{detail}")?;
+        self.h = h;
+        self.v = v;
+        Ok(())
+    }
+}
+
+/// Prints one JSON object for each page.
+struct JsonDumper<W: Write> {
+    out: W,
+    n_pages: usize,
+    counters: Vec<i32>,
+    items: Vec<Value>,
+    defined_fonts: HashSet<i32>,
+}
+
+impl<W: Write> JsonDumper<W> {
+    fn new(out: W) -> Self {
+        JsonDumper {
+            out,
+            n_pages: 0,
+            counters: Vec::new(),
+            items: Vec::new(),
+            defined_fonts: HashSet::new(),
+        }
+    }
+
+    /// Record a font definition. Fonts defined between pages are attached to
+    /// the next page, and the repeated definitions in the postamble are
+    /// dropped.
+    fn add_font(&mut self, font_num: i32, item: Value) {
+        if self.defined_fonts.insert(font_num) {
+            self.items.push(item);
+        }
+    }
+}
+
+impl<W: Write> XdvEvents for JsonDumper<W> {
+    type Error = Error;
+
+    fn handle_begin_page(&mut self, counters: &[i32], _previous_bop: i32) -> Result<()> {
+        self.n_pages += 1;
+        self.counters = counters.to_owned();
+        Ok(())
+    }
+
+    fn handle_end_page(&mut self) -> Result<()> {
+        let record = json!({
+            "page": self.n_pages,
+            "counters": self.counters,
+            "items": std::mem::take(&mut self.items),
+        });
+
+        writeln!(self.out, "{record}")?;
+        Ok(())
+    }
+
+    fn handle_special(&mut self, x: i32, y: i32, contents: &[u8]) -> Result<()> {
+        self.items.push(json!({
+            "type": "special",
+            "x": x,
+            "y": y,
+            "contents": String::from_utf8_lossy(contents),
+        }));
+        Ok(())
+    }
+
+    fn handle_positioned_char_run(
+        &mut self,
+        font_num: i32,
+        chars: &[i32],
+        x: i32,
+        y: i32,
+    ) -> Result<()> {
+        self.items.push(json!({
+            "type": "chars",
+            "font": font_num,
+            "chars": chars,
+            "x": x,
+            "y": y,
+        }));
+        Ok(())
+    }
+
+    fn handle_glyph_run(
+        &mut self,
+        font_num: i32,
+        glyphs: &[u16],
+        x: &[i32],
+        y: &[i32],
+    ) -> Result<()> {
+        self.items.push(json!({
+            "type": "glyphs",
+            "font": font_num,
+            "glyphs": glyphs,
+            "x": x,
+            "y": y,
+        }));
+        Ok(())
+    }
+
+    fn handle_text_and_glyphs(
+        &mut self,
+        font_num: i32,
+        text: &str,
+        width: i32,
+        glyphs: &[u16],
+        x: &[i32],
+        y: &[i32],
+    ) -> Result<()> {
+        self.items.push(json!({
+            "type": "text-and-glyphs",
+            "font": font_num,
+            "text": text,
+            "width": width,
+            "glyphs": glyphs,
+            "x": x,
+            "y": y,
+        }));
+        Ok(())
+    }
+
+    fn handle_define_font(
+        &mut self,
+        area: &str,
+        name: &str,
+        font_num: i32,
+        checksum: u32,
+        scale_factor: u32,
+        design_size: u32,
+    ) -> Result<()> {
+        self.add_font(
+            font_num,
+            json!({
+                "type": "font",
+                "font": font_num,
+                "area": area,
+                "name": name,
+                "checksum": checksum,
+                "scale": scale_factor,
+                "design_size": design_size,
+            }),
+        );
+        Ok(())
+    }
+
+    fn handle_define_native_font(
+        &mut self,
+        name: &str,
+        font_num: i32,
+        size: i32,
+        face_index: u32,
+        color_rgba: Option<u32>,
+        extend: Option<u32>,
+        slant: Option<u32>,
+        embolden: Option<u32>,
+    ) -> Result<()> {
+        self.add_font(
+            font_num,
+            json!({
+                "type": "native-font",
+                "font": font_num,
+                "name": name,
+                "size": size,
+                "face_index": face_index,
+                "color": color_rgba,
+                "extend": extend.map(|e| e as i32),
+                "slant": slant.map(|s| s as i32),
+                "embolden": embolden.map(|e| e as i32),
+            }),
+        );
+        Ok(())
+    }
+
+    fn handle_rule(&mut self, x: i32, y: i32, height: i32, width: i32) -> Result<()> {
+        self.items.push(json!({
+            "type": "rule",
+            "x": x,
+            "y": y,
+            "height": height,
+            "width": width,
+        }));
+        Ok(())
+    }
+}
@@ -0,0 +1,984 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! A Language Server Protocol (LSP) server for Tectonic documents.
+//!
+//! The server speaks LSP over standard input and output. Whenever a source
+//! file is opened or saved, the workspace's document is rebuilt in a background
+//! thread, without writing any output files, and the diagnostics issued by the
+//! engine are published to the client. The results of the latest build are
+//! also used to answer requests for document symbols, which come from the
+//! outline entries that `hyperref` emits as `\special`s, and for completions
+//! of control sequences, which come from the document's format file.
+//!
+//! Builds read the source files from disk, so they only see what the client
+//! has saved. The text that the client sends us is only used to answer
+//! requests about the current state of the editor buffer; unsaved changes
+//! don't trigger a build, since the engine wouldn't see them.
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    CompletionTextEdit, Diagnostic, DiagnosticSeverity, DidChangeTextDocumentParams,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, DocumentSymbol, DocumentSymbolParams,
+    DocumentSymbolResponse, OneOf, Position, PublishDiagnosticsParams, Range, ServerCapabilities,
+    SymbolKind, TextDocumentSyncCapability, TextDocumentSyncKind, TextDocumentSyncOptions,
+    TextDocumentSyncSaveOptions, TextEdit, Url,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Arguments,
+    io::Read,
+    path::PathBuf,
+    result::Result as StdResult,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+use tectonic::{
+    ctry,
+    docmodel::{DocumentExt, DocumentSetupOptions},
+    driver::OutputFormat,
+    errmsg,
+    errors::{Result, SyncError},
+    io::{format_cache::FormatCache, IoProvider},
+    status::{ChatterLevel, MessageKind, SourceLocation, StatusBackend},
+    tt_warning,
+};
+use tectonic_docmodel::document::Document;
+use tectonic_errors::Error;
+use tectonic_status_base::plain::PlainStatusBackend;
+use tectonic_xdv::{XdvEvents, XdvParser};
+use tectonic_xetex_format::format::Format;
+
+/// Run the language server until the client shuts it down.
+///
+/// The document is built using the output profile named *target*.
+pub fn serve(
+    doc: Document,
+    target: String,
+    setup_options: DocumentSetupOptions,
+    format_cache_path: PathBuf,
+) -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Options(
+            TextDocumentSyncOptions {
+                open_close: Some(true),
+                change: Some(TextDocumentSyncKind::FULL),
+                save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                ..Default::default()
+            },
+        )),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec!["\\".to_owned()]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let capabilities =
+        ctry!(serde_json::to_value(capabilities); "couldn't serialize LSP server capabilities");
+    ctry!(connection.initialize(capabilities); "LSP initialization failed");
+
+    let analysis = Arc::new(Mutex::new(Analysis::default()));
+    let (build_tx, build_rx) = mpsc::channel();
+
+    let builder = BackgroundBuilder {
+        doc,
+        target,
+        setup_options,
+        format_cache_path,
+        analysis: analysis.clone(),
+        published: HashSet::new(),
+    };
+
+    let sender = connection.sender.clone();
+    let worker = thread::spawn(move || {
+        builder.run(build_rx, |msg| {
+            // If this fails, the client has gone away, and the main loop
+            // will notice soon enough.
+            let _ = sender.send(msg);
+        })
+    });
+
+    let mut server = Server {
+        connection: &connection,
+        build_tx,
+        analysis,
+        texts: HashMap::new(),
+    };
+
+    let result = server.main_loop();
+
+    // Let the builder finish up before we tear down the connection, so that
+    // its final messages get sent.
+    drop(server);
+    let _ = worker.join();
+    drop(connection);
+    ctry!(io_threads.join(); "LSP I/O failed");
+    result
+}
+
+/// The information derived from the latest build of the document.
+#[derive(Debug, Default)]
+struct Analysis {
+    /// The titles of the document outline entries, in order.
+    outline: Vec<String>,
+
+    /// The control sequences defined in the document's format, along with
+    /// descriptions of their meanings.
+    control_sequences: Vec<(String, String)>,
+}
+
+/// The state of the main thread, which handles messages from the client.
+struct Server<'a> {
+    connection: &'a Connection,
+
+    /// Used to ask the background thread to rebuild the document.
+    build_tx: mpsc::Sender<()>,
+
+    analysis: Arc<Mutex<Analysis>>,
+
+    /// The current text of each document that the client has opened.
+    texts: HashMap<Url, String>,
+}
+
+impl<'a> Server<'a> {
+    fn main_loop(&mut self) -> Result<()> {
+        for msg in &self.connection.receiver {
+            match msg {
+                Message::Request(req) => {
+                    if ctry!(self.connection.handle_shutdown(&req); "LSP shutdown failed") {
+                        return Ok(());
+                    }
+
+                    let response = self.handle_request(req);
+                    ctry!(self.connection.sender.send(response.into()); "couldn't send LSP response");
+                }
+
+                Message::Notification(not) => self.handle_notification(not)?,
+
+                Message::Response(_) => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_request(&mut self, req: Request) -> Response {
+        let result = match &*req.method {
+            "textDocument/completion" => serde_json::from_value(req.params)
+                .map(|params| self.completion(params))
+                .and_then(serde_json::to_value),
+
+            "textDocument/documentSymbol" => serde_json::from_value(req.params)
+                .map(|params| self.document_symbol(params))
+                .and_then(serde_json::to_value),
+
+            _ => {
+                return Response::new_err(
+                    req.id,
+                    ErrorCode::MethodNotFound as i32,
+                    format!("unsupported request `{}`", req.method),
+                );
+            }
+        };
+
+        match result {
+            Ok(v) => Response::new_ok(req.id, v),
+            Err(e) => Response::new_err(req.id, ErrorCode::InvalidParams as i32, e.to_string()),
+        }
+    }
+
+    fn handle_notification(&mut self, not: Notification) -> Result<()> {
+        match &*not.method {
+            "textDocument/didOpen" => {
+                let params: DidOpenTextDocumentParams =
+                    ctry!(serde_json::from_value(not.params); "invalid LSP notification");
+                self.texts
+                    .insert(params.text_document.uri, params.text_document.text);
+                self.request_build()
+            }
+
+            "textDocument/didChange" => {
+                let params: DidChangeTextDocumentParams =
+                    ctry!(serde_json::from_value(not.params); "invalid LSP notification");
+
+                // We only ask for full-document sync, so the last change has
+                // all of the text. Builds read from disk, so there's no point
+                // in starting one until the file is saved.
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    self.texts.insert(params.text_document.uri, change.text);
+                }
+
+                Ok(())
+            }
+
+            "textDocument/didSave" => self.request_build(),
+
+            "textDocument/didClose" => {
+                let params: DidCloseTextDocumentParams =
+                    ctry!(serde_json::from_value(not.params); "invalid LSP notification");
+                self.texts.remove(&params.text_document.uri);
+                Ok(())
+            }
+
+            _ => Ok(()),
+        }
+    }
+
+    fn request_build(&self) -> Result<()> {
+        self.build_tx
+            .send(())
+            .map_err(|_| errmsg!("the background build thread has exited"))
+    }
+
+    fn completion(&self, params: CompletionParams) -> CompletionResponse {
+        let pos = params.text_document_position;
+
+        let items = match self.texts.get(&pos.text_document.uri) {
+            Some(text) => {
+                let analysis = self.analysis.lock().unwrap();
+                complete_control_sequence(text, pos.position, &analysis.control_sequences)
+            }
+
+            None => Vec::new(),
+        };
+
+        CompletionResponse::Array(items)
+    }
+
+    fn document_symbol(&self, params: DocumentSymbolParams) -> DocumentSymbolResponse {
+        let symbols = match self.texts.get(&params.text_document.uri) {
+            Some(text) => {
+                let analysis = self.analysis.lock().unwrap();
+                document_symbols(text, &analysis.outline)
+            }
+
+            None => Vec::new(),
+        };
+
+        DocumentSymbolResponse::Nested(symbols)
+    }
+}
+
+/// The state of the background thread that builds the document.
+struct BackgroundBuilder {
+    doc: Document,
+    target: String,
+    setup_options: DocumentSetupOptions,
+    format_cache_path: PathBuf,
+    analysis: Arc<Mutex<Analysis>>,
+
+    /// The documents for which we have published nonempty diagnostics, so
+    /// that we can clear them when they're fixed.
+    published: HashSet<Url>,
+}
+
+impl BackgroundBuilder {
+    fn run<F: FnMut(Message)>(mut self, requests: mpsc::Receiver<()>, mut send: F) {
+        while requests.recv().is_ok() {
+            // Multiple saves may have piled up while we were busy, but one
+            // build takes care of all of them.
+            while requests.try_recv().is_ok() {}
+
+            let mut status = LspStatusBackend::default();
+            let outline = match self.build(&mut status) {
+                Ok(o) => Some(o),
+                Err(e) => {
+                    status.report_error(&SyncError::new(e).into());
+                    None
+                }
+            };
+
+            for params in self.collect_diagnostics(std::mem::take(&mut status.diagnostics)) {
+                send(Message::Notification(Notification::new(
+                    "textDocument/publishDiagnostics".to_owned(),
+                    params,
+                )));
+            }
+
+            let need_cseqs = self.analysis.lock().unwrap().control_sequences.is_empty();
+            let cseqs = if need_cseqs {
+                match self.load_control_sequences(&mut status) {
+                    Ok(c) => Some(c),
+                    Err(e) => {
+                        tt_warning!(status, "couldn't load control sequences from the format"; SyncError::new(e).into());
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
+            let mut analysis = self.analysis.lock().unwrap();
+
+            if let Some(o) = outline {
+                analysis.outline = o;
+            }
+
+            if let Some(c) = cseqs {
+                analysis.control_sequences = c;
+            }
+        }
+    }
+
+    fn tex_dir(&self) -> PathBuf {
+        self.doc.src_dir().join("src")
+    }
+
+    /// Build the document, returning the titles of its outline entries.
+    fn build(&self, status: &mut dyn StatusBackend) -> Result<Vec<String>> {
+        let mut builder = self
+            .doc
+            .setup_session(&self.target, &self.setup_options, status)?;
+
+        // We don't need to go any further than the XDV file.
+        builder
+            .format_cache_path(&self.format_cache_path)
+            .output_format(OutputFormat::Xdv)
+            .do_not_write_output_files();
+
+        let sess = crate::compile::run_and_report(builder, status)?;
+        let files = sess.into_file_data();
+
+        let xdv = match files.get(&format!("{}.xdv", self.target)) {
+            Some(info) => &info.data[..],
+            None => return Ok(Vec::new()),
+        };
+
+        let (outline, _) = XdvParser::process(xdv, OutlineCollector::default())?;
+        Ok(outline.titles)
+    }
+
+    /// Load the names of the control sequences defined by the document's
+    /// format, along with descriptions of their meanings. This must be called
+    /// after a build has ensured that the format file exists.
+    fn load_control_sequences(
+        &self,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Vec<(String, String)>> {
+        let profile = match self.doc.outputs.get(&self.target) {
+            Some(p) => p,
+            None => {
+                return Err(errmsg!(
+                    "unrecognized output profile name \"{}\"",
+                    self.target
+                ))
+            }
+        };
+
+        let mut bundle = self.doc.bundle(&self.setup_options, status)?;
+        let mut cache =
+            FormatCache::new(bundle.get_digest(status)?, self.format_cache_path.clone());
+
+        let mut ih = cache
+            .input_open_format(&profile.tex_format, status)
+            .must_exist()?;
+        let mut data = Vec::new();
+        ih.read_to_end(&mut data)?;
+
+        let format = Format::parse(&data[..])?;
+        let mut dump = Vec::new();
+        format.dump_cseqs(&mut dump, false)?;
+
+        Ok(parse_cseq_dump(&String::from_utf8_lossy(&dump)))
+    }
+
+    /// Convert the engine diagnostics into LSP notification parameters,
+    /// including empty notifications that clear out diagnostics that have
+    /// been fixed.
+    fn collect_diagnostics(
+        &mut self,
+        diagnostics: Vec<EngineDiagnostic>,
+    ) -> Vec<PublishDiagnosticsParams> {
+        let tex_dir = self.tex_dir();
+        let index_file = self
+            .doc
+            .outputs
+            .get(&self.target)
+            .map(|p| tex_dir.join(&p.index_file));
+        let mut by_uri: HashMap<Url, Vec<Diagnostic>> = HashMap::new();
+
+        for diag in diagnostics {
+            // Diagnostics that we can't attribute to a document source file
+            // get attached to the start of the main file.
+            let (path, line) = match diag.location {
+                Some(ref loc) if tex_dir.join(&loc.file).is_file() => {
+                    (Some(tex_dir.join(&loc.file)), loc.line.unwrap_or(1))
+                }
+                _ => (index_file.clone(), 1),
+            };
+
+            let uri = match path.and_then(|p| Url::from_file_path(p).ok()) {
+                Some(u) => u,
+                None => continue,
+            };
+
+            let severity = match diag.kind {
+                MessageKind::Note => DiagnosticSeverity::INFORMATION,
+                MessageKind::Warning => DiagnosticSeverity::WARNING,
+                MessageKind::Error => DiagnosticSeverity::ERROR,
+            };
+
+            let line = line.saturating_sub(1);
+            let range = Range::new(Position::new(line, 0), Position::new(line + 1, 0));
+
+            by_uri.entry(uri).or_default().push(Diagnostic {
+                range,
+                severity: Some(severity),
+                source: Some("tectonic".to_owned()),
+                message: diag.message_text(),
+                ..Default::default()
+            });
+        }
+
+        for uri in self.published.drain() {
+            by_uri.entry(uri).or_default();
+        }
+
+        by_uri
+            .into_iter()
+            .map(|(uri, diagnostics)| {
+                if !diagnostics.is_empty() {
+                    self.published.insert(uri.clone());
+                }
+
+                PublishDiagnosticsParams {
+                    uri,
+                    diagnostics,
+                    version: None,
+                }
+            })
+            .collect()
+    }
+}
+
+/// A diagnostic issued by the engine during a build.
+#[derive(Debug)]
+struct EngineDiagnostic {
+    kind: MessageKind,
+    message: String,
+    location: Option<SourceLocation>,
+}
+
+impl EngineDiagnostic {
+    /// Get the message text, minus the location prefix that the engine
+    /// includes for the benefit of humans.
+    fn message_text(&self) -> String {
+        let text = self.message.trim();
+
+        if let Some(SourceLocation {
+            file,
+            line: Some(line),
+        }) = &self.location
+        {
+            if let Some(rest) = text.strip_prefix(&format!("{file}:{line}: ")) {
+                return rest.to_owned();
+            }
+        }
+
+        text.to_owned()
+    }
+}
+
+/// A status backend that captures engine diagnostics, and sends other
+/// messages to standard error, since standard output is used for the protocol.
+struct LspStatusBackend {
+    inner: PlainStatusBackend,
+    diagnostics: Vec<EngineDiagnostic>,
+}
+
+impl Default for LspStatusBackend {
+    fn default() -> Self {
+        let mut inner = PlainStatusBackend::new(ChatterLevel::Minimal);
+        inner.always_stderr(true);
+
+        LspStatusBackend {
+            inner,
+            diagnostics: Vec::new(),
+        }
+    }
+}
+
+impl StatusBackend for LspStatusBackend {
+    fn report(&mut self, kind: MessageKind, args: Arguments, err: Option<&Error>) {
+        self.inner.report(kind, args, err)
+    }
+
+    fn report_error(&mut self, err: &Error) {
+        self.inner.report_error(err)
+    }
+
+    fn report_diagnostic(
+        &mut self,
+        kind: MessageKind,
+        args: Arguments,
+        location: Option<&SourceLocation>,
+    ) {
+        self.diagnostics.push(EngineDiagnostic {
+            kind,
+            message: args.to_string(),
+            location: location.cloned(),
+        });
+    }
+
+    fn dump_error_logs(&mut self, _output: &[u8]) {
+        // The diagnostics have the important information.
+    }
+}
+
+/// Gathers the titles of the outline (bookmark) entries that `hyperref`
+/// emits for the dvipdfmx-style backends in an XDV file.
+#[derive(Debug, Default)]
+struct OutlineCollector {
+    titles: Vec<String>,
+}
+
+impl XdvEvents for OutlineCollector {
+    type Error = Error;
+
+    fn handle_special(&mut self, _x: i32, _y: i32, contents: &[u8]) -> StdResult<(), Self::Error> {
+        if let Some(title) = outline_title(contents) {
+            self.titles.push(title);
+        }
+
+        Ok(())
+    }
+}
+
+/// Extract the title from an outline special, which looks something like
+/// `pdf:outline [-] 1 << /Title (Introduction) /A << ... >> >>`. The `outline`
+/// keyword can be abbreviated to `out`.
+fn outline_title(special: &[u8]) -> Option<String> {
+    let s = skip_ws(special).strip_prefix(b"pdf:")?;
+    let s = s
+        .strip_prefix(b"outline")
+        .or_else(|| s.strip_prefix(b"out"))?;
+
+    let idx = s.windows(6).position(|w| w == b"/Title")?;
+    let s = skip_ws(&s[idx + 6..]);
+
+    let bytes = match s.first()? {
+        b'(' => parse_pdf_literal_string(&s[1..])?,
+        b'<' => parse_pdf_hex_string(&s[1..])?,
+        _ => return None,
+    };
+
+    Some(decode_pdf_text_string(&bytes))
+}
+
+fn skip_ws(s: &[u8]) -> &[u8] {
+    let n = s.iter().take_while(|b| b.is_ascii_whitespace()).count();
+    &s[n..]
+}
+
+/// Parse a PDF literal string, starting just after the opening parenthesis.
+fn parse_pdf_literal_string(s: &[u8]) -> Option<Vec<u8>> {
+    let mut result = Vec::new();
+    let mut depth = 0;
+    let mut iter = s.iter().copied().peekable();
+
+    while let Some(b) = iter.next() {
+        match b {
+            b'(' => {
+                depth += 1;
+                result.push(b);
+            }
+
+            b')' => {
+                if depth == 0 {
+                    return Some(result);
+                }
+
+                depth -= 1;
+                result.push(b);
+            }
+
+            b'\\' => match iter.next()? {
+                b'n' => result.push(b'\n'),
+                b'r' => result.push(b'\r'),
+                b't' => result.push(b'\t'),
+                b'b' => result.push(0x08),
+                b'f' => result.push(0x0c),
+                b'\n' => {}
+                d @ b'0'..=b'7' => {
+                    let mut value = u32::from(d - b'0');
+
+                    for _ in 0..2 {
+                        match iter.peek() {
+                            Some(&d @ b'0'..=b'7') => {
+                                value = value * 8 + u32::from(d - b'0');
+                                iter.next();
+                            }
+                            _ => break,
+                        }
+                    }
+
+                    result.push(value as u8);
+                }
+                other => result.push(other),
+            },
+
+            _ => result.push(b),
+        }
+    }
+
+    // Unterminated string.
+    None
+}
+
+/// Parse a PDF hexadecimal string, starting just after the opening angle
+/// bracket.
+fn parse_pdf_hex_string(s: &[u8]) -> Option<Vec<u8>> {
+    let end = s.iter().position(|b| *b == b'>')?;
+    let mut digits: Vec<u8> = s[..end]
+        .iter()
+        .filter(|b| !b.is_ascii_whitespace())
+        .map(|b| (*b as char).to_digit(16).map(|d| d as u8))
+        .collect::<Option<_>>()?;
+
+    if digits.len() % 2 == 1 {
+        digits.push(0);
+    }
+
+    Some(digits.chunks(2).map(|p| p[0] * 16 + p[1]).collect())
+}
+
+/// Decode the bytes of a PDF text string: UTF-16BE if there's a byte-order
+/// mark, otherwise UTF-8 (as XeTeX generally produces) if valid, and
+/// otherwise Latin-1 as an approximation of PDFDocEncoding.
+fn decode_pdf_text_string(bytes: &[u8]) -> String {
+    if let Some(utf16) = bytes.strip_prefix(&[0xfe, 0xff]) {
+        let units = utf16
+            .chunks(2)
+            .map(|p| u16::from(p[0]) << 8 | u16::from(*p.get(1).unwrap_or(&0)));
+        return char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+    }
+
+    match std::str::from_utf8(bytes) {
+        Ok(s) => s.to_owned(),
+        Err(_) => bytes.iter().map(|b| *b as char).collect(),
+    }
+}
+
+/// Parse the output of `Format::dump_cseqs()` into pairs of control sequence
+/// names and descriptions. Control sequences that aren't made up of letters,
+/// or that are internal (containing `@`), are skipped.
+fn parse_cseq_dump(dump: &str) -> Vec<(String, String)> {
+    dump.lines()
+        .filter_map(|line| {
+            let (name, desc) = line.split_once(" => ")?;
+            let name = name.strip_prefix('\\')?;
+
+            if name.len() < 2 || !name.chars().all(|c| c.is_alphabetic()) {
+                return None;
+            }
+
+            Some((name.to_owned(), desc.to_owned()))
+        })
+        .collect()
+}
+
+/// Compute completions for a control sequence being typed at *position*.
+fn complete_control_sequence(
+    text: &str,
+    position: Position,
+    cseqs: &[(String, String)],
+) -> Vec<CompletionItem> {
+    let line = match text.lines().nth(position.line as usize) {
+        Some(l) => l,
+        None => return Vec::new(),
+    };
+
+    let before = &line[..byte_offset(line, position.character)];
+    let prefix_len = before
+        .chars()
+        .rev()
+        .take_while(|c| c.is_alphabetic())
+        .map(char::len_utf8)
+        .sum::<usize>();
+    let (before, prefix) = before.split_at(before.len() - prefix_len);
+
+    if !before.ends_with('\\') {
+        return Vec::new();
+    }
+
+    let start = position.character - utf16_len(prefix) - 1;
+    let range = Range::new(Position::new(position.line, start), position);
+
+    cseqs
+        .iter()
+        .filter(|(name, _)| name.starts_with(prefix))
+        .map(|(name, desc)| {
+            let label = format!("\\{name}");
+
+            CompletionItem {
+                label: label.clone(),
+                kind: Some(CompletionItemKind::FUNCTION),
+                detail: Some(desc.clone()),
+                filter_text: Some(label.clone()),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(range, label))),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// The sectioning commands that we recognize, and their nesting levels.
+const SECTIONING_COMMANDS: &[(&str, i32)] = &[
+    ("part", -1),
+    ("chapter", 0),
+    ("section", 1),
+    ("subsection", 2),
+    ("subsubsection", 3),
+    ("paragraph", 4),
+    ("subparagraph", 5),
+];
+
+/// Find the sectioning commands in a source file and turn them into nested
+/// symbols.
+///
+/// The engine's outline entries give the titles as they actually appear in
+/// the document, after macro expansion. We match them up with the titles in
+/// the source by comparing only their alphanumeric characters. If a title
+/// can't be matched, which will always be the case if the document doesn't
+/// use `hyperref`, we use the source text.
+fn document_symbols(text: &str, outline: &[String]) -> Vec<DocumentSymbol> {
+    let mut flat = Vec::new();
+    let mut outline_pos = 0;
+
+    for (line_num, line) in text.lines().enumerate() {
+        let (level, source_title) = match find_sectioning_command(line) {
+            Some(t) => t,
+            None => continue,
+        };
+
+        let key = normalize_title(&source_title);
+        let matched = outline[outline_pos..]
+            .iter()
+            .position(|t| normalize_title(t) == key);
+
+        let title = match matched {
+            Some(i) => {
+                outline_pos += i + 1;
+                outline[outline_pos - 1].clone()
+            }
+            None => source_title,
+        };
+
+        let line_num = line_num as u32;
+        let selection_range = Range::new(
+            Position::new(line_num, 0),
+            Position::new(line_num, utf16_len(line)),
+        );
+
+        #[allow(deprecated)] // the `deprecated` field
+        let symbol = DocumentSymbol {
+            name: title,
+            detail: None,
+            kind: SymbolKind::MODULE,
+            tags: None,
+            deprecated: None,
+            range: selection_range,
+            selection_range,
+            children: None,
+        };
+
+        flat.push((level, symbol));
+    }
+
+    nest_symbols(flat, text.lines().count() as u32)
+}
+
+/// Arrange a flat list of symbols into a tree based on their levels. Each
+/// symbol's range is extended to just before the next symbol that isn't one
+/// of its children.
+fn nest_symbols(flat: Vec<(i32, DocumentSymbol)>, n_lines: u32) -> Vec<DocumentSymbol> {
+    fn finish(
+        mut sym: DocumentSymbol,
+        end: Position,
+        stack: &mut [(i32, DocumentSymbol)],
+        roots: &mut Vec<DocumentSymbol>,
+    ) {
+        sym.range.end = end;
+
+        match stack.last_mut() {
+            Some((_, parent)) => parent.children.get_or_insert_with(Vec::new).push(sym),
+            None => roots.push(sym),
+        }
+    }
+
+    let mut stack: Vec<(i32, DocumentSymbol)> = Vec::new();
+    let mut roots = Vec::new();
+
+    for (level, sym) in flat {
+        let end = sym.range.start;
+
+        while matches!(stack.last(), Some((l, _)) if *l >= level) {
+            let (_, done) = stack.pop().unwrap();
+            finish(done, end, &mut stack, &mut roots);
+        }
+
+        stack.push((level, sym));
+    }
+
+    while let Some((_, done)) = stack.pop() {
+        finish(done, Position::new(n_lines, 0), &mut stack, &mut roots);
+    }
+
+    roots
+}
+
+/// If the line contains a sectioning command, return its level and title. The
+/// title is the optional short title if it is given, since that is what goes
+/// into the outline. Only titles that are contained on one line are handled.
+fn find_sectioning_command(line: &str) -> Option<(i32, String)> {
+    for (idx, _) in line.match_indices('\\') {
+        let rest = &line[idx + 1..];
+        let name_len = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let name = &rest[..name_len];
+
+        let level = match SECTIONING_COMMANDS.iter().find(|(n, _)| *n == name) {
+            Some((_, level)) => *level,
+            None => continue,
+        };
+
+        let rest = rest[name_len..].trim_start();
+        let rest = rest.strip_prefix('*').unwrap_or(rest).trim_start();
+
+        let title = if let Some(rest) = rest.strip_prefix('[') {
+            delimited_text(rest, '[', ']')
+        } else if let Some(rest) = rest.strip_prefix('{') {
+            delimited_text(rest, '{', '}')
+        } else {
+            None
+        };
+
+        if let Some(title) = title {
+            return Some((level, title.trim().to_owned()));
+        }
+    }
+
+    None
+}
+
+/// Get the text up to the closing delimiter that matches an opening one that
+/// has already been consumed, or to the end of the line.
+fn delimited_text(s: &str, open: char, close: char) -> Option<&str> {
+    let mut depth = 0;
+
+    for (idx, c) in s.char_indices() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            if depth == 0 {
+                return Some(&s[..idx]);
+            }
+
+            depth -= 1;
+        }
+    }
+
+    Some(s)
+}
+
+fn normalize_title(title: &str) -> String {
+    title
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// The length of a string in UTF-16 code units, which is how LSP measures
+/// character positions by default.
+fn utf16_len(s: &str) -> u32 {
+    s.encode_utf16().count() as u32
+}
+
+/// Convert an LSP character position in a line to a byte offset.
+fn byte_offset(line: &str, character: u32) -> usize {
+    let mut n = 0;
+
+    for (idx, c) in line.char_indices() {
+        if n >= character {
+            return idx;
+        }
+
+        n += c.len_utf16() as u32;
+    }
+
+    line.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outline_specials() {
+        assert_eq!(
+            outline_title(b"pdf:outline [-] 1 << /Title (Intro \\(part 1\\)) /A << /S /GoTo /D (section.1) >> >>"),
+            Some("Intro (part 1)".to_owned())
+        );
+        assert_eq!(
+            outline_title(b"pdf:out 2 << /Title <FEFF00C9007400E9> /A << >> >>"),
+            Some("\u{c9}t\u{e9}".to_owned())
+        );
+        assert_eq!(
+            outline_title(b"pdf:out 1<</Title(\\376\\377\\000A)>>"),
+            Some("A".to_owned())
+        );
+        assert_eq!(outline_title(b"pdf:dest (section.1) [@thispage]"), None);
+    }
+
+    #[test]
+    fn symbols() {
+        let text = "\\chapter{One}\n\\section[Short]{Long title}\n\\section*{The \\TeX{} engine}\n\\chapter{Two}\n";
+        let outline = vec![
+            "One".to_owned(),
+            "Short".to_owned(),
+            "The TeX engine".to_owned(),
+        ];
+        let symbols = document_symbols(text, &outline);
+
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols[0].name, "One");
+        assert_eq!(symbols[0].range.end, Position::new(3, 0));
+        let children = symbols[0].children.as_ref().unwrap();
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0].name, "Short");
+        assert_eq!(children[1].name, "The TeX engine");
+        assert_eq!(symbols[1].name, "Two");
+        assert_eq!(symbols[1].range.end, Position::new(4, 0));
+    }
+
+    #[test]
+    fn completions() {
+        let cseqs = vec![
+            ("relax".to_owned(), "relax".to_owned()),
+            ("rule".to_owned(), "hrule".to_owned()),
+            ("section".to_owned(), "macro".to_owned()),
+        ];
+        let items = complete_control_sequence("x \\re y", Position::new(0, 5), &cseqs);
+
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].label, "\\relax");
+        assert_eq!(
+            items[0].text_edit,
+            Some(CompletionTextEdit::Edit(TextEdit::new(
+                Range::new(Position::new(0, 2), Position::new(0, 5)),
+                "\\relax".to_owned()
+            )))
+        );
+
+        assert!(complete_control_sequence("x re", Position::new(0, 4), &cseqs).is_empty());
+    }
+}
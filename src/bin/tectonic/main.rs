@@ -20,6 +20,12 @@ use tectonic::{
 mod compile;
 mod watch;
 
+#[cfg(feature = "serialization")]
+mod dump_xdv;
+#[cfg(feature = "serialization")]
+mod lsp;
+#[cfg(feature = "serialization")]
+mod serve;
 #[cfg(feature = "serialization")]
 mod v2cli;
 
@@ -37,6 +43,21 @@ mod v2cli {
     }
 }
 
+#[cfg(feature = "serialization")]
+fn json_status_backend(chatter: ChatterLevel) -> Box<dyn StatusBackend> {
+    Box::new(tectonic::status::json::JsonStatusBackend::new(chatter))
+}
+
+// Likewise for JSON status messages.
+#[cfg(not(feature = "serialization"))]
+fn json_status_backend(_chatter: ChatterLevel) -> Box<dyn StatusBackend> {
+    eprintln!(
+        "fatal error: JSON status messages require the code to have been built \
+        with the \"serialization\" Cargo feature active. This one wasn't."
+    );
+    process::exit(1);
+}
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "Tectonic", about = "Process a (La)TeX document")]
 struct CliOptions {
@@ -52,11 +73,21 @@ struct CliOptions {
     #[structopt(long = "color", name = "when", default_value = "auto", possible_values(&["always", "auto", "never"]))]
     cli_color: String,
 
+    /// How to format status messages
+    #[structopt(long = "message-format", name = "style", default_value = "human", possible_values(&["human", "json"]))]
+    message_format: String,
+
     #[structopt(flatten)]
     compile: compile::CompileOptions,
 }
 
 fn main() {
+    // If we've been launched as a worker process by a `WorkerPool`, this
+    // call takes over and never returns.
+
+    #[cfg(feature = "serialization")]
+    tectonic::worker::maybe_run_worker();
+
     let os_args: Vec<_> = env::args_os().collect();
 
     // A hack so that you can just run `tectonic -Zhelp` without getting a usage
@@ -136,7 +167,9 @@ fn main() {
         _ => unreachable!(),
     };
 
-    let mut status = if use_cli_color {
+    let mut status = if args.message_format == "json" {
+        json_status_backend(chatter_level)
+    } else if use_cli_color {
         Box::new(TermcolorStatusBackend::new(chatter_level)) as Box<dyn StatusBackend>
     } else {
         Box::new(PlainStatusBackend::new(chatter_level)) as Box<dyn StatusBackend>
@@ -0,0 +1,186 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! A simple HTTP server for bundles, used by `tectonic -X bundle serve`.
+//!
+//! The bundle is converted into the "indexed tar" layout and served so that
+//! clients can access it just like the default web bundle: the index at
+//! `/bundle.tar.index.gz`, and the file contents through HTTP range requests
+//! on `/bundle.tar`.
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    sync::Arc,
+    thread,
+};
+use tectonic::{
+    ctry, errmsg,
+    errors::{ChainErrCompatExt, Result},
+    status::StatusBackend,
+    tt_note,
+};
+use tectonic_bundles::{create::write_bundle_as_indexed_tar, Bundle};
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+
+const TAR_PATH: &str = "/bundle.tar";
+const INDEX_PATH: &str = "/bundle.tar.index.gz";
+const N_WORKERS: usize = 8;
+
+/// Serve a bundle over HTTP until the process is killed.
+pub fn serve(
+    mut bundle: Box<dyn Bundle>,
+    addr: &str,
+    status: &mut dyn StatusBackend,
+) -> Result<()> {
+    let work_dir = ctry!(tempfile::tempdir(); "couldn't create a temporary directory");
+    let tar_path = work_dir.path().join("bundle.tar");
+
+    tt_note!(
+        status,
+        "converting the bundle to the indexed tar format ..."
+    );
+    let digest = write_bundle_as_indexed_tar(&mut bundle, &tar_path, status)
+        .chain_err(|| "couldn't convert the bundle")?;
+
+    let server = match Server::http(addr) {
+        Ok(s) => Arc::new(s),
+        Err(e) => return Err(errmsg!("couldn't listen on `{}`: {}", addr, e)),
+    };
+
+    tt_note!(status, "bundle digest is {}", digest.to_string());
+    tt_note!(
+        status,
+        "serving bundle at http://{}{}",
+        server.server_addr(),
+        TAR_PATH
+    );
+
+    let workers: Vec<_> = (0..N_WORKERS)
+        .map(|_| {
+            let server = server.clone();
+            let tar_path = tar_path.clone();
+
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    // Errors here generally mean that the client went away,
+                    // and there's nobody to tell about them.
+                    let _ = handle(request, &tar_path);
+                }
+            })
+        })
+        .collect();
+
+    for w in workers {
+        let _ = w.join();
+    }
+
+    Ok(())
+}
+
+fn handle(request: Request, tar_path: &Path) -> std::io::Result<()> {
+    if *request.method() != Method::Get && *request.method() != Method::Head {
+        return request.respond(Response::empty(405));
+    }
+
+    let (path, with_ranges) = match request.url() {
+        TAR_PATH => (tar_path.to_owned(), true),
+        INDEX_PATH => {
+            let mut p = tar_path.as_os_str().to_owned();
+            p.push(".index.gz");
+            (p.into(), false)
+        }
+        _ => return request.respond(Response::empty(404)),
+    };
+
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+
+    let range = if with_ranges {
+        request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Range"))
+            .map(|h| parse_range(h.value.as_str(), len))
+    } else {
+        None
+    };
+
+    let accept_ranges = Header::from_bytes("Accept-Ranges", "bytes").unwrap();
+
+    match range {
+        None if with_ranges => {
+            request.respond(Response::from_file(file).with_header(accept_ranges))
+        }
+
+        None => request.respond(Response::from_file(file)),
+
+        Some(None) => {
+            let content_range =
+                Header::from_bytes("Content-Range", format!("bytes */{len}")).unwrap();
+            request.respond(Response::empty(416).with_header(content_range))
+        }
+
+        Some(Some((start, end))) => {
+            file.seek(SeekFrom::Start(start))?;
+            let n = end + 1 - start;
+            let content_range =
+                Header::from_bytes("Content-Range", format!("bytes {start}-{end}/{len}")).unwrap();
+
+            request.respond(Response::new(
+                StatusCode(206),
+                vec![accept_ranges, content_range],
+                file.take(n),
+                Some(n as usize),
+                None,
+            ))
+        }
+    }
+}
+
+/// Parse the value of an HTTP `Range` header for a resource of length *len*.
+///
+/// Only single byte ranges are supported. Returns the inclusive start and end
+/// offsets, or None if the range isn't satisfiable.
+fn parse_range(value: &str, len: u64) -> Option<(u64, u64)> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    let (start, end) = spec.split_once('-')?;
+
+    let (start, end) = if start.is_empty() {
+        // A suffix range: the last N bytes.
+        let n: u64 = end.parse().ok()?;
+        (len.saturating_sub(n), len.checked_sub(1)?)
+    } else {
+        let start: u64 = start.parse().ok()?;
+        let end = if end.is_empty() {
+            len.checked_sub(1)?
+        } else {
+            end.parse::<u64>().ok()?.min(len.checked_sub(1)?)
+        };
+        (start, end)
+    };
+
+    if start > end {
+        None
+    } else {
+        Some((start, end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_range;
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("bytes=0-9", 100), Some((0, 9)));
+        assert_eq!(parse_range("bytes=90-200", 100), Some((90, 99)));
+        assert_eq!(parse_range("bytes=50-", 100), Some((50, 99)));
+        assert_eq!(parse_range("bytes=-10", 100), Some((90, 99)));
+        assert_eq!(parse_range("bytes=100-", 100), None);
+        assert_eq!(parse_range("bytes=0-0", 0), None);
+        assert_eq!(parse_range("items=0-9", 100), None);
+        assert_eq!(parse_range("bytes=0-1,5-9", 100), None);
+    }
+}
@@ -4,25 +4,51 @@
 //! The "v2cli" command-line interface -- a "multitool" interface resembling
 //! Cargo, as compared to the classic "rustc-like" CLI.
 
+use byte_unit::Byte;
 use std::{
-    convert::Infallible, env, ffi::OsString, io::Write, path::PathBuf, process, str::FromStr,
-    sync::Arc,
+    collections::VecDeque,
+    convert::Infallible,
+    env,
+    ffi::OsString,
+    fmt::Arguments,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime},
 };
 use structopt::{clap::AppSettings, StructOpt};
 use tectonic::{
     self,
     config::{is_config_test_mode_activated, PersistentConfig},
     ctry,
+    digest::DigestData,
     docmodel::{DocumentExt, DocumentSetupOptions, WorkspaceCreatorExt},
     driver::PassSetting,
-    errors::{Result, SyncError},
-    status::{termcolor::TermcolorStatusBackend, ChatterLevel, StatusBackend},
-    tt_error, tt_note,
+    errmsg,
+    errors::{ChainErrCompatExt, Result, SyncError},
+    io::format_cache::FormatCache,
+    status::{
+        json::JsonStatusBackend, termcolor::TermcolorStatusBackend, ChatterLevel, MessageKind,
+        SourceLocation, StatusBackend,
+    },
+    tt_error, tt_note, tt_warning,
+    worker::WorkerPoolBuilder,
 };
 use tectonic_bridge_core::{SecuritySettings, SecurityStance};
-use tectonic_bundles::Bundle;
-use tectonic_docmodel::workspace::{Workspace, WorkspaceCreator};
+use tectonic_bundles::{
+    cache::Cache, create::BundleCreator, dir::DirBundle, zip::ZipBundle, Bundle,
+};
+use tectonic_docmodel::{
+    document::Document,
+    workspace::{Workspace, WorkspaceCreator},
+};
+use tectonic_errors::anyhow;
 use tectonic_status_base::plain::PlainStatusBackend;
+use tectonic_synctex::SynctexFile;
 use tokio::runtime;
 use watchexec::event::ProcessEnd;
 use watchexec::{
@@ -71,6 +97,7 @@ struct V2CliOptions {
 struct CommandCustomizations {
     always_stderr: bool,
     minimal_chatter: bool,
+    json_messages: bool,
 }
 
 /// The main function for the Cargo-like, "V2" CLI. This intentionally
@@ -118,7 +145,9 @@ pub fn v2_main(effective_args: &[OsString]) {
         _ => unreachable!(),
     };
 
-    let mut status = if use_cli_color {
+    let mut status = if customizations.json_messages {
+        Box::new(JsonStatusBackend::new(chatter_level)) as Box<dyn StatusBackend>
+    } else if use_cli_color {
         let mut sb = TermcolorStatusBackend::new(chatter_level);
         sb.always_stderr(customizations.always_stderr);
         Box::new(sb) as Box<dyn StatusBackend>
@@ -167,6 +196,10 @@ enum Commands {
     /// Run a partial compilation and output an intermediate file
     Dump(DumpCommand),
 
+    #[structopt(name = "dump-xdv")]
+    /// Print the contents of an XDV or SPX file
+    DumpXdv(DumpXdvCommand),
+
     #[structopt(name = "new")]
     /// Create a new document project
     New(NewCommand),
@@ -175,10 +208,18 @@ enum Commands {
     #[structopt(name = "init")]
     Init(InitCommand),
 
+    #[structopt(name = "lsp")]
+    /// Run a Language Server Protocol server for the current document
+    Lsp(LspCommand),
+
     #[structopt(name = "show")]
     /// Display various useful pieces of information
     Show(ShowCommand),
 
+    #[structopt(name = "synctex")]
+    /// Map between locations in the output and in the input files
+    Synctex(SynctexCommand),
+
     #[structopt(name = "watch")]
     /// Watch input files and execute commands on change
     Watch(WatchCommand),
@@ -191,9 +232,12 @@ impl Commands {
             Commands::Bundle(o) => o.customize(cc),
             Commands::Compile(_) => {} // avoid namespacing/etc issues
             Commands::Dump(o) => o.customize(cc),
+            Commands::DumpXdv(o) => o.customize(cc),
             Commands::New(o) => o.customize(cc),
             Commands::Init(o) => o.customize(cc),
+            Commands::Lsp(o) => o.customize(cc),
             Commands::Show(o) => o.customize(cc),
+            Commands::Synctex(o) => o.customize(cc),
             Commands::Watch(o) => o.customize(cc),
         }
     }
@@ -204,9 +248,12 @@ impl Commands {
             Commands::Bundle(o) => o.execute(config, status),
             Commands::Compile(o) => o.execute(config, status),
             Commands::Dump(o) => o.execute(config, status),
+            Commands::DumpXdv(o) => o.execute(config, status),
             Commands::New(o) => o.execute(config, status),
             Commands::Init(o) => o.execute(config, status),
+            Commands::Lsp(o) => o.execute(config, status),
             Commands::Show(o) => o.execute(config, status),
+            Commands::Synctex(o) => o.execute(config, status),
             Commands::Watch(o) => o.execute(config, status),
         }
     }
@@ -223,6 +270,14 @@ pub struct BuildCommand {
     #[structopt(short = "C", long)]
     only_cached: bool,
 
+    /// Run shell-escape commands in a sandbox without network access (Linux only)
+    #[structopt(long)]
+    sandbox_shell_escape: bool,
+
+    /// Rebuild even if no inputs have changed since the last build
+    #[structopt(long)]
+    force: bool,
+
     /// Keep the intermediate files generated during processing
     #[structopt(short, long)]
     keep_intermediates: bool,
@@ -242,14 +297,57 @@ pub struct BuildCommand {
     /// Specify a target to be used by the build
     #[structopt(long, help = "Specify the target of the build.")]
     target: Option<String>,
+
+    /// Maximum number of outputs to build concurrently (0 for one per CPU)
+    #[structopt(long, short = "j", default_value = "1")]
+    jobs: usize,
+
+    /// Write a minimal bundle containing only the support files used by the build
+    #[structopt(long = "prune-bundle", name = "path")]
+    prune_bundle: Option<PathBuf>,
+
+    /// Build only the named workspace document (may be repeated)
+    //
+    // Cargo spells this `-p`, but here that's already taken by `--print`.
+    #[structopt(long = "doc", short = "d", name = "doc", number_of_values = 1)]
+    docs: Vec<String>,
+
+    /// How to format status messages
+    #[structopt(
+        long = "message-format",
+        name = "style",
+        default_value = "human",
+        possible_values(&["human", "json"])
+    )]
+    message_format: String,
 }
 
 impl BuildCommand {
-    fn customize(&self, _cc: &mut CommandCustomizations) {}
+    fn customize(&self, cc: &mut CommandCustomizations) {
+        cc.json_messages = self.message_format == "json";
+    }
 
     fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
         let ws = Workspace::open_from_environment()?;
-        let doc = ws.first_document();
+
+        // If no documents are named explicitly, build the one that we're in, or
+        // all of them if we're at the root of a multi-document workspace.
+        let docs = if !self.docs.is_empty() {
+            let mut docs = Vec::new();
+
+            for name in &self.docs {
+                match ws.document(name) {
+                    Some(d) => docs.push(d),
+                    None => return Err(errmsg!("no document named `{}` in the workspace", name)),
+                }
+            }
+
+            docs
+        } else if let Some(d) = ws.current_document() {
+            vec![d]
+        } else {
+            ws.documents().iter().collect()
+        };
 
         // Default to allowing insecure since it would be super duper annoying
         // to have to pass `--trusted` every time to build a personal document
@@ -264,45 +362,328 @@ impl BuildCommand {
         let mut setup_options =
             DocumentSetupOptions::new_with_security(SecuritySettings::new(stance));
         setup_options.only_cached(self.only_cached);
+        setup_options.shell_escape_sandbox(self.sandbox_shell_escape);
+
+        let mut jobs = Vec::new();
 
-        for output_name in doc.output_names() {
-            if let Some(out) = self.target.as_ref() {
-                if out != output_name {
-                    continue;
+        for doc in docs {
+            for output_name in doc.output_names() {
+                if let Some(out) = self.target.as_ref() {
+                    if out != output_name {
+                        continue;
+                    }
                 }
+
+                jobs.push((doc, output_name));
             }
+        }
 
-            let mut builder = doc.setup_session(output_name, &setup_options, status)?;
-
-            builder
-                .format_cache_path(config.format_cache_path()?)
-                .keep_intermediates(self.keep_intermediates)
-                .keep_logs(self.keep_logs)
-                .print_stdout(self.print_stdout);
-
-            crate::compile::run_and_report(builder, status)?;
-
-            if self.open {
-                let out_file = doc.output_main_file(output_name);
-
-                if is_config_test_mode_activated() {
-                    tt_note!(status, "not opening `{}` -- test mode", out_file.display());
-                } else {
-                    tt_note!(status, "opening `{}`", out_file.display());
-                    if let Err(e) = open::that(&out_file) {
-                        tt_error!(
-                            status,
-                            "failed to open `{}` with system handler",
-                            out_file.display();
-                            e.into()
-                        )
+        if self.prune_bundle.is_some() && jobs.len() > 1 {
+            return Err(errmsg!(
+                "a minimal bundle can only be created when building a single output; \
+                 use `--doc` and `--target` to select one"
+            ));
+        }
+
+        let n_workers = match self.jobs {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+
+        // The engine chatter of concurrent builds would be interleaved, so
+        // printing it forces the outputs to be built one by one.
+
+        if n_workers > 1 && jobs.len() > 1 && !self.print_stdout {
+            return self.build_concurrently(&jobs, n_workers, &setup_options, &config, status);
+        }
+
+        for (doc, output_name) in jobs {
+            self.build_one(doc, output_name, &setup_options, &config, status)?;
+        }
+
+        Ok(0)
+    }
+
+    /// Build one output in this process.
+    fn build_one(
+        &self,
+        doc: &Document,
+        output_name: &str,
+        setup_options: &DocumentSetupOptions,
+        config: &PersistentConfig,
+        status: &mut dyn StatusBackend,
+    ) -> Result<()> {
+        let mut builder = doc.setup_session(output_name, setup_options, status)?;
+
+        builder
+            .format_cache_path(config.format_cache_path()?)
+            .keep_intermediates(self.keep_intermediates)
+            .keep_logs(self.keep_logs)
+            .print_stdout(self.print_stdout)
+            .incremental(!self.force);
+
+        if let Some(p) = self.prune_bundle.as_ref() {
+            builder.pruned_bundle_path(p);
+        }
+
+        crate::compile::run_and_report(builder, status)?;
+        self.maybe_open(doc, output_name, status);
+        Ok(())
+    }
+
+    /// Build several outputs at once.
+    ///
+    /// The engines keep global state, so only one of them can run at a time
+    /// in any given process. Therefore each output is built in a processing
+    /// session of its own in a worker process from a `WorkerPool`. The
+    /// workers get their support files through this process, from a bundle
+    /// opened for each build; the bundle cache supports such concurrent use.
+    /// The messages of each build are recorded and reported in one piece when
+    /// it finishes, so that those of different builds aren't interleaved.
+    ///
+    /// Workers never enable insecure features, so outputs that use
+    /// shell-escape or external tools are built in this process, one after
+    /// another, once the others are done.
+    fn build_concurrently(
+        &self,
+        jobs: &[(&Document, &str)],
+        n_workers: usize,
+        setup_options: &DocumentSetupOptions,
+        config: &PersistentConfig,
+        status: &mut dyn StatusBackend,
+    ) -> Result<i32> {
+        let format_cache_path = config.format_cache_path()?;
+        let mut queue = VecDeque::new();
+        let mut local_jobs = Vec::new();
+
+        for &(doc, output_name) in jobs {
+            match doc.setup_worker_job(output_name, setup_options)? {
+                Some(mut job) => {
+                    job.format_cache_path(&format_cache_path)
+                        .keep_intermediates(self.keep_intermediates)
+                        .keep_logs(self.keep_logs)
+                        .incremental(!self.force);
+                    queue.push_back((doc, output_name, job));
+                }
+
+                None => local_jobs.push((doc, output_name)),
+            }
+        }
+
+        let n_workers = n_workers.min(queue.len());
+        let mut n_failed = 0;
+
+        if n_workers > 0 {
+            tt_note!(
+                status,
+                "building {} outputs with up to {} concurrent jobs",
+                queue.len(),
+                n_workers
+            );
+
+            let mut pool = WorkerPoolBuilder::default();
+            pool.max_workers(n_workers);
+            let pool = pool.build()?;
+            let queue = Mutex::new(queue);
+            let (tx, rx) = mpsc::channel();
+
+            thread::scope(|scope| {
+                for _ in 0..n_workers {
+                    let tx = tx.clone();
+                    let queue = &queue;
+                    let pool = &pool;
+
+                    scope.spawn(move || loop {
+                        let (doc, output_name, job) = match queue.lock().unwrap().pop_front() {
+                            Some(j) => j,
+                            None => break,
+                        };
+
+                        let mut recorder = RecordingStatusBackend::default();
+                        let result = doc
+                            .bundle(setup_options, &mut recorder)
+                            .and_then(|mut bundle| pool.run(&job, bundle.as_mut(), &mut recorder));
+
+                        if tx.send((doc, output_name, recorder, result)).is_err() {
+                            return;
+                        }
+                    });
+                }
+
+                drop(tx);
+
+                for (doc, output_name, recorder, result) in rx {
+                    recorder.replay(status);
+
+                    match result {
+                        Ok(_) => self.maybe_open(doc, output_name, status),
+
+                        Err(e) => {
+                            n_failed += 1;
+                            tt_error!(
+                                status,
+                                "failed to build output `{}` of document `{}`",
+                                output_name,
+                                doc.name;
+                                SyncError::new(e).into()
+                            );
+                        }
                     }
                 }
+            });
+        }
+
+        for (doc, output_name) in local_jobs {
+            if let Err(e) = self.build_one(doc, output_name, setup_options, config, status) {
+                n_failed += 1;
+                tt_error!(
+                    status,
+                    "failed to build output `{}` of document `{}`",
+                    output_name,
+                    doc.name;
+                    SyncError::new(e).into()
+                );
             }
         }
 
+        if n_failed > 0 {
+            return Err(errmsg!("{} of {} builds failed", n_failed, jobs.len()));
+        }
+
         Ok(0)
     }
+
+    /// Open a freshly built output with the system handler, if requested.
+    fn maybe_open(&self, doc: &Document, output_name: &str, status: &mut dyn StatusBackend) {
+        if !self.open {
+            return;
+        }
+
+        let out_file = doc.output_main_file(output_name);
+
+        if is_config_test_mode_activated() {
+            tt_note!(status, "not opening `{}` -- test mode", out_file.display());
+        } else {
+            tt_note!(status, "opening `{}`", out_file.display());
+            if let Err(e) = open::that(&out_file) {
+                tt_error!(
+                    status,
+                    "failed to open `{}` with system handler",
+                    out_file.display();
+                    e.into()
+                )
+            }
+        }
+    }
+}
+
+/// A status backend that records messages so that they can be reported later,
+/// possibly by another thread. Progress reports aren't recorded, since they'd
+/// be out of date by the time they were reported.
+#[derive(Default)]
+struct RecordingStatusBackend {
+    events: Vec<RecordedStatus>,
+}
+
+/// A status event recorded by [`RecordingStatusBackend`]. Errors are recorded
+/// as the messages of their chains of causes.
+enum RecordedStatus {
+    Report(MessageKind, String, Option<Vec<String>>),
+    Error(Vec<String>),
+    NoteHighlighted(String, String, String),
+    Diagnostic(MessageKind, String, Option<SourceLocation>),
+    PassBegin(String),
+    PassEnd(String, bool),
+    ErrorLogs(Vec<u8>),
+}
+
+impl RecordingStatusBackend {
+    /// Report the recorded events to another status backend.
+    fn replay(self, status: &mut dyn StatusBackend) {
+        fn error(causes: &[String]) -> anyhow::Error {
+            let mut causes = causes.iter().rev();
+            let mut err = anyhow::Error::msg(causes.next().cloned().unwrap_or_default());
+
+            for c in causes {
+                err = err.context(c.clone());
+            }
+
+            err
+        }
+
+        for event in self.events {
+            match event {
+                RecordedStatus::Report(kind, message, causes) => {
+                    let err = causes.map(|c| error(&c));
+                    status.report(kind, format_args!("{message}"), err.as_ref());
+                }
+                RecordedStatus::Error(causes) => status.report_error(&error(&causes)),
+                RecordedStatus::NoteHighlighted(before, highlighted, after) => {
+                    status.note_highlighted(&before, &highlighted, &after)
+                }
+                RecordedStatus::Diagnostic(kind, message, location) => {
+                    status.report_diagnostic(kind, format_args!("{message}"), location.as_ref())
+                }
+                RecordedStatus::PassBegin(pass) => status.pass_begin(&pass),
+                RecordedStatus::PassEnd(pass, success) => status.pass_end(&pass, success),
+                RecordedStatus::ErrorLogs(output) => status.dump_error_logs(&output),
+            }
+        }
+    }
+}
+
+/// Get the messages of the chain of causes of an error.
+fn error_causes(err: &anyhow::Error) -> Vec<String> {
+    err.chain().map(|c| c.to_string()).collect()
+}
+
+impl StatusBackend for RecordingStatusBackend {
+    fn report(&mut self, kind: MessageKind, args: Arguments, err: Option<&anyhow::Error>) {
+        self.events.push(RecordedStatus::Report(
+            kind,
+            args.to_string(),
+            err.map(error_causes),
+        ));
+    }
+
+    fn report_error(&mut self, err: &anyhow::Error) {
+        self.events.push(RecordedStatus::Error(error_causes(err)));
+    }
+
+    fn note_highlighted(&mut self, before: &str, highlighted: &str, after: &str) {
+        self.events.push(RecordedStatus::NoteHighlighted(
+            before.to_owned(),
+            highlighted.to_owned(),
+            after.to_owned(),
+        ));
+    }
+
+    fn report_diagnostic(
+        &mut self,
+        kind: MessageKind,
+        args: Arguments,
+        location: Option<&SourceLocation>,
+    ) {
+        self.events.push(RecordedStatus::Diagnostic(
+            kind,
+            args.to_string(),
+            location.cloned(),
+        ));
+    }
+
+    fn pass_begin(&mut self, pass: &str) {
+        self.events.push(RecordedStatus::PassBegin(pass.to_owned()));
+    }
+
+    fn pass_end(&mut self, pass: &str, success: bool) {
+        self.events
+            .push(RecordedStatus::PassEnd(pass.to_owned(), success));
+    }
+
+    fn dump_error_logs(&mut self, output: &[u8]) {
+        self.events
+            .push(RecordedStatus::ErrorLogs(output.to_owned()));
+    }
 }
 
 /// `bundle`: Commands relating to Tectonic bundles
@@ -314,27 +695,45 @@ pub struct BundleCommand {
 
 #[derive(Debug, Eq, PartialEq, StructOpt)]
 enum BundleCommands {
+    #[structopt(name = "cache")]
+    /// Inspect and clean up the local cache of bundle data
+    Cache(BundleCacheCommand),
+
     #[structopt(name = "cat")]
     /// Dump the contents of a file in the bundle
     Cat(BundleCatCommand),
 
+    #[structopt(name = "create")]
+    /// Create a new bundle from local files
+    Create(BundleCreateCommand),
+
     #[structopt(name = "search")]
     /// Filter the list of filenames contained in the bundle
     Search(BundleSearchCommand),
+
+    #[structopt(name = "serve")]
+    /// Serve a local bundle over HTTP
+    Serve(BundleServeCommand),
 }
 
 impl BundleCommand {
     fn customize(&self, cc: &mut CommandCustomizations) {
         match &self.command {
+            BundleCommands::Cache(c) => c.customize(cc),
             BundleCommands::Cat(c) => c.customize(cc),
+            BundleCommands::Create(c) => c.customize(cc),
             BundleCommands::Search(c) => c.customize(cc),
+            BundleCommands::Serve(c) => c.customize(cc),
         }
     }
 
     fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
         match self.command {
+            BundleCommands::Cache(c) => c.execute(config, status),
             BundleCommands::Cat(c) => c.execute(config, status),
+            BundleCommands::Create(c) => c.execute(config, status),
             BundleCommands::Search(c) => c.execute(config, status),
+            BundleCommands::Serve(c) => c.execute(config, status),
         }
     }
 }
@@ -342,13 +741,14 @@ impl BundleCommand {
 fn get_a_bundle(
     _config: PersistentConfig,
     only_cached: bool,
+    doc_name: Option<&str>,
     status: &mut dyn StatusBackend,
 ) -> Result<Box<dyn Bundle>> {
     use tectonic_docmodel::workspace::NoWorkspaceFoundError;
 
     match Workspace::open_from_environment() {
         Ok(ws) => {
-            let doc = ws.first_document();
+            let doc = select_document(ws, doc_name)?;
             let mut options: DocumentSetupOptions = Default::default();
             options.only_cached(only_cached);
             doc.bundle(&options, status)
@@ -372,12 +772,276 @@ fn get_a_bundle(
     }
 }
 
+/// Choose the workspace document that a command acting on just one document
+/// should use.
+///
+/// Without an explicit name, this is the document that we're in, or the only
+/// one in the workspace. It's an error to leave the choice open at the root of
+/// a workspace with more than one document.
+fn select_document(ws: Workspace, name: Option<&str>) -> Result<Document> {
+    if let Some(name) = name {
+        return match ws.into_documents().into_iter().find(|d| d.name == name) {
+            Some(d) => Ok(d),
+            None => Err(errmsg!("no document named `{}` in the workspace", name)),
+        };
+    }
+
+    if ws.current_document().is_none() && ws.documents().len() > 1 {
+        return Err(errmsg!(
+            "the workspace has more than one document; use `--doc` to choose one"
+        ));
+    }
+
+    Ok(ws.into_first_document())
+}
+
+#[derive(Debug, Eq, PartialEq, StructOpt)]
+struct BundleCacheCommand {
+    #[structopt(subcommand)]
+    command: BundleCacheCommands,
+}
+
+#[derive(Debug, Eq, PartialEq, StructOpt)]
+enum BundleCacheCommands {
+    #[structopt(name = "gc")]
+    /// Remove cached data for bundles that are no longer in use
+    Gc(BundleCacheGcCommand),
+
+    #[structopt(name = "list")]
+    /// List the bundles that have data in the cache
+    List(BundleCacheListCommand),
+
+    #[structopt(name = "purge")]
+    /// Remove all cached bundle data and formats
+    Purge(BundleCachePurgeCommand),
+
+    #[structopt(name = "size")]
+    /// Print the amount of disk space used by the cache
+    Size(BundleCacheSizeCommand),
+}
+
+impl BundleCacheCommand {
+    fn customize(&self, cc: &mut CommandCustomizations) {
+        match &self.command {
+            BundleCacheCommands::Gc(c) => c.customize(cc),
+            BundleCacheCommands::List(c) => c.customize(cc),
+            BundleCacheCommands::Purge(c) => c.customize(cc),
+            BundleCacheCommands::Size(c) => c.customize(cc),
+        }
+    }
+
+    fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        match self.command {
+            BundleCacheCommands::Gc(c) => c.execute(config, status),
+            BundleCacheCommands::List(c) => c.execute(config, status),
+            BundleCacheCommands::Purge(c) => c.execute(config, status),
+            BundleCacheCommands::Size(c) => c.execute(config, status),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, StructOpt)]
+struct BundleCacheGcCommand {
+    /// Keep the data for the bundle with this digest (may be repeated)
+    #[structopt(long = "keep-digest", number_of_values = 1)]
+    keep_digests: Vec<DigestData>,
+
+    /// Also remove the data for bundles that haven't been used in this many days
+    #[structopt(long = "max-age")]
+    max_age: Option<u64>,
+}
+
+impl BundleCacheGcCommand {
+    fn customize(&self, _cc: &mut CommandCustomizations) {}
+
+    fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        let cache = Cache::get_user_default()?;
+        let cutoff = match self.max_age {
+            Some(days) => match days
+                .checked_mul(86400)
+                .and_then(|secs| SystemTime::now().checked_sub(Duration::from_secs(secs)))
+            {
+                Some(c) => Some(c),
+                None => return Err(errmsg!("the maximum age of {} days is too large", days)),
+            },
+            None => None,
+        };
+
+        // Keep every bundle that a cached URL still refers to, as long as it
+        // has been used recently enough.
+        let bundles = cache.bundles()?;
+        let mut keep = self.keep_digests;
+
+        for info in &bundles {
+            if let Some(used) = info.last_used {
+                #[allow(clippy::unnecessary_map_or)] // is_none_or requires Rust 1.82
+                if cutoff.map_or(true, |c| used >= c) {
+                    keep.push(info.digest);
+                }
+            }
+        }
+
+        let summary = cache.gc(&keep)?;
+
+        // Formats are removed if they belong to a bundle whose data we just
+        // removed, or if they are from a different version of the engine. We
+        // don't know anything about formats for other bundles, such as local
+        // ZIP files, so we leave them alone.
+        let (n_formats, n_format_bytes) = remove_formats(&config.format_cache_path()?, |f| {
+            !f.is_current
+                || (bundles.iter().any(|b| b.digest == f.bundle_digest)
+                    && !keep.contains(&f.bundle_digest))
+        })?;
+
+        tt_note!(
+            status,
+            "removed data for {} bundle(s): {} cached file(s) and {} format(s), {} in total",
+            summary.n_bundles,
+            summary.n_files,
+            n_formats,
+            Byte::from_bytes((summary.n_bytes + n_format_bytes) as u128).get_appropriate_unit(true)
+        );
+        Ok(0)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, StructOpt)]
+struct BundleCacheListCommand {}
+
+impl BundleCacheListCommand {
+    fn customize(&self, cc: &mut CommandCustomizations) {
+        cc.always_stderr = true;
+    }
+
+    fn execute(self, config: PersistentConfig, _status: &mut dyn StatusBackend) -> Result<i32> {
+        let cache = Cache::get_user_default()?;
+        let formats = FormatCache::list_formats(&config.format_cache_path()?)?;
+        let now = SystemTime::now();
+
+        for info in cache.bundles()? {
+            let formats_size: u64 = formats
+                .iter()
+                .filter(|f| f.bundle_digest == info.digest)
+                .map(|f| f.size)
+                .sum();
+
+            let used = match info.last_used {
+                Some(t) => match now.duration_since(t).map(|d| d.as_secs() / 86400) {
+                    Ok(0) | Err(_) => "used today".to_owned(),
+                    Ok(1) => "used 1 day ago".to_owned(),
+                    Ok(n) => format!("used {n} days ago"),
+                },
+                None => "unreferenced".to_owned(),
+            };
+
+            println!(
+                "{}  {:>11}  {:>6} files  {:<16}  {}",
+                info.digest.to_string(),
+                Byte::from_bytes((info.size + formats_size) as u128)
+                    .get_appropriate_unit(true)
+                    .to_string(),
+                info.n_files,
+                used,
+                info.resolved_url.as_deref().unwrap_or("-")
+            );
+        }
+
+        Ok(0)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, StructOpt)]
+struct BundleCachePurgeCommand {}
+
+impl BundleCachePurgeCommand {
+    fn customize(&self, _cc: &mut CommandCustomizations) {}
+
+    fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        let summary = Cache::get_user_default()?.gc(&[])?;
+        let (n_formats, n_format_bytes) = remove_formats(&config.format_cache_path()?, |_| true)?;
+
+        tt_note!(
+            status,
+            "removed data for {} bundle(s): {} cached file(s) and {} format(s), {} in total",
+            summary.n_bundles,
+            summary.n_files,
+            n_formats,
+            Byte::from_bytes((summary.n_bytes + n_format_bytes) as u128).get_appropriate_unit(true)
+        );
+        Ok(0)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, StructOpt)]
+struct BundleCacheSizeCommand {}
+
+impl BundleCacheSizeCommand {
+    fn customize(&self, cc: &mut CommandCustomizations) {
+        cc.always_stderr = true;
+    }
+
+    fn execute(self, config: PersistentConfig, _status: &mut dyn StatusBackend) -> Result<i32> {
+        let data_size = Cache::get_user_default()?.size()?;
+        let formats_size: u64 = FormatCache::list_formats(&config.format_cache_path()?)?
+            .iter()
+            .map(|f| f.size)
+            .sum();
+
+        println!(
+            "bundle data: {}",
+            Byte::from_bytes(data_size as u128).get_appropriate_unit(true)
+        );
+        println!(
+            "formats:     {}",
+            Byte::from_bytes(formats_size as u128).get_appropriate_unit(true)
+        );
+        println!(
+            "total:       {}",
+            Byte::from_bytes((data_size + formats_size) as u128).get_appropriate_unit(true)
+        );
+        Ok(0)
+    }
+}
+
+/// Remove the cached formats matching a predicate.
+///
+/// Returns the number of formats removed and their total size.
+fn remove_formats<F>(formats_dir: &Path, mut predicate: F) -> Result<(usize, u64)>
+where
+    F: FnMut(&tectonic::io::format_cache::CachedFormatInfo) -> bool,
+{
+    let mut n = 0;
+    let mut size = 0;
+
+    for f in FormatCache::list_formats(formats_dir)? {
+        if predicate(&f) {
+            ctry!(fs::remove_file(&f.path); "failed to remove format file `{}`", f.path.display());
+
+            // Some formats have a record of their dependencies alongside.
+            let deps = f.path.with_extension("deps");
+            match fs::remove_file(&deps) {
+                Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                r => ctry!(r; "failed to remove format dependency file `{}`", deps.display()),
+            }
+
+            n += 1;
+            size += f.size;
+        }
+    }
+
+    Ok((n, size))
+}
+
 #[derive(Debug, Eq, PartialEq, StructOpt)]
 struct BundleCatCommand {
     /// Use only resource files cached locally
     #[structopt(short = "C", long)]
     only_cached: bool,
 
+    /// Use the named workspace document
+    #[structopt(long = "doc", short = "d", name = "doc")]
+    doc: Option<String>,
+
     #[structopt(help = "The name of the file to dump")]
     filename: String,
 }
@@ -388,7 +1052,7 @@ impl BundleCatCommand {
     }
 
     fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
-        let mut bundle = get_a_bundle(config, self.only_cached, status)?;
+        let mut bundle = get_a_bundle(config, self.only_cached, self.doc.as_deref(), status)?;
         let mut ih = bundle
             .input_open_name(&self.filename, status)
             .must_exist()?;
@@ -397,12 +1061,94 @@ impl BundleCatCommand {
     }
 }
 
+#[derive(Debug, Eq, PartialEq, StructOpt)]
+struct BundleCreateCommand {
+    /// The kind of bundle to create
+    #[structopt(
+        long,
+        default_value = "zip",
+        possible_values(&["zip", "itar"])
+    )]
+    format: String,
+
+    /// A file listing additional files to include, one path per line
+    #[structopt(long = "file-list", name = "list")]
+    file_list: Option<PathBuf>,
+
+    /// The path of the bundle file to create
+    #[structopt(long, short)]
+    output: PathBuf,
+
+    /// Directory trees whose files should be included
+    #[structopt(name = "dir")]
+    dirs: Vec<PathBuf>,
+}
+
+impl BundleCreateCommand {
+    fn customize(&self, _cc: &mut CommandCustomizations) {}
+
+    fn execute(self, _config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        let mut creator = BundleCreator::new();
+        let mut n_skipped = 0;
+
+        for dir in &self.dirs {
+            n_skipped += creator.add_tree(dir)?;
+        }
+
+        if let Some(list) = &self.file_list {
+            let text = ctry!(
+                std::fs::read_to_string(list);
+                "couldn\'t read file list `{}`", list.display()
+            );
+
+            for line in text.lines() {
+                let line = line.trim();
+
+                if !line.is_empty() && !creator.add_file(line)? {
+                    n_skipped += 1;
+                }
+            }
+        }
+
+        if creator.is_empty() {
+            return Err(errmsg!("no files were specified for the new bundle"));
+        }
+
+        if n_skipped > 0 {
+            tt_warning!(
+                status,
+                "skipped {} files whose names duplicated those of files already in the bundle",
+                n_skipped
+            );
+        }
+
+        let digest = if self.format == "itar" {
+            creator.write_indexed_tar(&self.output)?
+        } else {
+            creator.write_zip(&self.output)?
+        };
+
+        tt_note!(
+            status,
+            "wrote {} files to `{}`; bundle digest is {}",
+            creator.len(),
+            self.output.display(),
+            digest.to_string()
+        );
+        Ok(0)
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, StructOpt)]
 struct BundleSearchCommand {
     /// Use only resource files cached locally
     #[structopt(short = "C", long)]
     only_cached: bool,
 
+    /// Use the named workspace document
+    #[structopt(long = "doc", short = "d", name = "doc")]
+    doc: Option<String>,
+
     #[structopt(help = "The search term")]
     term: Option<String>,
 }
@@ -413,7 +1159,7 @@ impl BundleSearchCommand {
     }
 
     fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
-        let mut bundle = get_a_bundle(config, self.only_cached, status)?;
+        let mut bundle = get_a_bundle(config, self.only_cached, self.doc.as_deref(), status)?;
         let files = bundle.all_files(status)?;
 
         // Is there a better way to do this?
@@ -433,6 +1179,35 @@ impl BundleSearchCommand {
     }
 }
 
+#[derive(Debug, Eq, PartialEq, StructOpt)]
+struct BundleServeCommand {
+    /// The address to listen on
+    #[structopt(long, default_value = "127.0.0.1:8000")]
+    addr: String,
+
+    /// The ZIP file or directory containing the bundle to serve
+    #[structopt()]
+    path: PathBuf,
+}
+
+impl BundleServeCommand {
+    fn customize(&self, _cc: &mut CommandCustomizations) {}
+
+    fn execute(self, _config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        let bundle: Box<dyn Bundle> = if self.path.is_dir() {
+            Box::new(DirBundle::new(&self.path))
+        } else {
+            Box::new(
+                ZipBundle::open(&self.path)
+                    .chain_err(|| format!("couldn't open bundle `{}`", self.path.display()))?,
+            )
+        };
+
+        crate::serve::serve(bundle, &self.addr, status)?;
+        Ok(0)
+    }
+}
+
 /// `dump`: Run a partial build and dump an intermediate file
 #[derive(Debug, Eq, PartialEq, StructOpt)]
 pub struct DumpCommand {
@@ -448,6 +1223,10 @@ pub struct DumpCommand {
     #[structopt(short = "p", long)]
     profile: Option<String>,
 
+    /// Use the named workspace document
+    #[structopt(long = "doc", short = "d", name = "doc")]
+    doc: Option<String>,
+
     /// Dump the file or files whose names end with the argument
     #[structopt(long = "suffix", short)]
     suffix_mode: bool,
@@ -465,7 +1244,7 @@ impl DumpCommand {
 
     fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
         let ws = Workspace::open_from_environment()?;
-        let doc = ws.first_document();
+        let doc = select_document(ws, self.doc.as_deref())?;
 
         // Default to allowing insecure since it would be super duper annoying
         // to have to pass `--trusted` every time to build a personal document
@@ -531,6 +1310,34 @@ impl DumpCommand {
     }
 }
 
+/// `dump-xdv`: Print the contents of an XDV or SPX file
+#[derive(Debug, Eq, PartialEq, StructOpt)]
+pub struct DumpXdvCommand {
+    /// Print one JSON object per page instead of listing every command
+    #[structopt(long)]
+    json: bool,
+
+    /// The XDV or SPX file to dump
+    #[structopt(name = "file")]
+    file: PathBuf,
+}
+
+impl DumpXdvCommand {
+    fn customize(&self, cc: &mut CommandCustomizations) {
+        cc.always_stderr = true;
+    }
+
+    fn execute(self, _config: PersistentConfig, _status: &mut dyn StatusBackend) -> Result<i32> {
+        let stdout = std::io::stdout();
+        crate::dump_xdv::dump(
+            &self.file,
+            self.json,
+            std::io::BufWriter::new(stdout.lock()),
+        )?;
+        Ok(0)
+    }
+}
+
 /// `watch`: Watch input files and execute commands on change
 #[derive(Debug, Eq, PartialEq, StructOpt)]
 pub struct WatchCommand {
@@ -714,6 +1521,57 @@ impl InitCommand {
     }
 }
 
+/// `lsp`: Run a Language Server Protocol server for the current document
+#[derive(Debug, Eq, PartialEq, StructOpt)]
+pub struct LspCommand {
+    /// Document is untrusted -- disable all known-insecure features
+    #[structopt(long)]
+    untrusted: bool,
+
+    /// Use only resource files cached locally
+    #[structopt(short = "C", long)]
+    only_cached: bool,
+
+    /// Specify the output to build when analyzing the document
+    #[structopt(long)]
+    target: Option<String>,
+
+    /// Use the named workspace document
+    #[structopt(long = "doc", short = "d", name = "doc")]
+    doc: Option<String>,
+}
+
+impl LspCommand {
+    fn customize(&self, cc: &mut CommandCustomizations) {
+        // Standard output is used for the protocol.
+        cc.always_stderr = true;
+    }
+
+    fn execute(self, config: PersistentConfig, _status: &mut dyn StatusBackend) -> Result<i32> {
+        let ws = Workspace::open_from_environment()?;
+        let doc = select_document(ws, self.doc.as_deref())?;
+
+        let stance = if self.untrusted {
+            SecurityStance::DisableInsecures
+        } else {
+            SecurityStance::MaybeAllowInsecures
+        };
+
+        let mut setup_options =
+            DocumentSetupOptions::new_with_security(SecuritySettings::new(stance));
+        setup_options.only_cached(self.only_cached);
+
+        // If the output is unspecified, just grab one at (pseudo-)random.
+        let target = match self.target {
+            Some(t) => t,
+            None => ctry!(doc.output_names().next(); "the document has no outputs").to_owned(),
+        };
+
+        crate::lsp::serve(doc, target, setup_options, config.format_cache_path()?)?;
+        Ok(0)
+    }
+}
+
 /// `show`: Show various useful pieces of information.
 #[derive(Debug, Eq, PartialEq, StructOpt)]
 pub struct ShowCommand {
@@ -757,3 +1615,165 @@ impl ShowUserCacheDirCommand {
         Ok(0)
     }
 }
+
+/// `synctex`: Map between locations in the output and in the input files
+#[derive(Debug, PartialEq, StructOpt)]
+pub struct SynctexCommand {
+    #[structopt(subcommand)]
+    command: SynctexCommands,
+}
+
+#[derive(Debug, PartialEq, StructOpt)]
+enum SynctexCommands {
+    #[structopt(name = "edit")]
+    /// Find the input location that produced a point of the output
+    Edit(SynctexEditCommand),
+
+    #[structopt(name = "view")]
+    /// Find the parts of the output produced by a line of an input file
+    View(SynctexViewCommand),
+}
+
+impl SynctexCommand {
+    fn customize(&self, cc: &mut CommandCustomizations) {
+        match &self.command {
+            SynctexCommands::Edit(c) => c.customize(cc),
+            SynctexCommands::View(c) => c.customize(cc),
+        }
+    }
+
+    fn execute(self, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<i32> {
+        match self.command {
+            SynctexCommands::Edit(c) => c.execute(config, status),
+            SynctexCommands::View(c) => c.execute(config, status),
+        }
+    }
+}
+
+/// Load the SyncTeX data for *output*, which may name either the output
+/// document or its SyncTeX file.
+fn open_synctex(output: &Path) -> Result<SynctexFile> {
+    let name = output.to_string_lossy();
+
+    let path = if name.ends_with(".synctex.gz") || name.ends_with(".synctex") {
+        output.to_owned()
+    } else {
+        let gz = output.with_extension("synctex.gz");
+
+        if gz.exists() {
+            gz
+        } else {
+            output.with_extension("synctex")
+        }
+    };
+
+    Ok(SynctexFile::open(path)?)
+}
+
+#[derive(Debug, PartialEq, StructOpt)]
+struct SynctexEditCommand {
+    /// The output document, or its SyncTeX file
+    #[structopt(name = "output")]
+    output: PathBuf,
+
+    /// The page number, starting at 1
+    #[structopt(name = "page")]
+    page: u32,
+
+    /// The horizontal position, in points from the left edge of the page
+    #[structopt(name = "x")]
+    x: f64,
+
+    /// The vertical position, in points from the top edge of the page
+    #[structopt(name = "y")]
+    y: f64,
+}
+
+impl SynctexEditCommand {
+    fn customize(&self, cc: &mut CommandCustomizations) {
+        cc.always_stderr = true;
+    }
+
+    fn execute(self, _config: PersistentConfig, _status: &mut dyn StatusBackend) -> Result<i32> {
+        let synctex = open_synctex(&self.output)?;
+
+        let loc = match synctex.inverse_search(self.page, self.x, self.y) {
+            Some(loc) => loc,
+            None => {
+                return Err(errmsg!(
+                    "no input location corresponds to page {} of `{}`",
+                    self.page,
+                    self.output.display()
+                ))
+            }
+        };
+
+        // This mimics the output of the `synctex` program, so that tools that
+        // already parse it can use it unchanged.
+        println!("SyncTeX result begin");
+        println!("Output:{}", self.output.display());
+        println!("Input:{}", loc.file);
+        println!("Line:{}", loc.line);
+        println!("Column:-1");
+        println!("Offset:0");
+        println!("Context:");
+        println!("SyncTeX result end");
+        Ok(0)
+    }
+}
+
+#[derive(Debug, PartialEq, StructOpt)]
+struct SynctexViewCommand {
+    /// The output document, or its SyncTeX file
+    #[structopt(name = "output")]
+    output: PathBuf,
+
+    /// The input file
+    #[structopt(name = "input")]
+    input: String,
+
+    /// The line number in the input file, starting at 1
+    #[structopt(name = "line")]
+    line: u32,
+}
+
+impl SynctexViewCommand {
+    fn customize(&self, cc: &mut CommandCustomizations) {
+        cc.always_stderr = true;
+    }
+
+    fn execute(self, _config: PersistentConfig, _status: &mut dyn StatusBackend) -> Result<i32> {
+        let synctex = open_synctex(&self.output)?;
+        let boxes = synctex.forward_search(&self.input, self.line);
+
+        if boxes.is_empty() {
+            return Err(errmsg!(
+                "no part of `{}` corresponds to line {} of `{}`",
+                self.output.display(),
+                self.line,
+                self.input
+            ));
+        }
+
+        // As above, this mimics the output of the `synctex` program.
+        println!("SyncTeX result begin");
+
+        for b in boxes {
+            println!("Output:{}", self.output.display());
+            println!("Page:{}", b.page);
+            println!("x:{:.6}", b.x);
+            println!("y:{:.6}", b.y);
+            println!("h:{:.6}", b.x);
+            println!("v:{:.6}", b.y + b.depth);
+            println!("W:{:.6}", b.width);
+            println!("H:{:.6}", b.height + b.depth);
+            println!("before:");
+            println!("offset:0");
+            println!("middle:");
+            println!("after:");
+        }
+
+        println!("SyncTeX result end");
+        Ok(0)
+    }
+}
@@ -17,7 +17,7 @@ use tectonic_bundles::{
     cache::Cache, dir::DirBundle, itar::IndexedTarBackend, zip::ZipBundle, Bundle,
 };
 use tectonic_docmodel::{
-    document::{BuildTargetType, Document},
+    document::{BuildTargetType, Document, OutputProfile},
     workspace::{Workspace, WorkspaceCreator},
 };
 use tectonic_geturl::{DefaultBackend, GetUrlBackend};
@@ -25,11 +25,12 @@ use url::Url;
 
 use crate::{
     config, ctry,
-    driver::{OutputFormat, PassSetting, ProcessingSessionBuilder},
+    driver::{self, ExternalTool, OutputFormat, PassSetting, ProcessingSessionBuilder},
     errors::{ErrorKind, Result},
     status::StatusBackend,
-    test_util, tt_note,
+    test_util, tt_note, tt_warning,
     unstable_opts::UnstableOptions,
+    worker::WorkerJob,
 };
 
 /// Options for setting up [`Document`] instances with the driver
@@ -44,6 +45,9 @@ pub struct DocumentSetupOptions {
 
     /// Ensure a deterministic build environment.
     deterministic_mode: bool,
+
+    /// Run shell-escape commands in a sandbox.
+    shell_escape_sandbox: bool,
 }
 
 impl DocumentSetupOptions {
@@ -53,6 +57,7 @@ impl DocumentSetupOptions {
         DocumentSetupOptions {
             only_cached: false,
             deterministic_mode: false,
+            shell_escape_sandbox: false,
             security,
         }
     }
@@ -72,6 +77,15 @@ impl DocumentSetupOptions {
         self.deterministic_mode = s;
         self
     }
+
+    /// Specify whether shell-escape commands should be run in a sandbox.
+    ///
+    /// This only has an effect for outputs that activate shell-escape. See
+    /// [`ProcessingSessionBuilder::shell_escape_sandbox`].
+    pub fn shell_escape_sandbox(&mut self, s: bool) -> &mut Self {
+        self.shell_escape_sandbox = s;
+        self
+    }
 }
 
 pub trait DocumentExt {
@@ -95,6 +109,66 @@ pub trait DocumentExt {
         setup_options: &DocumentSetupOptions,
         status: &mut dyn StatusBackend,
     ) -> Result<ProcessingSessionBuilder>;
+
+    /// Set up a [`WorkerJob`] for one of the outputs.
+    ///
+    /// This is the counterpart of [`Self::setup_session`] for building outputs
+    /// in a [`crate::worker::WorkerPool`]. Workers never enable insecure
+    /// features, so outputs that use shell-escape or external tools can't be
+    /// built this way, and `None` is returned for them. The bundle isn't part
+    /// of the job: it has to be passed to the pool.
+    fn setup_worker_job(
+        &self,
+        output_profile: &str,
+        setup_options: &DocumentSetupOptions,
+    ) -> Result<Option<WorkerJob>>;
+}
+
+/// Get an output profile of a document.
+fn profile<'a>(doc: &'a Document, output_profile: &str) -> Result<&'a OutputProfile> {
+    doc.outputs.get(output_profile).ok_or_else(|| {
+        ErrorKind::Msg(format!(
+            "unrecognized output profile name \"{output_profile}\""
+        ))
+        .into()
+    })
+}
+
+/// Get the primary input for building an output, along with whether its
+/// preamble is to be precompiled, in which case the session reads it itself.
+fn primary_input(profile: &OutputProfile) -> Result<(String, bool)> {
+    let precompile_preamble = profile.precompile_preamble && !profile.preamble_file.is_empty();
+
+    let mut input_buffer = String::new();
+    if !profile.preamble_file.is_empty() && !precompile_preamble {
+        writeln!(input_buffer, "\\input{{{}}}", profile.preamble_file)?;
+    }
+    if !profile.index_file.is_empty() {
+        writeln!(input_buffer, "\\input{{{}}}", profile.index_file)?;
+    }
+    if !profile.postamble_file.is_empty() {
+        writeln!(input_buffer, "\\input{{{}}}", profile.postamble_file)?;
+    }
+
+    Ok((input_buffer, precompile_preamble))
+}
+
+/// Get the directory holding the source files of a document.
+fn tex_dir(doc: &Document) -> PathBuf {
+    let mut tex_dir = doc.src_dir().to_owned();
+    tex_dir.push("src");
+    tex_dir
+}
+
+/// Create the output directory for one of the outputs of a document.
+fn create_output_dir(doc: &Document, output_profile: &str) -> Result<PathBuf> {
+    let mut output_dir = doc.build_dir().to_owned();
+    output_dir.push(output_profile);
+    ctry!(
+        fs::create_dir_all(&output_dir);
+        "couldn\'t create output directory `{}`", output_dir.display()
+    );
+    Ok(output_dir)
 }
 
 impl DocumentExt for Document {
@@ -140,27 +214,14 @@ impl DocumentExt for Document {
         setup_options: &DocumentSetupOptions,
         status: &mut dyn StatusBackend,
     ) -> Result<ProcessingSessionBuilder> {
-        let profile = self.outputs.get(output_profile).ok_or_else(|| {
-            ErrorKind::Msg(format!(
-                "unrecognized output profile name \"{output_profile}\""
-            ))
-        })?;
+        let profile = profile(self, output_profile)?;
 
         let output_format = match profile.target_type {
             BuildTargetType::Html => OutputFormat::Html,
             BuildTargetType::Pdf => OutputFormat::Pdf,
         };
 
-        let mut input_buffer = String::new();
-        if !profile.preamble_file.is_empty() {
-            writeln!(input_buffer, "\\input{{{}}}", profile.preamble_file)?;
-        }
-        if !profile.index_file.is_empty() {
-            writeln!(input_buffer, "\\input{{{}}}", profile.index_file)?;
-        }
-        if !profile.postamble_file.is_empty() {
-            writeln!(input_buffer, "\\input{{{}}}", profile.postamble_file)?;
-        }
+        let (input_buffer, precompile_preamble) = primary_input(profile)?;
 
         let mut sess_builder =
             ProcessingSessionBuilder::new_with_security(setup_options.security.clone());
@@ -177,6 +238,10 @@ impl DocumentExt for Document {
             .primary_input_buffer(input_buffer.as_bytes())
             .tex_input_name(output_profile);
 
+        if precompile_preamble {
+            sess_builder.preamble_format(&profile.preamble_file);
+        }
+
         if profile.shell_escape {
             // For now, this is the only option we allow.
             if let Some(cwd) = &profile.shell_escape_cwd {
@@ -184,26 +249,78 @@ impl DocumentExt for Document {
             } else {
                 sess_builder.shell_escape_with_temp_dir();
             }
+
+            if let Some(commands) = &profile.shell_escape_commands {
+                sess_builder.shell_escape_commands(commands);
+            }
+
+            sess_builder.shell_escape_sandbox(setup_options.shell_escape_sandbox);
+        }
+
+        if !profile.external_tools.is_empty() {
+            if setup_options.security.allow_shell_escape() {
+                for tool in &profile.external_tools {
+                    sess_builder.external_tool(ExternalTool {
+                        command: tool.command.clone(),
+                        trigger: tool.trigger.clone(),
+                        produces: tool.produces.clone(),
+                        rerun_tex: tool.rerun_tex,
+                    });
+                }
+            } else {
+                tt_warning!(
+                    status,
+                    "not running the external tools of output `{}` since this build is untrusted",
+                    output_profile
+                );
+            }
         }
 
         if setup_options.only_cached {
             tt_note!(status, "using only cached resource files");
         }
         sess_builder.bundle(self.bundle(setup_options, status)?);
+        sess_builder.filesystem_root(tex_dir(self));
+        sess_builder.output_dir(create_output_dir(self, output_profile)?);
 
-        let mut tex_dir = self.src_dir().to_owned();
-        tex_dir.push("src");
-        sess_builder.filesystem_root(&tex_dir);
+        Ok(sess_builder)
+    }
 
-        let mut output_dir = self.build_dir().to_owned();
-        output_dir.push(output_profile);
-        ctry!(
-            fs::create_dir_all(&output_dir);
-            "couldn\'t create output directory `{}`", output_dir.display()
-        );
-        sess_builder.output_dir(&output_dir);
+    fn setup_worker_job(
+        &self,
+        output_profile: &str,
+        setup_options: &DocumentSetupOptions,
+    ) -> Result<Option<WorkerJob>> {
+        let profile = profile(self, output_profile)?;
 
-        Ok(sess_builder)
+        if profile.shell_escape || !profile.external_tools.is_empty() {
+            return Ok(None);
+        }
+
+        let output_format = match profile.target_type {
+            BuildTargetType::Html => OutputFormat::Html,
+            BuildTargetType::Pdf => OutputFormat::Pdf,
+        };
+
+        let (input_buffer, precompile_preamble) = primary_input(profile)?;
+
+        let mut job = WorkerJob::new(output_profile);
+        job.output_format(output_format)
+            .format_name(&profile.tex_format)
+            .build_date(driver::build_date_from_env(
+                setup_options.deterministic_mode,
+            ))
+            .deterministic_mode(setup_options.deterministic_mode)
+            .pass(PassSetting::Default)
+            .primary_input_buffer(input_buffer.as_bytes())
+            .filesystem_root(tex_dir(self))
+            .output_dir(create_output_dir(self, output_profile)?);
+
+        if precompile_preamble {
+            job.preamble_format(&profile.preamble_file);
+        }
+
+        Ok(Some(job))
     }
 }
 
@@ -18,21 +18,27 @@
 use byte_unit::Byte;
 use quick_xml::{events::Event, NsReader};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     fs::File,
-    io::{Cursor, Read, Write},
+    io::{self, Cursor, Read, Write},
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, Output, Stdio},
     rc::Rc,
     result::Result as StdResult,
     str::FromStr,
-    time::{Duration, SystemTime},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
 use tectonic_bridge_core::{CoreBridgeLauncher, DriverHooks, SecuritySettings, SystemRequestError};
-use tectonic_bundles::Bundle;
+use tectonic_bundles::{create::BundleCreator, Bundle};
+use tectonic_engine_makeindex::MakeindexOutcome;
 use tectonic_engine_spx2html::AssetSpecification;
 use tectonic_io_base::{
-    digest::DigestData,
+    digest::{self, Digest, DigestData},
     filesystem::{FilesystemIo, FilesystemPrimaryInputIo},
     stdstreams::{BufferedPrimaryIo, GenuineStdoutIo},
     InputHandle, IoProvider, OpenResult, OutputHandle,
@@ -40,7 +46,7 @@ use tectonic_io_base::{
 
 use crate::{
     ctry, errmsg,
-    errors::{ChainErrCompatExt, ErrorKind, Result},
+    errors::{ChainErrCompatExt, ErrorKind, Result, SyncError},
     io::{
         format_cache::FormatCache,
         memory::{MemoryFileCollection, MemoryIo},
@@ -49,9 +55,14 @@ use crate::{
     status::StatusBackend,
     tt_error, tt_note, tt_warning,
     unstable_opts::UnstableOptions,
-    BibtexEngine, Spx2HtmlEngine, TexEngine, TexOutcome, XdvipdfmxEngine,
+    BibtexEngine, MakeindexEngine, Spx2HtmlEngine, TexEngine, TexOutcome, Xdv2SvgEngine,
+    XdvipdfmxEngine,
 };
 
+mod sandbox;
+
+pub use tectonic_bridge_core::CancellationToken;
+
 /// Different patterns with which files may have been accessed by the
 /// underlying engines. Once a file is marked as ReadThenWritten or
 /// WrittenThenRead, its pattern does not evolve further.
@@ -129,6 +140,8 @@ pub enum OutputFormat {
     Pdf,
     /// A '.fmt' file, for initializing the TeX engine.
     Format,
+    /// A '.svg' file for each page.
+    Svg,
 }
 
 impl FromStr for OutputFormat {
@@ -141,6 +154,7 @@ impl FromStr for OutputFormat {
             "xdv" => Ok(OutputFormat::Xdv),
             "pdf" => Ok(OutputFormat::Pdf),
             "fmt" => Ok(OutputFormat::Format),
+            "svg" => Ok(OutputFormat::Svg),
             _ => Err("unsupported or unknown format"),
         }
     }
@@ -240,6 +254,14 @@ struct BridgeState {
     /// that assume continuity from one to the next.
     shell_escape_work: Option<FilesystemIo>,
 
+    /// If set, shell-escape is "restricted": only the programs named here may
+    /// be run, and commands are run directly rather than through the shell.
+    shell_escape_commands: Option<Vec<String>>,
+
+    /// Whether shell-escape commands and external tools should be run in a
+    /// sandbox.
+    shell_escape_sandbox: bool,
+
     /// I/O for saving any generated format files.
     format_cache: FormatCache,
 
@@ -254,6 +276,9 @@ struct BridgeState {
 
     /// The I/O events that occurred while processing.
     events: HashMap<String, FileSummary>,
+
+    /// Limits on the resources that the engines may use.
+    limits: Limits,
 }
 
 impl BridgeState {
@@ -356,7 +381,23 @@ impl BridgeState {
 
         // Now we can actually run the command.
 
-        let output = cmd.current_dir(tempdir.path()).output()?;
+        cmd.current_dir(tempdir.path());
+
+        if self.shell_escape_sandbox {
+            ctry!(
+                sandbox::sandbox_command(&mut cmd, tempdir.path());
+                "unable to set up the sandbox for the external tool"
+            );
+        }
+
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        let output = ctry!(
+            self.limits.run_command(&mut cmd);
+            "failed to run the external tool `{}`", tool.argv[0]
+        );
 
         if let Some(0) = output.status.code() {
         } else {
@@ -375,35 +416,87 @@ impl BridgeState {
             };
         }
 
-        // Search for any files that the tool created, and import them into the
-        // memory layer.
+        // If the tool says what it produces, import those files into the
+        // memory layer like any other output, so that the usual rerun
+        // detection applies to them.
 
-        for entry in std::fs::read_dir(tempdir.path())? {
-            let entry = entry?;
+        if !tool.produces.is_empty() {
+            let mut found = Vec::new();
+            let mut dirs = vec![PathBuf::new()];
 
-            if !entry.file_type()?.is_file() {
-                continue;
+            while let Some(rel_dir) = dirs.pop() {
+                for entry in std::fs::read_dir(tempdir.path().join(&rel_dir))? {
+                    let entry = entry?;
+                    let rel_path = rel_dir.join(entry.file_name());
+
+                    if entry.file_type()?.is_dir() {
+                        dirs.push(rel_path);
+                        continue;
+                    }
+
+                    if let Some(name) = rel_path.to_str() {
+                        let name = name.replace('\\', "/");
+
+                        if !read_files.contains(&name)
+                            && tool.produces.iter().any(|p| pattern_matches(p, &name))
+                        {
+                            found.push(name);
+                        }
+                    }
+                }
             }
 
-            if let Some(basename) = entry.file_name().to_str() {
-                if !self.mem.files.borrow().contains_key(basename) {
-                    let path = entry.path();
-                    let mut data = Vec::new();
+            found.sort();
 
-                    let mut f = ctry!(
-                        File::open(&path);
-                        "failed to open tool-created file `{}`", path.display()
-                    );
-                    ctry!(
-                        f.read_to_end(&mut data);
-                        "failed to read tool-created file `{}`", path.display()
-                    );
+            for name in found {
+                let path = tempdir.path().join(&name);
+                let data = ctry!(
+                    std::fs::read(&path);
+                    "failed to read tool-created file `{}`", path.display()
+                );
 
-                    self.mem.create_entry(basename, data);
-                    self.events.insert(
-                        basename.to_owned(),
-                        FileSummary::new(AccessPattern::Written, InputOrigin::NotInput),
-                    );
+                let mut oh = ctry!(
+                    self.output_open_name(&name).must_exist();
+                    "failed to import tool-created file `{}`", name
+                );
+                ctry!(
+                    oh.write_all(&data);
+                    "failed to import tool-created file `{}`", name
+                );
+                let (name, digest) = oh.into_name_digest();
+                self.event_output_closed(name, digest, status);
+            }
+        } else {
+            // Otherwise, search for any files that the tool created, and
+            // import them into the memory layer.
+
+            for entry in std::fs::read_dir(tempdir.path())? {
+                let entry = entry?;
+
+                if !entry.file_type()?.is_file() {
+                    continue;
+                }
+
+                if let Some(basename) = entry.file_name().to_str() {
+                    if !self.mem.files.borrow().contains_key(basename) {
+                        let path = entry.path();
+                        let mut data = Vec::new();
+
+                        let mut f = ctry!(
+                            File::open(&path);
+                            "failed to open tool-created file `{}`", path.display()
+                        );
+                        ctry!(
+                            f.read_to_end(&mut data);
+                            "failed to read tool-created file `{}`", path.display()
+                        );
+
+                        self.mem.create_entry(basename, data);
+                        self.events.insert(
+                            basename.to_owned(),
+                            FileSummary::new(AccessPattern::Written, InputOrigin::NotInput),
+                        );
+                    }
                 }
             }
         }
@@ -484,10 +577,23 @@ macro_rules! bridgestate_ioprovider_cascade {
 
 impl IoProvider for BridgeState {
     fn output_open_name(&mut self, name: &str) -> OpenResult<OutputHandle> {
-        let r = (|| {
+        let is_new_output = !matches!(
+            self.events.get(name),
+            Some(summ) if summ.access_pattern != AccessPattern::Read
+        );
+
+        if let Err(e) = self.limits.check_new_output(is_new_output) {
+            return OpenResult::Err(e);
+        }
+
+        let mut r = (|| {
             bridgestate_ioprovider_cascade!(self, output_open_name(name));
         })();
 
+        if let OpenResult::Ok(oh) = r {
+            r = OpenResult::Ok(self.limits.wrap_output(oh, is_new_output));
+        }
+
         if let OpenResult::Ok(_) = r {
             if let Some(summ) = self.events.get_mut(name) {
                 summ.access_pattern = match summ.access_pattern {
@@ -547,6 +653,10 @@ impl IoProvider for BridgeState {
         name: &str,
         status: &mut dyn StatusBackend,
     ) -> OpenResult<(InputHandle, Option<PathBuf>)> {
+        if let Err(e) = self.limits.check_open() {
+            return OpenResult::Err(e);
+        }
+
         let r = (|| {
             bridgestate_ioprovider_cascade!(self, input_open_name_with_abspath(name, status));
         })();
@@ -654,6 +764,10 @@ impl DriverHooks for BridgeState {
         summ.write_digest = Some(digest);
     }
 
+    fn check_interrupt(&mut self, _status: &mut dyn StatusBackend) -> bool {
+        self.limits.check()
+    }
+
     fn event_input_closed(
         &mut self,
         name: String,
@@ -714,16 +828,51 @@ impl DriverHooks for BridgeState {
                 })?;
             }
 
-            // Now we can actually run the command.
+            // Now we can actually run the command. In restricted mode, we
+            // split it into arguments ourselves and avoid the shell, so that
+            // only the allowed programs can be run.
 
-            tt_note!(status, "running shell command: `{}`", command);
+            let mut cmd = if let Some(allowed) = self.shell_escape_commands.as_ref() {
+                let argv = match split_restricted_command(command) {
+                    Some(argv) if !argv.is_empty() => argv,
+                    _ => {
+                        tt_warning!(status, "unable to parse shell command `{}`", command);
+                        return Err(SystemRequestError::Failed);
+                    }
+                };
 
-            match Command::new(SHELL[0])
-                .args(&SHELL[1..])
-                .arg(command)
-                .current_dir(work.root())
-                .status()
-            {
+                if !allowed.iter().any(|a| *a == argv[0]) {
+                    tt_warning!(
+                        status,
+                        "not running shell command `{}`: the program `{}` is not in the list of \
+                         allowed shell-escape commands",
+                        command,
+                        argv[0]
+                    );
+                    return Err(SystemRequestError::NotAllowed);
+                }
+
+                tt_note!(status, "running restricted shell command: `{}`", command);
+                let mut cmd = Command::new(&argv[0]);
+                cmd.args(&argv[1..]);
+                cmd
+            } else {
+                tt_note!(status, "running shell command: `{}`", command);
+                let mut cmd = Command::new(SHELL[0]);
+                cmd.args(&SHELL[1..]).arg(command);
+                cmd
+            };
+
+            cmd.current_dir(work.root());
+
+            if self.shell_escape_sandbox {
+                if let Err(e) = sandbox::sandbox_command(&mut cmd, work.root()) {
+                    tt_error!(status, "unable to set up the shell-escape sandbox"; e.into());
+                    return Err(SystemRequestError::Failed);
+                }
+            }
+
+            match self.limits.run_command(&mut cmd).map(|o| o.status) {
                 Ok(s) => match s.code() {
                     Some(0) => Ok(()),
                     Some(n) => {
@@ -735,6 +884,10 @@ impl DriverHooks for BridgeState {
                         Err(SystemRequestError::Failed)
                     }
                 },
+                Err(err) if self.shell_escape_sandbox => {
+                    tt_error!(status, "failed to run command in the shell-escape sandbox"; err.into());
+                    Err(SystemRequestError::Failed)
+                }
                 Err(err) => {
                     tt_warning!(status, "failed to run command"; err.into());
                     Err(SystemRequestError::Failed)
@@ -757,6 +910,293 @@ impl DriverHooks for BridgeState {
     }
 }
 
+/// Split a restricted-mode shell-escape command into its arguments.
+///
+/// Following TeX Live, arguments are separated by whitespace and may be
+/// grouped with single or double quotes. No other shell syntax is recognized.
+/// Returns None if a quote is left unterminated.
+fn split_restricted_command(command: &str) -> Option<Vec<String>> {
+    let mut argv = Vec::new();
+    let mut chars = command.chars().peekable();
+
+    loop {
+        while matches!(chars.peek(), Some(c) if c.is_whitespace()) {
+            chars.next();
+        }
+
+        if chars.peek().is_none() {
+            return Some(argv);
+        }
+
+        let mut arg = String::new();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '"' | '\'' => loop {
+                    match chars.next() {
+                        Some(q) if q == c => break,
+                        Some(other) => arg.push(other),
+                        None => return None,
+                    }
+                },
+                c if c.is_whitespace() => break,
+                c => arg.push(c),
+            }
+        }
+
+        argv.push(arg);
+    }
+}
+
+/// Limits on the resources used by a processing session, along with the
+/// bookkeeping needed to enforce them.
+#[derive(Debug, Default)]
+struct Limits {
+    max_pass_duration: Option<Duration>,
+    max_output_bytes: Option<u64>,
+    max_output_files: Option<usize>,
+    max_tex_passes: Option<usize>,
+
+    /// When the current pass has to finish, if there's a time limit. This is
+    /// shared with the output handles.
+    pass_deadline: Arc<Mutex<Option<Instant>>>,
+
+    /// The total number of bytes written to output files. This is shared with
+    /// the output handles.
+    output_bytes: Arc<AtomicU64>,
+
+    /// The number of distinct output files that have been opened.
+    output_files: usize,
+
+    /// The number of TeX passes that have been started.
+    tex_passes: usize,
+
+    /// A description of the limit that was exceeded, if any.
+    exceeded: Option<String>,
+}
+
+impl Limits {
+    /// Record that a limit was exceeded. Only the first one is remembered.
+    fn exceed(&mut self, what: String) {
+        if self.exceeded.is_none() {
+            self.exceeded = Some(what);
+        }
+    }
+
+    /// Start the clock for a new engine pass.
+    fn start_pass(&mut self) {
+        *self.pass_deadline.lock().unwrap() = self.max_pass_duration.map(|d| Instant::now() + d);
+    }
+
+    /// Stop the clock at the end of an engine pass, so that the deadline of
+    /// one pass can't affect whatever happens after it. A pass that overran
+    /// its deadline, even if it got to the end, is recorded as having
+    /// exceeded the limit.
+    fn end_pass(&mut self) {
+        self.check();
+        *self.pass_deadline.lock().unwrap() = None;
+    }
+
+    /// Record that a TeX pass is starting, if that's allowed.
+    fn start_tex_pass(&mut self) -> Result<()> {
+        if let Some(max) = self.max_tex_passes {
+            if self.tex_passes >= max {
+                self.exceed(format!("the number of TeX passes ({max})"));
+                return Err(ErrorKind::LimitExceeded(self.exceeded.clone().unwrap()).into());
+            }
+        }
+
+        self.tex_passes += 1;
+        self.start_pass();
+        Ok(())
+    }
+
+    /// Check whether any limit has been exceeded, returning true if so.
+    fn check(&mut self) -> bool {
+        let deadline = *self.pass_deadline.lock().unwrap();
+
+        if let (Some(deadline), Some(d)) = (deadline, self.max_pass_duration) {
+            if Instant::now() > deadline {
+                self.exceed(format!("the run time of a single pass ({d:?})"));
+            }
+        }
+
+        if let Some(max) = self.max_output_bytes {
+            if self.output_bytes.load(Ordering::Relaxed) > max {
+                self.exceed(format!("the total size of the output files ({max} bytes)"));
+            }
+        }
+
+        self.exceeded.is_some()
+    }
+
+    /// Check whether an output file may be opened.
+    fn check_new_output(&mut self, is_new_output: bool) -> tectonic_errors::Result<()> {
+        if let (true, Some(max)) = (is_new_output, self.max_output_files) {
+            if self.output_files >= max {
+                self.exceed(format!("the number of output files ({max})"));
+            }
+        }
+
+        self.check_open()
+    }
+
+    /// Check whether a file may be opened at all.
+    fn check_open(&mut self) -> tectonic_errors::Result<()> {
+        if self.check() {
+            tectonic_errors::anyhow::bail!("a processing limit has been exceeded");
+        }
+
+        Ok(())
+    }
+
+    /// Run a command to completion, like [`Command::output`], except that the
+    /// standard streams are inherited unless the caller set them up
+    /// otherwise. If the current pass has a time limit, the command is killed,
+    /// along with any processes that it started, once the time runs out.
+    fn run_command(&mut self, cmd: &mut Command) -> io::Result<Output> {
+        if self.max_pass_duration.is_none() {
+            return cmd.spawn()?.wait_with_output();
+        }
+
+        // Give the command a process group of its own, so that it can be
+        // killed along with its children.
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(cmd, 0);
+
+        let mut child = cmd.spawn()?;
+
+        fn collect<R: Read + Send + 'static>(r: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+            thread::spawn(move || {
+                let mut data = Vec::new();
+
+                if let Some(mut r) = r {
+                    let _ = r.read_to_end(&mut data);
+                }
+
+                data
+            })
+        }
+
+        let stdout = collect(child.stdout.take());
+        let stderr = collect(child.stderr.take());
+
+        let status = loop {
+            if let Some(s) = child.try_wait()? {
+                break s;
+            }
+
+            if self.check() {
+                kill_process_group(&mut child);
+                break child.wait()?;
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        };
+
+        Ok(Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        })
+    }
+
+    /// Account for a newly opened output file, arranging for the bytes
+    /// written to it to be counted if needed.
+    fn wrap_output(&mut self, oh: OutputHandle, is_new_output: bool) -> OutputHandle {
+        if is_new_output {
+            self.output_files += 1;
+        }
+
+        if self.max_output_bytes.is_none() && self.max_pass_duration.is_none() {
+            return oh;
+        }
+
+        let name = oh.name().to_owned();
+        OutputHandle::new(
+            name,
+            LimitedOutput {
+                inner: oh,
+                written: self.output_bytes.clone(),
+                max: self.max_output_bytes,
+                deadline: self.pass_deadline.clone(),
+            },
+        )
+    }
+}
+
+/// Kill a child process along with the other processes in its process group.
+#[cfg(unix)]
+fn kill_process_group(child: &mut std::process::Child) {
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_process_group(child: &mut std::process::Child) {
+    let _ = child.kill();
+}
+
+/// An output stream that fails once a session's total output size limit has
+/// been exceeded, or the current pass has run out of time. Since every engine
+/// writes its outputs through these streams, this stops engines that don't
+/// check for interruptions themselves.
+struct LimitedOutput {
+    inner: OutputHandle,
+    written: Arc<AtomicU64>,
+    max: Option<u64>,
+    deadline: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Write for LimitedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let total = self.written.fetch_add(buf.len() as u64, Ordering::Relaxed) + buf.len() as u64;
+
+        if let Some(max) = self.max {
+            if total > max {
+                return Err(io::Error::other(
+                    "the limit on the total size of the output files was exceeded",
+                ));
+            }
+        }
+
+        if let Some(deadline) = *self.deadline.lock().unwrap() {
+            if Instant::now() > deadline {
+                return Err(io::Error::other(
+                    "the time limit on a processing pass was exceeded",
+                ));
+            }
+        }
+
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Determine the build date from the environment, as described for
+/// [`ProcessingSessionBuilder::build_date_from_env`].
+pub(crate) fn build_date_from_env(force_deterministic: bool) -> SystemTime {
+    let build_date_str = std::env::var("SOURCE_DATE_EPOCH").ok();
+
+    match (force_deterministic, build_date_str) {
+        (_, Some(s)) => {
+            let epoch = s
+                .parse::<u64>()
+                .expect("invalid SOURCE_DATE_EPOCH (not a number)");
+
+            SystemTime::UNIX_EPOCH
+                .checked_add(Duration::from_secs(epoch))
+                .expect("time overflow")
+        }
+        (true, None) => SystemTime::UNIX_EPOCH,
+        (false, None) => SystemTime::now(),
+    }
+}
+
 /// Possible modes for handling shell-escape functionality
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
 enum ShellEscapeMode {
@@ -786,8 +1226,207 @@ enum ShellEscapeMode {
 struct ExternalToolPass {
     argv: Vec<String>,
     extra_requires: HashSet<String>,
+
+    /// Patterns for the files that the tool produces. If empty, any new files
+    /// at the top level of the tool's working directory are imported.
+    produces: Vec<String>,
+}
+
+/// An external program that the driver runs when the TeX engine writes
+/// certain files; see [`ProcessingSessionBuilder::external_tool`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ExternalTool {
+    /// The program to run, followed by its arguments. In each of these,
+    /// `{trigger}` is replaced with the name of the triggering file, and
+    /// `{stem}` with that name without its extension.
+    pub command: Vec<String>,
+
+    /// A pattern matching the names of the files that trigger the tool, in
+    /// which `*` matches any sequence of characters.
+    pub trigger: String,
+
+    /// Patterns matching the names of the files that the tool produces,
+    /// relative to its working directory. These use the same syntax as
+    /// `trigger`, and can also use the `{stem}` substitution. If empty, any new
+    /// files at the top level of the working directory are used.
+    pub produces: Vec<String>,
+
+    /// Whether the TeX engine should be rerun after the tool runs.
+    pub rerun_tex: bool,
+}
+
+/// Match a file name against a pattern in which `*` matches any sequence of
+/// characters, including path separators.
+fn pattern_matches(pattern: &str, name: &str) -> bool {
+    let mut pieces = pattern.split('*');
+    let first = pieces.next().unwrap_or("");
+
+    let mut rest = match name.strip_prefix(first) {
+        Some(r) => r,
+        None => return false,
+    };
+
+    let pieces: Vec<&str> = pieces.collect();
+
+    let (last, middle) = match pieces.split_last() {
+        Some(x) => x,
+        None => return rest.is_empty(),
+    };
+
+    for piece in middle {
+        match rest.find(piece) {
+            Some(i) => rest = &rest[i + piece.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
 }
 
+/// The header line of the file recording the digests of a successful build.
+/// Bump the version number if the format of the file changes.
+const BUILD_DIGESTS_HEADER: &str = "tectonic-build-digests 2";
+
+/// A record of the state of the filesystem after a successful build, used to
+/// skip processing if nothing has changed by the time of the next one. See
+/// [`ProcessingSessionBuilder::incremental`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct BuildDigests {
+    /// A digest of the session settings that might affect the outputs.
+    settings: DigestData,
+
+    /// The digests of the files that the build depended upon or created,
+    /// as they were on disk when it finished. Files that were looked for but
+    /// didn't exist are recorded with the digest of [`ABSENT_FILE_MARKER`].
+    files: BTreeMap<PathBuf, DigestData>,
+}
+
+impl BuildDigests {
+    /// Load a digest record from disk. Any problem with the file, including
+    /// its nonexistence, results in `None`: in all of those cases, we just
+    /// need to go ahead and rebuild.
+    fn load(path: &Path) -> Option<Self> {
+        let text = std::fs::read_to_string(path).ok()?;
+        let mut lines = text.lines();
+
+        if lines.next()? != BUILD_DIGESTS_HEADER {
+            return None;
+        }
+
+        let settings = lines.next()?.strip_prefix("settings ")?.parse().ok()?;
+        let mut files = BTreeMap::new();
+
+        for line in lines {
+            let (digest, path) = line.split_once(' ')?;
+            files.insert(PathBuf::from(path), digest.parse().ok()?);
+        }
+
+        Some(BuildDigests { settings, files })
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        let mut text = format!(
+            "{}\nsettings {}\n",
+            BUILD_DIGESTS_HEADER,
+            self.settings.to_string()
+        );
+
+        for (file, digest) in &self.files {
+            let file =
+                ctry!(file.to_str(); "build input path `{}` is not Unicode-able", file.display());
+            text.push_str(&format!("{} {}\n", digest.to_string(), file));
+        }
+
+        ctry!(std::fs::write(path, text); "couldn't write build digests file `{}`", path.display());
+        Ok(())
+    }
+
+    /// Check whether the filesystem still looks the way that it did at the end
+    /// of the build that created this record, and the settings are the same.
+    fn is_current(&self, settings: &DigestData) -> bool {
+        self.settings == *settings
+            && self
+                .files
+                .iter()
+                .all(|(path, digest)| matches!(digest_of_file(path), Ok(d) if d == *digest))
+    }
+}
+
+/// The data that [`digest_of_file`] hashes in place of the contents of a file
+/// that doesn't exist, so that creating an empty file counts as a change.
+const ABSENT_FILE_MARKER: &[u8] = b"\0tectonic: this file does not exist\0";
+
+/// Compute the digest of a file on disk. A file that doesn't exist is given
+/// the digest of [`ABSENT_FILE_MARKER`].
+fn digest_of_file(path: &Path) -> Result<DigestData> {
+    let mut dc = digest::create();
+
+    match File::open(path) {
+        Ok(mut f) => {
+            let mut data = Vec::new();
+            f.read_to_end(&mut data)?;
+            dc.update(&data);
+        }
+
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
+            dc.update(ABSENT_FILE_MARKER);
+        }
+
+        Err(e) => return Err(e.into()),
+    }
+
+    Ok(DigestData::from(dc))
+}
+
+/// The header line of the file recording what went into a precompiled
+/// preamble format. Bump the version number if the format of the file changes.
+const PREAMBLE_DEPS_HEADER: &str = "tectonic-preamble-deps 1";
+
+/// The TeX code that we run to precompile a preamble, with `@PREAMBLE@`
+/// standing in for the name of the preamble file. It's run in initex mode on
+/// top of the document's format.
+///
+/// If the preamble reaches `\begin{document}`, we dump the format right there,
+/// and arrange for `\tectonicresumepreamble` to skip back to that point and
+/// continue. Otherwise, the whole preamble goes into the format.
+const PREAMBLE_FORMAT_SOURCE: &str = r#"\expandafter\ifx\csname @@input\endcsname\relax
+  \let\tectonicpreambleinput=\input
+\else
+  \expandafter\let\expandafter\tectonicpreambleinput\csname @@input\endcsname
+\fi
+\def\tectonicresumepreamble{}
+\def\tectonicpreambledocument{document}
+\long\def\tectonicpreambleskip#1\begin#2{%
+  \def\tectonicpreambleenv{#2}%
+  \ifx\tectonicpreambleenv\tectonicpreambledocument
+    \expandafter\tectonicpreamblestart
+  \else
+    \expandafter\tectonicpreambleskip
+  \fi}
+\def\tectonicpreamblestart{\begin{document}}
+\ifx\begin\undefined\else
+  \let\tectonicpreamblebegin=\begin
+  \def\tectonicpreambledump#1{%
+    \let\begin=\tectonicpreamblebegin
+    \def\tectonicresumepreamble{%
+      \expandafter\tectonicpreambleskip\tectonicpreambleinput{@PREAMBLE@}}%
+    \primitive\dump}
+  \protected\def\begin#1{%
+    \def\tectonicpreambleenv{#1}%
+    \ifx\tectonicpreambleenv\tectonicpreambledocument
+      \expandafter\tectonicpreambledump
+    \else
+      \expandafter\tectonicpreamblebegin
+    \fi
+    {#1}}
+\fi
+\tectonicpreambleinput{@PREAMBLE@}
+\ifx\tectonicpreamblebegin\undefined\else
+  \let\begin=\tectonicpreamblebegin
+\fi
+\primitive\dump
+"#;
+
 /// A builder-style interface for creating a [`ProcessingSession`].
 ///
 /// This uses standard builder patterns. The `Default` implementation defaults
@@ -804,9 +1443,12 @@ pub struct ProcessingSessionBuilder {
     filesystem_root: Option<PathBuf>,
     format_name: Option<String>,
     format_cache_path: Option<PathBuf>,
+    preamble_format: Option<String>,
     output_format: OutputFormat,
     makefile_output_path: Option<PathBuf>,
+    pruned_bundle_path: Option<PathBuf>,
     hidden_input_paths: HashSet<PathBuf>,
+    external_tools: Vec<ExternalTool>,
     pass: PassSetting,
     reruns: Option<usize>,
     print_stdout: bool,
@@ -814,9 +1456,14 @@ pub struct ProcessingSessionBuilder {
     keep_intermediates: bool,
     keep_logs: bool,
     synctex: bool,
+    incremental: bool,
     build_date: Option<SystemTime>,
     unstables: UnstableOptions,
     shell_escape_mode: ShellEscapeMode,
+    shell_escape_commands: Option<Vec<String>>,
+    shell_escape_sandbox: bool,
+    limits: Limits,
+    cancellation: Option<CancellationToken>,
     html_assets_spec_path: Option<String>,
     html_precomputed_assets: Option<AssetSpecification>,
     html_do_not_emit_files: bool,
@@ -911,6 +1558,24 @@ impl ProcessingSessionBuilder {
         self
     }
 
+    /// Precompile a document preamble into a custom format file.
+    ///
+    /// The named file is read on top of the format given by
+    /// [`Self::format_name`], and the result is dumped into the format cache,
+    /// in the manner of the `mylatexformat` package. If the preamble contains
+    /// `\begin{document}`, the format captures everything that comes before
+    /// it. The custom format is reused for as long as neither the preamble nor
+    /// any of the files that it read change. If it can't be generated, the
+    /// preamble is processed normally.
+    ///
+    /// This requires the primary input to be provided with
+    /// [`Self::primary_input_buffer`], which should contain only what comes
+    /// *after* the preamble: the session takes care of reading the preamble.
+    pub fn preamble_format(&mut self, preamble_file: &str) -> &mut Self {
+        self.preamble_format = Some(preamble_file.to_owned());
+        self
+    }
+
     /// The type of output to create.
     pub fn output_format(&mut self, f: OutputFormat) -> &mut Self {
         self.output_format = f;
@@ -923,6 +1588,31 @@ impl ProcessingSessionBuilder {
         self
     }
 
+    /// If set, a minimal ZIP bundle will be written out at the given path.
+    ///
+    /// The new bundle contains every file that the session read from its
+    /// backing bundle, including the files needed to generate the format file,
+    /// which is regenerated for this purpose even if it's already cached. This
+    /// means that the same document can be rebuilt using only the new bundle.
+    pub fn pruned_bundle_path<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+        self.pruned_bundle_path = Some(p.as_ref().to_owned());
+        self
+    }
+
+    /// Add an external tool to be run during the default pass.
+    ///
+    /// After each TeX pass, the tool is run once for each file written by the
+    /// engine whose name matches its trigger pattern, if the file has changed
+    /// since the tool last ran on it. Since this means running arbitrary
+    /// programs, this setting is ignored unless the security settings allow
+    /// shell-escape.
+    pub fn external_tool(&mut self, tool: ExternalTool) -> &mut Self {
+        if self.security.allow_shell_escape() {
+            self.external_tools.push(tool);
+        }
+        self
+    }
+
     /// Which kind of pass should the `ProcessingSession` run? Defaults to `PassSetting::Default`
     /// (duh).
     pub fn pass(&mut self, p: PassSetting) -> &mut Self {
@@ -939,23 +1629,76 @@ impl ProcessingSessionBuilder {
         self
     }
 
-    /// If set to `true`, stdout from the TeX engine will be forwarded to actual stdout. (By
-    /// default, it will be suppressed.)
-    pub fn print_stdout(&mut self, p: bool) -> &mut Self {
-        self.print_stdout = p;
+    /// Limit the wall-clock time that each processing pass may take.
+    ///
+    /// If a pass runs for longer, the engine is stopped and processing fails
+    /// with an [`ErrorKind::LimitExceeded`] error. The TeX engine checks the
+    /// time as it goes; the other engines are stopped when they next open or
+    /// write a file. Programs run through shell-escape and external tools are
+    /// killed. There is no limit by default.
+    pub fn max_pass_duration(&mut self, d: Duration) -> &mut Self {
+        self.limits.max_pass_duration = Some(d);
         self
     }
 
-    /// Marks a path as hidden, meaning that the TeX engine will pretend that it doesn't exist in
-    /// the filesystem.
-    pub fn hide<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
-        self.hidden_input_paths.insert(p.as_ref().to_owned());
+    /// Limit the total number of bytes that the engines may write to output
+    /// files, across all passes.
+    ///
+    /// Once the limit is exceeded, writes fail, the engine is stopped, and
+    /// processing fails with an [`ErrorKind::LimitExceeded`] error. There is
+    /// no limit by default.
+    pub fn max_output_bytes(&mut self, n: u64) -> &mut Self {
+        self.limits.max_output_bytes = Some(n);
         self
     }
 
-    /// Sets the bundle, which the various engines will use for finding style files, font files,
-    /// etc.
-    pub fn bundle(&mut self, b: Box<dyn Bundle>) -> &mut Self {
+    /// Limit the number of distinct output files that the engines may create.
+    ///
+    /// Attempts to open more files fail, and processing fails with an
+    /// [`ErrorKind::LimitExceeded`] error. There is no limit by default.
+    pub fn max_output_files(&mut self, n: usize) -> &mut Self {
+        self.limits.max_output_files = Some(n);
+        self
+    }
+
+    /// Limit the number of times that the TeX engine may be run.
+    ///
+    /// Unlike [`Self::reruns`], this is a hard bound: if the processing
+    /// pipeline calls for more TeX passes, processing fails with an
+    /// [`ErrorKind::LimitExceeded`] error. There is no limit by default.
+    pub fn max_tex_passes(&mut self, n: usize) -> &mut Self {
+        self.limits.max_tex_passes = Some(n);
+        self
+    }
+
+    /// Allow processing to be cancelled with the specified token.
+    ///
+    /// If the token is cancelled, for instance from another thread, any
+    /// running engine aborts soon afterwards and no further passes are run.
+    /// [`ProcessingSession::run`] then fails with an [`ErrorKind::Cancelled`]
+    /// error.
+    pub fn cancellation_token(&mut self, token: CancellationToken) -> &mut Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// If set to `true`, stdout from the TeX engine will be forwarded to actual stdout. (By
+    /// default, it will be suppressed.)
+    pub fn print_stdout(&mut self, p: bool) -> &mut Self {
+        self.print_stdout = p;
+        self
+    }
+
+    /// Marks a path as hidden, meaning that the TeX engine will pretend that it doesn't exist in
+    /// the filesystem.
+    pub fn hide<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+        self.hidden_input_paths.insert(p.as_ref().to_owned());
+        self
+    }
+
+    /// Sets the bundle, which the various engines will use for finding style files, font files,
+    /// etc.
+    pub fn bundle(&mut self, b: Box<dyn Bundle>) -> &mut Self {
         self.bundle = Some(b);
         self
     }
@@ -978,6 +1721,23 @@ impl ProcessingSessionBuilder {
         self
     }
 
+    /// If set to `true`, the digests of the files that were read and written
+    /// during processing will be recorded in the output directory. The next
+    /// session with the same settings will skip processing altogether if none
+    /// of those files have changed since.
+    ///
+    /// This only takes effect if output files are written to disk, no Makefile
+    /// rules are requested, and the session doesn't involve shell-escape,
+    /// extra search paths, or external tools, because the files used by those
+    /// can't be tracked. Nor does it take effect if the engine's output is to
+    /// be printed, since skipping processing would mean printing nothing. The
+    /// build date is not considered when deciding whether anything has
+    /// changed.
+    pub fn incremental(&mut self, inc: bool) -> &mut Self {
+        self.incremental = inc;
+        self
+    }
+
     /// Sets the date and time of the processing session.
     /// See `TexEngine::build_date` for mor information.
     pub fn build_date(&mut self, date: SystemTime) -> &mut Self {
@@ -990,21 +1750,7 @@ impl ProcessingSessionBuilder {
     /// If `force_deterministic` is set, we fall back to UNIX_EPOCH.
     /// Otherwise, we use the current system time.
     pub fn build_date_from_env(&mut self, force_deterministic: bool) -> &mut Self {
-        let build_date_str = std::env::var("SOURCE_DATE_EPOCH").ok();
-        let build_date = match (force_deterministic, build_date_str) {
-            (_, Some(s)) => {
-                let epoch = s
-                    .parse::<u64>()
-                    .expect("invalid SOURCE_DATE_EPOCH (not a number)");
-
-                SystemTime::UNIX_EPOCH
-                    .checked_add(Duration::from_secs(epoch))
-                    .expect("time overflow")
-            }
-            (true, None) => SystemTime::UNIX_EPOCH,
-            (false, None) => SystemTime::now(),
-        };
-        self.build_date(build_date)
+        self.build_date(build_date_from_env(force_deterministic))
     }
 
     /// Loads unstable options into the processing session
@@ -1037,6 +1783,32 @@ impl ProcessingSessionBuilder {
         self
     }
 
+    /// Restrict shell-escape so that only the named programs may be run,
+    /// overriding any [`UnstableOptions`] settings. This doesn't enable
+    /// shell-escape by itself. In restricted mode, commands are split into
+    /// arguments by the driver and run without an intermediate shell.
+    pub fn shell_escape_commands<I, S>(&mut self, commands: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: ToString,
+    {
+        self.shell_escape_commands = Some(commands.into_iter().map(|c| c.to_string()).collect());
+        self
+    }
+
+    /// Run shell-escape commands in a sandbox, in which only the shell-escape
+    /// work directory is writable and the network is unavailable. External
+    /// tool passes, including `biber`, are sandboxed in the same way, with
+    /// their temporary directory as the writable one. This doesn't enable
+    /// shell-escape by itself. The sandbox is only available on Linux;
+    /// elsewhere, shell-escape commands and external tools will fail if it is
+    /// requested. The default is not to use a sandbox unless the
+    /// [`UnstableOptions`] say otherwise.
+    pub fn shell_escape_sandbox(&mut self, enabled: bool) -> &mut Self {
+        self.shell_escape_sandbox = enabled;
+        self
+    }
+
     /// Forcibly disable shell-escape mode, overriding any [`UnstableOptions`]
     /// settings. The default is to disable shell-escape unless the
     /// [`UnstableOptions`] say otherwise, in which case a driver-managed
@@ -1116,6 +1888,9 @@ impl ProcessingSessionBuilder {
         let mut bundle = self.bundle.expect("a bundle must be specified");
 
         let mut filesystem_root = self.filesystem_root.unwrap_or_default();
+        let mut primary_input_digest = None;
+        let preamble_requested = self.preamble_format.is_some();
+        let mut preamble = None;
 
         let (pio, primary_input_path, default_output_path) = match self.primary_input {
             PrimaryInputMode::Path(p) => {
@@ -1150,17 +1925,39 @@ impl ProcessingSessionBuilder {
                 (pio, None, "".into())
             }
 
-            PrimaryInputMode::Buffer(buf) => {
-                // Same behavior as with stdin.
+            PrimaryInputMode::Buffer(mut buf) => {
+                // If we're precompiling the preamble, the input that we start
+                // out with reads it in the usual way. We'll switch to the body
+                // alone if we manage to create the custom format.
+                if let Some(file) = self.preamble_format {
+                    let mut full = format!("\\input{{{file}}}\n").into_bytes();
+                    full.extend_from_slice(&buf);
+                    preamble = Some(PreambleSettings { file, body: buf });
+                    buf = full;
+                }
+
+                // Same behavior as with stdin. Since we have the data handy,
+                // we can record its digest for incremental builds.
+                let mut dc = digest::create();
+                dc.update(&buf);
+                primary_input_digest = Some(DigestData::from(dc));
+
                 let pio: Box<dyn IoProvider> = Box::new(BufferedPrimaryIo::from_buffer(buf));
                 (pio, None, "".into())
             }
         };
 
+        if preamble_requested && preamble.is_none() {
+            return Err(errmsg!(
+                "precompiling a preamble requires the primary input to be a buffer"
+            ));
+        }
+
         let format_cache_path = self
             .format_cache_path
             .unwrap_or_else(|| filesystem_root.clone());
-        let format_cache = FormatCache::new(bundle.get_digest(status)?, format_cache_path);
+        let bundle_digest = bundle.get_digest(status)?;
+        let format_cache = FormatCache::new(bundle_digest, format_cache_path);
 
         let genuine_stdout = if self.print_stdout {
             Some(GenuineStdoutIo::new())
@@ -1194,11 +1991,14 @@ impl ProcessingSessionBuilder {
             filesystem,
             extra_search_paths,
             shell_escape_work: None,
+            shell_escape_commands: None,
+            shell_escape_sandbox: false,
             format_cache,
             bundle,
             genuine_stdout,
             format_primary: None,
             events: HashMap::new(),
+            limits: self.limits,
         };
 
         // Now we can do the rest.
@@ -1241,26 +2041,48 @@ impl ProcessingSessionBuilder {
             }
         };
 
+        let shell_escape_commands = match self.shell_escape_commands {
+            Some(commands) => Some(commands),
+            None => self.unstables.shell_escape_commands.clone(),
+        };
+        let shell_escape_sandbox = self.shell_escape_sandbox || self.unstables.shell_escape_sandbox;
+
+        if self.external_tools.iter().any(|t| t.command.is_empty()) {
+            return Err(errmsg!("external tool commands must not be empty"));
+        }
+
         Ok(ProcessingSession {
             security: self.security,
             bs,
             pass: self.pass,
             primary_input_path,
+            primary_input_digest,
             primary_input_tex_path: tex_input_name,
             format_name: self.format_name.unwrap(),
+            preamble,
+            preamble_format_name: None,
             tex_aux_path: aux_path.display().to_string(),
             tex_xdv_path: xdv_path.display().to_string(),
             tex_pdf_path: pdf_path.display().to_string(),
             output_format: self.output_format,
             makefile_output_path: self.makefile_output_path,
+            pruned_bundle_path: self.pruned_bundle_path,
             output_path,
             tex_rerun_specification: self.reruns,
             keep_intermediates: self.keep_intermediates,
             keep_logs: self.keep_logs,
             synctex_enabled: self.synctex,
+            incremental: self.incremental,
+            bundle_digest,
+            external_tools: self.external_tools,
+            external_tool_triggers: HashMap::new(),
+            external_tools_run: false,
             build_date: self.build_date.unwrap_or(SystemTime::UNIX_EPOCH),
             unstables: self.unstables,
             shell_escape_mode,
+            shell_escape_commands,
+            shell_escape_sandbox,
+            cancellation: self.cancellation,
             html_assets_spec_path: self.html_assets_spec_path,
             html_precomputed_assets: self.html_precomputed_assets,
             html_emit_files: !self.html_do_not_emit_files,
@@ -1269,10 +2091,68 @@ impl ProcessingSessionBuilder {
     }
 }
 
+/// Settings for precompiling a document preamble; see
+/// [`ProcessingSessionBuilder::preamble_format`].
+struct PreambleSettings {
+    /// The name of the preamble file.
+    file: String,
+
+    /// The primary input to use if the precompiled preamble is available.
+    body: Vec<u8>,
+}
+
+/// One run of the index processor.
+#[derive(Debug)]
+struct IndexJob {
+    input: String,
+    style: Option<String>,
+    output: Option<String>,
+    log: Option<String>,
+    letter_ordering: bool,
+}
+
+/// Information about glossaries that the `glossaries` package records in the
+/// `.aux` file for the benefit of `makeglossaries`.
+#[derive(Debug, Default)]
+struct GlossariesInfo {
+    /// The style file written by the package.
+    ist: Option<String>,
+
+    /// Whether to sort entries in letter order rather than word order.
+    letter_ordering: bool,
+
+    /// The log, output, and input extensions of each glossary.
+    glossaries: Vec<(String, String, String)>,
+}
+
+impl GlossariesInfo {
+    fn parse(aux: &str) -> Self {
+        let mut info = GlossariesInfo::default();
+
+        for line in aux.lines() {
+            if let Some(args) = line.strip_prefix("\\@newglossary{") {
+                let args: Vec<&str> = args.trim_end().trim_end_matches('}').split("}{").collect();
+
+                if let [_label, log, out, input] = args[..] {
+                    info.glossaries
+                        .push((log.to_owned(), out.to_owned(), input.to_owned()));
+                }
+            } else if let Some(arg) = line.strip_prefix("\\@istfilename{") {
+                info.ist = Some(arg.trim_end().trim_end_matches('}').to_owned());
+            } else if let Some(arg) = line.strip_prefix("\\@glsorder{") {
+                info.letter_ordering = arg.trim_end().trim_end_matches('}') == "letter";
+            }
+        }
+
+        info
+    }
+}
+
 #[derive(Debug, Clone)]
 enum RerunReason {
     Biber,
     Bibtex,
+    ExternalTool(String),
     FileChange(String),
 }
 
@@ -1290,6 +2170,9 @@ pub struct ProcessingSession {
     /// If our primary input is an actual file on disk, this is its path.
     primary_input_path: Option<PathBuf>,
 
+    /// If our primary input is an in-memory buffer, this is its digest.
+    primary_input_digest: Option<DigestData>,
+
     /// This is the name of the input that we tell TeX. It is the basename of
     /// the UTF8-ified version of `primary_input_path`; or something anodyne
     /// if the latter is None. (Name, "texput.tex").
@@ -1299,6 +2182,13 @@ pub struct ProcessingSession {
     /// internally, so it has to be String compatible.
     format_name: String,
 
+    /// If we're precompiling the document preamble, the relevant settings.
+    preamble: Option<PreambleSettings>,
+
+    /// If we're using a precompiled preamble, the name of its format file,
+    /// which takes the place of `format_name` in the TeX passes.
+    preamble_format_name: Option<String>,
+
     /// These are the paths of the various output files as TeX knows them --
     /// just `primary_input_tex_path` with the extension changed.
     tex_aux_path: String,
@@ -1309,6 +2199,9 @@ pub struct ProcessingSession {
     /// engine doesn't know about this path at all.
     makefile_output_path: Option<PathBuf>,
 
+    /// If we're writing out a minimal bundle, this is where it goes.
+    pruned_bundle_path: Option<PathBuf>,
+
     /// This is the path that the processed file will be saved at. It defaults
     /// to the path of `primary_input_path` or `.` if STDIN is used. If set to
     /// None, the output files will not be saved to disk — in which case, the
@@ -1323,6 +2216,25 @@ pub struct ProcessingSession {
     keep_logs: bool,
     synctex_enabled: bool,
 
+    /// Whether to record and check build digests; see
+    /// `ProcessingSessionBuilder::incremental`.
+    incremental: bool,
+
+    /// The digest of the backing bundle, used to detect whether anything
+    /// changed for incremental builds.
+    bundle_digest: DigestData,
+
+    /// External tools to run after TeX passes.
+    external_tools: Vec<ExternalTool>,
+
+    /// The digests of the files that triggered each external tool, as of the
+    /// last time that it ran, keyed by tool index and file name.
+    external_tool_triggers: HashMap<(usize, String), DigestData>,
+
+    /// Whether an external tool was run during processing. If so, the build
+    /// depends on files that we can't track.
+    external_tools_run: bool,
+
     /// See `TexEngine::with_date` and `XdvipdfmxEngine::with_date`.
     build_date: SystemTime,
 
@@ -1332,6 +2244,15 @@ pub struct ProcessingSession {
     /// be used here.
     shell_escape_mode: ShellEscapeMode,
 
+    /// If set, shell-escape may only run these programs.
+    shell_escape_commands: Option<Vec<String>>,
+
+    /// Whether to run shell-escape commands in a sandbox.
+    shell_escape_sandbox: bool,
+
+    /// A token that may be used to cancel processing.
+    cancellation: Option<CancellationToken>,
+
     html_assets_spec_path: Option<String>,
     html_precomputed_assets: Option<AssetSpecification>,
     html_emit_files: bool,
@@ -1342,6 +2263,13 @@ const DEFAULT_MAX_TEX_PASSES: usize = 6;
 const ALWAYS_INTERMEDIATE_EXTENSIONS: &[&str] = &[
     ".snm", ".toc", // generated by Beamer
 ];
+const LOG_EXTENSIONS: &[&str] = &[
+    ".log", // TeX
+    ".blg", // BibTeX
+    ".ilg", // makeindex
+    ".glg", ".alg", // glossaries
+    ".nlg", // nomencl
+];
 
 impl ProcessingSession {
     /// Assess whether we need to rerun an engine. This is the case if there
@@ -1377,6 +2305,115 @@ impl ProcessingSession {
         None
     }
 
+    /// Get the path of the file recording the build digests, if incremental
+    /// builds are possible for this session.
+    fn build_digests_path(&self) -> Option<PathBuf> {
+        if !self.incremental
+            || self.makefile_output_path.is_some()
+            || self.pruned_bundle_path.is_some()
+            || self.shell_escape_mode != ShellEscapeMode::Disabled
+            || !self.bs.extra_search_paths.is_empty()
+            || self.bs.genuine_stdout.is_some()
+            || (self.primary_input_path.is_none() && self.primary_input_digest.is_none())
+        {
+            return None;
+        }
+
+        let mut path = self.output_path.as_ref()?.join(&self.tex_aux_path);
+        path.set_extension("digests");
+        Some(path)
+    }
+
+    /// Compute a digest of the session settings that might affect the
+    /// outputs of an incremental build.
+    fn build_settings_digest(&self) -> DigestData {
+        let settings = format!(
+            "version={}\nbundle={}\nprimary={:?}\nformat={}\noutput_format={:?}\npass={:?}\n\
+             reruns={:?}\nkeep_intermediates={}\nkeep_logs={}\nsynctex={}\nunstables={:?}\n\
+             html={:?} {:?} {} {}\nsecurity={:?}\nshell_escape={:?} {:?} {}\n\
+             external_tools={:?}\n",
+            env!("CARGO_PKG_VERSION"),
+            self.bundle_digest.to_string(),
+            self.primary_input_digest.map(|d| d.to_string()),
+            self.format_name,
+            self.output_format,
+            self.pass,
+            self.tex_rerun_specification,
+            self.keep_intermediates,
+            self.keep_logs,
+            self.synctex_enabled,
+            self.unstables,
+            self.html_assets_spec_path,
+            self.html_precomputed_assets,
+            self.html_emit_files,
+            self.html_emit_assets,
+            self.security,
+            self.shell_escape_mode,
+            self.shell_escape_commands,
+            self.shell_escape_sandbox,
+            self.external_tools,
+        );
+
+        let mut dc = digest::create();
+        dc.update(settings.as_bytes());
+        DigestData::from(dc)
+    }
+
+    /// Gather the digests of the files used and created by a successful run.
+    /// Returns `None` if it looks like an input changed while we were
+    /// processing it, in which case the outputs can't be trusted to be up to
+    /// date.
+    fn collect_build_digests(&self) -> Result<Option<BuildDigests>> {
+        let input_root = self.bs.filesystem.root();
+        let output_root = self.output_path.as_ref().unwrap();
+        let mut files = BTreeMap::new();
+
+        if let Some(ref p) = self.primary_input_path {
+            files.insert(p.clone(), digest_of_file(p)?);
+        }
+
+        // Record everything that the engines tried to read. If a file didn't
+        // come from the filesystem, we still want to notice if one appears
+        // there, since it would then take precedence.
+        for (name, info) in &self.bs.events {
+            if name.is_empty()
+                || !matches!(
+                    info.access_pattern,
+                    AccessPattern::Read | AccessPattern::ReadThenWritten
+                )
+            {
+                continue;
+            }
+
+            let path = input_root.join(name);
+            let digest = digest_of_file(&path)?;
+
+            if info.access_pattern == AccessPattern::Read
+                && info.input_origin == InputOrigin::Filesystem
+                && matches!(info.read_digest, Some(d) if d != digest)
+            {
+                return Ok(None);
+            }
+
+            files.insert(path, digest);
+        }
+
+        // And record the outputs, so that we rebuild if they get deleted or
+        // modified.
+        for (name, info) in &self.bs.events {
+            if info.got_written_to_disk {
+                let path = output_root.join(name);
+                let digest = digest_of_file(&path)?;
+                files.insert(path, digest);
+            }
+        }
+
+        Ok(Some(BuildDigests {
+            settings: self.build_settings_digest(),
+            files,
+        }))
+    }
+
     #[allow(dead_code)]
     fn _dump_access_info(&self, status: &mut dyn StatusBackend) {
         for (name, info) in &self.bs.events {
@@ -1439,9 +2476,20 @@ impl ProcessingSession {
         };
 
         self.bs.shell_escape_work = shell_escape_work;
+        self.bs.shell_escape_commands = self.shell_escape_commands.clone();
+        self.bs.shell_escape_sandbox = self.shell_escape_sandbox;
 
         // Go-time!
-        let result = self.run_inner(status);
+        let mut result = self.run_inner(status);
+
+        // If a limit was exceeded, the engine probably failed with some
+        // generic error. Report the actual problem instead.
+
+        if let Some(what) = self.bs.limits.exceeded.take() {
+            result = Err(ErrorKind::LimitExceeded(what).into());
+        } else if result.is_err() && self.is_cancelled() {
+            result = Err(ErrorKind::Cancelled.into());
+        }
 
         // Do that cleanup.
 
@@ -1463,10 +2511,36 @@ impl ProcessingSession {
     /// lifecycle of resources like the shell-escape temporary directory, if
     /// needed.
     fn run_inner(&mut self, status: &mut dyn StatusBackend) -> Result<()> {
+        // If we're building incrementally, maybe there's nothing to do.
+
+        let digests_path = self.build_digests_path();
+
+        if let Some(ref p) = digests_path {
+            if let Some(digests) = BuildDigests::load(p) {
+                if digests.is_current(&self.build_settings_digest()) {
+                    tt_note!(
+                        status,
+                        "no inputs have changed since the last build; skipping processing"
+                    );
+                    return Ok(());
+                }
+            }
+
+            // Get rid of the old record so that it can't be trusted if this
+            // run fails partway through.
+            match std::fs::remove_file(p) {
+                Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                r => ctry!(r; "couldn't remove build digests file `{}`", p.display()),
+            }
+        }
+
         // Do we need to generate the format file?
 
         let generate_format = if self.output_format == OutputFormat::Format {
             false
+        } else if self.pruned_bundle_path.is_some() {
+            // We need to see which files go into the format.
+            true
         } else {
             match self.bs.input_open_format(&self.format_name, status) {
                 OpenResult::Ok(_) => false,
@@ -1483,6 +2557,12 @@ impl ProcessingSession {
             self.make_format_pass(status)?;
         }
 
+        // Maybe we can skip the document preamble, too.
+
+        if self.preamble.is_some() && self.output_format != OutputFormat::Format {
+            self.prepare_preamble_format(status)?;
+        }
+
         // Do the meat of the work.
 
         let result = match self.pass {
@@ -1569,6 +2649,29 @@ impl ProcessingSession {
             ctry!(writeln!(mf_dest, ""); "couldn't write to Makefile-rules file");
         }
 
+        // Write the minimal bundle, maybe.
+
+        if let Some(p) = self.pruned_bundle_path.take() {
+            self.write_pruned_bundle(&p, status)?;
+        }
+
+        // Record what we did, if we're building incrementally. Failing to do
+        // so shouldn't make the whole build fail.
+
+        if let Some(ref p) = digests_path {
+            if !self.external_tools_run {
+                let r = match self.collect_build_digests() {
+                    Ok(Some(digests)) => digests.save(p),
+                    Ok(None) => Ok(()),
+                    Err(e) => Err(e),
+                };
+
+                if let Err(e) = r {
+                    tt_warning!(status, "couldn't record digests for incremental builds"; SyncError::new(e).into());
+                }
+            }
+        }
+
         // All done.
 
         Ok(())
@@ -1618,7 +2721,7 @@ impl ProcessingSession {
                 continue;
             }
 
-            let is_logfile = sname.ends_with(".log") || sname.ends_with(".blg");
+            let is_logfile = LOG_EXTENSIONS.iter().any(|ext| sname.ends_with(ext));
 
             if is_logfile && !self.keep_logs {
                 continue;
@@ -1645,6 +2748,10 @@ impl ProcessingSession {
                 &format!(" ({})", byte_len.get_appropriate_unit(true)),
             );
 
+            if let Some(parent) = real_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
             let mut f = File::create(&real_path)?;
             f.write_all(&file.data)?;
             summ.got_written_to_disk = true;
@@ -1664,6 +2771,21 @@ impl ProcessingSession {
         Ok(n_skipped_intermediates)
     }
 
+    /// Whether the session's cancellation token, if any, has been cancelled.
+    fn is_cancelled(&self) -> bool {
+        matches!(&self.cancellation, Some(token) if token.is_cancelled())
+    }
+
+    /// Fail if processing has been cancelled. Passes that don't go through
+    /// the engine bridge use this to check for cancellation before starting.
+    fn check_cancelled(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(ErrorKind::Cancelled.into())
+        } else {
+            Ok(())
+        }
+    }
+
     /// The "default" pass really runs a bunch of sub-passes. It is a "Do What
     /// I Mean" operation.
     fn default_pass(&mut self, bibtex_first: bool, status: &mut dyn StatusBackend) -> Result<i32> {
@@ -1678,16 +2800,25 @@ impl ProcessingSession {
             Some(RerunReason::Bibtex)
         } else {
             warnings = self.tex_pass(None, status)?;
+            self.makeindex_pass(status)?;
+            let tool_rerun = self.configured_tools_pass(status)?;
             let maybe_biber = self.check_biber_requirement()?;
 
             if let Some(biber) = maybe_biber {
-                self.bs.external_tool_pass(&biber, status)?;
+                self.check_cancelled()?;
+                self.external_tools_run = true;
+                self.bs.limits.start_pass();
+                status.pass_begin("external");
+                let r = self.bs.external_tool_pass(&biber, status);
+                self.bs.limits.end_pass();
+                status.pass_end("external", r.is_ok());
+                r?;
                 Some(RerunReason::Biber)
             } else if self.is_bibtex_needed() {
                 self.bibtex_pass(status)?;
                 Some(RerunReason::Bibtex)
             } else {
-                self.is_rerun_needed(status)
+                tool_rerun.or_else(|| self.is_rerun_needed(status))
             }
         };
 
@@ -1705,6 +2836,7 @@ impl ProcessingSession {
                 match rerun_result {
                     Some(RerunReason::Biber) => "biber was run".to_owned(),
                     Some(RerunReason::Bibtex) => "bibtex was run".to_owned(),
+                    Some(RerunReason::ExternalTool(ref s)) => format!("{s} was run"),
                     Some(RerunReason::FileChange(ref s)) => format!("\"{s}\" changed"),
                     None => break,
                 }
@@ -1721,9 +2853,11 @@ impl ProcessingSession {
             }
 
             warnings = self.tex_pass(Some(&rerun_explanation), status)?;
+            self.makeindex_pass(status)?;
+            let tool_rerun = self.configured_tools_pass(status)?;
 
             if !reruns_fixed {
-                rerun_result = self.is_rerun_needed(status);
+                rerun_result = tool_rerun.or_else(|| self.is_rerun_needed(status));
 
                 if rerun_result.is_some() && i == DEFAULT_MAX_TEX_PASSES - 1 {
                     tt_warning!(
@@ -1741,17 +2875,78 @@ impl ProcessingSession {
             tt_warning!(status, "{}", warnings);
         }
 
-        // And finally, xdvipdfmx or spx2html. Maybe.
+        // And finally, xdvipdfmx, spx2html, or xdv2svg. Maybe.
 
-        if let OutputFormat::Pdf = self.output_format {
-            self.xdvipdfmx_pass(status)?;
-        } else if let OutputFormat::Html = self.output_format {
-            self.spx2html_pass(status)?;
+        match self.output_format {
+            OutputFormat::Pdf => {
+                self.xdvipdfmx_pass(status)?;
+            }
+            OutputFormat::Html => {
+                self.spx2html_pass(status)?;
+            }
+            OutputFormat::Svg => {
+                self.xdv2svg_pass(status)?;
+            }
+            _ => {}
         }
 
         Ok(0)
     }
 
+    /// Write out a ZIP bundle containing every file that was read from the
+    /// backing bundle during processing.
+    ///
+    /// We can't tell from the access summaries alone where a file came from,
+    /// since some bundles load files from the filesystem and the memory layer
+    /// serves files too. So we include any file that was read, but never
+    /// written, and that the bundle can provide.
+    fn write_pruned_bundle(&mut self, path: &Path, status: &mut dyn StatusBackend) -> Result<()> {
+        let names: Vec<String> = self
+            .bs
+            .events
+            .iter()
+            .filter(|(_, info)| {
+                info.access_pattern == AccessPattern::Read
+                    && info.input_origin != InputOrigin::NotInput
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        let mut creator = BundleCreator::new();
+
+        for name in names {
+            match self.bs.bundle.input_open_name(&name, status) {
+                OpenResult::Ok(mut ih) => {
+                    let mut data = Vec::new();
+                    ctry!(ih.read_to_end(&mut data); "couldn't read bundle file `{}`", name);
+                    ctry!(creator.add_data(name.clone(), data); "couldn't add `{}` to the bundle", name);
+                }
+                OpenResult::NotAvailable => {}
+                OpenResult::Err(e) => {
+                    return Err(e).chain_err(|| format!("couldn't open bundle file `{name}`"));
+                }
+            }
+        }
+
+        let digest = match creator.write_zip(path) {
+            Ok(d) => d,
+            Err(e) => {
+                return Err(e).chain_err(|| format!("couldn't write bundle `{}`", path.display()))
+            }
+        };
+
+        status.note_highlighted(
+            "Wrote ",
+            &format!("{} files", creator.len()),
+            &format!(
+                " to minimal bundle `{}` (digest {})",
+                path.display(),
+                digest.to_string()
+            ),
+        );
+        Ok(())
+    }
+
     fn is_bibtex_needed(&self) -> bool {
         const BIBDATA: &[u8] = b"\\bibdata";
 
@@ -1784,19 +2979,33 @@ impl ProcessingSession {
         let stem = r?;
 
         let result = {
+            self.bs.limits.start_pass();
+            status.pass_begin("format");
             self.bs
                 .enter_format_mode(&format!("tectonic-format-{stem}.tex"));
             let mut launcher =
                 CoreBridgeLauncher::new_with_security(&mut self.bs, status, self.security.clone());
+
+            if let Some(token) = self.cancellation.as_ref() {
+                launcher.with_cancellation_token(token.clone());
+            }
+
             let r = TexEngine::default()
                 .halt_on_error_mode(true)
                 .initex_mode(true)
                 .shell_escape(self.shell_escape_mode != ShellEscapeMode::Disabled)
+                .shell_escape_restricted(self.shell_escape_commands.is_some())
                 .process(&mut launcher, "UNUSED.fmt", "texput");
             self.bs.leave_format_mode();
             r
         };
 
+        self.bs.limits.end_pass();
+        status.pass_end(
+            "format",
+            matches!(result, Ok(TexOutcome::Spotless | TexOutcome::Warnings)),
+        );
+
         match result {
             Ok(TexOutcome::Spotless) => {}
             Ok(TexOutcome::Warnings) => {
@@ -1836,6 +3045,296 @@ impl ProcessingSession {
         Ok(0)
     }
 
+    /// Get ready to use a precompiled format for the document preamble,
+    /// generating it if needed. If we end up with one, the primary input is
+    /// swapped out for the document body, prefixed with a command that
+    /// resumes the preamble where the format left off.
+    fn prepare_preamble_format(&mut self, status: &mut dyn StatusBackend) -> Result<()> {
+        let preamble_file = self.preamble.as_ref().unwrap().file.clone();
+
+        // Each preamble gets its own slot in the format cache, so that a
+        // modified preamble replaces its old format rather than piling up new
+        // ones. Whether the format is current is tracked by its dependency
+        // record.
+        let key = {
+            let mut dc = digest::create();
+            dc.update(
+                format!(
+                    "format={}\nroot={}\npreamble={}\njob={}\nshell_escape={}\n",
+                    self.format_name,
+                    self.bs.filesystem.root().display(),
+                    preamble_file,
+                    self.primary_input_tex_path,
+                    self.shell_escape_mode != ShellEscapeMode::Disabled,
+                )
+                .as_bytes(),
+            );
+            DigestData::from(dc)
+        };
+        let stock_stem = self.format_name.split('.').next().unwrap_or_default();
+        let stem = format!("{}-preamble-{}", stock_stem, key.to_string());
+        let format_name = format!("{stem}.fmt");
+
+        let recorded = match self.bs.format_cache.read_dependencies(&stem) {
+            Ok(Some(deps)) => self.check_preamble_dependencies(&deps, status)?,
+            Ok(None) => None,
+            Err(e) => {
+                tt_warning!(status, "couldn't read the record of the precompiled preamble"; e);
+                None
+            }
+        };
+
+        let available = match recorded {
+            Some(false) => false,
+            Some(true)
+                if matches!(
+                    self.bs.format_cache.input_open_format(&format_name, status),
+                    OpenResult::Ok(_)
+                ) =>
+            {
+                true
+            }
+            _ => {
+                tt_note!(status, "precompiling preamble \"{}\"", preamble_file);
+                self.make_preamble_format_pass(&stem, &preamble_file, status)?
+            }
+        };
+
+        if available {
+            let mut input = b"\\tectonicresumepreamble\n".to_vec();
+            input.extend_from_slice(&self.preamble.as_ref().unwrap().body);
+            self.bs.primary_input = Box::new(BufferedPrimaryIo::from_buffer(input));
+            self.preamble_format_name = Some(format_name);
+        }
+
+        Ok(())
+    }
+
+    /// Check the dependency record of a precompiled preamble against the files
+    /// that we would read now. Returns `None` if the record is out of date,
+    /// and otherwise whether the format was successfully created.
+    fn check_preamble_dependencies(
+        &mut self,
+        deps: &str,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Option<bool>> {
+        let mut lines = deps.lines();
+
+        if lines.next() != Some(PREAMBLE_DEPS_HEADER) {
+            return Ok(None);
+        }
+
+        let created = match lines.next() {
+            Some("status ok") => true,
+            Some("status failed") => false,
+            _ => return Ok(None),
+        };
+
+        for line in lines {
+            let (digest, name) = match line.split_once(' ') {
+                Some(t) => t,
+                None => return Ok(None),
+            };
+
+            let digest: DigestData = match digest.parse() {
+                Ok(d) => d,
+                Err(_) => return Ok(None),
+            };
+
+            if self.digest_of_input(name, status)? != digest {
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(created))
+    }
+
+    /// Use the TeX engine to precompile the document preamble into a custom
+    /// format, and save it in the format cache along with a record of the
+    /// files that went into it. Since the preamble can always be processed
+    /// normally, failure to create the format isn't fatal: this returns
+    /// whether it worked.
+    fn make_preamble_format_pass(
+        &mut self,
+        stem: &str,
+        preamble_file: &str,
+        status: &mut dyn StatusBackend,
+    ) -> Result<bool> {
+        let source = PREAMBLE_FORMAT_SOURCE.replace("@PREAMBLE@", preamble_file);
+        let primary_input = std::mem::replace(
+            &mut self.bs.primary_input,
+            Box::new(BufferedPrimaryIo::from_text(source)),
+        );
+
+        // Track the I/O of this pass separately, so that we know what the
+        // format depends on.
+        let prior_events = std::mem::take(&mut self.bs.events);
+
+        let result = {
+            self.bs.limits.start_pass();
+            status.pass_begin("format");
+            let mut launcher =
+                CoreBridgeLauncher::new_with_security(&mut self.bs, status, self.security.clone());
+
+            if let Some(token) = self.cancellation.as_ref() {
+                launcher.with_cancellation_token(token.clone());
+            }
+
+            if self.unstables.deterministic_mode {
+                launcher.with_expose_absolute_paths(false);
+                launcher.with_mtime_override(Some(
+                    self.build_date
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .map(|x| x.as_secs() as i64)
+                        .expect("invalid build date in deterministic mode"),
+                ));
+            }
+
+            TexEngine::default()
+                .halt_on_error_mode(true)
+                .initex_mode(true)
+                .initex_load_format(true)
+                .shell_escape(self.shell_escape_mode != ShellEscapeMode::Disabled)
+                .shell_escape_restricted(self.shell_escape_commands.is_some())
+                .build_date(self.build_date)
+                .process(
+                    &mut launcher,
+                    &self.format_name,
+                    &self.primary_input_tex_path,
+                )
+        };
+
+        self.bs.limits.end_pass();
+        status.pass_end(
+            "format",
+            matches!(result, Ok(TexOutcome::Spotless | TexOutcome::Warnings)),
+        );
+
+        self.bs.primary_input = primary_input;
+        let events = std::mem::replace(&mut self.bs.events, prior_events);
+
+        // The only thing that the pass should have created is the format file
+        // (and its log). Anything else would get lost when the format is used.
+
+        let result: Result<Vec<u8>> = match result {
+            Ok(TexOutcome::Spotless | TexOutcome::Warnings) => {
+                let mut data = None;
+                let mut unexpected = None;
+
+                for (name, file) in &*self.bs.mem.files.borrow() {
+                    if name == self.bs.mem.stdout_key() || name.ends_with(".log") {
+                        continue;
+                    }
+
+                    if name.ends_with(".fmt") {
+                        data = Some(file.data.clone());
+                    } else {
+                        unexpected = Some(name.clone());
+                    }
+                }
+
+                match (data, unexpected) {
+                    (_, Some(name)) => Err(errmsg!(
+                        "the preamble created the file `{}`, which would be lost",
+                        name
+                    )),
+                    (None, None) => Err(errmsg!("no format file was created")),
+                    (Some(data), None) => Ok(data),
+                }
+            }
+            Ok(TexOutcome::Errors) => Err(errmsg!("errors were issued by the TeX engine")),
+            Err(e) => Err(e.into()),
+        };
+
+        self.bs.mem.files.borrow_mut().clear();
+
+        let result = result.and_then(|data| {
+            self.bs
+                .format_cache
+                .write_format(stem, &data, status)
+                .chain_err(|| "cannot write the preamble format file")
+        });
+
+        let created = match result {
+            Ok(()) => true,
+            Err(e) => {
+                tt_warning!(status, "couldn't precompile the preamble \"{}\"; it will be processed normally", preamble_file; SyncError::new(e).into());
+                false
+            }
+        };
+
+        // Now record what went into the format, even if it failed, so that we
+        // don't try again until something changes. We keep the events of the
+        // pass, since the outputs of the session depend on these files too.
+
+        let mut names = Vec::new();
+
+        for (name, info) in events {
+            if name.is_empty()
+                || name == self.format_name
+                || !matches!(
+                    info.access_pattern,
+                    AccessPattern::Read | AccessPattern::ReadThenWritten
+                )
+            {
+                continue;
+            }
+
+            names.push(name.clone());
+            self.bs.events.entry(name).or_insert(info);
+        }
+
+        names.sort();
+
+        let mut deps = format!(
+            "{}\nstatus {}\n",
+            PREAMBLE_DEPS_HEADER,
+            if created { "ok" } else { "failed" }
+        );
+
+        for name in &names {
+            let digest = self.digest_of_input(name, status)?;
+            deps.push_str(&format!("{} {}\n", digest.to_string(), name));
+        }
+
+        if let Err(e) = self.bs.format_cache.write_dependencies(stem, &deps) {
+            tt_warning!(status, "couldn't save the record of the precompiled preamble"; e);
+            return Ok(false);
+        }
+
+        Ok(created)
+    }
+
+    /// Compute the digest of an input file as the engines would see it,
+    /// recording the access. A file that doesn't exist is treated as being
+    /// empty.
+    fn digest_of_input(
+        &mut self,
+        name: &str,
+        status: &mut dyn StatusBackend,
+    ) -> Result<DigestData> {
+        let mut dc = digest::create();
+
+        match self.bs.input_open_name(name, status) {
+            OpenResult::Ok(mut ih) => {
+                let mut data = Vec::new();
+                ctry!(ih.read_to_end(&mut data); "couldn't read input file `{}`", name);
+                dc.update(&data);
+            }
+
+            OpenResult::NotAvailable => {}
+
+            OpenResult::Err(e) => {
+                return Err(e).chain_err(|| format!("couldn't open input file `{name}`"));
+            }
+        }
+
+        let digest = DigestData::from(dc);
+        self.bs
+            .event_input_closed(name.to_owned(), Some(digest), status);
+        Ok(digest)
+    }
+
     /// Run one pass of the TeX engine.
     fn tex_pass(
         &mut self,
@@ -1849,9 +3348,15 @@ impl ProcessingSession {
                 status.note_highlighted("Running ", "TeX", " ...");
             }
 
+            self.bs.limits.start_tex_pass()?;
+            status.pass_begin("tex");
             let mut launcher =
                 CoreBridgeLauncher::new_with_security(&mut self.bs, status, self.security.clone());
 
+            if let Some(token) = self.cancellation.as_ref() {
+                launcher.with_cancellation_token(token.clone());
+            }
+
             // In deterministic mode, we stub a few aspects of the environment.
             // They default to a "realistic" view, but we override them with static values:
             if self.unstables.deterministic_mode {
@@ -1870,14 +3375,23 @@ impl ProcessingSession {
                 .synctex(self.synctex_enabled)
                 .semantic_pagination(self.output_format == OutputFormat::Html)
                 .shell_escape(self.shell_escape_mode != ShellEscapeMode::Disabled)
+                .shell_escape_restricted(self.shell_escape_commands.is_some())
                 .build_date(self.build_date)
                 .process(
                     &mut launcher,
-                    &self.format_name,
+                    self.preamble_format_name
+                        .as_deref()
+                        .unwrap_or(&self.format_name),
                     &self.primary_input_tex_path,
                 )
         };
 
+        self.bs.limits.end_pass();
+        status.pass_end(
+            "tex",
+            matches!(result, Ok(TexOutcome::Spotless | TexOutcome::Warnings)),
+        );
+
         let warnings = match result {
             Ok(TexOutcome::Spotless) => None,
             Ok(TexOutcome::Warnings) =>
@@ -1909,12 +3423,22 @@ impl ProcessingSession {
     ) -> Result<i32> {
         let result = {
             status.note_highlighted("Running ", "BibTeX", &format!(" on {aux_file} ..."));
+            self.bs.limits.start_pass();
+            status.pass_begin("bibtex");
             let mut launcher =
                 CoreBridgeLauncher::new_with_security(&mut self.bs, status, self.security.clone());
+
+            if let Some(token) = self.cancellation.as_ref() {
+                launcher.with_cancellation_token(token.clone());
+            }
+
             let mut engine = BibtexEngine::new();
             engine.process(&mut launcher, aux_file, &self.unstables)
         };
 
+        self.bs.limits.end_pass();
+        status.pass_end("bibtex", result.is_ok());
+
         match result {
             Ok(TexOutcome::Spotless) => {}
             Ok(TexOutcome::Warnings) => {
@@ -1955,12 +3479,220 @@ impl ProcessingSession {
         Ok(0)
     }
 
+    /// Run any configured external tools whose trigger files have changed.
+    ///
+    /// Returns a rerun reason if a tool ran that calls for TeX to be rerun.
+    fn configured_tools_pass(
+        &mut self,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Option<RerunReason>> {
+        let mut rerun = None;
+
+        for (index, tool) in self.external_tools.iter().enumerate() {
+            let mut triggers: Vec<(String, DigestData)> = self
+                .bs
+                .events
+                .iter()
+                .filter(|(name, info)| {
+                    info.access_pattern != AccessPattern::Read
+                        && pattern_matches(&tool.trigger, name)
+                })
+                .filter_map(|(name, info)| info.write_digest.map(|d| (name.clone(), d)))
+                .collect();
+            triggers.sort_by(|a, b| a.0.cmp(&b.0));
+
+            for (trigger, digest) in triggers {
+                let key = (index, trigger);
+
+                if self.external_tool_triggers.get(&key) == Some(&digest) {
+                    continue;
+                }
+
+                let trigger = &key.1;
+                let stem = match trigger.rfind('.') {
+                    Some(i) => &trigger[..i],
+                    None => trigger,
+                };
+                let expand = |s: &String| s.replace("{trigger}", trigger).replace("{stem}", stem);
+
+                let pass = ExternalToolPass {
+                    argv: tool.command.iter().map(expand).collect(),
+                    extra_requires: std::iter::once(trigger.clone()).collect(),
+                    produces: tool.produces.iter().map(expand).collect(),
+                };
+
+                self.check_cancelled()?;
+                self.external_tools_run = true;
+                self.bs.limits.start_pass();
+                status.pass_begin("external");
+                let r = self.bs.external_tool_pass(&pass, status);
+                self.bs.limits.end_pass();
+                status.pass_end("external", r.is_ok());
+                r?;
+
+                if tool.rerun_tex && rerun.is_none() {
+                    rerun = Some(RerunReason::ExternalTool(tool.command[0].clone()));
+                }
+
+                self.external_tool_triggers.insert(key, digest);
+            }
+        }
+
+        Ok(rerun)
+    }
+
+    /// Figure out which index-like files need processing after a TeX pass.
+    ///
+    /// Besides plain `.idx` files, we handle the files written by the
+    /// `glossaries` package, which describes them in the `.aux` file, and by
+    /// the `nomencl` package. These all use the `makeindex` file format.
+    fn index_jobs(&mut self, status: &mut dyn StatusBackend) -> Vec<IndexJob> {
+        let mut written: Vec<String> = self
+            .bs
+            .events
+            .iter()
+            .filter(|(_, info)| {
+                matches!(
+                    info.access_pattern,
+                    AccessPattern::Written | AccessPattern::WrittenThenRead
+                )
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        written.sort();
+
+        let mut jobs = Vec::new();
+        let aux_stem = self.tex_aux_path.trim_end_matches(".aux").to_owned();
+
+        let glossaries = self
+            .bs
+            .mem
+            .files
+            .borrow()
+            .get(&self.tex_aux_path)
+            .map(|file| GlossariesInfo::parse(&String::from_utf8_lossy(&file.data)))
+            .unwrap_or_default();
+
+        for (log_ext, out_ext, in_ext) in &glossaries.glossaries {
+            let input = format!("{aux_stem}.{in_ext}");
+
+            if !written.contains(&input) {
+                continue;
+            }
+
+            if glossaries.ist.is_none() {
+                tt_warning!(
+                    status,
+                    "not processing glossary file `{}` since the document did not write a style file",
+                    input
+                );
+                continue;
+            }
+
+            jobs.push(IndexJob {
+                input,
+                style: glossaries.ist.clone(),
+                output: Some(format!("{aux_stem}.{out_ext}")),
+                log: Some(format!("{aux_stem}.{log_ext}")),
+                letter_ordering: glossaries.letter_ordering,
+            });
+        }
+
+        for name in written {
+            if jobs.iter().any(|j| j.input == name) {
+                continue;
+            }
+
+            if let Some(stem) = name.strip_suffix(".idx") {
+                let style = self.unstables.index_style.clone().or_else(|| {
+                    let ist = format!("{stem}.ist");
+
+                    // Don't mistake the glossaries style for an index style,
+                    // since they both default to `\jobname.ist`.
+                    if glossaries.ist.as_ref() != Some(&ist)
+                        && matches!(self.bs.input_open_name(&ist, status), OpenResult::Ok(_))
+                    {
+                        Some(ist)
+                    } else {
+                        None
+                    }
+                });
+
+                jobs.push(IndexJob {
+                    input: name.clone(),
+                    style,
+                    output: None,
+                    log: None,
+                    letter_ordering: false,
+                });
+            } else if let Some(stem) = name.strip_suffix(".nlo") {
+                jobs.push(IndexJob {
+                    input: name.clone(),
+                    style: Some("nomencl.ist".to_owned()),
+                    output: Some(format!("{stem}.nls")),
+                    log: Some(format!("{stem}.nlg")),
+                    letter_ordering: false,
+                });
+            }
+        }
+
+        jobs
+    }
+
+    /// Generate indices, glossaries, and nomenclatures from the files written
+    /// by the TeX engine.
+    ///
+    /// The outputs go into the memory layer, so if they've changed, the usual
+    /// rerun detection will notice and cause TeX to be run again.
+    fn makeindex_pass(&mut self, status: &mut dyn StatusBackend) -> Result<i32> {
+        for job in self.index_jobs(status) {
+            self.check_cancelled()?;
+            let mut engine = MakeindexEngine::default();
+            engine.letter_ordering(job.letter_ordering);
+
+            if let Some(style) = job.style {
+                engine.style(style);
+            }
+
+            if let Some(output) = job.output {
+                engine.output_name(output);
+            }
+
+            if let Some(log) = job.log {
+                engine.log_name(log);
+            }
+
+            status.note_highlighted("Running ", "makeindex", &format!(" on {} ...", job.input));
+            self.bs.limits.start_pass();
+            status.pass_begin("makeindex");
+            let r = engine.process(&mut self.bs, status, &job.input);
+            self.bs.limits.end_pass();
+            status.pass_end("makeindex", r.is_ok());
+
+            if r.chain_err(|| ErrorKind::EngineError("makeindex"))? == MakeindexOutcome::Warnings {
+                tt_note!(
+                    status,
+                    "warnings were issued by makeindex; use --keep-logs for details."
+                );
+            }
+        }
+
+        Ok(0)
+    }
+
     fn xdvipdfmx_pass(&mut self, status: &mut dyn StatusBackend) -> Result<i32> {
         {
             status.note_highlighted("Running ", "xdvipdfmx", " ...");
+            self.bs.limits.start_pass();
+            status.pass_begin("xdvipdfmx");
 
             let mut launcher =
                 CoreBridgeLauncher::new_with_security(&mut self.bs, status, self.security.clone());
+
+            if let Some(token) = self.cancellation.as_ref() {
+                launcher.with_cancellation_token(token.clone());
+            }
+
             let mut engine = XdvipdfmxEngine::default();
 
             engine.build_date(self.build_date);
@@ -1969,7 +3701,10 @@ impl ProcessingSession {
                 engine.paper_spec(ps.clone());
             }
 
-            engine.process(&mut launcher, &self.tex_xdv_path, &self.tex_pdf_path)?;
+            let r = engine.process(&mut launcher, &self.tex_xdv_path, &self.tex_pdf_path);
+            self.bs.limits.end_pass();
+            status.pass_end("xdvipdfmx", r.is_ok());
+            r?;
         }
 
         self.bs.mem.files.borrow_mut().remove(&self.tex_xdv_path);
@@ -1977,6 +3712,8 @@ impl ProcessingSession {
     }
 
     fn spx2html_pass(&mut self, status: &mut dyn StatusBackend) -> Result<i32> {
+        self.check_cancelled()?;
+
         {
             let mut engine = Spx2HtmlEngine::default();
 
@@ -1997,7 +3734,36 @@ impl ProcessingSession {
             }
 
             status.note_highlighted("Running ", "spx2html", " ...");
-            engine.process_to_filesystem(&mut self.bs, status, &self.tex_xdv_path)?;
+            self.bs.limits.start_pass();
+            status.pass_begin("spx2html");
+            let r = engine.process_to_filesystem(&mut self.bs, status, &self.tex_xdv_path);
+            self.bs.limits.end_pass();
+            status.pass_end("spx2html", r.is_ok());
+            r?;
+        }
+
+        self.bs.mem.files.borrow_mut().remove(&self.tex_xdv_path);
+        Ok(0)
+    }
+
+    fn xdv2svg_pass(&mut self, status: &mut dyn StatusBackend) -> Result<i32> {
+        self.check_cancelled()?;
+
+        {
+            let mut engine = Xdv2SvgEngine::default();
+
+            match self.output_path.as_ref() {
+                Some(p) => engine.output_base(p),
+                None => return Err(errmsg!("SVG output must be saved directly to disk")),
+            };
+
+            status.note_highlighted("Running ", "xdv2svg", " ...");
+            self.bs.limits.start_pass();
+            status.pass_begin("xdv2svg");
+            let r = engine.process_to_filesystem(&mut self.bs, status, &self.tex_xdv_path);
+            self.bs.limits.end_pass();
+            status.pass_end("xdv2svg", r.is_ok());
+            r?;
         }
 
         self.bs.mem.files.borrow_mut().remove(&self.tex_xdv_path);
@@ -2204,6 +3970,7 @@ impl ProcessingSession {
             Some(ExternalToolPass {
                 argv,
                 extra_requires,
+                produces: Vec::new(),
             })
         })
     }
@@ -0,0 +1,178 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Sandboxing for shell-escape commands.
+//!
+//! On Linux, commands are run in new user, mount, and network namespaces. Inside
+//! them, the whole filesystem is remounted read-only, except for the
+//! shell-escape work directory, and there is no network access. The command
+//! runs as an unprivileged user inside the namespaces, even if Tectonic is run
+//! as root, so that it has no capabilities with which to undo these
+//! restrictions. This relies on unprivileged user namespaces and the
+//! `mount_setattr()` system call (Linux 5.12 or newer). On other platforms, the
+//! sandbox is unavailable.
+//!
+//! The same sandbox is used for external tool passes, such as `biber`, with the
+//! tool's temporary directory as the writable one.
+
+use std::{io, path::Path, process::Command};
+
+/// Set up *cmd* so that it will run inside the sandbox, with *work_dir* as the
+/// only writable directory.
+///
+/// Some problems are only detected when the command is launched, in which case
+/// they will be reported as errors from the launch.
+#[cfg(target_os = "linux")]
+pub(crate) fn sandbox_command(cmd: &mut Command, work_dir: &Path) -> io::Result<()> {
+    use std::{
+        ffi::CString,
+        os::unix::{ffi::OsStrExt, process::CommandExt},
+        ptr,
+    };
+
+    // Everything that the child needs is prepared in advance, because the
+    // `pre_exec` hook runs after `fork()` and must not allocate.
+
+    let work_dir = work_dir.canonicalize()?;
+    let c_work_dir = CString::new(work_dir.as_os_str().as_bytes())?;
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+    let uid_map = format!("{SANDBOX_ID} {uid} 1");
+    let gid_map = format!("{SANDBOX_ID} {gid} 1");
+
+    // Programs commonly create temporary files, so point them at a place where
+    // that will work.
+    cmd.env("TMPDIR", &work_dir);
+
+    unsafe {
+        cmd.pre_exec(move || {
+            check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+            check(libc::unshare(
+                libc::CLONE_NEWUSER | libc::CLONE_NEWNS | libc::CLONE_NEWNET,
+            ))?;
+
+            // Map our own user and group to an unprivileged one in the new
+            // user namespace, so that files created in the work directory
+            // have the right owner outside of it. Because the user isn't root
+            // inside the namespace, the capabilities that we have in it until
+            // now are dropped when the command is executed, so that it can't
+            // undo the mount changes below, even if we are root outside.
+            write_proc_file(b"/proc/self/setgroups\0", b"deny")?;
+            write_proc_file(b"/proc/self/uid_map\0", uid_map.as_bytes())?;
+            write_proc_file(b"/proc/self/gid_map\0", gid_map.as_bytes())?;
+
+            // Keep our mount changes from propagating outside the namespace.
+            let root = b"/\0".as_ptr() as *const libc::c_char;
+            check(libc::mount(
+                ptr::null(),
+                root,
+                ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                ptr::null(),
+            ))?;
+
+            // Make the work directory a mount point of its own, then make
+            // everything but it read-only.
+            check(libc::mount(
+                c_work_dir.as_ptr(),
+                c_work_dir.as_ptr(),
+                ptr::null(),
+                libc::MS_BIND | libc::MS_REC,
+                ptr::null(),
+            ))?;
+            set_mount_attrs(root, MOUNT_ATTR_RDONLY, 0, true)?;
+            set_mount_attrs(c_work_dir.as_ptr(), 0, MOUNT_ATTR_RDONLY, false)?;
+
+            // The working directory was set before this hook ran, so it still
+            // refers to the old, now read-only, mount.
+            check(libc::chdir(c_work_dir.as_ptr()))?;
+            Ok(())
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn sandbox_command(_cmd: &mut Command, _work_dir: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "the shell-escape sandbox is only available on Linux",
+    ))
+}
+
+/// The user and group IDs that sandboxed commands run as, inside their user
+/// namespace: those of the traditional `nobody` user.
+#[cfg(target_os = "linux")]
+const SANDBOX_ID: u32 = 65534;
+
+#[cfg(target_os = "linux")]
+const MOUNT_ATTR_RDONLY: u64 = 0x1;
+
+#[cfg(target_os = "linux")]
+const AT_RECURSIVE: libc::c_int = 0x8000;
+
+/// The argument structure of `mount_setattr()`, which the `libc` crate doesn't
+/// provide.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct MountAttr {
+    attr_set: u64,
+    attr_clr: u64,
+    propagation: u64,
+    userns_fd: u64,
+}
+
+#[cfg(target_os = "linux")]
+fn check(rv: libc::c_int) -> io::Result<()> {
+    if rv < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Write to a file in `/proc`. The data must be written in one call.
+#[cfg(target_os = "linux")]
+unsafe fn write_proc_file(path: &[u8], data: &[u8]) -> io::Result<()> {
+    let fd = libc::open(path.as_ptr() as *const libc::c_char, libc::O_WRONLY);
+    check(fd)?;
+    let n = libc::write(fd, data.as_ptr() as *const libc::c_void, data.len());
+    let err = io::Error::last_os_error();
+    libc::close(fd);
+
+    if n < 0 {
+        Err(err)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn set_mount_attrs(
+    path: *const libc::c_char,
+    attr_set: u64,
+    attr_clr: u64,
+    recursive: bool,
+) -> io::Result<()> {
+    let attr = MountAttr {
+        attr_set,
+        attr_clr,
+        propagation: 0,
+        userns_fd: 0,
+    };
+
+    let rv = libc::syscall(
+        libc::SYS_mount_setattr,
+        libc::AT_FDCWD,
+        path,
+        if recursive { AT_RECURSIVE } else { 0 },
+        &attr as *const MountAttr,
+        std::mem::size_of::<MountAttr>(),
+    );
+
+    if rv < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,4 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+pub use tectonic_engine_makeindex::{MakeindexEngine, MakeindexOutcome};
@@ -100,6 +100,16 @@ error_chain! {
             description("some engine had an unrecoverable error")
             display("the {} engine had an unrecoverable error", engine)
         }
+
+        LimitExceeded(what: String) {
+            description("a processing limit was exceeded")
+            display("processing stopped: the limit on {} was exceeded", what)
+        }
+
+        Cancelled {
+            description("processing was cancelled")
+            display("processing was cancelled")
+        }
     }
 }
 
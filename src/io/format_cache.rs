@@ -6,8 +6,10 @@
 //! Code for locally caching compiled format files.
 
 use std::{
-    io::{BufReader, Write},
-    path::PathBuf,
+    fs,
+    io::{BufReader, ErrorKind, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
 };
 use tectonic_errors::{anyhow::bail, Result};
 
@@ -38,10 +40,92 @@ impl FormatCache {
         }
     }
 
+    /// List the format files stored in a format cache directory.
+    ///
+    /// Files whose names don't follow the scheme used by the format cache are
+    /// ignored. A missing directory is treated as empty.
+    pub fn list_formats(formats_base: &Path) -> Result<Vec<CachedFormatInfo>> {
+        let entries = match fs::read_dir(formats_base) {
+            Ok(e) => e,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut formats = Vec::new();
+
+        for entry in entries {
+            let entry = entry?;
+            let name = match entry.file_name().into_string() {
+                Ok(n) => n,
+                Err(_) => continue,
+            };
+
+            // See `path_for_format()` for the naming scheme.
+            let (digest, serial) = match name
+                .strip_suffix(".fmt")
+                .and_then(|n| n.split_once('-'))
+                .and_then(|(d, rest)| Some((d, rest.rsplit_once('-')?.1)))
+            {
+                Some(t) => t,
+                None => continue,
+            };
+
+            let bundle_digest = match DigestData::from_str(digest) {
+                Ok(d) => d,
+                Err(_) => continue,
+            };
+
+            formats.push(CachedFormatInfo {
+                path: entry.path(),
+                bundle_digest,
+                is_current: serial == crate::FORMAT_SERIAL.to_string(),
+                size: entry.metadata()?.len(),
+            });
+        }
+
+        Ok(formats)
+    }
+
+    /// Load the dependency record saved alongside a format file, if there is
+    /// one.
+    ///
+    /// Formats generated purely from bundle files don't need such a record,
+    /// but formats that incorporate other files, such as a document preamble,
+    /// can only be reused if none of those files have changed. The contents of
+    /// the record are up to the caller.
+    pub fn read_dependencies(&mut self, name: &str) -> Result<Option<String>> {
+        let path = self.path_for(name, "deps")?;
+
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(Some(text)),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Save the dependency record for a format file. See
+    /// [`Self::read_dependencies`].
+    pub fn write_dependencies(&mut self, name: &str, deps: &str) -> Result<()> {
+        let final_path = self.path_for(name, "deps")?;
+        let mut temp_dest = tempfile::Builder::new()
+            .prefix("format_")
+            .rand_bytes(6)
+            .tempfile_in(&self.formats_base)?;
+        temp_dest.write_all(deps.as_bytes())?;
+        temp_dest.persist(final_path)?;
+        Ok(())
+    }
+
     /// Get an on-disk path name for a given format file. This function simply
     /// produces a path that may or may not exist.
-    #[allow(clippy::manual_split_once)] // requires Rust 1.52 (note that we don't actually define our MSRV)
     fn path_for_format(&mut self, name: &str) -> Result<PathBuf> {
+        self.path_for(name, "fmt")
+    }
+
+    /// Get an on-disk path name for a file associated with a given format,
+    /// distinguished by its extension.
+    #[allow(clippy::manual_split_once)] // requires Rust 1.52 (note that we don't actually define our MSRV)
+    fn path_for(&mut self, name: &str, extension: &str) -> Result<PathBuf> {
         // Remove all extensions from the format name. PathBuf.file_stem() doesn't
         // do what we want since it only strips one extension, so here we go:
 
@@ -54,15 +138,36 @@ impl FormatCache {
 
         let mut p = self.formats_base.clone();
         p.push(format!(
-            "{}-{}-{}.fmt",
+            "{}-{}-{}.{}",
             self.bundle_digest.to_string(),
             stem,
-            crate::FORMAT_SERIAL
+            crate::FORMAT_SERIAL,
+            extension
         ));
         Ok(p)
     }
 }
 
+/// Information about a format file stored in a format cache directory.
+///
+/// This type is returned by [`FormatCache::list_formats`].
+#[derive(Clone, Debug)]
+pub struct CachedFormatInfo {
+    /// The path of the format file.
+    pub path: PathBuf,
+
+    /// The digest of the bundle from which the format was generated.
+    pub bundle_digest: DigestData,
+
+    /// Whether the format was generated by the current version of the engine.
+    ///
+    /// Formats generated by other versions will never be used by this one.
+    pub is_current: bool,
+
+    /// The size of the format file, in bytes.
+    pub size: u64,
+}
+
 impl IoProvider for FormatCache {
     fn input_open_format(
         &mut self,
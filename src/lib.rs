@@ -76,6 +76,8 @@
 //!
 //! The [`driver`] module provides a high-level interface for driving the
 //! engines in more realistic circumstances.
+//! The [`worker`] module runs such sessions in child processes, so that
+//! several documents can be processed at the same time.
 
 pub mod config;
 pub mod digest;
@@ -87,6 +89,8 @@ pub mod errors;
 pub mod io;
 pub mod status;
 pub mod unstable_opts;
+#[cfg(feature = "serialization")]
+pub mod worker;
 
 // Note: this module is intentionally *not* gated by #[cfg(test)] -- see its
 // docstring for details.
@@ -94,7 +98,8 @@ pub mod unstable_opts;
 pub mod test_util;
 
 pub use crate::engines::bibtex::BibtexEngine;
-pub use crate::engines::spx2html::Spx2HtmlEngine;
+pub use crate::engines::makeindex::MakeindexEngine;
+pub use crate::engines::spx2html::{Spx2HtmlEngine, Xdv2SvgEngine};
 pub use crate::engines::tex::{TexEngine, TexOutcome};
 pub use crate::engines::xdvipdfmx::XdvipdfmxEngine;
 pub use crate::errors::{Error, ErrorKind, Result};
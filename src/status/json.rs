@@ -0,0 +1,214 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+//! A status backend that emits machine-readable JSON.
+//!
+//! Each status event is printed to standard error as a single line containing
+//! one JSON object, so that the stream can be consumed incrementally by tools
+//! such as CI systems and editor integrations without mixing with the engine
+//! output printed by `--print`. Every object has a `"type"` field that
+//! determines the other fields that it contains:
+//!
+//! - `"message"`: a general status message. Fields: `kind` (`"note"`,
+//!   `"warning"`, or `"error"`), `message`, `causes` (an array of strings
+//!   describing the error that caused the message, possibly empty), and `pass`.
+//! - `"diagnostic"`: a warning or error issued by a processing engine about
+//!   the document being processed. Fields: `kind`, `message`, `file`, `line`,
+//!   and `pass`. The `file` and `line` fields identify the input that the
+//!   diagnostic pertains to, and are `null` if the engine did not provide them.
+//! - `"pass-begin"`: a processing pass is starting. Fields: `pass` and `index`.
+//! - `"pass-end"`: a processing pass has finished. Fields: `pass`, `index`,
+//!   and `success`.
+//! - `"progress"`: an engine made progress. Fields: `event`, `file`, `page`,
+//!   and `pass`. The `event` is `"input-opened"` when the engine starts reading
+//!   an input file of the document (not a bundle file), whose name is given by
+//!   `file`, or `"page"` when it ships out a page. In the latter case, `page`
+//!   counts the pages shipped out by the pass so far, and `file` names the
+//!   input file being read at the time, if any. Otherwise, `page` is `null`.
+//! - `"error-log"`: the terminal output of an engine that failed. Fields:
+//!   `text`.
+//!
+//! The `pass` field gives the name of the processing pass that was active when
+//! the event was emitted, or is `null` if no pass was active. Pass names
+//! include `"format"`, `"tex"`, `"bibtex"`, `"external"`, `"xdvipdfmx"`,
+//! `"spx2html"`, and `"xdv2svg"`. The `index` field counts passes from zero
+//! over the lifetime of the backend, so that reruns of the same engine can be
+//! distinguished.
+
+use serde_json::{json, Value};
+use std::{
+    fmt::Arguments,
+    io::{self, Write},
+};
+use tectonic_errors::Error;
+
+use super::{ChatterLevel, MessageKind, Progress, SourceLocation, StatusBackend};
+
+/// A status backend that prints newline-delimited JSON records to standard
+/// error.
+#[derive(Clone, Debug, Default)]
+pub struct JsonStatusBackend {
+    chatter: ChatterLevel,
+    current_pass: Option<(String, usize)>,
+    n_passes: usize,
+}
+
+impl JsonStatusBackend {
+    /// Create a new backend with the specified chatter level.
+    pub fn new(chatter: ChatterLevel) -> Self {
+        JsonStatusBackend {
+            chatter,
+            ..Default::default()
+        }
+    }
+
+    fn kind_name(kind: MessageKind) -> &'static str {
+        match kind {
+            MessageKind::Note => "note",
+            MessageKind::Warning => "warning",
+            MessageKind::Error => "error",
+        }
+    }
+
+    fn pass_name(&self) -> Value {
+        match self.current_pass {
+            Some((ref name, _)) => json!(name),
+            None => Value::Null,
+        }
+    }
+
+    fn emit(&mut self, record: Value) {
+        let mut text = record.to_string();
+        text.push('\n');
+
+        io::stderr()
+            .write_all(text.as_bytes())
+            .expect("write to stderr failed");
+    }
+
+    fn message(&mut self, kind: MessageKind, message: String, err: Option<&Error>) {
+        if self.chatter.suppress_message(kind) {
+            return;
+        }
+
+        let causes: Vec<String> = err
+            .map(|e| e.chain().map(|item| item.to_string()).collect())
+            .unwrap_or_default();
+
+        let record = json!({
+            "type": "message",
+            "kind": Self::kind_name(kind),
+            "message": message,
+            "causes": causes,
+            "pass": self.pass_name(),
+        });
+        self.emit(record);
+    }
+}
+
+impl StatusBackend for JsonStatusBackend {
+    fn report(&mut self, kind: MessageKind, args: Arguments, err: Option<&Error>) {
+        self.message(kind, args.to_string(), err);
+    }
+
+    fn report_error(&mut self, err: &Error) {
+        let mut chain = err.chain();
+        let message = chain.next().map(|e| e.to_string()).unwrap_or_default();
+        let causes: Vec<String> = chain.map(|item| item.to_string()).collect();
+
+        let record = json!({
+            "type": "message",
+            "kind": Self::kind_name(MessageKind::Error),
+            "message": message,
+            "causes": causes,
+            "pass": self.pass_name(),
+        });
+        self.emit(record);
+    }
+
+    fn note_highlighted(&mut self, before: &str, highlighted: &str, after: &str) {
+        self.message(
+            MessageKind::Note,
+            format!("{before}{highlighted}{after}"),
+            None,
+        );
+    }
+
+    fn report_diagnostic(
+        &mut self,
+        kind: MessageKind,
+        args: Arguments,
+        location: Option<&SourceLocation>,
+    ) {
+        if self.chatter.suppress_message(kind) {
+            return;
+        }
+
+        let record = json!({
+            "type": "diagnostic",
+            "kind": Self::kind_name(kind),
+            "message": args.to_string(),
+            "file": location.map(|l| &l.file),
+            "line": location.and_then(|l| l.line),
+            "pass": self.pass_name(),
+        });
+        self.emit(record);
+    }
+
+    fn pass_begin(&mut self, pass: &str) {
+        let index = self.n_passes;
+        self.n_passes += 1;
+        self.current_pass = Some((pass.to_owned(), index));
+
+        let record = json!({
+            "type": "pass-begin",
+            "pass": pass,
+            "index": index,
+        });
+        self.emit(record);
+    }
+
+    fn pass_end(&mut self, pass: &str, success: bool) {
+        let index = match self.current_pass.take() {
+            Some((_, index)) => json!(index),
+            None => Value::Null,
+        };
+
+        let record = json!({
+            "type": "pass-end",
+            "pass": pass,
+            "index": index,
+            "success": success,
+        });
+        self.emit(record);
+    }
+
+    fn report_progress(&mut self, progress: &Progress) {
+        if self.chatter.suppress_message(MessageKind::Note) {
+            return;
+        }
+
+        let (event, file, page) = match *progress {
+            Progress::InputOpened { name } => ("input-opened", Some(name), None),
+            Progress::PageShippedOut { page, file } => ("page", file, Some(page)),
+            _ => return,
+        };
+
+        let record = json!({
+            "type": "progress",
+            "event": event,
+            "file": file,
+            "page": page,
+            "pass": self.pass_name(),
+        });
+        self.emit(record);
+    }
+
+    fn dump_error_logs(&mut self, output: &[u8]) {
+        let record = json!({
+            "type": "error-log",
+            "text": String::from_utf8_lossy(output),
+        });
+        self.emit(record);
+    }
+}
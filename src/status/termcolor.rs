@@ -11,7 +11,7 @@ use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
 use tectonic_errors::Error;
 
-use super::{ChatterLevel, MessageKind, StatusBackend};
+use super::{ChatterLevel, MessageKind, Progress, StatusBackend};
 
 pub struct TermcolorStatusBackend {
     chatter: ChatterLevel,
@@ -22,6 +22,20 @@ pub struct TermcolorStatusBackend {
     highlight_spec: ColorSpec,
     warning_spec: ColorSpec,
     error_spec: ColorSpec,
+    show_progress: bool,
+    progress: ProgressLine,
+}
+
+/// The maximum width of the progress line, in characters.
+const MAX_PROGRESS_WIDTH: usize = 79;
+
+/// The state of the progress line, which is shown on the terminal while an
+/// engine runs and erased before anything else is printed.
+#[derive(Debug, Default)]
+struct ProgressLine {
+    page: u32,
+    file: String,
+    width: usize,
 }
 
 impl TermcolorStatusBackend {
@@ -47,6 +61,8 @@ impl TermcolorStatusBackend {
             highlight_spec,
             warning_spec,
             error_spec,
+            show_progress: atty::is(atty::Stream::Stderr),
+            progress: ProgressLine::default(),
         }
     }
 
@@ -55,6 +71,15 @@ impl TermcolorStatusBackend {
         self
     }
 
+    /// Erase the progress line, if it is being shown.
+    fn clear_progress(&mut self) {
+        if self.progress.width > 0 {
+            write!(self.stderr, "\r{:1$}\r", "", self.progress.width)
+                .expect("write to stderr failed");
+            self.progress.width = 0;
+        }
+    }
+
     fn styled<F>(&mut self, kind: MessageKind, f: F)
     where
         F: FnOnce(&mut StandardStream),
@@ -63,6 +88,8 @@ impl TermcolorStatusBackend {
             return;
         }
 
+        self.clear_progress();
+
         let (spec, stream) = match kind {
             MessageKind::Note => {
                 if self.always_stderr {
@@ -88,6 +115,8 @@ impl TermcolorStatusBackend {
             return;
         }
 
+        self.clear_progress();
+
         let stream = match kind {
             MessageKind::Note => {
                 if self.always_stderr {
@@ -127,6 +156,8 @@ impl TermcolorStatusBackend {
 
     pub fn note_styled(&mut self, args: Arguments) {
         if self.chatter > ChatterLevel::Minimal {
+            self.clear_progress();
+
             if self.always_stderr {
                 writeln!(self.stderr, "{args}").expect("write to stderr failed");
             } else {
@@ -189,6 +220,8 @@ impl StatusBackend for TermcolorStatusBackend {
 
     fn note_highlighted(&mut self, before: &str, highlighted: &str, after: &str) {
         if self.chatter > ChatterLevel::Minimal {
+            self.clear_progress();
+
             let stream = if self.always_stderr {
                 &mut self.stderr
             } else {
@@ -205,6 +238,46 @@ impl StatusBackend for TermcolorStatusBackend {
         }
     }
 
+    fn pass_end(&mut self, _pass: &str, _success: bool) {
+        self.clear_progress();
+        self.progress = ProgressLine::default();
+    }
+
+    fn report_progress(&mut self, progress: &Progress) {
+        if !self.show_progress || self.chatter <= ChatterLevel::Minimal {
+            return;
+        }
+
+        match *progress {
+            Progress::InputOpened { name } => {
+                self.progress.file = name.to_owned();
+            }
+
+            Progress::PageShippedOut { page, file } => {
+                self.progress.page = page;
+
+                if let Some(file) = file {
+                    self.progress.file = file.to_owned();
+                }
+            }
+
+            _ => return,
+        }
+
+        let text = match self.progress.page {
+            0 => format!("[{}]", self.progress.file),
+            n => format!("[page {}, {}]", n, self.progress.file),
+        };
+
+        // Keep the line from wrapping on typical terminals, since then it
+        // couldn't be erased, and pad it with spaces to cover up any longer
+        // text shown previously.
+        let text: String = text.chars().take(MAX_PROGRESS_WIDTH).collect();
+        let width = text.chars().count();
+        write!(self.stderr, "\r{:1$}", text, self.progress.width).expect("write to stderr failed");
+        self.progress.width = self.progress.width.max(width);
+    }
+
     fn dump_error_logs(&mut self, output: &[u8]) {
         tt_error_styled!(
             self,
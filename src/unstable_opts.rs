@@ -20,15 +20,24 @@ const HELPMSG: &str = r#"Available unstable options:
 
     -Z help                     List all unstable options
     -Z continue-on-errors       Keep compiling even when severe errors occur
+    -Z index-style=<file>       Use the index style file <file> when generating indices from `.idx`
+                                    files [default: `<name>.ist` if it exists]
     -Z min-crossrefs=<num>      Equivalent to bibtex's -min-crossrefs flag - "include after <num>
                                     crossrefs" [default: 2]
     -Z paper-size=<spec>        Change the initial paper size [default: letter]
     -Z search-path=<path>       Also look in <path> for files (unless --untrusted has been specified),
                                     like TEXINPUTS. Can be specified multiple times.
     -Z shell-escape             Enable \write18 (unless --untrusted has been specified)
+    -Z shell-escape-commands=<list>
+                                Only allow \write18 to run the programs in the comma-separated
+                                    <list>, without using a shell. Can be specified multiple
+                                    times. Implies -Z shell-escape
     -Z shell-escape-cwd=<path>  Working directory to use for \write18. Use $(pwd) for same behaviour as
                                     most other engines (e.g. for relative paths in \inputminted).
                                     Implies -Z shell-escape
+    -Z shell-escape-sandbox     Run \write18 commands in a sandbox where only the shell-escape working
+                                    directory is writable and the network is unavailable (Linux only).
+                                    External tools such as biber are sandboxed too
     -Z deterministic-mode       Force a deterministic build environment. Note that setting
                                     `SOURCE_DATE_EPOCH` is usually sufficient for reproducible builds,
                                     and this option makes some extra functionality trade-offs.
@@ -41,10 +50,13 @@ const HELPMSG: &str = r#"Available unstable options:
 pub enum UnstableArg {
     ContinueOnErrors,
     Help,
+    IndexStyle(String),
     MinCrossrefs(u32),
     PaperSize(String),
     SearchPath(PathBuf),
     ShellEscapeEnabled,
+    ShellEscapeCommands(Vec<String>),
+    ShellEscapeSandbox,
     ShellEscapeCwd(String),
     DeterministicModeEnabled,
 }
@@ -87,6 +99,8 @@ impl FromStr for UnstableArg {
 
             "continue-on-errors" => Ok(UnstableArg::ContinueOnErrors),
 
+            "index-style" => require_value("file").map(|s| UnstableArg::IndexStyle(s.to_string())),
+
             "min-crossrefs" => require_value("num")
                 .and_then(|s| {
                     FromStr::from_str(s).map_err(|e| format!("-Z min-crossrefs: {e}").into())
@@ -99,6 +113,18 @@ impl FromStr for UnstableArg {
 
             "shell-escape" => require_no_value(value, UnstableArg::ShellEscapeEnabled),
 
+            "shell-escape-commands" => require_value("list").map(|s| {
+                UnstableArg::ShellEscapeCommands(
+                    s.split(',')
+                        .map(|c| c.trim())
+                        .filter(|c| !c.is_empty())
+                        .map(|c| c.to_owned())
+                        .collect(),
+                )
+            }),
+
+            "shell-escape-sandbox" => require_no_value(value, UnstableArg::ShellEscapeSandbox),
+
             "shell-escape-cwd" => {
                 require_value("path").map(|s| UnstableArg::ShellEscapeCwd(s.to_string()))
             }
@@ -116,9 +142,17 @@ pub struct UnstableOptions {
     pub paper_size: Option<String>,
     pub shell_escape: bool,
     pub min_crossrefs: Option<u32>,
+    pub index_style: Option<String>,
     pub extra_search_paths: Vec<PathBuf>,
     pub shell_escape_cwd: Option<String>,
 
+    /// If set, shell-escape may only run the programs with these names.
+    pub shell_escape_commands: Option<Vec<String>>,
+
+    /// Whether shell-escape commands and external tools should be run in a
+    /// sandbox.
+    pub shell_escape_sandbox: bool,
+
     /// Ensure a deterministic build environment.
     ///
     /// The most significant user-facing difference is a static document build
@@ -144,10 +178,18 @@ impl UnstableOptions {
             match u {
                 Help => print_unstable_help_and_exit(),
                 ContinueOnErrors => opts.continue_on_errors = true,
+                IndexStyle(s) => opts.index_style = Some(s),
                 MinCrossrefs(num) => opts.min_crossrefs = Some(num),
                 PaperSize(size) => opts.paper_size = Some(size),
                 ShellEscapeEnabled => opts.shell_escape = true,
                 SearchPath(p) => opts.extra_search_paths.push(p),
+                ShellEscapeCommands(c) => {
+                    opts.shell_escape_commands
+                        .get_or_insert_with(Vec::new)
+                        .extend(c);
+                    opts.shell_escape = true;
+                }
+                ShellEscapeSandbox => opts.shell_escape_sandbox = true,
                 ShellEscapeCwd(p) => {
                     opts.shell_escape_cwd = Some(p);
                     opts.shell_escape = true;
@@ -0,0 +1,1188 @@
+// Copyright 2023 the Tectonic Project
+// Licensed under the MIT License.
+
+#![deny(missing_docs)]
+
+//! Running processing sessions in worker processes.
+//!
+//! The TeX engines keep their state in global variables, so only one of them
+//! can run in a given process at a time: the launcher in
+//! `tectonic_bridge_core` holds a global lock while an engine is running. A
+//! program that wants to process many documents in parallel, such as a server,
+//! can use a [`WorkerPool`] instead. The pool runs each [`WorkerJob`] in a
+//! child process, starting up to a configurable number of them as needed and
+//! reusing them for later jobs.
+//!
+//! The workers don't open any bundle of their own. Instead, whenever an engine
+//! needs a support file, the request is forwarded to the process that owns the
+//! pool, which answers it using the bundle that was passed to
+//! [`WorkerPool::run`]. Status messages from the worker are forwarded in the
+//! same way, and so are the output files of jobs that don't have an output
+//! directory.
+//!
+//! Other filesystem access is *not* proxied: the worker reads the document's
+//! files from the job's filesystem root, writes its outputs to the job's
+//! output directory if it has one, and uses the format cache, all by itself.
+//! The workers run the same code as the pool's owner, as the same user, so
+//! routing these accesses through the pool wouldn't make them any safer; the
+//! point of the workers is to give each engine a process of its own, not to
+//! confine it. Proxying them would also add a round trip for each of the many
+//! files that the engines probe for.
+//!
+//! Worker processes are started by running a program, by default the current
+//! executable, with `--tectonic-worker` as its first argument. That program
+//! must call [`maybe_run_worker`] early on in its `main` function, which turns
+//! the process into a worker if that argument is present. The `tectonic` CLI
+//! program does this, so it can be used as the worker program if needed.
+//!
+//! The pool and its workers communicate over the worker's standard input and
+//! output. Each message is a frame consisting of a header, which is a JSON
+//! object with a `"type"` field, and a binary payload, which is often empty.
+//! The header is prefixed with its length as a little-endian `u32`, and the
+//! payload with its length as a little-endian `u64`. This protocol is an
+//! internal detail that may change at any time; the pool and the workers must
+//! be built from the same version of this crate.
+
+use serde_json::{json, Value};
+use std::{
+    cell::RefCell,
+    env,
+    ffi::{OsStr, OsString},
+    fmt::Arguments,
+    io::{self, BufReader, BufWriter, Cursor, Read, Write},
+    path::{Path, PathBuf},
+    process::{self, Child, Command, Stdio},
+    rc::Rc,
+    sync::{Condvar, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tectonic_bridge_core::{SecuritySettings, SecurityStance};
+use tectonic_bundles::Bundle;
+use tectonic_errors::{anyhow::anyhow, Error as NewError, Result as NewResult};
+use tectonic_io_base::{digest::DigestData, InputHandle, InputOrigin, IoProvider, OpenResult};
+
+use crate::{
+    ctry,
+    driver::{OutputFormat, PassSetting, ProcessingSessionBuilder},
+    errmsg,
+    errors::{Error, ErrorKind, Result},
+    io::memory::{MemoryFileCollection, MemoryFileInfo},
+    status::{MessageKind, Progress, SourceLocation, StatusBackend},
+    unstable_opts::UnstableOptions,
+};
+
+/// The first command-line argument that tells a program to act as a worker.
+const WORKER_ARG: &str = "--tectonic-worker";
+
+/// The version of the protocol spoken between the pool and its workers.
+const PROTOCOL_VERSION: u64 = 1;
+
+/// A description of a processing job to be run by a worker process.
+///
+/// This provides a subset of the settings of a [`ProcessingSessionBuilder`],
+/// with methods of the same names. Since the job is run in another process,
+/// the settings must be ones that can be transferred there. Jobs are always
+/// processed with insecure features such as shell-escape disabled.
+#[derive(Clone, Debug)]
+pub struct WorkerJob {
+    primary_input: JobInput,
+    tex_input_name: String,
+    filesystem_root: Option<PathBuf>,
+    output_dir: Option<PathBuf>,
+    format_name: String,
+    format_cache_path: Option<PathBuf>,
+    preamble_format: Option<String>,
+    output_format: OutputFormat,
+    pass: PassSetting,
+    reruns: Option<usize>,
+    keep_intermediates: bool,
+    keep_logs: bool,
+    synctex: bool,
+    incremental: bool,
+    deterministic_mode: bool,
+    build_date: Option<SystemTime>,
+    max_pass_duration: Option<Duration>,
+    max_output_bytes: Option<u64>,
+    max_output_files: Option<usize>,
+    max_tex_passes: Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+enum JobInput {
+    Path(PathBuf),
+    Buffer(Vec<u8>),
+}
+
+impl WorkerJob {
+    /// Create a new job.
+    ///
+    /// The *tex_input_name* has the same role as in
+    /// [`ProcessingSessionBuilder::tex_input_name`]. The primary input is
+    /// initially empty, and the format name defaults to `latex`.
+    pub fn new(tex_input_name: &str) -> Self {
+        WorkerJob {
+            primary_input: JobInput::Buffer(Vec::new()),
+            tex_input_name: tex_input_name.to_owned(),
+            filesystem_root: None,
+            output_dir: None,
+            format_name: "latex".to_owned(),
+            format_cache_path: None,
+            preamble_format: None,
+            output_format: OutputFormat::default(),
+            pass: PassSetting::default(),
+            reruns: None,
+            keep_intermediates: false,
+            keep_logs: false,
+            synctex: false,
+            incremental: false,
+            deterministic_mode: false,
+            build_date: None,
+            max_pass_duration: None,
+            max_output_bytes: None,
+            max_output_files: None,
+            max_tex_passes: None,
+        }
+    }
+
+    /// Set the path to the primary input file.
+    pub fn primary_input_path<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+        self.primary_input = JobInput::Path(p.as_ref().to_owned());
+        self
+    }
+
+    /// Set the contents of the primary input.
+    pub fn primary_input_buffer(&mut self, buf: &[u8]) -> &mut Self {
+        self.primary_input = JobInput::Buffer(buf.to_owned());
+        self
+    }
+
+    /// Set the directory that serves as the root for finding files on disk.
+    pub fn filesystem_root<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+        self.filesystem_root = Some(p.as_ref().to_owned());
+        self
+    }
+
+    /// Set the directory that output files will be written to.
+    ///
+    /// If no output directory is given, nothing is written to disk, and the
+    /// files created by the job are returned by [`WorkerPool::run`] instead.
+    pub fn output_dir<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+        self.output_dir = Some(p.as_ref().to_owned());
+        self
+    }
+
+    /// Set the name of the `.fmt` file used to initialize the TeX engine.
+    pub fn format_name(&mut self, p: &str) -> &mut Self {
+        self.format_name = p.to_owned();
+        self
+    }
+
+    /// Set the path to the format file cache.
+    ///
+    /// All of the workers may share the same cache.
+    pub fn format_cache_path<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+        self.format_cache_path = Some(p.as_ref().to_owned());
+        self
+    }
+
+    /// Set the preamble file to precompile into a format of its own.
+    ///
+    /// See [`ProcessingSessionBuilder::preamble_format`].
+    pub fn preamble_format(&mut self, preamble_file: &str) -> &mut Self {
+        self.preamble_format = Some(preamble_file.to_owned());
+        self
+    }
+
+    /// Set the desired output format.
+    pub fn output_format(&mut self, f: OutputFormat) -> &mut Self {
+        self.output_format = f;
+        self
+    }
+
+    /// Set the processing pass to run.
+    pub fn pass(&mut self, p: PassSetting) -> &mut Self {
+        self.pass = p;
+        self
+    }
+
+    /// Set the number of times to rerun TeX.
+    pub fn reruns(&mut self, r: usize) -> &mut Self {
+        self.reruns = Some(r);
+        self
+    }
+
+    /// Set whether intermediate files should be kept.
+    pub fn keep_intermediates(&mut self, k: bool) -> &mut Self {
+        self.keep_intermediates = k;
+        self
+    }
+
+    /// Set whether the TeX log file should be kept.
+    pub fn keep_logs(&mut self, k: bool) -> &mut Self {
+        self.keep_logs = k;
+        self
+    }
+
+    /// Set whether SyncTeX data should be generated.
+    pub fn synctex(&mut self, s: bool) -> &mut Self {
+        self.synctex = s;
+        self
+    }
+
+    /// Set whether processing is skipped if nothing has changed since the last
+    /// build.
+    ///
+    /// See [`ProcessingSessionBuilder::incremental`].
+    pub fn incremental(&mut self, inc: bool) -> &mut Self {
+        self.incremental = inc;
+        self
+    }
+
+    /// Set whether the engines should avoid nondeterministic behavior.
+    pub fn deterministic_mode(&mut self, d: bool) -> &mut Self {
+        self.deterministic_mode = d;
+        self
+    }
+
+    /// Set the date and time used for the build.
+    pub fn build_date(&mut self, date: SystemTime) -> &mut Self {
+        self.build_date = Some(date);
+        self
+    }
+
+    /// Limit the amount of time that one processing pass may take.
+    pub fn max_pass_duration(&mut self, d: Duration) -> &mut Self {
+        self.max_pass_duration = Some(d);
+        self
+    }
+
+    /// Limit the total number of bytes that may be written to output files.
+    pub fn max_output_bytes(&mut self, n: u64) -> &mut Self {
+        self.max_output_bytes = Some(n);
+        self
+    }
+
+    /// Limit the number of distinct output files that may be created.
+    pub fn max_output_files(&mut self, n: usize) -> &mut Self {
+        self.max_output_files = Some(n);
+        self
+    }
+
+    /// Limit the number of times that the TeX engine may be run.
+    pub fn max_tex_passes(&mut self, n: usize) -> &mut Self {
+        self.max_tex_passes = Some(n);
+        self
+    }
+
+    /// Encode this job as a message to a worker.
+    fn encode(&self) -> Result<(Value, Vec<u8>)> {
+        fn path_value(p: Option<&PathBuf>) -> Result<Value> {
+            match p {
+                None => Ok(Value::Null),
+                Some(p) => match p.to_str() {
+                    Some(s) => Ok(json!(s)),
+                    None => Err(errmsg!(
+                        "paths passed to worker processes must be valid Unicode, but `{}` is not",
+                        p.display()
+                    )),
+                },
+            }
+        }
+
+        let (input_path, payload) = match self.primary_input {
+            JobInput::Path(ref p) => (path_value(Some(p))?, Vec::new()),
+            JobInput::Buffer(ref b) => (Value::Null, b.clone()),
+        };
+
+        let output_format = match self.output_format {
+            OutputFormat::Aux => "aux",
+            OutputFormat::Html => "html",
+            OutputFormat::Xdv => "xdv",
+            OutputFormat::Pdf => "pdf",
+            OutputFormat::Format => "fmt",
+            OutputFormat::Svg => "svg",
+        };
+
+        let pass = match self.pass {
+            PassSetting::Default => "default",
+            PassSetting::Tex => "tex",
+            PassSetting::BibtexFirst => "bibtex_first",
+        };
+
+        let build_date = self
+            .build_date
+            .and_then(|d| d.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs());
+
+        let header = json!({
+            "type": "job",
+            "primary_input_path": input_path,
+            "tex_input_name": self.tex_input_name,
+            "filesystem_root": path_value(self.filesystem_root.as_ref())?,
+            "output_dir": path_value(self.output_dir.as_ref())?,
+            "format_name": self.format_name,
+            "format_cache_path": path_value(self.format_cache_path.as_ref())?,
+            "preamble_format": self.preamble_format,
+            "output_format": output_format,
+            "pass": pass,
+            "reruns": self.reruns,
+            "keep_intermediates": self.keep_intermediates,
+            "keep_logs": self.keep_logs,
+            "synctex": self.synctex,
+            "incremental": self.incremental,
+            "deterministic_mode": self.deterministic_mode,
+            "build_date": build_date,
+            "max_pass_duration_ms": self.max_pass_duration.map(|d| d.as_millis() as u64),
+            "max_output_bytes": self.max_output_bytes,
+            "max_output_files": self.max_output_files,
+            "max_tex_passes": self.max_tex_passes,
+        });
+
+        Ok((header, payload))
+    }
+
+    /// Decode a job from a message sent by the pool.
+    fn decode(header: &Value, payload: Vec<u8>) -> Result<Self> {
+        let path = |name: &str| header[name].as_str().map(PathBuf::from);
+        let usize_field = |name: &str| header[name].as_u64().map(|n| n as usize);
+        let bool_field = |name: &str| header[name].as_bool() == Some(true);
+
+        let primary_input = match path("primary_input_path") {
+            Some(p) => JobInput::Path(p),
+            None => JobInput::Buffer(payload),
+        };
+
+        let output_format = header["output_format"]
+            .as_str()
+            .unwrap_or_default()
+            .parse::<OutputFormat>()
+            .map_err(|e| ErrorKind::Msg(format!("malformed job: {e}")))?;
+
+        let pass = header["pass"]
+            .as_str()
+            .unwrap_or_default()
+            .parse::<PassSetting>()
+            .map_err(|e| ErrorKind::Msg(format!("malformed job: {e}")))?;
+
+        Ok(WorkerJob {
+            primary_input,
+            tex_input_name: str_field(header, "tex_input_name")?.to_owned(),
+            filesystem_root: path("filesystem_root"),
+            output_dir: path("output_dir"),
+            format_name: str_field(header, "format_name")?.to_owned(),
+            format_cache_path: path("format_cache_path"),
+            preamble_format: header["preamble_format"].as_str().map(str::to_owned),
+            output_format,
+            pass,
+            reruns: usize_field("reruns"),
+            keep_intermediates: bool_field("keep_intermediates"),
+            keep_logs: bool_field("keep_logs"),
+            synctex: bool_field("synctex"),
+            incremental: bool_field("incremental"),
+            deterministic_mode: bool_field("deterministic_mode"),
+            build_date: header["build_date"]
+                .as_u64()
+                .map(|s| UNIX_EPOCH + Duration::from_secs(s)),
+            max_pass_duration: header["max_pass_duration_ms"]
+                .as_u64()
+                .map(Duration::from_millis),
+            max_output_bytes: header["max_output_bytes"].as_u64(),
+            max_output_files: usize_field("max_output_files"),
+            max_tex_passes: usize_field("max_tex_passes"),
+        })
+    }
+
+    /// Transfer the settings of this job to a session builder.
+    fn apply(&self, builder: &mut ProcessingSessionBuilder) {
+        match self.primary_input {
+            JobInput::Path(ref p) => builder.primary_input_path(p),
+            JobInput::Buffer(ref b) => builder.primary_input_buffer(b),
+        };
+
+        match self.output_dir {
+            Some(ref p) => builder.output_dir(p),
+            None => builder.do_not_write_output_files(),
+        };
+
+        builder
+            .tex_input_name(&self.tex_input_name)
+            .format_name(&self.format_name)
+            .output_format(self.output_format)
+            .pass(self.pass)
+            .keep_intermediates(self.keep_intermediates)
+            .keep_logs(self.keep_logs)
+            .synctex(self.synctex)
+            .incremental(self.incremental)
+            .unstables(UnstableOptions {
+                deterministic_mode: self.deterministic_mode,
+                ..Default::default()
+            })
+            .print_stdout(false);
+
+        if let Some(ref p) = self.filesystem_root {
+            builder.filesystem_root(p);
+        }
+
+        if let Some(ref p) = self.format_cache_path {
+            builder.format_cache_path(p);
+        }
+
+        if let Some(ref f) = self.preamble_format {
+            builder.preamble_format(f);
+        }
+
+        if let Some(r) = self.reruns {
+            builder.reruns(r);
+        }
+
+        if let Some(d) = self.build_date {
+            builder.build_date(d);
+        }
+
+        if let Some(d) = self.max_pass_duration {
+            builder.max_pass_duration(d);
+        }
+
+        if let Some(n) = self.max_output_bytes {
+            builder.max_output_bytes(n);
+        }
+
+        if let Some(n) = self.max_output_files {
+            builder.max_output_files(n);
+        }
+
+        if let Some(n) = self.max_tex_passes {
+            builder.max_tex_passes(n);
+        }
+    }
+}
+
+/// A builder for [`WorkerPool`].
+#[derive(Clone, Debug, Default)]
+pub struct WorkerPoolBuilder {
+    program: Option<PathBuf>,
+    args: Vec<OsString>,
+    max_workers: Option<usize>,
+}
+
+impl WorkerPoolBuilder {
+    /// Set the program that is run to start worker processes.
+    ///
+    /// The program must call [`maybe_run_worker`] when it starts. It defaults
+    /// to the current executable.
+    pub fn program<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+        self.program = Some(p.as_ref().to_owned());
+        self
+    }
+
+    /// Add a command-line argument to pass to the worker program.
+    ///
+    /// These arguments follow the one that marks the process as a worker.
+    pub fn arg<S: Into<OsString>>(&mut self, arg: S) -> &mut Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Set the maximum number of worker processes, and therefore of jobs that
+    /// can be run at the same time.
+    ///
+    /// This defaults to the number of CPUs. Values smaller than one are
+    /// treated as one.
+    pub fn max_workers(&mut self, n: usize) -> &mut Self {
+        self.max_workers = Some(n);
+        self
+    }
+
+    /// Create the pool.
+    ///
+    /// No worker processes are started until they are needed.
+    pub fn build(self) -> Result<WorkerPool> {
+        let program = match self.program {
+            Some(p) => p,
+            None => ctry!(env::current_exe(); "couldn't locate the current executable"),
+        };
+
+        let max_workers = self.max_workers.unwrap_or_else(|| {
+            thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        });
+
+        Ok(WorkerPool {
+            program,
+            args: self.args,
+            max_workers: max_workers.max(1),
+            state: Mutex::new(PoolState::default()),
+            worker_available: Condvar::new(),
+        })
+    }
+}
+
+/// A pool of worker processes that run processing jobs.
+///
+/// The pool can be shared between threads. Each call to [`Self::run`] blocks
+/// until a worker is available and has finished the job, so to run several
+/// jobs in parallel, call it from several threads.
+#[derive(Debug)]
+pub struct WorkerPool {
+    program: PathBuf,
+    args: Vec<OsString>,
+    max_workers: usize,
+    state: Mutex<PoolState>,
+    worker_available: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct PoolState {
+    idle: Vec<Worker>,
+    n_workers: usize,
+}
+
+impl WorkerPool {
+    /// Run a job in one of the pool's workers.
+    ///
+    /// The worker's requests for support files are served from *bundle*, and
+    /// its status messages are reported to *status*. If the job has no output
+    /// directory, the files that it created are returned; otherwise, the
+    /// returned collection is empty.
+    ///
+    /// If the worker process fails, it is discarded, and a new one will be
+    /// started for later jobs.
+    pub fn run(
+        &self,
+        job: &WorkerJob,
+        bundle: &mut dyn Bundle,
+        status: &mut dyn StatusBackend,
+    ) -> Result<MemoryFileCollection> {
+        let (header, payload) = job.encode()?;
+        let mut worker = self.check_out()?;
+
+        match worker.run_job(header, &payload, bundle, status) {
+            Ok(result) => {
+                self.check_in(Some(worker));
+                result
+            }
+
+            Err(e) => {
+                self.check_in(None);
+                Err(Error::with_chain(
+                    e,
+                    ErrorKind::Msg("communication with a worker process failed".to_owned()),
+                ))
+            }
+        }
+    }
+
+    /// Get an idle worker, starting a new one if allowed, or waiting until one
+    /// becomes available.
+    fn check_out(&self) -> Result<Worker> {
+        let mut state = self.state.lock().unwrap();
+
+        loop {
+            if let Some(worker) = state.idle.pop() {
+                return Ok(worker);
+            }
+
+            if state.n_workers < self.max_workers {
+                state.n_workers += 1;
+                drop(state);
+
+                let result = Worker::spawn(&self.program, &self.args);
+
+                if result.is_err() {
+                    self.check_in(None);
+                }
+
+                return result;
+            }
+
+            state = self.worker_available.wait(state).unwrap();
+        }
+    }
+
+    /// Return a worker to the pool, or record that it has gone away if
+    /// *worker* is None.
+    fn check_in(&self, worker: Option<Worker>) {
+        let mut state = self.state.lock().unwrap();
+
+        match worker {
+            Some(w) => state.idle.push(w),
+            None => state.n_workers -= 1,
+        }
+
+        self.worker_available.notify_one();
+    }
+}
+
+/// A worker process, as seen from the pool.
+#[derive(Debug)]
+struct Worker {
+    child: Child,
+    channel: Channel,
+}
+
+impl Worker {
+    fn spawn(program: &Path, args: &[OsString]) -> Result<Worker> {
+        let mut child = ctry!(
+            Command::new(program)
+                .arg(WORKER_ARG)
+                .args(args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn();
+            "couldn't start worker program `{}`", program.display()
+        );
+
+        let channel = Channel::new(child.stdout.take().unwrap(), child.stdin.take().unwrap());
+        let mut worker = Worker { child, channel };
+
+        match worker.channel.recv() {
+            Ok((header, _))
+                if header["type"] == "ready" && header["version"] == PROTOCOL_VERSION =>
+            {
+                Ok(worker)
+            }
+
+            _ => Err(errmsg!(
+                "program `{}` did not start up as a compatible Tectonic worker",
+                program.display()
+            )),
+        }
+    }
+
+    /// Have the worker run a job, serving its requests until it is done.
+    ///
+    /// The outer result reports problems communicating with the worker, and
+    /// the inner one the outcome of the job.
+    fn run_job(
+        &mut self,
+        header: Value,
+        payload: &[u8],
+        bundle: &mut dyn Bundle,
+        status: &mut dyn StatusBackend,
+    ) -> io::Result<Result<MemoryFileCollection>> {
+        self.channel.send(header, payload)?;
+        let mut files = MemoryFileCollection::new();
+
+        loop {
+            let (msg, data) = self.channel.recv()?;
+
+            match str_field(&msg, "type").map_err(io::Error::other)? {
+                "open-input" => {
+                    let name = str_field(&msg, "name").map_err(io::Error::other)?;
+
+                    let result = if msg["format"] == true {
+                        bundle.input_open_format(name, status)
+                    } else {
+                        bundle.input_open_name(name, status)
+                    };
+
+                    self.send_open_result(result)?;
+                }
+
+                "get-digest" => match bundle.get_digest(status) {
+                    Ok(d) => self
+                        .channel
+                        .send(json!({"type": "digest", "digest": d.to_string()}), &[])?,
+                    Err(e) => self.send_error(&e)?,
+                },
+
+                "all-files" => match bundle.all_files(status) {
+                    Ok(names) => self
+                        .channel
+                        .send(json!({"type": "files", "names": names}), &[])?,
+                    Err(e) => self.send_error(&e)?,
+                },
+
+                "status" => replay_status(&msg, &data, status),
+
+                "output-file" => {
+                    let name = str_field(&msg, "name").map_err(io::Error::other)?;
+                    files.insert(
+                        name.to_owned(),
+                        MemoryFileInfo {
+                            data,
+                            unix_mtime: None,
+                        },
+                    );
+                }
+
+                "done" => {
+                    return Ok(if msg["success"] == true {
+                        Ok(files)
+                    } else {
+                        Err(error_from_causes(&msg["causes"]))
+                    });
+                }
+
+                other => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unexpected message `{other}` from worker"),
+                    ));
+                }
+            }
+        }
+    }
+
+    fn send_open_result(&mut self, result: OpenResult<InputHandle>) -> io::Result<()> {
+        match result {
+            OpenResult::Ok(mut handle) => {
+                let mut data = Vec::new();
+
+                if let Err(e) = handle.read_to_end(&mut data) {
+                    return self.send_error(&e.into());
+                }
+
+                let origin = match handle.origin() {
+                    InputOrigin::Filesystem => "filesystem",
+                    InputOrigin::NotInput => "not-input",
+                    InputOrigin::Other => "other",
+                };
+
+                self.channel
+                    .send(json!({"type": "opened", "origin": origin}), &data)
+            }
+
+            OpenResult::NotAvailable => self.channel.send(json!({"type": "not-available"}), &[]),
+            OpenResult::Err(e) => self.send_error(&e),
+        }
+    }
+
+    fn send_error(&mut self, err: &NewError) -> io::Result<()> {
+        let causes: Vec<String> = err.chain().map(|c| c.to_string()).collect();
+        self.channel
+            .send(json!({"type": "error", "causes": causes}), &[])
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Report a status event forwarded from a worker.
+fn replay_status(msg: &Value, data: &[u8], status: &mut dyn StatusBackend) {
+    let kind = match msg["kind"].as_str() {
+        Some("warning") => MessageKind::Warning,
+        Some("error") => MessageKind::Error,
+        _ => MessageKind::Note,
+    };
+    let text = |name: &str| msg[name].as_str().unwrap_or_default();
+
+    match text("event") {
+        "message" => {
+            let err = anyhow_from_causes(&msg["causes"]);
+            status.report(kind, format_args!("{}", text("message")), err.as_ref());
+        }
+
+        "error" => {
+            if let Some(err) = anyhow_from_causes(&msg["causes"]) {
+                status.report_error(&err);
+            }
+        }
+
+        "note-highlighted" => {
+            status.note_highlighted(text("before"), text("highlighted"), text("after"))
+        }
+
+        "diagnostic" => {
+            let location = msg["file"].as_str().map(|file| SourceLocation {
+                file: file.to_owned(),
+                line: msg["line"].as_u64().map(|l| l as u32),
+            });
+            status.report_diagnostic(kind, format_args!("{}", text("message")), location.as_ref());
+        }
+
+        "pass-begin" => status.pass_begin(text("pass")),
+        "pass-end" => status.pass_end(text("pass"), msg["success"] == true),
+
+        "input-opened" => status.report_progress(&Progress::InputOpened { name: text("file") }),
+
+        "page" => status.report_progress(&Progress::PageShippedOut {
+            page: msg["page"].as_u64().unwrap_or_default() as u32,
+            file: msg["file"].as_str(),
+        }),
+
+        "error-log" => status.dump_error_logs(data),
+        _ => {}
+    }
+}
+
+/// Turn this process into a worker, if it was started by a [`WorkerPool`].
+///
+/// A process is a worker if its first command-line argument is the one that
+/// the pool passes to mark it as such. If the process wasn't started as a
+/// worker, this function returns right away. Otherwise, it runs jobs until the
+/// pool goes away, then exits the process without returning.
+pub fn maybe_run_worker() {
+    if env::args_os().nth(1).as_deref() != Some(OsStr::new(WORKER_ARG)) {
+        return;
+    }
+
+    if let Err(e) = run_worker() {
+        eprintln!("error: Tectonic worker process failed: {e}");
+        process::exit(1);
+    }
+
+    process::exit(0);
+}
+
+fn run_worker() -> io::Result<()> {
+    let channel = Channel::new(io::stdin(), protocol_output()?);
+    let channel = Rc::new(RefCell::new(channel));
+
+    channel
+        .borrow_mut()
+        .send(json!({"type": "ready", "version": PROTOCOL_VERSION}), &[])?;
+
+    loop {
+        let received = channel.borrow_mut().recv();
+
+        let (header, payload) = match received {
+            Ok(m) => m,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        if header["type"] != "job" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "expected a job from the worker pool",
+            ));
+        }
+
+        let result = WorkerJob::decode(&header, payload).and_then(|job| run_job(&job, &channel));
+        let mut channel = channel.borrow_mut();
+
+        match result {
+            Ok(files) => {
+                for (name, info) in files {
+                    channel.send(json!({"type": "output-file", "name": name}), &info.data)?;
+                }
+
+                channel.send(json!({"type": "done", "success": true}), &[])?;
+            }
+
+            Err(e) => {
+                let causes: Vec<String> = e.iter().map(|c| c.to_string()).collect();
+                channel.send(
+                    json!({"type": "done", "success": false, "causes": causes}),
+                    &[],
+                )?;
+            }
+        }
+    }
+}
+
+fn run_job(job: &WorkerJob, channel: &Rc<RefCell<Channel>>) -> Result<MemoryFileCollection> {
+    let mut status = ProxyStatusBackend {
+        channel: channel.clone(),
+    };
+
+    let security = SecuritySettings::new(SecurityStance::DisableInsecures);
+    let mut builder = ProcessingSessionBuilder::new_with_security(security);
+    job.apply(&mut builder);
+    builder.bundle(Box::new(ProxyBundle {
+        channel: channel.clone(),
+    }));
+
+    let mut session = builder.create(&mut status)?;
+    session.run(&mut status)?;
+
+    if job.output_dir.is_some() {
+        Ok(MemoryFileCollection::new())
+    } else {
+        Ok(session.into_file_data())
+    }
+}
+
+/// Get a stream for sending messages to the pool.
+///
+/// On Unix, the original standard output is set aside for this purpose, and
+/// standard output is redirected to standard error, so that stray output
+/// can't corrupt the message stream.
+#[cfg(unix)]
+fn protocol_output() -> io::Result<Box<dyn Write + Send>> {
+    use std::{fs::File, os::unix::io::FromRawFd};
+
+    unsafe {
+        let fd = libc::dup(1);
+
+        if fd < 0 || libc::dup2(2, 1) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Box::new(File::from_raw_fd(fd)))
+    }
+}
+
+#[cfg(not(unix))]
+fn protocol_output() -> io::Result<Box<dyn Write + Send>> {
+    Ok(Box::new(io::stdout()))
+}
+
+/// A bundle that forwards requests to the pool.
+struct ProxyBundle {
+    channel: Rc<RefCell<Channel>>,
+}
+
+impl ProxyBundle {
+    fn request(&mut self, header: Value) -> NewResult<(Value, Vec<u8>)> {
+        let mut channel = self.channel.borrow_mut();
+        channel.send(header, &[])?;
+        let (reply, data) = channel.recv()?;
+
+        if reply["type"] == "error" {
+            return Err(anyhow_from_causes(&reply["causes"])
+                .unwrap_or_else(|| anyhow!("the worker pool reported an unspecified error")));
+        }
+
+        Ok((reply, data))
+    }
+
+    fn open(&mut self, name: &str, format: bool) -> OpenResult<InputHandle> {
+        let request = json!({"type": "open-input", "name": name, "format": format});
+
+        let (reply, data) = match self.request(request) {
+            Ok(r) => r,
+            Err(e) => return OpenResult::Err(e),
+        };
+
+        if reply["type"] == "not-available" {
+            return OpenResult::NotAvailable;
+        }
+
+        let origin = match reply["origin"].as_str() {
+            Some("filesystem") => InputOrigin::Filesystem,
+            Some("not-input") => InputOrigin::NotInput,
+            _ => InputOrigin::Other,
+        };
+
+        OpenResult::Ok(InputHandle::new_read_only(name, Cursor::new(data), origin))
+    }
+}
+
+impl IoProvider for ProxyBundle {
+    fn input_open_name(
+        &mut self,
+        name: &str,
+        _status: &mut dyn StatusBackend,
+    ) -> OpenResult<InputHandle> {
+        self.open(name, false)
+    }
+
+    fn input_open_format(
+        &mut self,
+        name: &str,
+        _status: &mut dyn StatusBackend,
+    ) -> OpenResult<InputHandle> {
+        self.open(name, true)
+    }
+}
+
+impl Bundle for ProxyBundle {
+    fn get_digest(&mut self, _status: &mut dyn StatusBackend) -> NewResult<DigestData> {
+        let (reply, _) = self.request(json!({"type": "get-digest"}))?;
+        str_field(&reply, "digest")?.parse()
+    }
+
+    fn all_files(&mut self, _status: &mut dyn StatusBackend) -> NewResult<Vec<String>> {
+        let (reply, _) = self.request(json!({"type": "all-files"}))?;
+
+        Ok(reply["names"]
+            .as_array()
+            .map(|a| {
+                a.iter()
+                    .filter_map(|n| n.as_str().map(str::to_owned))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
+
+/// A status backend that forwards events to the pool.
+struct ProxyStatusBackend {
+    channel: Rc<RefCell<Channel>>,
+}
+
+impl ProxyStatusBackend {
+    fn kind_name(kind: MessageKind) -> &'static str {
+        match kind {
+            MessageKind::Note => "note",
+            MessageKind::Warning => "warning",
+            MessageKind::Error => "error",
+        }
+    }
+
+    fn causes(err: Option<&NewError>) -> Vec<String> {
+        err.map(|e| e.chain().map(|c| c.to_string()).collect())
+            .unwrap_or_default()
+    }
+
+    fn emit(&mut self, mut event: Value, payload: &[u8]) {
+        event["type"] = json!("status");
+
+        // If the pool has gone away, we'll find out soon enough when the next
+        // request fails, so there's no need to do anything about errors here.
+        let _ = self.channel.borrow_mut().send(event, payload);
+    }
+}
+
+impl StatusBackend for ProxyStatusBackend {
+    fn report(&mut self, kind: MessageKind, args: Arguments, err: Option<&NewError>) {
+        let event = json!({
+            "event": "message",
+            "kind": Self::kind_name(kind),
+            "message": args.to_string(),
+            "causes": Self::causes(err),
+        });
+        self.emit(event, &[]);
+    }
+
+    fn report_error(&mut self, err: &NewError) {
+        let event = json!({"event": "error", "causes": Self::causes(Some(err))});
+        self.emit(event, &[]);
+    }
+
+    fn note_highlighted(&mut self, before: &str, highlighted: &str, after: &str) {
+        let event = json!({
+            "event": "note-highlighted",
+            "before": before,
+            "highlighted": highlighted,
+            "after": after,
+        });
+        self.emit(event, &[]);
+    }
+
+    fn report_diagnostic(
+        &mut self,
+        kind: MessageKind,
+        args: Arguments,
+        location: Option<&SourceLocation>,
+    ) {
+        let event = json!({
+            "event": "diagnostic",
+            "kind": Self::kind_name(kind),
+            "message": args.to_string(),
+            "file": location.map(|l| l.file.clone()),
+            "line": location.and_then(|l| l.line),
+        });
+        self.emit(event, &[]);
+    }
+
+    fn pass_begin(&mut self, pass: &str) {
+        self.emit(json!({"event": "pass-begin", "pass": pass}), &[]);
+    }
+
+    fn pass_end(&mut self, pass: &str, success: bool) {
+        let event = json!({"event": "pass-end", "pass": pass, "success": success});
+        self.emit(event, &[]);
+    }
+
+    fn report_progress(&mut self, progress: &Progress) {
+        let event = match *progress {
+            Progress::InputOpened { name } => json!({"event": "input-opened", "file": name}),
+            Progress::PageShippedOut { page, file } => {
+                json!({"event": "page", "page": page, "file": file})
+            }
+            _ => return,
+        };
+        self.emit(event, &[]);
+    }
+
+    fn dump_error_logs(&mut self, output: &[u8]) {
+        self.emit(json!({"event": "error-log"}), output);
+    }
+}
+
+/// One end of the connection between the pool and a worker.
+struct Channel {
+    reader: BufReader<Box<dyn Read + Send>>,
+    writer: BufWriter<Box<dyn Write + Send>>,
+}
+
+impl std::fmt::Debug for Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Channel")
+    }
+}
+
+impl Channel {
+    fn new<R: Read + Send + 'static, W: Write + Send + 'static>(reader: R, writer: W) -> Self {
+        Channel {
+            reader: BufReader::new(Box::new(reader)),
+            writer: BufWriter::new(Box::new(writer)),
+        }
+    }
+
+    fn send(&mut self, header: Value, payload: &[u8]) -> io::Result<()> {
+        let header = header.to_string();
+        self.writer
+            .write_all(&(header.len() as u32).to_le_bytes())?;
+        self.writer.write_all(header.as_bytes())?;
+        self.writer
+            .write_all(&(payload.len() as u64).to_le_bytes())?;
+        self.writer.write_all(payload)?;
+        self.writer.flush()
+    }
+
+    fn recv(&mut self) -> io::Result<(Value, Vec<u8>)> {
+        let mut len = [0; 4];
+        self.reader.read_exact(&mut len)?;
+        let mut header = vec![0; u32::from_le_bytes(len) as usize];
+        self.reader.read_exact(&mut header)?;
+        let header = serde_json::from_slice(&header)?;
+
+        let mut len = [0; 8];
+        self.reader.read_exact(&mut len)?;
+        let mut payload = vec![0; u64::from_le_bytes(len) as usize];
+        self.reader.read_exact(&mut payload)?;
+
+        Ok((header, payload))
+    }
+}
+
+fn str_field<'a>(msg: &'a Value, name: &str) -> NewResult<&'a str> {
+    msg[name]
+        .as_str()
+        .ok_or_else(|| anyhow!("malformed message: missing field `{}`", name))
+}
+
+/// Rebuild an error from a list of its causes, outermost first.
+fn anyhow_from_causes(causes: &Value) -> Option<NewError> {
+    let causes: Vec<&str> = causes
+        .as_array()?
+        .iter()
+        .filter_map(|c| c.as_str())
+        .collect();
+    let (innermost, outer) = causes.split_last()?;
+    let mut err = anyhow!("{}", innermost);
+
+    for cause in outer.iter().rev() {
+        err = err.context(cause.to_string());
+    }
+
+    Some(err)
+}
+
+/// Like [`anyhow_from_causes`], but for the error type of this crate.
+fn error_from_causes(causes: &Value) -> Error {
+    let causes: Vec<&str> = causes
+        .as_array()
+        .map(|a| a.iter().filter_map(|c| c.as_str()).collect())
+        .unwrap_or_default();
+
+    let mut iter = causes.iter().rev();
+    let innermost = iter.next().copied().unwrap_or("the worker process failed");
+    let mut err: Error = ErrorKind::Msg(innermost.to_owned()).into();
+
+    for cause in iter {
+        err = Error::with_chain(err, ErrorKind::Msg((*cause).to_owned()));
+    }
+
+    err
+}
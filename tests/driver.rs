@@ -8,15 +8,153 @@
 //! ProcessingSessionBuilder will need to learn how to tell `xdvipdfmx` to
 //! enable the reproducibility options used in the `tex-outputs` test rig.
 
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
 use tectonic::config::PersistentConfig;
-use tectonic::driver::ProcessingSessionBuilder;
+use tectonic::driver::{CancellationToken, OutputFormat, ProcessingSessionBuilder};
+use tectonic::errors::{ErrorKind, Result};
 use tectonic::status::termcolor::TermcolorStatusBackend;
 use tectonic::status::ChatterLevel;
+#[cfg(feature = "serialization")]
+use tectonic::worker::{WorkerJob, WorkerPoolBuilder};
 
 mod util;
 
+/// Process a plain TeX document with some limits applied.
+fn run_limited(input: &str, limit: impl FnOnce(&mut ProcessingSessionBuilder)) -> Result<()> {
+    util::set_test_root();
+
+    let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+
+    let tempdir = tempfile::Builder::new()
+        .prefix("tectonic_driver_test")
+        .tempdir()
+        .unwrap();
+
+    let mut pbuilder = ProcessingSessionBuilder::default();
+    pbuilder
+        .primary_input_buffer(input.as_bytes())
+        .tex_input_name("texput.tex")
+        .format_name("plain")
+        .format_cache_path(util::test_path(&[]))
+        .output_dir(tempdir.path())
+        .bundle(Box::new(util::TestBundle::default()));
+    limit(&mut pbuilder);
+
+    let mut session = pbuilder
+        .create(&mut status)
+        .expect("couldn't create processing session");
+    session.run(&mut status)
+}
+
+fn assert_limit_exceeded(result: Result<()>) {
+    match result {
+        Err(e) => match e.kind() {
+            ErrorKind::LimitExceeded(_) => {}
+            _ => panic!("expected a limit to be exceeded; got: {}", e),
+        },
+        Ok(_) => panic!("expected a limit to be exceeded, but processing succeeded"),
+    }
+}
+
 // Keep these alphabetized.
 
+#[test]
+fn cancelled_before_start() {
+    let token = CancellationToken::new();
+    token.cancel();
+
+    let result = run_limited("a\\bye", |b| {
+        b.cancellation_token(token);
+    });
+
+    match result {
+        Err(e) => assert!(matches!(e.kind(), ErrorKind::Cancelled), "got: {}", e),
+        Ok(_) => panic!("expected processing to be cancelled, but it succeeded"),
+    }
+}
+
+#[test]
+fn cancelled_while_running() {
+    let token = CancellationToken::new();
+    let canceller = {
+        let token = token.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(500));
+            token.cancel();
+        })
+    };
+
+    let result = run_limited("\\def\\a{\\a}\\a", |b| {
+        b.cancellation_token(token);
+    });
+    canceller.join().unwrap();
+
+    match result {
+        Err(e) => assert!(matches!(e.kind(), ErrorKind::Cancelled), "got: {}", e),
+        Ok(_) => panic!("expected processing to be cancelled, but it succeeded"),
+    }
+}
+
+#[test]
+fn output_files_limit() {
+    assert_limit_exceeded(run_limited(
+        "\\count1=0 \\loop\\immediate\\openout1=f\\the\\count1.txt \\immediate\\closeout1 \\advance\\count1 by1 \\ifnum\\count1<100 \\repeat a\\bye",
+        |b| {
+            b.max_output_files(10);
+        },
+    ));
+}
+
+#[test]
+fn output_size_limit() {
+    assert_limit_exceeded(run_limited(
+        "\\immediate\\openout1=big.txt \\loop\\immediate\\write1{xxxxxxxxxxxxxxxx}\\iftrue\\repeat",
+        |b| {
+            b.max_output_bytes(10_000);
+        },
+    ));
+}
+
+#[test]
+fn pass_time_limit() {
+    assert_limit_exceeded(run_limited("\\def\\a{\\a}\\a", |b| {
+        b.max_pass_duration(Duration::from_secs(1));
+    }));
+}
+
+/// A runaway TeX pass is stopped when producing a PDF, too.
+#[test]
+fn pass_time_limit_pdf() {
+    assert_limit_exceeded(run_limited("\\loop\\iftrue\\repeat", |b| {
+        b.output_format(OutputFormat::Pdf)
+            .max_pass_duration(Duration::from_millis(200));
+    }));
+}
+
+/// Programs run through shell-escape are killed when the time runs out.
+#[cfg(unix)]
+#[test]
+fn pass_time_limit_shell_escape() {
+    let start = Instant::now();
+
+    assert_limit_exceeded(run_limited("\\immediate\\write18{sleep 60}a\\bye", |b| {
+        b.shell_escape_with_temp_dir()
+            .max_pass_duration(Duration::from_secs(1));
+    }));
+
+    assert!(start.elapsed() < Duration::from_secs(30));
+}
+
+#[test]
+fn tex_passes_limit() {
+    assert_limit_exceeded(run_limited("a\\bye", |b| {
+        b.reruns(3).max_tex_passes(2);
+    }));
+}
+
 #[test]
 fn the_letter_a() {
     util::set_test_root();
@@ -50,3 +188,45 @@ fn the_letter_a() {
         .run(&mut status)
         .expect("failed to execute processing session");
 }
+
+#[cfg(feature = "serialization")]
+#[test]
+fn worker_pool() {
+    util::set_test_root();
+
+    let mut builder = WorkerPoolBuilder::default();
+    builder
+        .program(env!("CARGO_BIN_EXE_tectonic"))
+        .max_workers(2);
+    let pool = builder.build().expect("couldn't create worker pool");
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let pool = &pool;
+
+                scope.spawn(move || {
+                    let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+                    let mut bundle = util::TestBundle::default();
+                    let input = format!("page {i}\\bye");
+
+                    let mut job = WorkerJob::new("texput.tex");
+                    job.primary_input_buffer(input.as_bytes())
+                        .format_name("plain")
+                        .format_cache_path(util::test_path(&[]))
+                        .output_format(OutputFormat::Xdv);
+
+                    pool.run(&job, &mut bundle, &mut status)
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            let files = handle
+                .join()
+                .unwrap()
+                .expect("failed to run job in worker pool");
+            assert!(files.contains_key("texput.xdv"));
+        }
+    });
+}
@@ -220,6 +220,17 @@ fn error_or_panic(output: &Output) {
     }
 }
 
+/// Parse the JSON status records that `--message-format=json` prints to
+/// stderr.
+#[cfg(feature = "serialization")]
+fn json_records(output: &Output) -> Vec<serde_json::Value> {
+    str::from_utf8(&output.stderr)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("status line is not valid JSON"))
+        .collect()
+}
+
 fn check_file(tempdir: &TempDir, rest: &str) {
     let mut p = tempdir.path().to_owned();
     p.push(rest);
@@ -236,11 +247,18 @@ fn setup_v2() -> (tempfile::TempDir, PathBuf) {
     util::set_test_root();
 
     let tempdir = setup_and_copy_files(&[]);
-    let mut temppath = tempdir.path().to_owned();
-    let output = run_tectonic(&temppath, &["-X", "new", "doc"]);
+    let temppath = setup_v2_doc(tempdir.path(), "doc");
+    (tempdir, temppath)
+}
+
+/// Create a new V2 document named `name` inside `parent` that can be built in
+/// our test setup, returning its path.
+fn setup_v2_doc(parent: &Path, name: &str) -> PathBuf {
+    let mut temppath = parent.to_owned();
+    let output = run_tectonic(&temppath, &["-X", "new", name]);
     success_or_panic(&output);
 
-    temppath.push("doc");
+    temppath.push(name);
 
     // To run a build in our test setup, we can only use plain TeX. So, jankily
     // change the format ...
@@ -277,7 +295,7 @@ fn setup_v2() -> (tempfile::TempDir, PathBuf) {
         }
     }
 
-    (tempdir, temppath)
+    temppath
 }
 
 /* Keep tests alphabetized */
@@ -489,12 +507,143 @@ fn bibtex_multiple_aux_files() {
     success_or_panic(&output);
 }
 
+/// Check that glossaries are generated from the information that the
+/// `glossaries` package leaves in the `.aux` file, as `makeglossaries` would.
+#[test]
+fn glossaries_basic() {
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&["glossaries_basic.tex", "glossaries_basic.ist"]);
+    let output = run_tectonic(
+        tempdir.path(),
+        &[
+            &fmt_arg,
+            "--keep-intermediates",
+            "--keep-logs",
+            "glossaries_basic.tex",
+        ],
+    );
+    success_or_panic(&output);
+
+    let gls = fs::read_to_string(tempdir.path().join("glossaries_basic.gls")).unwrap();
+    assert_eq!(gls, "\nAlpha: 1\nZeta: 2\n");
+    check_file(&tempdir, "glossaries_basic.glg");
+
+    let log = fs::read_to_string(tempdir.path().join("glossaries_basic.log")).unwrap();
+    assert!(log.contains("second pass"));
+}
+
 #[test]
 fn help_flag() {
     let output = run_tectonic(&PathBuf::from("."), &["-h"]);
     success_or_panic(&output);
 }
 
+#[cfg(feature = "serialization")]
+#[test]
+fn json_diagnostic_location() {
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    fs::write(
+        tempdir.path().join("inner.tex"),
+        "% line 1\n\\undefinedcs\n",
+    )
+    .unwrap();
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "-", "--message-format=json"],
+        "\\input inner \\bye",
+    );
+    error_or_panic(&output);
+
+    let records = json_records(&output);
+
+    assert!(records.iter().any(|r| r["type"] == "diagnostic"
+        && r["kind"] == "error"
+        && r["file"] == "inner.tex"
+        && r["line"] == 2));
+}
+
+/// Warnings issued outside of the engine's own diagnostics, like the one for a
+/// character missing from a font, are located too.
+#[cfg(feature = "serialization")]
+#[test]
+fn json_issued_warning_location() {
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    fs::write(tempdir.path().join("inner.tex"), "% line 1\n\\char200\n").unwrap();
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "-", "--message-format=json"],
+        "\\input inner \\bye",
+    );
+    success_or_panic(&output);
+
+    let records = json_records(&output);
+
+    assert!(records.iter().any(|r| r["type"] == "diagnostic"
+        && r["kind"] == "warning"
+        && r["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("could not represent character")
+        && r["file"] == "inner.tex"
+        && r["line"] == 2));
+}
+
+#[cfg(feature = "serialization")]
+#[test]
+fn json_message_format() {
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "-", "--message-format=json", "--print"],
+        "Hello\\bye",
+    );
+    success_or_panic(&output);
+
+    // The records go to stderr, so they don't mix with the engine output.
+    assert!(str::from_utf8(&output.stdout).unwrap().contains("[1]"));
+
+    let records = json_records(&output);
+
+    assert!(records
+        .iter()
+        .any(|r| r["type"] == "pass-begin" && r["pass"] == "tex"));
+    assert!(records
+        .iter()
+        .any(|r| r["type"] == "pass-end" && r["pass"] == "tex" && r["success"] == true));
+}
+
+#[cfg(feature = "serialization")]
+#[test]
+fn json_progress() {
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    fs::write(
+        tempdir.path().join("chapter.tex"),
+        "One\\eject Two\\eject\n",
+    )
+    .unwrap();
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "-", "--message-format=json"],
+        "\\input chapter \\bye",
+    );
+    success_or_panic(&output);
+
+    let records = json_records(&output);
+
+    assert!(records.iter().any(|r| r["type"] == "progress"
+        && r["event"] == "input-opened"
+        && r["file"] == "chapter.tex"));
+    assert!(records.iter().any(|r| r["type"] == "progress"
+        && r["event"] == "page"
+        && r["page"] == 2
+        && r["file"] == "chapter.tex"
+        && r["pass"] == "tex"));
+}
+
 #[test]
 fn keep_logs_on_error() {
     // No input files here, but output files are created.
@@ -516,6 +665,31 @@ fn keep_logs_on_error() {
     assert!(log.contains(r"job aborted, no legal \end found"));
 }
 
+/// Check that indices are generated without an external `makeindex`, using
+/// a style file named after the input file.
+#[test]
+fn makeindex_basic() {
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&["makeindex_basic.tex", "makeindex_basic.ist"]);
+    let output = run_tectonic(
+        tempdir.path(),
+        &[
+            &fmt_arg,
+            "--keep-intermediates",
+            "--keep-logs",
+            "makeindex_basic.tex",
+        ],
+    );
+    success_or_panic(&output);
+
+    let ind = fs::read_to_string(tempdir.path().join("makeindex_basic.ind")).unwrap();
+    assert_eq!(ind, "\nalpha -- beta: \\textit{1}\n\ngamma: 3--5\n");
+    check_file(&tempdir, "makeindex_basic.ilg");
+
+    let log = fs::read_to_string(tempdir.path().join("makeindex_basic.log")).unwrap();
+    assert!(log.contains("second pass"));
+}
+
 #[test]
 fn no_color_option() {
     // No input files here, but output files are created.
@@ -636,6 +810,29 @@ fn stdin_content() {
     success_or_panic(&output);
 }
 
+#[test]
+fn svg_output() {
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "-", "--outfmt=svg"],
+        "\\font\\x=\"[lmroman12-regular]\" \\x A <b> \\& c \\vrule width 1pt height 2pt \\bye",
+    );
+    success_or_panic(&output);
+
+    check_file(&tempdir, "texput.svg");
+    check_file(&tempdir, "lmroman12-regular.otf");
+    assert!(!tempdir.path().join("texput.xdv").exists());
+
+    let svg = fs::read_to_string(tempdir.path().join("texput.svg")).unwrap();
+    assert!(svg.contains(r#"src: url("lmroman12-regular.otf") format("opentype");"#));
+    assert!(svg.contains("&lt;b&gt;"));
+    assert!(svg.contains("&amp;"));
+    assert!(svg.contains("<rect "));
+}
+
 #[cfg(feature = "serialization")]
 #[test]
 fn v2_build_basic() {
@@ -644,6 +841,100 @@ fn v2_build_basic() {
     success_or_panic(&output);
 }
 
+/// Check that an external tool declared in `Tectonic.toml` is run on the
+/// files that trigger it, and that its outputs are fed back into TeX.
+#[cfg(all(unix, feature = "serialization"))]
+#[test]
+fn v2_build_external_tool() {
+    let (_tempdir, temppath) = setup_v2();
+
+    {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(temppath.join("Tectonic.toml"))
+            .unwrap();
+        writeln!(
+            file,
+            "[[output.external_tool]]\n\
+             command = ['sh', '-c', 'tr a-z A-Z <{{trigger}} >{{stem}}.upper']\n\
+             trigger = '*.lower'\n\
+             produces = ['{{stem}}.upper']"
+        )
+        .unwrap();
+    }
+
+    fs::write(
+        temppath.join("src").join("index.tex"),
+        "\\newwrite\\w\n\
+         \\immediate\\openout\\w=greeting.lower\n\
+         \\immediate\\write\\w{hello}\n\
+         \\immediate\\closeout\\w\n\
+         \\newread\\r\n\
+         \\openin\\r=greeting.upper\n\
+         \\ifeof\\r \\message{first pass}\\else \\closein\\r \\message{second pass}\\fi\n",
+    )
+    .unwrap();
+
+    let output = run_tectonic(&temppath, &["-X", "build", "--keep-logs"]);
+    success_or_panic(&output);
+
+    let log =
+        fs::read_to_string(temppath.join("build").join("default").join("default.log")).unwrap();
+    assert!(log.contains("second pass"));
+
+    // The tool isn't run when the build is untrusted.
+
+    let output = run_tectonic(&temppath, &["-X", "build", "--untrusted", "--force"]);
+    success_or_panic(&output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("external tools"));
+}
+
+#[cfg(feature = "serialization")]
+#[test]
+fn v2_build_incremental() {
+    const SKIPPED: &str = "skipping processing";
+
+    let (_tempdir, temppath) = setup_v2();
+    let skipped = |output: &Output| {
+        String::from_utf8_lossy(&output.stdout).contains(SKIPPED)
+            || String::from_utf8_lossy(&output.stderr).contains(SKIPPED)
+    };
+
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    success_or_panic(&output);
+    assert!(!skipped(&output));
+
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    success_or_panic(&output);
+    assert!(skipped(&output));
+
+    let output = run_tectonic(&temppath, &["-X", "build", "--force"]);
+    success_or_panic(&output);
+    assert!(!skipped(&output));
+
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(temppath.join("src").join("index.tex"))
+        .unwrap();
+    writeln!(file, "Changed.").unwrap();
+    drop(file);
+
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    success_or_panic(&output);
+    assert!(!skipped(&output));
+
+    // Printing the engine's output, or changing the security settings, means
+    // processing again.
+
+    let output = run_tectonic(&temppath, &["-X", "build", "--print"]);
+    success_or_panic(&output);
+    assert!(!skipped(&output));
+
+    let output = run_tectonic(&temppath, &["-X", "build", "--untrusted"]);
+    success_or_panic(&output);
+    assert!(!skipped(&output));
+}
+
 #[test]
 #[cfg(all(feature = "serialization", not(windows)))] // `echo` may not be available
 fn v2_build_open() {
@@ -652,6 +943,45 @@ fn v2_build_open() {
     success_or_panic(&output);
 }
 
+#[cfg(feature = "serialization")]
+#[test]
+fn v2_build_jobs() {
+    let (_tempdir, temppath) = setup_v2();
+
+    {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(temppath.join("Tectonic.toml"))
+            .unwrap();
+        writeln!(
+            file,
+            "
+            [[output]]
+            name = 'alt'
+            type = 'pdf'
+            tex_format = 'plain'
+            "
+        )
+        .unwrap();
+    }
+
+    // The global options apply to the concurrent builds, too.
+    let output = run_tectonic(
+        &temppath,
+        &["-X", "--chatter", "minimal", "build", "--jobs", "2"],
+    );
+    success_or_panic(&output);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("Running TeX"));
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Running TeX"));
+
+    for name in ["default", "alt"] {
+        let mut pdf = temppath.join("build");
+        pdf.push(name);
+        pdf.push(format!("{name}.pdf"));
+        assert!(pdf.exists(), "missing `{}`", pdf.display());
+    }
+}
+
 #[cfg(feature = "serialization")]
 #[test]
 fn v2_build_multiple_outputs() {
@@ -737,6 +1067,400 @@ fn v2_build_multiple_outputs() {
     success_or_panic(&output);
 }
 
+#[cfg(feature = "serialization")]
+#[test]
+fn v2_build_precompiled_preamble() {
+    const PRECOMPILING: &str = "precompiling preamble";
+
+    let (_tempdir, temppath) = setup_v2();
+    let messages = |output: &Output| {
+        String::from_utf8_lossy(&output.stdout).into_owned()
+            + &String::from_utf8_lossy(&output.stderr)
+    };
+
+    {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(temppath.join("Tectonic.toml"))
+            .unwrap();
+        writeln!(file, "precompile_preamble = true").unwrap();
+    }
+
+    fs::write(
+        temppath.join("src").join("_preamble.tex"),
+        "\\def\\greeting{Hello, preamble.}\n",
+    )
+    .unwrap();
+    fs::write(temppath.join("src").join("index.tex"), "\\greeting\n").unwrap();
+
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    success_or_panic(&output);
+    let out = messages(&output);
+    assert!(out.contains(PRECOMPILING));
+    assert!(!out.contains("couldn't precompile"));
+
+    // The format is reused until the preamble changes.
+
+    let output = run_tectonic(&temppath, &["-X", "build", "--force"]);
+    success_or_panic(&output);
+    assert!(!messages(&output).contains(PRECOMPILING));
+
+    fs::write(
+        temppath.join("src").join("_preamble.tex"),
+        "\\def\\greeting{Hello again, preamble.}\n",
+    )
+    .unwrap();
+
+    let output = run_tectonic(&temppath, &["-X", "build"]);
+    success_or_panic(&output);
+    assert!(messages(&output).contains(PRECOMPILING));
+
+    // Don't leave the formats for this throwaway document in the test cache.
+
+    for entry in fs::read_dir(&*TEST_ROOT).unwrap() {
+        let path = entry.unwrap().path();
+
+        if path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.contains("-plain-preamble-"))
+        {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
+
+#[cfg(feature = "serialization")]
+#[test]
+fn v2_build_prune_bundle() {
+    use tectonic::status::NoopStatusBackend;
+    use tectonic_bundles::{zip::ZipBundle, Bundle};
+
+    let (_tempdir, temppath) = setup_v2();
+    let output = run_tectonic(&temppath, &["-X", "build", "--prune-bundle", "min.zip"]);
+    success_or_panic(&output);
+
+    let mut status = NoopStatusBackend::default();
+    let mut bundle = ZipBundle::open(temppath.join("min.zip")).unwrap();
+    let files = bundle.all_files(&mut status).unwrap();
+
+    // Files used to make the format and to typeset the document should be
+    // present, and unused ones shouldn't.
+    for name in [
+        "SHA256SUM",
+        "tectonic-format-plain.tex",
+        "plain.tex",
+        "cmr10.tfm",
+    ] {
+        assert!(files.iter().any(|f| f == name), "missing `{}`", name);
+    }
+
+    assert!(!files.iter().any(|f| f == "redbox.png"));
+}
+
+#[cfg(feature = "serialization")]
+#[test]
+fn v2_build_workspace() {
+    util::set_test_root();
+
+    let tempdir = setup_and_copy_files(&[]);
+    let root = tempdir.path().to_owned();
+    let paper = setup_v2_doc(&root, "paper");
+    let slides = setup_v2_doc(&root, "slides");
+
+    {
+        let mut file = File::create(root.join("Tectonic.toml")).unwrap();
+        writeln!(file, "[workspace]\nmembers = ['paper', 'slides']").unwrap();
+    }
+
+    let paper_pdf = paper.join("build").join("default").join("default.pdf");
+    let slides_pdf = slides.join("build").join("default").join("default.pdf");
+
+    // Selecting a document only builds that one ...
+
+    let output = run_tectonic(&root, &["-X", "build", "--doc", "slides"]);
+    success_or_panic(&output);
+    assert!(!paper_pdf.exists());
+    assert!(slides_pdf.exists());
+
+    // ... unknown names are an error ...
+
+    let output = run_tectonic(&root, &["-X", "build", "--doc", "nonesuch"]);
+    error_or_panic(&output);
+
+    // ... and from the root, everything gets built.
+
+    let output = run_tectonic(&root, &["-X", "build"]);
+    success_or_panic(&output);
+    assert!(paper_pdf.exists());
+
+    // Commands that act on just one document need to be told which one to use.
+
+    let output = run_tectonic(&root, &["-X", "dump", "-s", "log"]);
+    error_or_panic(&output);
+
+    let output = run_tectonic(&root, &["-X", "dump", "--doc", "paper", "-s", "log"]);
+    success_or_panic(&output);
+}
+
+#[test]
+#[cfg(feature = "serialization")]
+fn v2_bundle_cache() {
+    let tempdir = setup_and_copy_files(&[]);
+    let cache = tempdir.path().join("cache");
+
+    // Fake up a cache with two bundles that share a file. Only the first is
+    // still referenced by a URL.
+
+    let digest_a = "a".repeat(64);
+    let digest_b = "b".repeat(64);
+    let file_shared = format!("11{}", "1".repeat(62));
+    let file_b = format!("22{}", "2".repeat(62));
+
+    let write = |path: &str, text: &str| {
+        let path = cache.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    };
+
+    write("urls/https___example.com_bundle.tar", &digest_a);
+
+    for (digest, files) in [
+        (&digest_a, vec![&file_shared]),
+        (&digest_b, vec![&file_shared, &file_b]),
+    ] {
+        write(&format!("redirects/{digest}.txt"), "https://example.com/");
+        write(&format!("indexes/{digest}.txt"), "");
+        let manifest: String = files.iter().map(|f| format!("x.tex 5 {f}\n")).collect();
+        write(&format!("manifests/{digest}.txt"), &manifest);
+    }
+
+    for f in [&file_shared, &file_b] {
+        write(&format!("files/{}/{}", &f[..2], &f[2..]), "hello");
+    }
+
+    let run = |args: &[&str]| {
+        let mut command = prep_tectonic(tempdir.path(), args);
+        command.env("TECTONIC_CACHE_DIR", &cache);
+        println!("running {command:?}");
+        let output = command.output().expect("tectonic failed to start");
+        success_or_panic(&output);
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let listing = run(&["-X", "bundle", "cache", "list"]);
+    assert!(listing.contains(&format!("{digest_a} ")));
+    assert!(listing.contains(&format!("{digest_b} ")));
+    assert!(listing.contains("unreferenced"));
+
+    // GC drops the unreferenced bundle, but not the file it shares.
+
+    run(&["-X", "bundle", "cache", "gc"]);
+    assert!(cache.join(format!("manifests/{digest_a}.txt")).exists());
+    assert!(!cache.join(format!("manifests/{digest_b}.txt")).exists());
+    assert!(cache.join("files/11").join(&file_shared[2..]).exists());
+    assert!(!cache.join("files/22").join(&file_b[2..]).exists());
+
+    let listing = run(&["-X", "bundle", "cache", "list"]);
+    assert!(listing.contains(&format!("{digest_a} ")));
+    assert!(!listing.contains(&format!("{digest_b} ")));
+
+    // An age that's too large to handle is an error.
+
+    let max_age = u64::MAX.to_string();
+    let mut command = prep_tectonic(
+        tempdir.path(),
+        &["-X", "bundle", "cache", "gc", "--max-age", &max_age],
+    );
+    command.env("TECTONIC_CACHE_DIR", &cache);
+    error_or_panic(&command.output().expect("tectonic failed to start"));
+    assert!(cache.join(format!("manifests/{digest_a}.txt")).exists());
+
+    // Purging drops everything.
+
+    run(&["-X", "bundle", "cache", "purge"]);
+    assert!(!cache.join(format!("manifests/{digest_a}.txt")).exists());
+    assert!(!cache.join("files/11").join(&file_shared[2..]).exists());
+    assert_eq!(run(&["-X", "bundle", "cache", "list"]), "");
+}
+
+#[cfg(feature = "serialization")]
+#[test]
+fn v2_bundle_create() {
+    use tectonic::{io::IoProvider, status::NoopStatusBackend};
+    use tectonic_bundles::{zip::ZipBundle, Bundle};
+
+    util::set_test_root();
+
+    let tempdir = setup_and_copy_files(&[]);
+    let root = tempdir.path();
+
+    for (path, text) in [
+        ("tree/a/x.tex", "first"),
+        ("tree/b/c/y.sty", "y"),
+        ("tree/b/x.tex", "second"),
+        ("tree/.git/z", "hidden"),
+    ] {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+
+    // ZIP format: the tree is flattened, and the first of two same-named
+    // files wins.
+
+    let output = run_tectonic(root, &["-X", "bundle", "create", "-o", "b.zip", "tree"]);
+    success_or_panic(&output);
+
+    let mut status = NoopStatusBackend::default();
+    let mut bundle = ZipBundle::open(root.join("b.zip")).unwrap();
+    let mut files = bundle.all_files(&mut status).unwrap();
+    files.sort();
+    assert_eq!(files, ["SHA256SUM", "x.tex", "y.sty"]);
+
+    let mut text = String::new();
+    bundle
+        .input_open_name("x.tex", &mut status)
+        .must_exist()
+        .unwrap()
+        .read_to_string(&mut text)
+        .unwrap();
+    assert_eq!(text, "first");
+
+    // Indexed tar format: the index should point at the file contents, and the
+    // digest should be the same.
+
+    let output = run_tectonic(
+        root,
+        &[
+            "-X", "bundle", "create", "--format", "itar", "-o", "b.tar", "tree",
+        ],
+    );
+    success_or_panic(&output);
+
+    let tar = fs::read(root.join("b.tar")).unwrap();
+    let mut index = String::new();
+    flate2::read::GzDecoder::new(File::open(root.join("b.tar.index.gz")).unwrap())
+        .read_to_string(&mut index)
+        .unwrap();
+
+    let lookup = |name: &str| {
+        let line = index
+            .lines()
+            .find(|l| l.split_whitespace().next() == Some(name))
+            .unwrap();
+        let bits: Vec<usize> = line
+            .split_whitespace()
+            .skip(1)
+            .map(|b| b.parse().unwrap())
+            .collect();
+        str::from_utf8(&tar[bits[0]..bits[0] + bits[1]]).unwrap()
+    };
+
+    assert_eq!(lookup("x.tex"), "first");
+    assert_eq!(lookup("y.sty"), "y");
+    assert_eq!(
+        lookup("SHA256SUM").trim(),
+        bundle.get_digest(&mut status).unwrap().to_string()
+    );
+}
+
+#[test]
+#[cfg(feature = "serialization")]
+fn v2_bundle_serve() {
+    use std::{
+        io::BufRead,
+        io::BufReader,
+        net::{Shutdown, TcpStream},
+    };
+
+    util::set_test_root();
+
+    let tempdir = setup_and_copy_files(&[]);
+    let root = tempdir.path();
+    fs::create_dir(root.join("bundle")).unwrap();
+    fs::write(root.join("bundle/hello.tex"), "Hello, world.").unwrap();
+
+    let mut command = prep_tectonic(
+        root,
+        &["-X", "bundle", "serve", "--addr", "127.0.0.1:0", "bundle"],
+    );
+    command.stdout(Stdio::piped()).stderr(Stdio::null());
+    println!("running {command:?}");
+    let mut child = command.spawn().expect("tectonic failed to start");
+
+    // Find out where the server ended up listening.
+
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut addr = None;
+    let mut line = String::new();
+
+    while stdout.read_line(&mut line).unwrap() > 0 {
+        if let Some(rest) = line.trim().split("http://").nth(1) {
+            addr = rest.strip_suffix("/bundle.tar").map(|a| a.to_owned());
+            break;
+        }
+        line.clear();
+    }
+
+    let addr = match addr {
+        Some(a) => a,
+        None => {
+            child.kill().unwrap();
+            panic!("bundle server did not report its address");
+        }
+    };
+
+    let request = |path: &str, extra: &str| {
+        let mut conn = TcpStream::connect(&addr).unwrap();
+        write!(
+            conn,
+            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n{}\r\n",
+            path, addr, extra
+        )
+        .unwrap();
+        conn.shutdown(Shutdown::Write).unwrap();
+        let mut response = Vec::new();
+        conn.read_to_end(&mut response).unwrap();
+        response
+    };
+
+    let body = |response: &[u8]| {
+        let i = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        response[i + 4..].to_vec()
+    };
+
+    // The index tells us where the file is, and a range request gets it.
+
+    let response = request("/bundle.tar.index.gz", "");
+    assert!(response.starts_with(b"HTTP/1.1 200"));
+    let mut index = String::new();
+    flate2::read::GzDecoder::new(&body(&response)[..])
+        .read_to_string(&mut index)
+        .unwrap();
+
+    let line = index
+        .lines()
+        .find(|l| l.starts_with("hello.tex "))
+        .expect("file missing from index");
+    let bits: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .map(|b| b.parse().unwrap())
+        .collect();
+
+    let range = format!("Range: bytes={}-{}\r\n", bits[0], bits[0] + bits[1] - 1);
+    let response = request("/bundle.tar", &range);
+    assert!(response.starts_with(b"HTTP/1.1 206"));
+    assert_eq!(body(&response), b"Hello, world.");
+
+    let response = request("/nonexistent", "");
+    assert!(response.starts_with(b"HTTP/1.1 404"));
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
 #[test]
 #[cfg(feature = "serialization")]
 fn v2_dump_basic() {
@@ -806,6 +1530,147 @@ fn v2_dump_suffix() {
     assert!(saw_first && saw_second);
 }
 
+#[test]
+#[cfg(feature = "serialization")]
+fn v2_dump_xdv() {
+    let xdv = util::test_path(&["tex-outputs", "a4paper.xdv"]);
+    let xdv = xdv.to_str().unwrap();
+
+    let output = run_tectonic(&PathBuf::from("."), &["-X", "dump-xdv", xdv]);
+    success_or_panic(&output);
+
+    let t = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = t.lines().collect();
+    assert_eq!(lines[0], "0: pre XDV 'tectonic'");
+    assert!(lines.contains(&"68: xxx1 'pdf:pagesize default'"));
+    assert!(lines.contains(&"108: right3 1310720 h:=1310720"));
+    assert!(lines.contains(&"133: fntnum0 current font is cmr10"));
+    assert!(lines.last().unwrap().contains("post_post"));
+
+    let output = run_tectonic(&PathBuf::from("."), &["-X", "dump-xdv", "--json", xdv]);
+    success_or_panic(&output);
+
+    let t = String::from_utf8_lossy(&output.stdout);
+    let pages: Vec<serde_json::Value> = t
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(pages.len(), 1);
+    assert_eq!(pages[0]["page"], 1);
+
+    let items = pages[0]["items"].as_array().unwrap();
+    assert_eq!(items[0]["type"], "special");
+    assert_eq!(items[0]["contents"], "pdf:pagesize default");
+    assert!(items
+        .iter()
+        .any(|i| i["type"] == "font" && i["name"] == "cmr10"));
+    assert!(items
+        .iter()
+        .any(|i| i["type"] == "chars" && i["chars"] == serde_json::json!([97])));
+}
+
+#[test]
+#[cfg(all(feature = "serialization", not(windows)))] // simplistic file URLs
+fn v2_lsp_diagnostics() {
+    let (_tempdir, temppath) = setup_v2();
+    let index_path = temppath.join("src").join("index.tex");
+    let index_text = "Hello.\n\\undefinedcs\n";
+    fs::write(&index_path, index_text).unwrap();
+
+    let messages = [
+        serde_json::json!({
+            "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": { "capabilities": {} }
+        }),
+        serde_json::json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": {
+                "textDocument": {
+                    "uri": format!("file://{}", index_path.display()),
+                    "languageId": "latex",
+                    "version": 1,
+                    "text": index_text,
+                }
+            }
+        }),
+        serde_json::json!({ "jsonrpc": "2.0", "id": 2, "method": "shutdown", "params": null }),
+        serde_json::json!({ "jsonrpc": "2.0", "method": "exit", "params": null }),
+    ];
+
+    let mut input = String::new();
+
+    for msg in &messages {
+        let text = msg.to_string();
+        input.push_str(&format!("Content-Length: {}\r\n\r\n{}", text.len(), text));
+    }
+
+    let output = run_tectonic_with_stdin(&temppath, &["-X", "lsp"], &input);
+    success_or_panic(&output);
+
+    let t = String::from_utf8_lossy(&output.stdout);
+    assert!(t.contains("\"capabilities\""));
+    assert!(t.contains("textDocument/publishDiagnostics"));
+    assert!(t.contains("Undefined control sequence"));
+}
+
+#[cfg(feature = "serialization")]
+#[test]
+fn v2_synctex() {
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    fs::write(
+        tempdir.path().join("doc.tex"),
+        "First page.\n\n\\eject\nSecond page.\n\n\\bye\n",
+    )
+    .unwrap();
+    let output = run_tectonic(tempdir.path(), &[&fmt_arg, "--synctex", "doc.tex"]);
+    success_or_panic(&output);
+
+    let output = run_tectonic(
+        tempdir.path(),
+        &["-X", "synctex", "view", "doc.pdf", "doc.tex", "4"],
+    );
+    success_or_panic(&output);
+
+    let t = String::from_utf8_lossy(&output.stdout);
+    let field = |name: &str| -> String {
+        t.lines()
+            .find_map(|l| l.strip_prefix(name))
+            .unwrap_or_else(|| panic!("no `{}` in synctex view output", name))
+            .to_owned()
+    };
+    assert_eq!(field("Page:"), "2");
+    let x: f64 = field("x:").parse().unwrap();
+    let y: f64 = field("y:").parse().unwrap();
+
+    let output = run_tectonic(
+        tempdir.path(),
+        &[
+            "-X",
+            "synctex",
+            "edit",
+            "doc.pdf",
+            "2",
+            &(x + 1.0).to_string(),
+            &(y - 1.0).to_string(),
+        ],
+    );
+    success_or_panic(&output);
+
+    let t = String::from_utf8_lossy(&output.stdout);
+    assert!(t
+        .lines()
+        .any(|l| l.starts_with("Input:") && l.ends_with("doc.tex")));
+    assert!(t.lines().any(|l| l == "Line:4"));
+
+    let output = run_tectonic(
+        tempdir.path(),
+        &["-X", "synctex", "view", "doc.pdf", "missing.tex", "1"],
+    );
+    error_or_panic(&output);
+}
+
 const SHELL_ESCAPE_TEST_DOC: &str = r#"\immediate\write18{mkdir shellwork}
 \immediate\write18{echo 123 >shellwork/persist}
 \ifnum123=\input{shellwork/persist}
@@ -888,6 +1753,111 @@ fn shell_escape_env_override() {
     error_or_panic(&output);
 }
 
+/// Test that restricted shell escape only runs the allowed programs
+#[cfg(unix)]
+#[test]
+fn shell_escape_restricted() {
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "-", "-Zshell-escape-commands=cp"],
+        r#"\ifnum\shellescape=2 \else \ohnotheshellescapeisntrestricted \fi
+\immediate\openout1=source.txt
+\immediate\write1{123}
+\immediate\closeout1
+\immediate\write18{cp source.txt 'copy.txt'}
+\immediate\write18{sh -c "echo 456 >forbidden.txt"}
+\ifnum123=\input{copy.txt}
+a
+\else
+\ohnotheallowedcommanddidntrun
+\fi
+\openin2=forbidden.txt
+\ifeof2 \else \ohnotheforbiddencommandran \fi
+\bye
+"#,
+    );
+    success_or_panic(&output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not in the list"));
+}
+
+/// Check whether the shell-escape sandbox can be used here: not all systems
+/// allow unprivileged users to create namespaces.
+#[cfg(target_os = "linux")]
+fn user_namespaces_available() -> bool {
+    let probe = Command::new("unshare")
+        .args(["--user", "--mount", "--net", "true"])
+        .status();
+
+    if !probe.map(|s| s.success()).unwrap_or(false) {
+        println!("skipping test: user namespaces seem to be unavailable");
+        return false;
+    }
+
+    true
+}
+
+/// Test that sandboxed shell escape can only write to its work directory
+#[cfg(target_os = "linux")]
+#[test]
+fn shell_escape_sandbox() {
+    if !user_namespaces_available() {
+        return;
+    }
+
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    let escaped = tempdir.path().join("escaped");
+
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "-", "-Zshell-escape", "-Zshell-escape-sandbox"],
+        &format!(
+            r#"\immediate\write18{{echo 123 >persist}}
+\immediate\write18{{echo 456 >{}}}
+\ifnum123=\input{{persist}}
+a
+\else
+\ohnotheshellescapedidntwork
+\fi
+\bye
+"#,
+            escaped.display()
+        ),
+    );
+    success_or_panic(&output);
+    assert!(!escaped.exists());
+}
+
+/// Test that sandboxed shell escape can't make the filesystem writable again,
+/// even when Tectonic runs as root
+#[cfg(target_os = "linux")]
+#[test]
+fn shell_escape_sandbox_remount() {
+    if !user_namespaces_available() {
+        return;
+    }
+
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    let escaped = tempdir.path().join("escaped");
+
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "-", "-Zshell-escape", "-Zshell-escape-sandbox"],
+        &format!(
+            r#"\immediate\write18{{for m in $(cut -d' ' -f5 /proc/self/mountinfo); do mount -o remount,bind,rw $m; mount -o remount,rw $m; done; echo 456 >{}}}
+a\bye
+"#,
+            escaped.display()
+        ),
+    );
+    success_or_panic(&output);
+    assert!(!escaped.exists());
+}
+
 /// Test that include paths work
 #[test]
 fn extra_search_paths() {